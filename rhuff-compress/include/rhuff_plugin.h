@@ -0,0 +1,50 @@
+/*
+ * Plugin interface of rhuff-compress, loaded with `--plugin path.so`.
+ *
+ * A plugin is a shared library exporting the four functions below. Plugins run on the
+ * input before it is compressed, and in reverse order on the output after it is
+ * decompressed. The container records a fingerprint of the ids of the plugins, in order,
+ * and decompressing fails unless the same plugins are passed.
+ *
+ * plugins/delta.c is a complete plugin.
+ */
+#ifndef RHUFF_PLUGIN_H
+#define RHUFF_PLUGIN_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#define RHUFF_PLUGIN_ABI_VERSION 1
+
+/* Direction of a transform */
+#define RHUFF_FORWARD 0 /* before compressing */
+#define RHUFF_REVERSE 1 /* after decompressing */
+
+/* Appends `len` bytes to the output of the transform. May be called any number of times. */
+typedef void (*rhuff_emit)(void *ctx, const uint8_t *data, size_t len);
+
+/*
+ * Called once after loading. Returns the plugin state passed to the other functions, or
+ * NULL on failure. `abi_version` is RHUFF_PLUGIN_ABI_VERSION; plugins should fail when it
+ * is not the one they were built against.
+ */
+void *rhuff_plugin_init(uint32_t abi_version);
+
+/*
+ * Returns a NUL-terminated name of the plugin and of the version of its transform, e.g.
+ * "delta-1". It is recorded in the containers, so it must change whenever the transform
+ * does, and stay the same otherwise.
+ */
+const char *rhuff_plugin_id(void);
+
+/*
+ * Transforms the whole input, passing the result to `emit` along with `ctx`. Returns 0 on
+ * success, anything else aborts the run.
+ */
+int rhuff_plugin_transform(void *state, int direction, const uint8_t *input, size_t len,
+                           rhuff_emit emit, void *ctx);
+
+/* Called once before unloading, releases the state */
+void rhuff_plugin_finish(void *state);
+
+#endif
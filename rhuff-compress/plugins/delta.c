@@ -0,0 +1,56 @@
+/*
+ * Sample plugin replacing every byte by its difference with the previous one, which turns
+ * slowly changing data into runs of small values.
+ *
+ *     cc -shared -fPIC -I ../include -o libdelta.so delta.c
+ *     rhuff-compress -c --plugin ./libdelta.so -o out.rhf in
+ */
+#include <stdlib.h>
+
+#include "rhuff_plugin.h"
+
+/* Bytes emitted at a time */
+#define CHUNK 4096
+
+/* The plugin has no state, but NULL means failure */
+static int state;
+
+void *rhuff_plugin_init(uint32_t abi_version) {
+    if (abi_version != RHUFF_PLUGIN_ABI_VERSION) {
+        return NULL;
+    }
+    return &state;
+}
+
+const char *rhuff_plugin_id(void) {
+    return "delta-1";
+}
+
+int rhuff_plugin_transform(void *state, int direction, const uint8_t *input, size_t len,
+                           rhuff_emit emit, void *ctx) {
+    uint8_t chunk[CHUNK];
+    uint8_t previous = 0;
+    size_t i;
+    (void)state;
+    if (direction != RHUFF_FORWARD && direction != RHUFF_REVERSE) {
+        return -1;
+    }
+    for (i = 0; i < len; i++) {
+        if (direction == RHUFF_FORWARD) {
+            chunk[i % CHUNK] = (uint8_t)(input[i] - previous);
+            previous = input[i];
+        } else {
+            previous = (uint8_t)(previous + input[i]);
+            chunk[i % CHUNK] = previous;
+        }
+        if (i % CHUNK == CHUNK - 1) {
+            emit(ctx, chunk, CHUNK);
+        }
+    }
+    emit(ctx, chunk, len % CHUNK);
+    return 0;
+}
+
+void rhuff_plugin_finish(void *state) {
+    (void)state;
+}
@@ -0,0 +1,144 @@
+//! Compressing many files under a single thread budget. Files are started largest first and
+//! each takes as many threads as its share of the bytes is worth, so a large file encodes its
+//! blocks in parallel while small files are compressed side by side, never using more
+//! threads than the budget in total.
+use crate::error::{Context, Error};
+use crate::interrupt::Output;
+use crate::paths;
+use crate::presets;
+use crate::space;
+use rhuffman::container;
+use std::cmp::Reverse;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+
+/// Extension of the compressed files
+const EXTENSION: &str = "rhf";
+
+/// Threads not in use, taken by files as they start and given back as they finish
+struct Budget {
+    free: Mutex<usize>,
+    given_back: Condvar,
+}
+
+impl Budget {
+    /// Takes up to `wanted` threads, waiting until at least one is free
+    fn take(&self, wanted: usize) -> usize {
+        let mut free = self.free.lock().unwrap_or_else(|e| e.into_inner());
+        while *free == 0 {
+            free = self
+                .given_back
+                .wait(free)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+        let taken = wanted.min(*free);
+        *free -= taken;
+        taken
+    }
+
+    fn give_back(&self, threads: usize) {
+        *self.free.lock().unwrap_or_else(|e| e.into_inner()) += threads;
+        self.given_back.notify_all();
+    }
+}
+
+/// Compresses every input to `<name>.rhf`, in `output_dir` or next to the input, on at most
+/// `threads` threads. `options` gives the options of each file from its data. With
+/// `reserve_check`, each file checks for room for its output before compressing, see
+/// [space::reserve()]. With a `preset_dir`, the presets that changed in it are swapped in
+/// before each file starts, see [presets::reload_dir()]. Stops starting files after the first
+/// failure, and returns it.
+pub fn compress(
+    inputs: &[PathBuf],
+    output_dir: Option<&Path>,
+    preset_dir: Option<&Path>,
+    threads: usize,
+    reserve_check: bool,
+    options: impl Fn(&[u8]) -> container::Options + Sync,
+) -> Result<(), Error> {
+    let mut files = inputs
+        .iter()
+        .map(|path| {
+            let len = fs::metadata(paths::long(path))
+                .context("file", path.display())?
+                .len();
+            Ok((path.as_path(), len))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    // The last files to finish are then small ones, leaving no thread idle for long
+    files.sort_by_key(|&(_, len)| Reverse(len));
+    let total: u64 = files.iter().map(|&(_, len)| len).sum();
+    let share = (total / threads as u64).max(1);
+
+    let budget = Budget {
+        free: Mutex::new(threads),
+        given_back: Condvar::new(),
+    };
+    let failure = Mutex::new(None);
+    let (budget, options, failure) = (&budget, &options, &failure);
+    thread::scope(|scope| {
+        for (path, len) in files {
+            let wanted = (len.div_ceil(share) as usize).clamp(1, threads);
+            let taken = budget.take(wanted);
+            if let Some(dir) = preset_dir {
+                if let Err(error) = presets::reload_dir(dir) {
+                    let mut failure = failure.lock().unwrap_or_else(|e| e.into_inner());
+                    failure.get_or_insert(error);
+                }
+            }
+            if failure.lock().unwrap_or_else(|e| e.into_inner()).is_some() {
+                break;
+            }
+            scope.spawn(move || {
+                let result = compress_file(path, output_dir, taken, reserve_check, options);
+                budget.give_back(taken);
+                if let Err(error) = result {
+                    let mut failure = failure.lock().unwrap_or_else(|e| e.into_inner());
+                    failure.get_or_insert(error);
+                }
+            });
+        }
+    });
+    let failure = failure.lock().unwrap_or_else(|e| e.into_inner()).take();
+    match failure {
+        Some(error) => Err(error),
+        None => Ok(()),
+    }
+}
+
+fn compress_file(
+    path: &Path,
+    output_dir: Option<&Path>,
+    threads: usize,
+    reserve_check: bool,
+    options: impl Fn(&[u8]) -> container::Options,
+) -> Result<(), Error> {
+    // Built from the OsStr, names that are not UTF-8 are kept as they are
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(".");
+    name.push(EXTENSION);
+    let output = match output_dir {
+        Some(dir) => dir.join(name),
+        None => path.with_file_name(name),
+    };
+
+    let data = fs::read(paths::long(path)).context("file", path.display())?;
+    if reserve_check {
+        space::reserve(&output, &data).context("file", output.display())?;
+    }
+    let options = container::Options {
+        threads,
+        ..options(&data)
+    };
+    let compressed = container::compress(&data, &options)
+        .context("operation", "compress")
+        .context("file", path.display())?;
+
+    let mut out = Output::create(Some(&output)).context("file", output.display())?;
+    out.write_all(&compressed)
+        .context("file", output.display())?;
+    out.commit().context("file", output.display())
+}
@@ -0,0 +1,96 @@
+//! The stub of the self-extracting archives of `rhuff-compress --self-extracting`. Run as
+//! an archive, it writes the file it carries:
+//!
+//! ```text
+//! archive [--force] [OUTPUT]
+//! ```
+//!
+//! OUTPUT defaults to the name of the original file in the current directory. Existing
+//! files are only overwritten with `--force`.
+//!
+//! The stub only shares the few modules of rhuff-compress it needs, so that archives stay
+//! small.
+#[allow(dead_code)]
+#[path = "../error.rs"]
+mod error;
+#[allow(dead_code)]
+#[path = "../interrupt.rs"]
+mod interrupt;
+#[allow(dead_code)]
+#[path = "../paths.rs"]
+mod paths;
+#[allow(dead_code)]
+#[path = "../space.rs"]
+mod space;
+#[allow(dead_code)]
+#[path = "../sfx/trailer.rs"]
+mod trailer;
+
+use error::{Context, Error};
+use interrupt::Output;
+use rhuffman::container;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+const USAGE: &str = "usage: archive [--force] [OUTPUT]";
+
+fn main() {
+    interrupt::install(false);
+    if let Err(error) = run() {
+        eprintln!("error: {}", error);
+        std::process::exit(error.exit_code());
+    }
+}
+
+fn run() -> Result<(), Error> {
+    let (mut force, mut output) = (false, None);
+    for arg in std::env::args_os().skip(1) {
+        match arg.to_str() {
+            Some("-f") | Some("--force") => force = true,
+            Some("-h") | Some("--help") => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            Some(flag) if flag.starts_with('-') && flag != "-" => {
+                return Err(Error::new(USAGE).with("argument", flag))
+            }
+            _ if output.is_none() => output = Some(PathBuf::from(arg)),
+            _ => return Err(Error::new(USAGE)),
+        }
+    }
+
+    let exe = std::env::current_exe().context("operation", "locate the archive")?;
+    let payload = File::open(paths::long(&exe))
+        .map_err(Error::from)
+        .and_then(|mut file| trailer::read(&mut file).map_err(Error::from))
+        .context("file", exe.display())?
+        .ok_or("This is the stub of self-extracting archives, it carries no file")?;
+    let output = match output {
+        Some(output) => output,
+        None => {
+            let name = paths::from_bytes(&payload.name)
+                .ok_or("The file name can not be used on this platform")?;
+            // Never write outside the current directory, whatever the archive claims
+            let name = Path::new(&name)
+                .file_name()
+                .ok_or("Corrupted self-extracting archive")?;
+            PathBuf::from(name)
+        }
+    };
+    if output != Path::new("-") && paths::long(&output).exists() && !force {
+        return Err(
+            Error::new("The output exists, pass --force to overwrite it")
+                .with("file", output.display()),
+        );
+    }
+
+    let decoded = container::decompress(&payload.compressed)
+        .context("operation", "decompress")
+        .context("file", exe.display())?;
+    let mut out = Output::create(Some(&output)).context("file", output.display())?;
+    out.write_all(&decoded).context("file", output.display())?;
+    out.commit().context("file", output.display())?;
+    eprintln!("Extracted {}", output.display());
+    Ok(())
+}
@@ -0,0 +1,440 @@
+//! The `compat` subcommand: what a reader needs to decode a given file.
+use crate::error::Error;
+use rhuffman::container::{self, minimal, ContainerFormat, TreeSource};
+use rhuffman::entropy;
+use rhuffman::filters::Filter;
+use rhuffman::presets;
+
+/// Something a file can require from its reader
+struct Capability {
+    name: &'static str,
+    /// Container format version it came with, which readers of that version and later
+    /// have. None for the legacy format, which predates them.
+    format: Option<u8>,
+    /// Cargo feature of rhuff-compress it depends on
+    feature: Option<&'static str>,
+    /// Whether this binary has it
+    supported: bool,
+}
+
+const CONTAINER_V1: &str = "container format v1";
+const CONTAINER_V2: &str = "container format v2";
+const CONTAINER_V3: &str = "container format v3";
+const CONTAINER_V4: &str = "container format v4";
+const CONTAINER_V5: &str = "container format v5";
+const CONTAINER_V6: &str = "container format v6";
+const CONTAINER_V7: &str = "container format v7";
+const CONTAINER_MINIMAL: &str = "minimal header";
+const LEGACY: &str = "legacy MessagePack format";
+const HUFFMAN: &str = "huffman entropy coding";
+const TIMESERIES: &str = "timeseries filter";
+const NIBBLES: &str = "nibble filters";
+const RLE: &str = "run-length filter";
+const CHARS: &str = "UTF-8 char symbols";
+const STORED: &str = "stored blocks";
+const ALIGNED: &str = "aligned blocks";
+const BLOCK_CHECKSUMS: &str = "block checksums";
+const STREAMED: &str = "streamed container";
+const PLUGINS: &str = "plugin fingerprint";
+const BACKENDS: &str = "entropy backends";
+
+const CAPABILITIES: &[Capability] = &[
+    Capability {
+        name: CONTAINER_V1,
+        format: Some(1),
+        feature: None,
+        supported: true,
+    },
+    Capability {
+        name: CONTAINER_V2,
+        format: Some(2),
+        feature: None,
+        supported: true,
+    },
+    Capability {
+        name: CONTAINER_V3,
+        format: Some(3),
+        feature: None,
+        supported: true,
+    },
+    Capability {
+        name: CONTAINER_V4,
+        format: Some(4),
+        feature: None,
+        supported: true,
+    },
+    Capability {
+        name: CONTAINER_V5,
+        format: Some(5),
+        feature: None,
+        supported: true,
+    },
+    Capability {
+        name: CONTAINER_V6,
+        format: Some(6),
+        feature: None,
+        supported: true,
+    },
+    Capability {
+        name: CONTAINER_V7,
+        format: Some(7),
+        feature: None,
+        supported: true,
+    },
+    Capability {
+        name: CONTAINER_MINIMAL,
+        format: Some(7),
+        feature: None,
+        supported: true,
+    },
+    Capability {
+        name: LEGACY,
+        format: None,
+        feature: Some("legacy-format"),
+        supported: cfg!(feature = "legacy-format"),
+    },
+    Capability {
+        name: HUFFMAN,
+        format: Some(1),
+        feature: None,
+        supported: true,
+    },
+    Capability {
+        name: TIMESERIES,
+        format: Some(1),
+        feature: None,
+        supported: true,
+    },
+    Capability {
+        name: NIBBLES,
+        format: Some(4),
+        feature: None,
+        supported: true,
+    },
+    Capability {
+        name: RLE,
+        format: Some(4),
+        feature: None,
+        supported: true,
+    },
+    Capability {
+        name: CHARS,
+        format: Some(7),
+        feature: None,
+        supported: true,
+    },
+    Capability {
+        name: STORED,
+        format: Some(5),
+        feature: None,
+        supported: true,
+    },
+    Capability {
+        name: ALIGNED,
+        format: Some(5),
+        feature: None,
+        supported: true,
+    },
+    Capability {
+        name: BLOCK_CHECKSUMS,
+        format: Some(6),
+        feature: None,
+        supported: true,
+    },
+    Capability {
+        name: STREAMED,
+        format: Some(7),
+        feature: None,
+        supported: true,
+    },
+    Capability {
+        name: PLUGINS,
+        format: Some(7),
+        feature: None,
+        supported: true,
+    },
+    Capability {
+        name: BACKENDS,
+        format: Some(7),
+        feature: None,
+        supported: true,
+    },
+];
+
+fn capability(name: &str) -> &'static Capability {
+    CAPABILITIES
+        .iter()
+        .find(|capability| capability.name == name)
+        .expect("Missing capability")
+}
+
+/// A line of the report
+struct Row {
+    name: String,
+    needs: String,
+    status: &'static str,
+    ok: bool,
+}
+
+/// Prints the requirements of the file, returns whether this binary can decode it
+pub fn report(contents: &[u8]) -> Result<bool, Error> {
+    let rows = rows(contents)?;
+    for row in &rows {
+        println!("{:<28} {:<40} {}", row.name, row.needs, row.status);
+    }
+    Ok(rows.iter().all(|row| row.ok))
+}
+
+/// The requirements of the file
+fn rows(contents: &[u8]) -> Result<Vec<Row>, &'static str> {
+    let mut required = vec![];
+    let mut presets = vec![];
+    let mut backends = vec![];
+    // Minimal headers name a preset without its fingerprint
+    let mut unfingerprinted = None;
+    let mut plugins = None;
+    match ContainerFormat::detect(contents) {
+        Some(format @ ContainerFormat::V1)
+        | Some(format @ ContainerFormat::V2)
+        | Some(format @ ContainerFormat::V3)
+        | Some(format @ ContainerFormat::V4)
+        | Some(format @ ContainerFormat::V5)
+        | Some(format @ ContainerFormat::V6)
+        | Some(format @ ContainerFormat::V7) => {
+            let header = container::inspect(contents)?;
+            required.push(capability(match format {
+                ContainerFormat::V1 => CONTAINER_V1,
+                ContainerFormat::V2 => CONTAINER_V2,
+                ContainerFormat::V3 => CONTAINER_V3,
+                ContainerFormat::V4 => CONTAINER_V4,
+                ContainerFormat::V5 => CONTAINER_V5,
+                ContainerFormat::V6 => CONTAINER_V6,
+                _ => CONTAINER_V7,
+            }));
+            required.push(capability(HUFFMAN));
+            match header.filter {
+                Filter::None => {}
+                Filter::Timeseries => required.push(capability(TIMESERIES)),
+                Filter::Nibbles | Filter::NibblePlanes => required.push(capability(NIBBLES)),
+                Filter::Rle => required.push(capability(RLE)),
+                Filter::Chars(_) => required.push(capability(CHARS)),
+                _ => return Err("The filter of the file has no requirements listed"),
+            }
+            for block in &header.blocks {
+                match block.tree {
+                    TreeSource::Preset { id, fingerprint }
+                        if !presets.contains(&(id, fingerprint)) =>
+                    {
+                        presets.push((id, fingerprint))
+                    }
+                    TreeSource::Backend { id } if !backends.contains(&id) => backends.push(id),
+                    _ => {}
+                }
+            }
+            if header
+                .blocks
+                .iter()
+                .any(|block| block.tree == TreeSource::Stored)
+            {
+                required.push(capability(STORED));
+            }
+            if header.align > 0 {
+                required.push(capability(ALIGNED));
+            }
+            if header.block_checksums {
+                required.push(capability(BLOCK_CHECKSUMS));
+            }
+            if header.streamed {
+                required.push(capability(STREAMED));
+            }
+            if header.plugins.is_some() {
+                required.push(capability(PLUGINS));
+            }
+            if !backends.is_empty() {
+                required.push(capability(BACKENDS));
+            }
+            plugins = header.plugins;
+        }
+        Some(ContainerFormat::Minimal) => {
+            required.push(capability(CONTAINER_MINIMAL));
+            required.push(capability(HUFFMAN));
+            unfingerprinted = Some(minimal::preset_id(contents)?);
+        }
+        Some(ContainerFormat::Legacy) => {
+            required.push(capability(LEGACY));
+            required.push(capability(HUFFMAN));
+        }
+        Some(_) => return Err("The format of the file has no requirements listed"),
+        None => return Err("Not a compressed file"),
+    }
+
+    let mut rows = vec![];
+    for capability in &required {
+        let mut needs = match capability.format {
+            Some(format) => format!("container format >= v{}", format),
+            None => "legacy reader".to_string(),
+        };
+        if let Some(feature) = capability.feature {
+            needs += &format!(", feature {}", feature);
+        }
+        rows.push(Row {
+            name: capability.name.to_string(),
+            needs,
+            status: if capability.supported {
+                "supported"
+            } else {
+                "unsupported"
+            },
+            ok: capability.supported,
+        });
+    }
+    for (id, fingerprint) in presets {
+        let (status, ok) = match presets::global().get(id) {
+            Some(registered) if registered.fingerprint() == fingerprint => ("registered", true),
+            Some(_) => ("registered with another fingerprint", false),
+            None => ("not registered, see --preset-dir", false),
+        };
+        rows.push(Row {
+            name: format!("preset {}", id),
+            needs: format!("fingerprint {:016x}", fingerprint),
+            status,
+            ok,
+        });
+    }
+    for id in backends {
+        let (status, ok) = match entropy::global().get(id) {
+            Some(_) => ("registered", true),
+            None => ("not registered", false),
+        };
+        rows.push(Row {
+            name: format!("backend {}", id),
+            needs: "entropy coder registered in the reader".to_string(),
+            status,
+            ok,
+        });
+    }
+    if let Some(id) = unfingerprinted {
+        let (status, ok) = match presets::global().get(id) {
+            Some(_) => ("registered, fingerprint not checked", true),
+            None => ("not registered, see --preset-dir", false),
+        };
+        rows.push(Row {
+            name: format!("preset {}", id),
+            needs: "no fingerprint recorded".to_string(),
+            status,
+            ok,
+        });
+    }
+    if let Some(plugins) = plugins {
+        rows.push(Row {
+            name: "plugins".to_string(),
+            needs: format!("fingerprint {:016x}", plugins),
+            status: "pass the same --plugin to decompress",
+            ok: true,
+        });
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rhuffman::container::{BlockSize, HeaderKind, Options};
+
+    fn names(rows: &[Row]) -> Vec<&str> {
+        rows.iter().map(|row| row.name.as_str()).collect()
+    }
+
+    #[test]
+    fn capabilities_come_with_known_formats() {
+        for capability in CAPABILITIES {
+            if let Some(format) = capability.format {
+                assert!((1..=container::VERSION).contains(&format));
+            }
+        }
+        for (name, version) in &[(CONTAINER_V1, 1), (CONTAINER_V5, 5), (CONTAINER_V7, 7)] {
+            assert_eq!(capability(name).format, Some(*version));
+        }
+        // The container version the writer stamps is listed
+        let name = format!("container format v{}", container::VERSION);
+        assert!(CAPABILITIES
+            .iter()
+            .any(|capability| capability.name == name));
+    }
+
+    #[test]
+    fn rows_list_what_the_file_uses() {
+        let plain = container::compress(b"hello hello", &Options::default()).unwrap();
+        let required = rows(&plain).unwrap();
+        assert_eq!(names(&required), [CONTAINER_V7, HUFFMAN]);
+        assert_eq!(required[0].needs, "container format >= v7");
+        assert!(required.iter().all(|row| row.ok));
+
+        let options = Options {
+            filter: Filter::Rle,
+            block_size: BlockSize::Fixed(4),
+            block_checksums: true,
+            checksum: container::Checksum::Crc32,
+            align: 64,
+            plugins: Some(1),
+            ..Options::default()
+        };
+        let required = rows(&container::compress(b"aaaaaaaab", &options).unwrap()).unwrap();
+        assert_eq!(
+            names(&required),
+            [
+                CONTAINER_V7,
+                HUFFMAN,
+                RLE,
+                ALIGNED,
+                BLOCK_CHECKSUMS,
+                PLUGINS,
+                "plugins"
+            ]
+        );
+        assert_eq!(required[2].needs, "container format >= v4");
+
+        let chars = Options {
+            filter: Filter::Chars(rhuffman::filters::utf8::Utf8Policy::Escape),
+            ..Options::default()
+        };
+        let text = container::compress(b"caf\xe9", &chars).unwrap();
+        assert_eq!(names(&rows(&text).unwrap()), [CONTAINER_V7, HUFFMAN, CHARS]);
+    }
+
+    #[test]
+    fn presets_must_be_registered() {
+        let options = Options {
+            preset: Some(230),
+            header: HeaderKind::Minimal,
+            ..Options::default()
+        };
+        let tree = {
+            let mut gen = rhuffman::huffman_tree::huffman_generator::HuffmanGenerator::new();
+            gen.add_occurences_from_iterator(&mut b"abc".iter());
+            gen.into_huffman_tree().unwrap()
+        };
+        presets::global().replace(230, tree);
+        let compressed = container::compress(b"abc", &options).unwrap();
+        let required = rows(&compressed).unwrap();
+        assert_eq!(names(&required), [CONTAINER_MINIMAL, HUFFMAN, "preset 230"]);
+        assert!(required.iter().all(|row| row.ok));
+
+        assert!(rows(b"garbage").is_err());
+    }
+
+    #[test]
+    fn backends_must_be_registered() {
+        let options = Options {
+            backend: Some(entropy::HUFFMAN),
+            ..Options::default()
+        };
+        let compressed = container::compress(b"abracadabra", &options).unwrap();
+        let required = rows(&compressed).unwrap();
+        assert_eq!(
+            names(&required),
+            [CONTAINER_V7, HUFFMAN, BACKENDS, "backend 0"]
+        );
+        assert!(required.iter().all(|row| row.ok));
+    }
+}
@@ -0,0 +1,105 @@
+//! Failures of rhuff-compress, carrying what was being done where when they happened so a
+//! single log line is enough to diagnose them.
+use crate::space;
+use rhuffman::report;
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+
+#[derive(Debug)]
+pub struct Error {
+    message: String,
+    /// Innermost first
+    context: Vec<(&'static str, String)>,
+    /// The output ran out of disk space, see [space]
+    no_space: bool,
+}
+
+impl Error {
+    pub fn new(message: impl Into<String>) -> Error {
+        Error {
+            message: message.into(),
+            context: vec![],
+            no_space: false,
+        }
+    }
+
+    /// A failure for lack of disk space
+    pub fn no_space(message: impl Into<String>) -> Error {
+        Error {
+            no_space: true,
+            ..Error::new(message)
+        }
+    }
+
+    /// The exit code of a run failing with this error
+    pub fn exit_code(&self) -> i32 {
+        match self.no_space {
+            true => space::EXIT_NO_SPACE,
+            false => 1,
+        }
+    }
+
+    /// Adds what the failure happened in, e.g. `("file", path)`
+    pub fn with(mut self, key: &'static str, value: impl Display) -> Error {
+        self.context.push((key, value.to_string()));
+        self
+    }
+
+    /// A single line JSON object, see [report::Failure]
+    pub fn to_json(&self) -> String {
+        let mut context = BTreeMap::new();
+        for (key, value) in &self.context {
+            // The innermost value of a key is the most precise
+            context
+                .entry(key.to_string())
+                .or_insert_with(|| value.clone());
+        }
+        let failure = report::Failure {
+            schema: report::SCHEMA_VERSION,
+            error: self.message.clone(),
+            context,
+        };
+        serde_json::to_string(&failure).expect("Reports always serialize")
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if !self.context.is_empty() {
+            let context: Vec<String> = self
+                .context
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, value))
+                .collect();
+            write!(f, " ({})", context.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl From<&str> for Error {
+    fn from(message: &str) -> Error {
+        Error::new(message)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Error {
+        match space::is_no_space(&error) {
+            true => Error::no_space(error.to_string()),
+            false => Error::new(error.to_string()),
+        }
+    }
+}
+
+/// Adds context to the error of a result, see [Error::with]
+pub trait Context<T> {
+    fn context(self, key: &'static str, value: impl Display) -> Result<T, Error>;
+}
+
+impl<T, E: Into<Error>> Context<T> for Result<T, E> {
+    fn context(self, key: &'static str, value: impl Display) -> Result<T, Error> {
+        self.map_err(|error| error.into().with(key, value))
+    }
+}
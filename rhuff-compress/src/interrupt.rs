@@ -0,0 +1,118 @@
+//! Ctrl-C handling. Outputs are written next to their destination and renamed into place once
+//! complete, and an interrupted run removes them before exiting with [EXIT_INTERRUPTED], so
+//! it never leaves a truncated file where a good one is expected.
+use crate::paths;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// 128 + SIGINT, like shells report processes killed by Ctrl-C
+pub const EXIT_INTERRUPTED: i32 = 130;
+
+/// Outputs being written, removed on interrupt
+static PARTIAL: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Handles Ctrl-C for the rest of the process. With `keep_partial`, outputs being written
+/// are left in place under their partial name.
+pub fn install(keep_partial: bool) {
+    ctrlc::set_handler(move || {
+        // Held until exit so no output gets committed in the meantime
+        let partial = PARTIAL.lock().unwrap_or_else(|e| e.into_inner());
+        for path in partial.iter() {
+            if keep_partial {
+                eprintln!("Interrupted, partial output kept in {}", path.display());
+            } else {
+                let _ = fs::remove_file(path);
+            }
+        }
+        if partial.is_empty() || !keep_partial {
+            eprintln!("Interrupted");
+        }
+        std::process::exit(EXIT_INTERRUPTED);
+    })
+    .expect("Unable to handle Ctrl-C");
+}
+
+/// Where the result of the run goes
+pub enum Output {
+    Stdout(io::Stdout),
+    File(PartialFile),
+}
+
+impl Output {
+    /// Creates the file, or writes to stdout if there is none or it is `-`
+    pub fn create(path: Option<&Path>) -> io::Result<Output> {
+        match path {
+            Some(path) if path != Path::new("-") => {
+                Ok(Output::File(PartialFile::create(&paths::long(path))?))
+            }
+            _ => Ok(Output::Stdout(io::stdout())),
+        }
+    }
+
+    /// Completes the output, moving a file into place
+    pub fn commit(self) -> io::Result<()> {
+        match self {
+            Output::Stdout(mut stdout) => stdout.flush(),
+            Output::File(file) => file.commit(),
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Stdout(stdout) => stdout.write(buf),
+            Output::File(file) => file.file.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Stdout(stdout) => stdout.flush(),
+            Output::File(file) => file.file.flush(),
+        }
+    }
+}
+
+/// A file written as `<path>.partial` until committed. Dropping it uncommitted removes it.
+pub struct PartialFile {
+    file: File,
+    partial: PathBuf,
+    path: PathBuf,
+}
+
+impl PartialFile {
+    pub fn create(path: &Path) -> io::Result<PartialFile> {
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".partial");
+        let partial = PathBuf::from(partial);
+        let mut registered = PARTIAL.lock().unwrap_or_else(|e| e.into_inner());
+        let file = File::create(&partial)?;
+        registered.push(partial.clone());
+        Ok(PartialFile {
+            file,
+            partial,
+            path: path.to_path_buf(),
+        })
+    }
+
+    pub fn commit(mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let mut registered = PARTIAL.lock().unwrap_or_else(|e| e.into_inner());
+        fs::rename(&self.partial, &self.path)?;
+        registered.retain(|path| *path != self.partial);
+        Ok(())
+    }
+}
+
+impl Drop for PartialFile {
+    fn drop(&mut self) {
+        let mut registered = PARTIAL.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(index) = registered.iter().position(|path| *path == self.partial) {
+            registered.remove(index);
+            let _ = fs::remove_file(&self.partial);
+        }
+    }
+}
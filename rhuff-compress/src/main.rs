@@ -1,72 +1,1111 @@
-mod compressed;
+mod batch;
+mod compat;
+mod error;
+mod interrupt;
+mod migrate;
+mod paths;
+mod plugin;
+mod presets;
+mod sfx;
+mod space;
+mod stats;
+mod stream;
+mod telemetry;
+mod testvectors;
+mod tune;
 
-use bit_vec::BitVec;
-use rhuffman::huffman_tree::huffman_decoder::HuffmanDecoder;
-use rhuffman::huffman_tree::huffman_generator::HuffmanGenerator;
-use std::io::prelude::*;
-use std::{fs::File, path::PathBuf};
+use error::{Context, Error};
+use interrupt::Output;
+use plugin::Plugin;
+use rhuffman::container::merge::{self, MergeTree};
+use rhuffman::container::{
+    self, delta, tar, BlockSize, ByteOrder, Checksum, Content, HeaderKind, Padding,
+};
+use rhuffman::entropy::BackendId;
+use rhuffman::filters::utf8::Utf8Policy;
+use rhuffman::filters::{rle, Filter};
+use rhuffman::huffman_tree::analysis::{self, analyze};
+use rhuffman::presets::PresetId;
+use rhuffman::report;
+use std::fs::OpenOptions;
+use std::io::{prelude::*, SeekFrom};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use std::{fs::File, path::Path, path::PathBuf};
+use structopt::clap::{self, AppSettings};
 use structopt::StructOpt;
 
-use compressed::Compressed;
-
 #[derive(Debug, StructOpt)]
-#[structopt(about = "Size shall be brought to entropy", author)]
+#[structopt(
+    about = "Size shall be brought to entropy",
+    author,
+    setting = AppSettings::SubcommandsNegateReqs
+)]
 struct Opt {
     /// Compress input file into output file
-    #[structopt(short = "c", long = "compress", required_unless = "decompress")]
+    #[structopt(
+        short = "c",
+        long = "compress",
+        required_unless_one = &["decompress", "test"]
+    )]
     compress: bool,
     /// Decompress input file into output file
-    #[structopt(short = "d", long = "decompress", required_unless = "compress")]
+    #[structopt(
+        short = "d",
+        long = "decompress",
+        required_unless_one = &["compress", "test"]
+    )]
     decompress: bool,
+    /// Check that the input decompresses and matches its checksums, without writing anything.
+    /// Stops at the first damaged block.
+    #[structopt(short = "t", long = "test", conflicts_with_all = &["compress", "decompress"])]
+    test: bool,
+
+    /// Preprocessing stage applied before compressing: none, timeseries, nibbles,
+    /// nibble-planes, rle, or chars, chars-replace and chars-escape for UTF-8 text with the
+    /// policies of --utf8
+    #[structopt(long = "filter", default_value = "none")]
+    filter: Filter,
+
+    /// Do not code long runs as their length when no --filter or --symbols is given, which
+    /// is otherwise done when long runs make up a good part of the input
+    #[structopt(long = "no-rle")]
+    no_rle: bool,
+
+    /// Symbols the input is coded as: byte, nibble, nibble-planes for nibbles coded with a
+    /// tree for high nibbles and one for low nibbles, or char for UTF-8 text, see --utf8.
+    /// Symbols other than bytes can not be combined with --filter.
+    #[structopt(long = "symbols", default_value = "byte")]
+    symbols: Symbols,
+
+    /// With --symbols char, what to do with input that is not UTF-8: fail (the default),
+    /// replace invalid sequences with U+FFFD, or escape their bytes so that decompressing
+    /// restores them. The policy is recorded in the compressed file.
+    #[structopt(long = "utf8")]
+    utf8: Option<Utf8Policy>,
+
+    /// Estimate how well byte, u16, nibble and digram symbols compress a sample of the input,
+    /// print the estimates on stderr, and compress with the best of the byte and nibble ones,
+    /// which compressed files can code. Overrides --symbols.
+    #[structopt(long = "probe", requires = "compress")]
+    probe: bool,
+
+    /// Size of the blocks the input is split in, each getting its own tree: auto, whole, or
+    /// a number of bytes with an optional K or M suffix
+    #[structopt(long = "block-size", default_value = "auto")]
+    block_size: BlockSize,
+
+    /// Compress the input as it is read, in blocks of this many bytes (with an optional K or
+    /// M suffix) each trained on its own data and flushed as soon as it is full, so that a
+    /// reader at the other end of a pipe can decode them without waiting for the input to end
+    #[structopt(
+        long = "flush-every",
+        requires = "compress",
+        parse(try_from_str = byte_count),
+        conflicts_with_all = &[
+            "block-size",
+            "probe",
+            "preset",
+            "self-extracting",
+            "plugins",
+            "reserve-check",
+            "telemetry",
+            "telemetry-interval",
+            "threads"
+        ]
+    )]
+    flush_every: Option<usize>,
+
+    /// What the input is, recorded in the compressed file: raw or tar. Detected from the
+    /// input if not present.
+    #[structopt(long = "format")]
+    format: Option<Content>,
+
+    /// How the encoded bits are packed in bytes, recorded in the compressed file: msb, lsb, or
+    /// words32le
+    #[structopt(long = "byte-order", default_value = "msb")]
+    byte_order: ByteOrder,
+
+    /// What the bits padding the encoded data to whole bytes are set to: zeros or ones
+    #[structopt(long = "padding", default_value = "zeros")]
+    padding: Padding,
+
+    /// Start every block at a multiple of this many bytes, e.g. 4096, and record where each
+    /// starts so blocks can be replaced later with the rewrite subcommand. 0 packs them.
+    #[structopt(long = "align", default_value = "0")]
+    align: usize,
+
+    /// Checksum of the data recorded in the compressed file and verified when decompressing:
+    /// none, crc32, xxhash64, or blake3
+    #[structopt(long = "checksum", default_value = "none")]
+    checksum: Checksum,
+
+    /// Also record the checksum of every block, so that --test and recover can tell which
+    /// blocks are damaged
+    #[structopt(long = "block-checksums")]
+    block_checksums: bool,
+
+    /// Number of threads to compress or decompress with, 0 for one per core. The blocks of a
+    /// file are encoded or decoded in parallel, and batch also compresses files side by side.
+    #[structopt(long = "threads", default_value = "0")]
+    threads: usize,
+
+    /// Header of the compressed file: standard, or minimal to only record the --preset, for
+    /// payloads so small the standard header would outweigh them
+    #[structopt(long = "header", default_value = "standard")]
+    header: HeaderKind,
+
+    /// Directory of <id>.rhp preset files to register at startup. batch reads it again before
+    /// starting each file, swapping in the presets that changed
+    #[structopt(long = "preset-dir", parse(from_os_str))]
+    preset_dir: Option<PathBuf>,
+
+    /// Compress with this preset instead of a tree trained on the input, decompressing then
+    /// requires the same preset
+    #[structopt(long = "preset")]
+    preset: Option<PresetId>,
+
+    /// Code the blocks with the entropy backend registered under this id instead of embedded
+    /// trees, 0 being the built-in Huffman coder. Decompressing then requires the same backend
+    #[structopt(long = "backend", conflicts_with = "preset")]
+    backend: Option<BackendId>,
+
+    /// Write an executable that restores the input file when run, instead of a compressed
+    /// file. The executable runs on the platform of its stub, see --sfx-stub.
+    #[structopt(
+        long = "self-extracting",
+        requires = "compress",
+        conflicts_with_all = &["preset", "plugins"]
+    )]
+    self_extracting: bool,
 
-    /// Input file
+    /// The rhuff-sfx executable self-extracting archives start with, e.g. one built for
+    /// another platform. Defaults to the one installed next to rhuff-compress.
+    #[structopt(long = "sfx-stub", parse(from_os_str), requires = "self-extracting")]
+    sfx_stub: Option<PathBuf>,
+
+    /// Shared library implementing include/rhuff_plugin.h, run on the input before
+    /// compressing or on the output after decompressing. May be repeated, plugins then run
+    /// in order when compressing and in reverse order when decompressing.
+    #[structopt(long = "plugin", parse(from_os_str), number_of_values = 1)]
+    plugins: Vec<PathBuf>,
+
+    /// Keep live metrics of the job in this file, in the Prometheus textfile format
+    #[structopt(long = "telemetry", parse(from_os_str))]
+    telemetry: Option<PathBuf>,
+
+    /// Seconds between updates of the --telemetry file
+    #[structopt(long = "telemetry-interval", default_value = "1")]
+    telemetry_interval: f64,
+
+    /// On Ctrl-C, keep the output written so far as <output>.partial instead of removing it
+    #[structopt(long = "keep-partial")]
+    keep_partial: bool,
+
+    /// Before compressing to a file, check that its file system has room for the estimated
+    /// size of the output, failing early like a full disk would instead
+    #[structopt(long = "reserve-check")]
+    reserve_check: bool,
+
+    /// When decompressing, reject padding that does not match the compressed file's header and
+    /// bytes trailing the compressed data, which are otherwise ignored
+    #[structopt(long = "strict")]
+    strict: bool,
+
+    /// When decompressing, fail on codes longer than this many bits, which only a corrupt
+    /// file holds, instead of decoding on
+    #[structopt(long = "stall-bits")]
+    stall_bits: Option<usize>,
+
+    /// When decompressing, fail on files claiming to hold more than this many bytes (with an
+    /// optional K or M suffix), before allocating room for them
+    #[structopt(long = "max-output", parse(try_from_str = byte_count))]
+    max_output: Option<usize>,
+
+    /// Print sizes, timings and resource usage on stderr when done
+    #[structopt(long = "stats")]
+    stats: bool,
+
+    /// Print failures and --stats on stderr as JSON objects instead of lines of text
+    #[structopt(long = "json")]
+    json: bool,
+
+    /// Input file, - for stdin
     #[structopt(parse(from_os_str))]
-    input: PathBuf,
+    input: Option<PathBuf>,
 
-    /// Output file, stdout if not present
+    /// Output file, stdout if not present or -
     #[structopt(parse(from_os_str))]
-    output: PathBuf,
+    output: Option<PathBuf>,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Report what is needed to decompress a file, exits with an error if this binary can't
+    Compat {
+        /// Compressed file
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+    },
+    /// List the members of a compressed tar archive, decoding as little of it as possible
+    List {
+        /// Compressed file, - for stdin
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+    },
+    /// Report how well the bytes of a file can be Huffman coded: their entropy and the
+    /// average length of their codes
+    Inspect {
+        /// File to analyze, - for stdin
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+
+        /// Print the count, frequency and code length of each byte instead, ready to plot: csv
+        /// or vega-lite
+        #[structopt(long = "plot-data")]
+        plot_data: Option<PlotData>,
+    },
+    /// Re-encode a compressed file with other settings, decoding a few blocks at a time
+    /// instead of the whole file
+    Transcode {
+        /// Compressed file, - for stdin
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+
+        /// Re-encoded file, - for stdout
+        #[structopt(parse(from_os_str))]
+        output: PathBuf,
+
+        /// Preprocessing stage applied before compressing: none, timeseries, nibbles,
+        /// nibble-planes, rle
+        #[structopt(long = "filter", default_value = "none")]
+        filter: Filter,
+
+        /// Size of the blocks the data is split in: auto, whole, or a number of bytes with an
+        /// optional K or M suffix
+        #[structopt(long = "block-size", default_value = "auto")]
+        block_size: BlockSize,
+
+        /// How the encoded bits are packed in bytes: msb, lsb, or words32le
+        #[structopt(long = "byte-order", default_value = "msb")]
+        byte_order: ByteOrder,
+
+        /// What the bits padding the encoded data to whole bytes are set to: zeros or ones
+        #[structopt(long = "padding", default_value = "zeros")]
+        padding: Padding,
+
+        /// Start every block at a multiple of this many bytes, 0 packs them
+        #[structopt(long = "align", default_value = "0")]
+        align: usize,
+
+        /// Checksum of the data: none, crc32, xxhash64, or blake3
+        #[structopt(long = "checksum", default_value = "none")]
+        checksum: Checksum,
+
+        /// Also record the checksum of every block
+        #[structopt(long = "block-checksums")]
+        block_checksums: bool,
+
+        /// Encode with this preset instead of trees trained on the data
+        #[structopt(long = "preset")]
+        preset: Option<PresetId>,
+
+        /// Code the blocks with this entropy backend instead of trees trained on the data
+        #[structopt(long = "backend", conflicts_with = "preset")]
+        backend: Option<BackendId>,
+    },
+    /// Compress each input to <input>.rhf, sharing --threads between the files: small files
+    /// are compressed side by side, large ones encode their blocks in parallel. Takes the
+    /// compression options given before the subcommand.
+    Batch {
+        /// Files to compress
+        #[structopt(parse(from_os_str), required = true)]
+        inputs: Vec<PathBuf>,
+
+        /// Write the compressed files in this directory instead of next to their input
+        #[structopt(long = "output-dir", parse(from_os_str))]
+        output_dir: Option<PathBuf>,
+    },
+    /// Merge compressed files into one holding their data one after the other, copying their
+    /// blocks instead of compressing again
+    Merge {
+        /// Compressed files, in order
+        #[structopt(parse(from_os_str), required = true)]
+        inputs: Vec<PathBuf>,
+
+        /// Merged file, - for stdout
+        #[structopt(short = "o", long = "output", parse(from_os_str))]
+        output: PathBuf,
+
+        /// Encode every block with a tree trained on all the data instead of keeping their own
+        #[structopt(long = "shared-tree")]
+        shared_tree: bool,
+    },
+    /// Write the blocks of the new data that the old compressed file does not have, referring
+    /// to the others, to be turned back into a compressed file with apply
+    Diff {
+        /// Compressed file the delta is made against
+        #[structopt(parse(from_os_str))]
+        old: PathBuf,
+
+        /// New data, - for stdin
+        #[structopt(parse(from_os_str))]
+        new: PathBuf,
+
+        /// Delta file, - for stdout
+        #[structopt(short = "o", long = "output", parse(from_os_str))]
+        output: PathBuf,
+    },
+    /// Compress the new data of a delta, reusing the blocks of the file it was made against
+    Apply {
+        /// Compressed file the delta was made against
+        #[structopt(parse(from_os_str))]
+        old: PathBuf,
+
+        /// Delta file written by diff, - for stdin
+        #[structopt(parse(from_os_str))]
+        delta: PathBuf,
+
+        /// Compressed file, - for stdout
+        #[structopt(short = "o", long = "output", parse(from_os_str))]
+        output: PathBuf,
+    },
+    /// Write the data of every block of a damaged compressed file that still decodes, leaving
+    /// out the damaged blocks. Damage is only pinned to blocks written with --block-checksums.
+    Recover {
+        /// Compressed file
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+
+        /// Recovered data, - for stdout
+        #[structopt(short = "o", long = "output", parse(from_os_str))]
+        output: PathBuf,
+    },
+    /// Replace the data of a block of a file compressed with --align, writing only that block
+    /// back to the file
+    Rewrite {
+        /// Compressed file, changed in place
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+
+        /// Index of the block, from 0
+        block: usize,
+
+        /// New data of the block, - for stdin
+        #[structopt(parse(from_os_str))]
+        data: PathBuf,
+    },
+    /// Train a preset on the input file, to be placed in a --preset-dir
+    Train {
+        /// Training data
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+
+        /// Preset file, named <id>.rhp
+        #[structopt(parse(from_os_str))]
+        output: PathBuf,
+
+        /// Count in bounded memory, only giving codes to this many most frequent bytes
+        #[structopt(long = "approx-counts")]
+        approx_counts: Option<usize>,
+    },
+    /// Find the preset compressing files like those of the corpus best: byte and nibble models
+    /// are trained on part of the files and compared on the files held out, then the best is
+    /// trained on all the files and written as a preset file
+    Tune {
+        /// Directory of sample files
+        #[structopt(long = "corpus", parse(from_os_str))]
+        corpus: PathBuf,
+
+        /// Preset file, named <id>.rhp
+        #[structopt(parse(from_os_str))]
+        output: PathBuf,
+
+        /// Number of parts the files are split in, each held out in turn
+        #[structopt(long = "folds", default_value = "5")]
+        folds: usize,
+
+        /// Also try models only coding this many most frequent symbols. May be repeated.
+        #[structopt(long = "prune", number_of_values = 1)]
+        prune: Vec<usize>,
+    },
+    /// Rewrite a file of the legacy MessagePack format as a current container with checksums,
+    /// keeping its payload where the format allows, and report what changed
+    Migrate {
+        /// Legacy file
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+
+        /// Container, - for stdout
+        #[structopt(parse(from_os_str))]
+        output: PathBuf,
+
+        /// Checksum of the data and of the block: none, crc32, xxhash64, or blake3
+        #[structopt(long = "checksum", default_value = "crc32")]
+        checksum: Checksum,
+    },
+    /// Write the conformance vectors other implementations of the format check their output
+    /// against: inputs, models and the bytes expected for them, listed in manifest.json
+    #[structopt(name = "testvectors")]
+    TestVectors {
+        /// Directory to write the vectors in, created if missing
+        #[structopt(parse(from_os_str))]
+        output_dir: PathBuf,
+    },
+}
+
+/// The alphabet the input is coded in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Symbols {
+    Byte,
+    Nibble,
+    NibblePlanes,
+    Char,
+}
+
+impl FromStr for Symbols {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "byte" => Ok(Symbols::Byte),
+            "nibble" => Ok(Symbols::Nibble),
+            "nibble-planes" => Ok(Symbols::NibblePlanes),
+            "char" => Ok(Symbols::Char),
+            _ => Err(format!("Unknown symbols \"{}\"", s)),
+        }
+    }
+}
+
+/// The formats of `inspect --plot-data`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PlotData {
+    Csv,
+    VegaLite,
+}
+
+impl FromStr for PlotData {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(PlotData::Csv),
+            "vega-lite" => Ok(PlotData::VegaLite),
+            _ => Err(format!("Unknown plot data format \"{}\"", s)),
+        }
+    }
+}
+
+/// The filter coding the input as the symbols, which are filters themselves
+fn filter(filter: Filter, symbols: Symbols, utf8: Option<Utf8Policy>) -> Filter {
+    if utf8.is_some() && symbols != Symbols::Char {
+        clap::Error::with_description(
+            "--utf8 only applies to --symbols char",
+            clap::ErrorKind::ArgumentConflict,
+        )
+        .exit()
+    }
+    match (filter, symbols) {
+        (filter, Symbols::Byte) => filter,
+        (Filter::None, Symbols::Nibble) => Filter::Nibbles,
+        (Filter::None, Symbols::NibblePlanes) => Filter::NibblePlanes,
+        (Filter::None, Symbols::Char) => Filter::Chars(utf8.unwrap_or_default()),
+        _ => clap::Error::with_description(
+            "--symbols nibble and char can not be combined with --filter",
+            clap::ErrorKind::ArgumentConflict,
+        )
+        .exit(),
+    }
+}
+
+/// [Filter::Rle] when no filter is set and long runs make up a good part of the data, unless
+/// --no-rle is set
+fn runs_filter(filter: Filter, no_rle: bool, data: &[u8]) -> Filter {
+    match filter {
+        Filter::None if !no_rle && rle::has_long_runs(data) => Filter::Rle,
+        filter => filter,
+    }
+}
+
+/// A number of bytes with an optional K or M suffix, for --flush-every and --max-output
+fn byte_count(s: &str) -> Result<usize, String> {
+    match BlockSize::from_str(s)? {
+        BlockSize::Fixed(size) => Ok(size),
+        _ => Err(format!(
+            "Invalid size \"{}\", it needs a number of bytes",
+            s
+        )),
+    }
+}
+
+/// Reads the file, or stdin for `-`
+fn read_file(path: &Path) -> Result<Vec<u8>, Error> {
+    let mut contents = Vec::new();
+    if path == Path::new("-") {
+        std::io::stdin()
+            .read_to_end(&mut contents)
+            .context("file", "stdin")?;
+        return Ok(contents);
+    }
+    File::open(paths::long(path))
+        .and_then(|mut file| file.read_to_end(&mut contents))
+        .context("file", path.display())?;
+    Ok(contents)
+}
+
+/// Input and output are only optional when a subcommand is used
+fn required<T>(value: Option<T>, name: &str) -> T {
+    value.unwrap_or_else(|| {
+        clap::Error::with_description(
+            &format!("The <{}> argument is required", name),
+            clap::ErrorKind::MissingRequiredArgument,
+        )
+        .exit()
+    })
+}
+
+/// The number of threads for --threads, detecting the number of cores for 0
+fn threads(threads: usize) -> usize {
+    match threads {
+        0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    }
+}
+
+/// The content recorded for the data, detected unless given with --format
+fn content(format: Option<Content>, data: &[u8]) -> Content {
+    match format {
+        Some(Content::Tar) if !tar::is_tar(data) => clap::Error::with_description(
+            "The input is not a tar archive",
+            clap::ErrorKind::InvalidValue,
+        )
+        .exit(),
+        Some(content) => content,
+        None if tar::is_tar(data) => Content::Tar,
+        None => Content::Raw,
+    }
 }
 
 fn main() {
     let opt = Opt::from_args();
-    //println!("{:?}", opt);
+    let json = opt.json;
+    interrupt::install(opt.keep_partial);
+    if let Err(error) = run(opt) {
+        if json {
+            eprintln!("{}", error.to_json());
+        } else {
+            eprintln!("error: {}", error);
+        }
+        std::process::exit(error.exit_code());
+    }
+}
 
-    let mut file = File::open(opt.input).expect("Unable to open the file");
-    let mut contents = Vec::new();
-    file.read_to_end(&mut contents)
-        .expect("Unable to read the file");
+fn run(opt: Opt) -> Result<(), Error> {
+    let start = Instant::now();
+    if let Some(dir) = &opt.preset_dir {
+        presets::register_dir(dir)?;
+    }
+
+    match &opt.command {
+        Some(Command::Compat { input }) => {
+            let supported = compat::report(&read_file(input)?)
+                .context("operation", "compat")
+                .context("file", input.display())?;
+            if !supported {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Command::List { input }) => {
+            let members = tar::members(&read_file(input)?)
+                .context("operation", "list")
+                .context("file", input.display())?;
+            for member in members {
+                println!("{:>12} {}", member.size, member.name);
+            }
+            return Ok(());
+        }
+        Some(Command::Inspect { input, plot_data }) => {
+            let data = read_file(input)?;
+            let mut counts = [0u64; 256];
+            for &byte in &data {
+                counts[usize::from(byte)] += 1;
+            }
+            let counts: Vec<(u8, u64)> = (0..=255).zip(counts.iter().copied()).collect();
+            match plot_data {
+                Some(PlotData::Csv) => print!("{}", analysis::to_csv(&counts)),
+                Some(PlotData::VegaLite) => println!("{}", analysis::to_vega_lite_json(&counts)),
+                None => match analyze(&counts) {
+                    Some(analysis) => {
+                        println!("bytes:       {}", analysis.total);
+                        println!("symbols:     {}", analysis.symbols);
+                        println!("entropy:     {:.3} bits per byte", analysis.entropy);
+                        println!("code length: {:.3} bits per byte", analysis.code_bits);
+                        println!("uniformity:  {:.3}", analysis.uniformity);
+                        println!("ratio:       {:.3}", analysis.ratio(8));
+                    }
+                    None => println!("bytes:       0"),
+                },
+            }
+            return Ok(());
+        }
+        Some(Command::Transcode {
+            input,
+            output,
+            filter,
+            block_size,
+            byte_order,
+            padding,
+            align,
+            checksum,
+            block_checksums,
+            preset,
+            backend,
+        }) => {
+            let contents = read_file(input)?;
+            // The content and plugins are kept, transcoding does not change what the data is
+            let source = container::inspect(&contents).ok();
+            let options = container::Options {
+                filter: *filter,
+                preset: *preset,
+                backend: *backend,
+                block_size: *block_size,
+                content: source
+                    .as_ref()
+                    .map(|header| header.content)
+                    .unwrap_or_default(),
+                byte_order: *byte_order,
+                padding: *padding,
+                align: *align,
+                stored: false,
+                checksum: *checksum,
+                block_checksums: *block_checksums,
+                threads: threads(opt.threads),
+                header: HeaderKind::Standard,
+                plugins: source.and_then(|header| header.plugins),
+            };
+            let transcoded = container::transcode(&contents, &options)
+                .context("operation", "transcode")
+                .context("file", input.display())?;
+            let mut out = Output::create(Some(output)).context("file", output.display())?;
+            out.write_all(&transcoded)
+                .context("file", output.display())?;
+            out.commit().context("file", output.display())?;
+            return Ok(());
+        }
+        Some(Command::Batch { inputs, output_dir }) => {
+            if !opt.plugins.is_empty() || opt.self_extracting {
+                clap::Error::with_description(
+                    "--plugin and --self-extracting can not be used with batch",
+                    clap::ErrorKind::ArgumentConflict,
+                )
+                .exit()
+            }
+            let filter = filter(opt.filter, opt.symbols, opt.utf8);
+            let options = |data: &[u8]| container::Options {
+                filter: runs_filter(filter, opt.no_rle, data),
+                preset: opt.preset,
+                backend: opt.backend,
+                block_size: opt.block_size,
+                content: content(opt.format, data),
+                byte_order: opt.byte_order,
+                padding: opt.padding,
+                align: opt.align,
+                stored: false,
+                checksum: opt.checksum,
+                block_checksums: opt.block_checksums,
+                threads: 0,
+                header: opt.header,
+                plugins: None,
+            };
+            return batch::compress(
+                inputs,
+                output_dir.as_deref(),
+                opt.preset_dir.as_deref(),
+                threads(opt.threads),
+                opt.reserve_check,
+                options,
+            );
+        }
+        Some(Command::Merge {
+            inputs,
+            output,
+            shared_tree,
+        }) => {
+            let contents = inputs
+                .iter()
+                .map(|input| read_file(input))
+                .collect::<Result<Vec<_>, _>>()?;
+            let contents: Vec<&[u8]> = contents.iter().map(Vec::as_slice).collect();
+            let tree = if *shared_tree {
+                MergeTree::Shared
+            } else {
+                MergeTree::Keep
+            };
+            let merged = merge::merge(&contents, tree).context("operation", "merge")?;
+            let mut out = Output::create(Some(output)).context("file", output.display())?;
+            out.write_all(&merged).context("file", output.display())?;
+            out.commit().context("file", output.display())?;
+            return Ok(());
+        }
+        Some(Command::Diff { old, new, output }) => {
+            let diff = delta::diff(&read_file(old)?, &read_file(new)?)
+                .context("operation", "diff")
+                .context("file", old.display())?;
+            let mut out = Output::create(Some(output)).context("file", output.display())?;
+            out.write_all(&diff).context("file", output.display())?;
+            out.commit().context("file", output.display())?;
+            return Ok(());
+        }
+        Some(Command::Apply { old, delta, output }) => {
+            let applied = delta::apply(&read_file(old)?, &read_file(delta)?)
+                .context("operation", "apply")
+                .context("file", delta.display())?;
+            let mut out = Output::create(Some(output)).context("file", output.display())?;
+            out.write_all(&applied).context("file", output.display())?;
+            out.commit().context("file", output.display())?;
+            return Ok(());
+        }
+        Some(Command::Recover { input, output }) => {
+            let recovered = container::recover(&read_file(input)?)
+                .context("operation", "recover")
+                .context("file", input.display())?;
+            for block in &recovered.damaged {
+                if opt.json {
+                    let warning = report::Warning {
+                        schema: report::SCHEMA_VERSION,
+                        warning: "damaged block".to_owned(),
+                        block: Some(*block as u64),
+                        estimated_ratio: None,
+                    };
+                    eprintln!(
+                        "{}",
+                        serde_json::to_string(&warning).expect("Reports always serialize")
+                    );
+                } else {
+                    eprintln!("warning: block {} is damaged, its data is left out", block);
+                }
+            }
+            let mut out = Output::create(Some(output)).context("file", output.display())?;
+            out.write_all(&recovered.data)
+                .context("file", output.display())?;
+            out.commit().context("file", output.display())?;
+            return Ok(());
+        }
+        Some(Command::Rewrite { file, block, data }) => {
+            let mut contents = read_file(file)?;
+            let changed = container::rewrite_block(&mut contents, *block, &read_file(data)?)
+                .context("operation", "rewrite")
+                .context("file", file.display())?;
+            let mut out = OpenOptions::new()
+                .write(true)
+                .open(file)
+                .context("file", file.display())?;
+            out.seek(SeekFrom::Start(changed.start as u64))
+                .and_then(|_| out.write_all(&contents[changed]))
+                .context("file", file.display())?;
+            return Ok(());
+        }
+        Some(Command::Train {
+            input,
+            output,
+            approx_counts,
+        }) => {
+            presets::train(&read_file(input)?, output, *approx_counts);
+            return Ok(());
+        }
+        Some(Command::Tune {
+            corpus,
+            output,
+            folds,
+            prune,
+        }) => {
+            return tune::tune(corpus, output, *folds, prune, opt.json);
+        }
+        Some(Command::Migrate {
+            input,
+            output,
+            checksum,
+        }) => {
+            return migrate::migrate(input, output, *checksum, opt.json);
+        }
+        Some(Command::TestVectors { output_dir }) => {
+            return testvectors::write(output_dir);
+        }
+        None => {}
+    }
+
+    if let (true, Some(flush_every)) = (opt.compress, opt.flush_every) {
+        let input = required(opt.input, "input");
+        let options = container::Options {
+            filter: filter(opt.filter, opt.symbols, opt.utf8),
+            preset: None,
+            backend: None,
+            block_size: BlockSize::Fixed(flush_every),
+            content: opt.format.unwrap_or(Content::Raw),
+            byte_order: opt.byte_order,
+            padding: opt.padding,
+            align: opt.align,
+            stored: false,
+            checksum: opt.checksum,
+            block_checksums: opt.block_checksums,
+            threads: 1,
+            header: opt.header,
+            plugins: None,
+        };
+        let (bytes_in, bytes_out) = stream::compress(&input, opt.output.as_deref(), &options)?;
+        if opt.stats {
+            let stats = stats::Stats {
+                operation: "compress",
+                bytes_in,
+                bytes_out,
+                wall: start.elapsed(),
+                usage: stats::usage(),
+            };
+            stats.print(opt.json);
+        }
+        return Ok(());
+    }
 
-    let mut out = File::create(opt.output).expect("could not create output file");
+    let plugins = opt
+        .plugins
+        .iter()
+        .map(|path| Plugin::load(path))
+        .collect::<Result<Vec<_>, _>>()?;
+    let input = required(opt.input, "input");
+    let contents = read_file(&input)?;
+    let symbols = if opt.probe {
+        probe_symbols(&contents, opt.filter, opt.json)
+    } else {
+        opt.symbols
+    };
+    let filter = filter(opt.filter, symbols, opt.utf8);
+    let bytes_in = contents.len() as u64;
 
-    if opt.compress {
-        // Basic byte-wise Huffman compression
-        let mut gen = HuffmanGenerator::new();
-        gen.add_occurences_from_iterator(&mut contents.iter());
-        let (encoder, _decoder) = gen.into_encoder_decoder_pair().unwrap();
-        let compressed = encoder.encode(&mut contents.iter()).unwrap();
-
-        let data = Compressed {
-            tree: _decoder.get_tree().clone(),
-            data: compressed.to_bytes(),
-            data_len: compressed.len(),
+    if opt.self_extracting {
+        let output = required(opt.output, "output");
+        let options = container::Options {
+            filter: runs_filter(filter, opt.no_rle, &contents),
+            preset: None,
+            backend: None,
+            block_size: opt.block_size,
+            content: content(opt.format, &contents),
+            byte_order: opt.byte_order,
+            padding: opt.padding,
+            align: opt.align,
+            stored: false,
+            checksum: opt.checksum,
+            block_checksums: opt.block_checksums,
+            threads: threads(opt.threads),
+            header: HeaderKind::Standard,
+            plugins: None,
         };
+        let compressed = container::compress(&contents, &options)
+            .context("operation", "compress")
+            .context("file", input.display())?;
+        sfx::write(&compressed, &input, &output, opt.sfx_stub.as_deref())?;
+        return Ok(());
+    }
 
-        let compressed = rmp_serde::to_vec(&data).unwrap();
-        out.write_all(&compressed).unwrap();
-    } else if opt.decompress {
-        // Basic byte-wise Huffman compression
+    if opt.test {
+        let options = container::DecodeOptions {
+            strict: opt.strict,
+            threads: threads(opt.threads),
+            plugins: plugin::fingerprint(&plugins),
+            stall_bits: opt.stall_bits,
+            max_output: opt.max_output.map(|max| max as u64),
+        };
+        container::decompress_with_options(&contents, &options)
+            .map_err(|message| locate(message, &contents, &options))
+            .context("operation", "test")
+            .context("file", input.display())?;
+        return Ok(());
+    }
 
-        let data: Compressed<u8> = rmp_serde::from_slice(contents.as_slice()).unwrap();
-        let mut bitvec = BitVec::from_bytes(data.data.as_slice());
+    let output = opt.output.as_deref();
+    let output_name = output.unwrap_or_else(|| Path::new("-")).display();
+    let mut out = Output::create(output).context("file", &output_name)?;
+    let interval = Duration::from_secs_f64(opt.telemetry_interval);
 
-        // Restore bit length from bytes
-        unsafe {
-            bitvec.set_len(data.data_len);
+    let (operation, bytes_out) = if opt.compress {
+        // Basic byte-wise Huffman compression
+        let contents = plugins
+            .iter()
+            .try_fold(contents, |data, plugin| plugin.forward(&data))
+            .context("operation", "compress")
+            .context("file", input.display())?;
+        let filter = runs_filter(filter, opt.no_rle, &contents);
+        let options = container::Options {
+            filter,
+            preset: opt.preset,
+            backend: opt.backend,
+            block_size: opt.block_size,
+            content: content(opt.format, &contents),
+            byte_order: opt.byte_order,
+            padding: opt.padding,
+            align: opt.align,
+            stored: false,
+            checksum: opt.checksum,
+            block_checksums: opt.block_checksums,
+            threads: threads(opt.threads),
+            header: opt.header,
+            plugins: plugin::fingerprint(&plugins),
+        };
+        if filter == Filter::None {
+            warn_if_incompressible(&contents, opt.json);
+        }
+        if let (Some(output), true) = (output, opt.reserve_check) {
+            space::reserve(output, &contents).context("file", &output_name)?;
+        }
+        let compressed = match &opt.telemetry {
+            Some(path) => {
+                container::compress_with_telemetry(&contents, &options, interval, &mut |snapshot| {
+                    telemetry::write_textfile(path, "compress", snapshot)
+                })
+            }
+            None => container::compress(&contents, &options),
         }
-        let decoder = HuffmanDecoder::new(data.tree);
-        out.write_all(&decoder.decode_unbounded(&bitvec)).unwrap();
+        .context("operation", "compress")
+        .context("file", input.display())?;
+        out.write_all(&compressed).context("file", &output_name)?;
+        out.commit().context("file", &output_name)?;
+        ("compress", compressed.len())
+    } else if opt.decompress {
+        let options = container::DecodeOptions {
+            strict: opt.strict,
+            threads: threads(opt.threads),
+            plugins: plugin::fingerprint(&plugins),
+            stall_bits: opt.stall_bits,
+            max_output: opt.max_output.map(|max| max as u64),
+        };
+        let decoded = match &opt.telemetry {
+            Some(path) => container::decompress_with_telemetry(
+                &contents,
+                &options,
+                interval,
+                &mut |snapshot| telemetry::write_textfile(path, "decompress", snapshot),
+            ),
+            None => container::decompress_with_options(&contents, &options),
+        }
+        .map_err(|message| locate(message, &contents, &options))
+        .context("operation", "decompress")
+        .context("file", input.display())?;
+        let decoded = plugins
+            .iter()
+            .rev()
+            .try_fold(decoded, |data, plugin| plugin.reverse(&data))
+            .context("operation", "decompress")
+            .context("file", input.display())?;
+        out.write_all(&decoded).context("file", &output_name)?;
+        out.commit().context("file", &output_name)?;
+        ("decompress", decoded.len())
     } else {
         panic!("Neither compress or decompress was set. This is a bug in rhuff-compress")
+    };
+
+    if opt.stats {
+        let stats = stats::Stats {
+            operation,
+            bytes_in,
+            bytes_out: bytes_out as u64,
+            wall: start.elapsed(),
+            usage: stats::usage(),
+        };
+        stats.print(opt.json);
+    }
+    Ok(())
+}
+
+/// The symbols of the model [analysis::probe()] finds best among those compressed files can
+/// code, printing how every model did on stderr. Nibbles can not be combined with a filter.
+fn probe_symbols(data: &[u8], filter: Filter, json: bool) -> Symbols {
+    let probes = analysis::probe(data);
+    let chosen = probes
+        .iter()
+        .find_map(|probe| match probe.model {
+            analysis::Model::Byte => Some((probe.model, Symbols::Byte)),
+            analysis::Model::Nibble if filter == Filter::None => {
+                Some((probe.model, Symbols::Nibble))
+            }
+            _ => None,
+        })
+        .unwrap_or((analysis::Model::Byte, Symbols::Byte));
+    if json {
+        let probe = report::Probe {
+            schema: report::SCHEMA_VERSION,
+            models: probes
+                .iter()
+                .map(|probe| report::ProbedModel {
+                    model: probe.model.to_string(),
+                    symbols: probe.symbols as u64,
+                    ratio: probe.ratio,
+                })
+                .collect(),
+            chosen: chosen.0.to_string(),
+        };
+        eprintln!(
+            "{}",
+            serde_json::to_string(&probe).expect("Reports always serialize")
+        );
+    } else {
+        eprintln!("{:<8} {:>8} {:>8}", "model", "ratio", "symbols");
+        for probe in &probes {
+            eprintln!(
+                "{:<8} {:>8.4} {:>8}",
+                probe.model.to_string(),
+                probe.ratio,
+                probe.symbols
+            );
+        }
+        eprintln!("compressing with {} symbols", chosen.0);
+    }
+    chosen.1
+}
+
+/// Warns on stderr when the byte frequencies of the data are too close to uniform for
+/// compressing to save anything
+fn warn_if_incompressible(data: &[u8], json: bool) {
+    let mut counts = [0u64; 256];
+    for &byte in data {
+        counts[byte as usize] += 1;
+    }
+    let counts: Vec<(u8, u64)> = (0..=255).zip(counts.iter().copied()).collect();
+    let analysis = match analyze(&counts) {
+        Some(analysis) if analysis.looks_incompressible(8) => analysis,
+        _ => return,
+    };
+    let message = "the input looks incompressible";
+    if json {
+        let warning = report::Warning {
+            schema: report::SCHEMA_VERSION,
+            warning: message.to_owned(),
+            block: None,
+            estimated_ratio: Some(analysis.ratio(8)),
+        };
+        eprintln!(
+            "{}",
+            serde_json::to_string(&warning).expect("Reports always serialize")
+        );
+    } else {
+        eprintln!(
+            "warning: {}, expect a ratio of about {:.3}",
+            message,
+            analysis.ratio(8)
+        );
+    }
+}
+
+/// The error of decompressing the data, with where in the data it happened
+fn locate(message: &str, data: &[u8], options: &container::DecodeOptions) -> Error {
+    let mut error = Error::new(message);
+    if let Some(diagnosis) = container::diagnose(data, options) {
+        if let Some(block) = diagnosis.block {
+            error = error.with("block", block);
+        }
+        error = error.with("offset", diagnosis.offset);
     }
+    error
 }
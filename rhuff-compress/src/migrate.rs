@@ -0,0 +1,64 @@
+//! `migrate`: rewriting a file of the legacy MessagePack format as a versioned container, and
+//! reporting what changed.
+use crate::error::{Context, Error};
+use rhuffman::container::Checksum;
+use std::path::Path;
+
+#[cfg(feature = "legacy-format")]
+pub fn migrate(input: &Path, output: &Path, checksum: Checksum, json: bool) -> Result<(), Error> {
+    use crate::interrupt::Output;
+    use rhuffman::container::legacy;
+    use rhuffman::report;
+    use std::io::Write;
+
+    let contents = crate::read_file(input)?;
+    let migration = legacy::migrate(&contents, checksum)
+        .context("operation", "migrate")
+        .context("file", input.display())?;
+    let mut out = Output::create(Some(output)).context("file", output.display())?;
+    out.write_all(&migration.container)
+        .context("file", output.display())?;
+    out.commit().context("file", output.display())?;
+
+    if json {
+        let report = report::Migrate {
+            schema: report::SCHEMA_VERSION,
+            bytes_in: contents.len() as u64,
+            bytes_out: migration.container.len() as u64,
+            filter: migration.filter.to_string(),
+            checksum: migration.checksum.to_string(),
+            payload_kept: migration.payload_kept,
+            payload_bits: migration.payload_bits,
+        };
+        eprintln!(
+            "{}",
+            serde_json::to_string(&report).expect("Reports always serialize")
+        );
+    } else {
+        eprintln!(
+            "{} bytes -> {} bytes, {} bytes of data",
+            contents.len(),
+            migration.container.len(),
+            migration.data_len
+        );
+        eprintln!("filter: {}", migration.filter);
+        eprintln!("checksum: {}", migration.checksum);
+        if migration.payload_kept {
+            eprintln!("payload: kept, {} bits", migration.payload_bits);
+        } else {
+            eprintln!(
+                "payload: encoded again with the canonical tree, {} bits",
+                migration.payload_bits
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "legacy-format"))]
+pub fn migrate(input: &Path, _: &Path, _: Checksum, _: bool) -> Result<(), Error> {
+    Err(Error::new(
+        "Legacy files can only be read with the legacy-format feature",
+    ))
+    .context("file", input.display())
+}
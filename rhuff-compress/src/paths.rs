@@ -0,0 +1,146 @@
+//! Paths as the platform has them, never converted lossily to UTF-8.
+//!
+//! Names stored in archives are bytes read back on any platform: UTF-8 for names that are
+//! valid Unicode, so that they round-trip between platforms. Other names keep the bytes of
+//! the platform, raw bytes on Unix and WTF-8 on Windows, which round-trip on the platform
+//! they come from.
+use std::borrow::Cow;
+use std::ffi::{OsStr, OsString};
+use std::path::Path;
+#[cfg(windows)]
+use std::path::{Component, PathBuf, Prefix};
+
+/// The bytes stored for the name
+pub fn to_bytes(name: &OsStr) -> Vec<u8> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        name.as_bytes().to_vec()
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::ffi::OsStrExt;
+        wtf8_encode(&name.encode_wide().collect::<Vec<u16>>())
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        name.to_string_lossy().into_owned().into_bytes()
+    }
+}
+
+/// The name stored as `bytes` by [to_bytes()], None if this platform can not name a file so
+// Only the rhuff-sfx stub reads names back
+#[allow(dead_code)]
+pub fn from_bytes(bytes: &[u8]) -> Option<OsString> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        Some(OsStr::from_bytes(bytes).to_owned())
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::ffi::OsStringExt;
+        wtf8_decode(bytes).map(|wide| OsString::from_wide(&wide))
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        std::str::from_utf8(bytes).ok().map(OsString::from)
+    }
+}
+
+/// The path to hand to the file system: on Windows, the `\\?\` form of the absolute path, so
+/// that paths longer than `MAX_PATH` can be opened. The path itself elsewhere.
+#[cfg(windows)]
+pub fn long(path: &Path) -> Cow<'_, Path> {
+    let absolute = match std::path::absolute(path) {
+        Ok(absolute) => absolute,
+        Err(_) => return Cow::Borrowed(path),
+    };
+    let mut components = absolute.components();
+    let mut long = match components.next() {
+        Some(Component::Prefix(prefix)) => match prefix.kind() {
+            Prefix::Disk(_) => {
+                let mut long = OsString::from(r"\\?\");
+                long.push(prefix.as_os_str());
+                long
+            }
+            Prefix::UNC(server, share) => {
+                let mut long = OsString::from(r"\\?\UNC\");
+                long.push(server);
+                long.push(r"\");
+                long.push(share);
+                long
+            }
+            // Already verbatim, or a device
+            _ => return Cow::Owned(absolute),
+        },
+        _ => return Cow::Owned(absolute),
+    };
+    for component in components {
+        if component != Component::RootDir {
+            long.push(r"\");
+            long.push(component.as_os_str());
+        }
+    }
+    Cow::Owned(PathBuf::from(long))
+}
+
+/// The path to hand to the file system: on Windows, the `\\?\` form of the absolute path, so
+/// that paths longer than `MAX_PATH` can be opened. The path itself elsewhere.
+#[cfg(not(windows))]
+pub fn long(path: &Path) -> Cow<'_, Path> {
+    Cow::Borrowed(path)
+}
+
+/// UTF-8 extended to unpaired surrogates, which Windows names may hold
+#[cfg(windows)]
+fn wtf8_encode(wide: &[u16]) -> Vec<u8> {
+    let mut bytes = vec![];
+    for unit in std::char::decode_utf16(wide.iter().copied()) {
+        match unit {
+            Ok(c) => bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+            Err(error) => {
+                let s = u32::from(error.unpaired_surrogate());
+                bytes.extend_from_slice(&[
+                    0xE0 | (s >> 12) as u8,
+                    0x80 | (s >> 6 & 0x3F) as u8,
+                    0x80 | (s & 0x3F) as u8,
+                ]);
+            }
+        }
+    }
+    bytes
+}
+
+#[cfg(windows)]
+fn wtf8_decode(bytes: &[u8]) -> Option<Vec<u16>> {
+    let mut wide = vec![];
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let first = bytes[pos];
+        let (len, bits) = match first {
+            0x00..=0x7F => (1, u32::from(first)),
+            0xC0..=0xDF => (2, u32::from(first & 0x1F)),
+            0xE0..=0xEF => (3, u32::from(first & 0x0F)),
+            0xF0..=0xF7 => (4, u32::from(first & 0x07)),
+            _ => return None,
+        };
+        let mut c = bits;
+        for &byte in bytes.get(pos + 1..pos + len)? {
+            if byte & 0xC0 != 0x80 {
+                return None;
+            }
+            c = c << 6 | u32::from(byte & 0x3F);
+        }
+        match c {
+            0..=0xFFFF => wide.push(c as u16),
+            0x1_0000..=0x10_FFFF => {
+                let c = c - 0x1_0000;
+                wide.extend_from_slice(&[0xD800 | (c >> 10) as u16, 0xDC00 | (c & 0x3FF) as u16]);
+            }
+            _ => return None,
+        }
+        pos += len;
+    }
+    Some(wide)
+}
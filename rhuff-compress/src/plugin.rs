@@ -0,0 +1,201 @@
+//! User-provided preprocessing stages loaded from shared libraries, see
+//! `include/rhuff_plugin.h` for the interface a plugin implements.
+use crate::error::{Context, Error};
+use libloading::{Library, Symbol};
+use rhuffman::container::Checksum;
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_int, c_void};
+use std::path::Path;
+
+pub const ABI_VERSION: u32 = 1;
+
+const FORWARD: c_int = 0;
+const REVERSE: c_int = 1;
+
+type Emit = extern "C" fn(ctx: *mut c_void, data: *const u8, len: usize);
+type Init = unsafe extern "C" fn(abi_version: u32) -> *mut c_void;
+type Id = unsafe extern "C" fn() -> *const c_char;
+type Transform = unsafe extern "C" fn(
+    state: *mut c_void,
+    direction: c_int,
+    input: *const u8,
+    len: usize,
+    emit: Emit,
+    ctx: *mut c_void,
+) -> c_int;
+type Finish = unsafe extern "C" fn(state: *mut c_void);
+
+/// A loaded plugin, finished and unloaded on drop
+pub struct Plugin {
+    id: String,
+    state: *mut c_void,
+    transform: Transform,
+    finish: Finish,
+    // Must outlive the function pointers above
+    _library: Library,
+}
+
+extern "C" fn emit(ctx: *mut c_void, data: *const u8, len: usize) {
+    // Safety: ctx is the output Vec handed to the plugin by run(), and the plugin promises
+    // data points to len readable bytes
+    unsafe {
+        let out = &mut *(ctx as *mut Vec<u8>);
+        if len > 0 {
+            out.extend_from_slice(std::slice::from_raw_parts(data, len));
+        }
+    }
+}
+
+/// The symbol of the library, Err naming it if the library does not export it
+///
+/// # Safety
+/// `T` must be the type the symbol is declared with in the header
+unsafe fn symbol<'a, T>(library: &'a Library, name: &str) -> Result<Symbol<'a, T>, Error> {
+    library
+        .get(format!("{}\0", name).as_bytes())
+        .map_err(|_| Error::new(format!("The plugin does not export {}", name)))
+}
+
+impl Plugin {
+    pub fn load(path: &Path) -> Result<Plugin, Error> {
+        // Safety: loading a library runs its initializers, which the user vouches for by
+        // passing it to --plugin, and the symbols are declared with the types of the header
+        unsafe {
+            let library = Library::new(path)
+                .map_err(|error| Error::new(format!("Unable to load the plugin: {}", error)))
+                .context("plugin", path.display())?;
+            let id: Symbol<Id> =
+                symbol(&library, "rhuff_plugin_id").context("plugin", path.display())?;
+            let init: Symbol<Init> =
+                symbol(&library, "rhuff_plugin_init").context("plugin", path.display())?;
+            let transform: Symbol<Transform> =
+                symbol(&library, "rhuff_plugin_transform").context("plugin", path.display())?;
+            let finish: Symbol<Finish> =
+                symbol(&library, "rhuff_plugin_finish").context("plugin", path.display())?;
+
+            let id = id();
+            if id.is_null() {
+                return Err(Error::new("The plugin has no id").with("plugin", path.display()));
+            }
+            let id = CStr::from_ptr(id).to_string_lossy().into_owned();
+            let state = init(ABI_VERSION);
+            if state.is_null() {
+                return Err(Error::new("The plugin failed to initialize").with("plugin", id));
+            }
+            Ok(Plugin {
+                id,
+                state,
+                transform: *transform,
+                finish: *finish,
+                _library: library,
+            })
+        }
+    }
+
+    pub fn forward(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        self.run(FORWARD, data)
+    }
+
+    pub fn reverse(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        self.run(REVERSE, data)
+    }
+
+    fn run(&self, direction: c_int, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut out: Vec<u8> = Vec::new();
+        // Safety: the state came from init and is only released on drop
+        let status = unsafe {
+            (self.transform)(
+                self.state,
+                direction,
+                data.as_ptr(),
+                data.len(),
+                emit,
+                &mut out as *mut Vec<u8> as *mut c_void,
+            )
+        };
+        match status {
+            0 => Ok(out),
+            _ => Err(
+                Error::new(format!("The plugin failed with status {}", status))
+                    .with("plugin", &self.id),
+            ),
+        }
+    }
+}
+
+impl Drop for Plugin {
+    fn drop(&mut self) {
+        // Safety: the state came from init and is not used afterwards
+        unsafe { (self.finish)(self.state) }
+    }
+}
+
+/// The fingerprint of the plugins recorded in containers, see
+/// [Options::plugins](rhuffman::container::Options::plugins). It depends on the ids of
+/// the plugins and their order, None without plugins.
+pub fn fingerprint(plugins: &[Plugin]) -> Option<u64> {
+    if plugins.is_empty() {
+        return None;
+    }
+    // Ids are C strings, so can not contain the separator
+    let ids: Vec<u8> = plugins
+        .iter()
+        .flat_map(|plugin| plugin.id.bytes().chain(Some(0)))
+        .collect();
+    let mut digest = [0; 8];
+    digest.copy_from_slice(&Checksum::XxHash64.digest(&ids));
+    Some(u64::from_be_bytes(digest))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::process::Command;
+
+    /// Builds plugins/delta.c with the C compiler, as the comment at its top says
+    fn delta_plugin() -> PathBuf {
+        let manifest = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let library = std::env::temp_dir().join(format!("rhuff-delta-{}.so", std::process::id()));
+        let status = Command::new("cc")
+            .args(["-shared", "-fPIC", "-I"])
+            .arg(manifest.join("include"))
+            .arg("-o")
+            .arg(&library)
+            .arg(manifest.join("plugins/delta.c"))
+            .status()
+            .expect("A C compiler is needed to build the sample plugin");
+        assert!(status.success());
+        library
+    }
+
+    #[test]
+    fn sample_plugin_round_trips() {
+        let library = delta_plugin();
+        let plugin = Plugin::load(&library).unwrap();
+        assert_eq!(plugin.id, "delta-1");
+
+        assert_eq!(plugin.forward(&[3, 5, 5, 4]).unwrap(), [3, 2, 0, 255]);
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i / 7) as u8).collect();
+        let transformed = plugin.forward(&data).unwrap();
+        assert_eq!(transformed.len(), data.len());
+        assert_eq!(plugin.reverse(&transformed).unwrap(), data);
+        assert!(plugin.forward(&[]).unwrap().is_empty());
+
+        // The sample fails on unknown directions
+        assert!(plugin.run(2, b"abc").is_err());
+
+        let other = Plugin::load(&library).unwrap();
+        let once = fingerprint(std::slice::from_ref(&plugin));
+        let twice = fingerprint(&[plugin, other]);
+        assert!(once.is_some());
+        assert_ne!(once, twice);
+        assert_eq!(fingerprint(&[]), None);
+        std::fs::remove_file(library).unwrap();
+    }
+
+    #[test]
+    fn missing_libraries_are_errors() {
+        assert!(Plugin::load(Path::new("/nonexistent/librhuff-plugin.so")).is_err());
+    }
+}
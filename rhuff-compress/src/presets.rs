@@ -0,0 +1,86 @@
+use crate::error::{Context, Error};
+use rhuffman::huffman_tree::count_min::CountMinSketch;
+use rhuffman::huffman_tree::huffman_generator::{HuffmanGenerator, HuffmanTree};
+use rhuffman::presets::{self, PresetId};
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::path::Path;
+
+/// Extension of preset files, which are named after their id e.g. `12.rhp`
+pub const PRESET_EXTENSION: &str = "rhp";
+
+/// Registers every preset file of the directory in the global registry
+pub fn register_dir(dir: &Path) -> Result<(), Error> {
+    for (id, tree) in read_dir(dir)? {
+        presets::global()
+            .register(id, tree)
+            .context("preset", id)
+            .context("file", dir.display())?;
+    }
+    Ok(())
+}
+
+/// Registers the preset files of the directory that are not registered yet, and atomically
+/// swaps in those whose tree changed, see [Registry::replace()](presets::Registry::replace).
+/// Compressions already holding the previous preset finish with it, and every container
+/// records the fingerprint of the preset it was coded with. Returns the number of presets
+/// registered or swapped.
+pub fn reload_dir(dir: &Path) -> Result<usize, Error> {
+    let mut reloaded = 0;
+    for (id, tree) in read_dir(dir)? {
+        match presets::global().get(id) {
+            Some(preset) if *preset.tree() == tree => {}
+            _ => {
+                presets::global().replace(id, tree);
+                reloaded += 1;
+            }
+        }
+    }
+    Ok(reloaded)
+}
+
+/// The id and tree of every preset file of the directory
+fn read_dir(dir: &Path) -> Result<Vec<(PresetId, HuffmanTree<u8>)>, Error> {
+    let mut presets = vec![];
+    for entry in fs::read_dir(dir).context("file", dir.display())? {
+        let path = entry.context("file", dir.display())?.path();
+        if path.extension() != Some(PRESET_EXTENSION.as_ref()) {
+            continue;
+        }
+        let id: PresetId = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse().ok())
+            .ok_or("Preset files must be named <id>.rhp")
+            .context("file", path.display())?;
+        let contents = fs::read(&path).context("file", path.display())?;
+        let tree: HuffmanTree<u8> = rmp_serde::from_slice(&contents)
+            .map_err(|_| "Unable to parse the preset file")
+            .context("file", path.display())?;
+        presets.push((id, tree));
+    }
+    Ok(presets)
+}
+
+/// Trains a byte codebook on the contents and writes it as a preset file. With
+/// `approx_counts`, only that many bytes estimated most frequent get a code.
+pub fn train(contents: &[u8], output: &Path, approx_counts: Option<usize>) {
+    let mut gen = match approx_counts {
+        Some(top_k) => HuffmanGenerator::builder()
+            .approximate_counts(CountMinSketch::new(top_k))
+            .build()
+            .unwrap(),
+        None => HuffmanGenerator::new(),
+    };
+    gen.add_occurences_from_iterator(&mut contents.iter());
+    let tree = gen
+        .into_huffman_tree()
+        .expect("Cannot train a preset on an empty file");
+    write(&tree, output);
+}
+
+/// Writes the tree as a preset file
+pub fn write(tree: &HuffmanTree<u8>, output: &Path) {
+    let mut out = File::create(output).expect("could not create output file");
+    out.write_all(&rmp_serde::to_vec(tree).unwrap()).unwrap();
+}
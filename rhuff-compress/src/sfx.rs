@@ -0,0 +1,61 @@
+//! Self-extracting archives: the rhuff-sfx stub followed by a compressed container, see
+//! [trailer]. Run, such an executable writes the original file, see `src/bin/rhuff-sfx.rs`.
+//!
+//! The stub is a small executable of its own, so archives run on the platform the stub was
+//! built for. By default it is the rhuff-sfx installed next to rhuff-compress, and
+//! `--sfx-stub` picks one built for another target.
+pub mod trailer;
+
+use crate::error::{Context, Error};
+use crate::interrupt::Output;
+use crate::paths;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+/// The stub installed next to this executable
+fn installed_stub() -> Result<PathBuf, Error> {
+    let exe = std::env::current_exe().context("operation", "locate rhuff-compress")?;
+    Ok(exe.with_file_name(format!("rhuff-sfx{}", std::env::consts::EXE_SUFFIX)))
+}
+
+/// Writes a self-extracting executable restoring `name` from the compressed container,
+/// made of the stub, or of the installed one if None
+pub fn write(
+    compressed: &[u8],
+    name: &Path,
+    output: &Path,
+    stub: Option<&Path>,
+) -> Result<(), Error> {
+    let stub = match stub {
+        Some(stub) => stub.to_path_buf(),
+        None => installed_stub()?,
+    };
+    let mut file = File::open(paths::long(&stub)).context("stub", stub.display())?;
+    if trailer::read(&mut file)
+        .context("stub", stub.display())?
+        .is_some()
+    {
+        return Err(
+            Error::new("The stub is a self-extracting archive").with("stub", stub.display())
+        );
+    }
+    let mut archive = vec![];
+    file.rewind()
+        .and_then(|_| file.read_to_end(&mut archive))
+        .context("stub", stub.display())?;
+    let name = name.file_name().ok_or("The input must be a file")?;
+    trailer::append(&mut archive, compressed, &paths::to_bytes(name));
+
+    let mut out = Output::create(Some(output)).context("file", output.display())?;
+    out.write_all(&archive).context("file", output.display())?;
+    out.commit().context("file", output.display())?;
+    #[cfg(unix)]
+    {
+        use std::fs;
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(paths::long(output), fs::Permissions::from_mode(0o755))
+            .context("file", output.display())?;
+    }
+    Ok(())
+}
@@ -0,0 +1,120 @@
+//! The payload appended to the stub of a self-extracting archive, read back by the stub:
+//!
+//! | Field         | Encoding                                |
+//! |---------------|-----------------------------------------|
+//! | container     | the output of `container::compress()`   |
+//! | name          | file name of the original, see `paths`  |
+//! | name length   | `u32` little endian                     |
+//! | payload length| `u64` little endian, container included |
+//! | magic         | `RHFSFX01`                              |
+use std::convert::TryFrom;
+use std::io::{Read, Seek, SeekFrom};
+
+const MAGIC: &[u8; 8] = b"RHFSFX01";
+const TRAILER_LEN: u64 = 4 + 8 + MAGIC.len() as u64;
+
+/// What a self-extracting archive carries
+#[derive(PartialEq, Eq, Debug)]
+pub struct Payload {
+    pub compressed: Vec<u8>,
+    /// File name of the original, as [paths::to_bytes()](crate::paths::to_bytes) encodes it
+    pub name: Vec<u8>,
+}
+
+/// Appends the payload to the stub
+pub fn append(archive: &mut Vec<u8>, compressed: &[u8], name: &[u8]) {
+    archive.extend_from_slice(compressed);
+    archive.extend_from_slice(name);
+    archive.extend_from_slice(&(name.len() as u32).to_le_bytes());
+    archive.extend_from_slice(&((compressed.len() + name.len()) as u64).to_le_bytes());
+    archive.extend_from_slice(MAGIC);
+}
+
+/// The payload of the archive, None if it does not end with one
+/// ## Errors
+/// Returns Err if the trailer is corrupted or the archive can not be read
+pub fn read(archive: &mut (impl Read + Seek)) -> Result<Option<Payload>, &'static str> {
+    const UNREADABLE: &str = "Unable to read the self-extracting archive";
+    const CORRUPTED: &str = "Corrupted self-extracting archive";
+    let len = archive.seek(SeekFrom::End(0)).map_err(|_| UNREADABLE)?;
+    if len < TRAILER_LEN {
+        return Ok(None);
+    }
+    let mut trailer = [0u8; TRAILER_LEN as usize];
+    archive
+        .seek(SeekFrom::Start(len - TRAILER_LEN))
+        .and_then(|_| archive.read_exact(&mut trailer))
+        .map_err(|_| UNREADABLE)?;
+    if &trailer[12..] != MAGIC {
+        return Ok(None);
+    }
+
+    let mut name_len = [0; 4];
+    name_len.copy_from_slice(&trailer[..4]);
+    let name_len = u64::from(u32::from_le_bytes(name_len));
+    let mut payload_len = [0; 8];
+    payload_len.copy_from_slice(&trailer[4..12]);
+    let payload_len = u64::from_le_bytes(payload_len);
+    if name_len > payload_len || payload_len > len - TRAILER_LEN {
+        return Err(CORRUPTED);
+    }
+
+    let mut payload = vec![0; usize::try_from(payload_len).map_err(|_| CORRUPTED)?];
+    archive
+        .seek(SeekFrom::Start(len - TRAILER_LEN - payload_len))
+        .and_then(|_| archive.read_exact(&mut payload))
+        .map_err(|_| UNREADABLE)?;
+    let name = payload.split_off((payload_len - name_len) as usize);
+    Ok(Some(Payload {
+        compressed: payload,
+        name,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn archive(compressed: &[u8], name: &[u8]) -> Vec<u8> {
+        let mut archive = b"stub".to_vec();
+        append(&mut archive, compressed, name);
+        archive
+    }
+
+    #[test]
+    fn payloads_round_trip() {
+        let archive = archive(b"container", b"notes.txt");
+        assert_eq!(
+            read(&mut Cursor::new(archive)).unwrap(),
+            Some(Payload {
+                compressed: b"container".to_vec(),
+                name: b"notes.txt".to_vec(),
+            })
+        );
+    }
+
+    #[test]
+    fn plain_executables_have_no_payload() {
+        assert_eq!(read(&mut Cursor::new(b"stub".to_vec())).unwrap(), None);
+        assert_eq!(read(&mut Cursor::new(vec![0; 100])).unwrap(), None);
+    }
+
+    #[test]
+    fn corrupted_trailers_are_errors() {
+        let good = archive(b"container", b"notes.txt");
+        let at = |field: usize| good.len() - TRAILER_LEN as usize + field;
+        let with = |field: usize, bytes: &[u8]| {
+            let mut archive = good.clone();
+            archive[at(field)..at(field) + bytes.len()].copy_from_slice(bytes);
+            read(&mut Cursor::new(archive))
+        };
+        // Longer than the archive, than the payload, or than memory can hold
+        assert!(with(4, &1000u64.to_le_bytes()).is_err());
+        assert!(with(4, &u64::MAX.to_le_bytes()).is_err());
+        assert!(with(0, &100u32.to_le_bytes()).is_err());
+        assert!(with(0, &u32::MAX.to_le_bytes()).is_err());
+        // The whole archive may be the payload
+        assert!(with(4, &(at(0) as u64).to_le_bytes()).unwrap().is_some());
+    }
+}
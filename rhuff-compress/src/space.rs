@@ -0,0 +1,76 @@
+//! Running out of disk space. Outputs are partial files until committed (see
+//! [interrupt](crate::interrupt)), so a write failing for lack of space leaves no truncated
+//! output behind, and the run exits with [EXIT_NO_SPACE] so that batch jobs can tell it from
+//! other failures. `--reserve-check` fails before compressing instead of while writing.
+use crate::error::Error;
+use rhuffman::huffman_tree::huffman_encoder::HuffmanEncoder;
+use std::io;
+use std::path::Path;
+
+/// EX_TEMPFAIL of sysexits: the run can be retried once space is freed
+pub const EXIT_NO_SPACE: i32 = 75;
+
+/// Bytes of headers and trees counted on top of the coded data: some for the container, a
+/// little per block
+const HEADER_MARGIN: u64 = 4096;
+const BLOCK_MARGIN_RATIO: u64 = 256;
+
+pub fn is_no_space(error: &io::Error) -> bool {
+    error.kind() == io::ErrorKind::StorageFull
+}
+
+/// Bytes compressing the data is estimated to take: its bytes coded with a tree trained on
+/// all of them, never more than the data stored as it is, plus a margin for headers and trees
+pub fn estimate(data: &[u8]) -> u64 {
+    let mut counts = [0u64; 256];
+    for &byte in data {
+        counts[usize::from(byte)] += 1;
+    }
+    let counts: Vec<(u8, u64)> = (0..=255).zip(counts.iter().copied()).collect();
+    let coded = HuffmanEncoder::from_frequencies(&counts)
+        .ok()
+        .and_then(|encoder| encoder.encoded_len_of(&counts).ok())
+        .map_or(0, |bits| bits.div_ceil(8));
+    let len = data.len() as u64;
+    coded.min(len) + HEADER_MARGIN + len / BLOCK_MARGIN_RATIO
+}
+
+/// Bytes that can be written on the file system holding `path`, None where it is not known
+#[cfg(unix)]
+pub fn available(path: &Path) -> Option<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let dir = CString::new(dir.as_os_str().as_bytes()).ok()?;
+    let mut stats = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: statvfs fills the struct when it returns 0
+    let stats = unsafe {
+        if libc::statvfs(dir.as_ptr(), stats.as_mut_ptr()) != 0 {
+            return None;
+        }
+        stats.assume_init()
+    };
+    Some((stats.f_bavail as u64).saturating_mul(stats.f_frsize as u64))
+}
+
+#[cfg(not(unix))]
+pub fn available(_path: &Path) -> Option<u64> {
+    None
+}
+
+/// Fails if compressing the data to `output` is estimated to need more space than the file
+/// system has. Passes where the free space is not known.
+pub fn reserve(output: &Path, data: &[u8]) -> Result<(), Error> {
+    let needed = estimate(data);
+    match available(output) {
+        Some(available) if available < needed => Err(Error::no_space(format!(
+            "Not enough space for the output, about {} bytes needed and {} available",
+            needed, available
+        ))),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,90 @@
+//! The `--stats` summary of a run: sizes, timings and resource usage, printed on stderr so
+//! that batch jobs can track performance across versions.
+use rhuffman::report;
+use std::time::Duration;
+
+/// Resources used by the process so far
+pub struct Usage {
+    pub user: Duration,
+    pub system: Duration,
+    /// Peak resident set size in bytes
+    pub peak_rss: u64,
+}
+
+/// Resource usage of the process, None where it is not supported
+#[cfg(unix)]
+pub fn usage() -> Option<Usage> {
+    let mut usage = std::mem::MaybeUninit::<libc::rusage>::uninit();
+    // SAFETY: getrusage fills the struct when it returns 0
+    let usage = unsafe {
+        if libc::getrusage(libc::RUSAGE_SELF, usage.as_mut_ptr()) != 0 {
+            return None;
+        }
+        usage.assume_init()
+    };
+    let duration = |time: libc::timeval| {
+        Duration::from_secs(time.tv_sec as u64) + Duration::from_micros(time.tv_usec as u64)
+    };
+    // Linux reports kilobytes, macOS bytes
+    let unit = if cfg!(target_os = "macos") { 1 } else { 1024 };
+    Some(Usage {
+        user: duration(usage.ru_utime),
+        system: duration(usage.ru_stime),
+        peak_rss: usage.ru_maxrss as u64 * unit,
+    })
+}
+
+#[cfg(not(unix))]
+pub fn usage() -> Option<Usage> {
+    None
+}
+
+pub struct Stats {
+    pub operation: &'static str,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub wall: Duration,
+    pub usage: Option<Usage>,
+}
+
+impl Stats {
+    pub fn print(&self, json: bool) {
+        let ratio = if self.bytes_in == 0 {
+            0.0
+        } else {
+            self.bytes_out as f64 / self.bytes_in as f64
+        };
+        if json {
+            let stats = report::Stats {
+                schema: report::SCHEMA_VERSION,
+                operation: self.operation.to_owned(),
+                bytes_in: self.bytes_in,
+                bytes_out: self.bytes_out,
+                ratio,
+                wall_seconds: self.wall.as_secs_f64(),
+                user_seconds: self.usage.as_ref().map(|usage| usage.user.as_secs_f64()),
+                system_seconds: self.usage.as_ref().map(|usage| usage.system.as_secs_f64()),
+                peak_rss_bytes: self.usage.as_ref().map(|usage| usage.peak_rss),
+            };
+            eprintln!(
+                "{}",
+                serde_json::to_string(&stats).expect("Reports always serialize")
+            );
+            return;
+        }
+        eprintln!("{:<12} {}", "operation", self.operation);
+        eprintln!("{:<12} {} bytes", "input", self.bytes_in);
+        eprintln!("{:<12} {} bytes", "output", self.bytes_out);
+        eprintln!("{:<12} {:.3}", "ratio", ratio);
+        eprintln!("{:<12} {:.3} s", "wall time", self.wall.as_secs_f64());
+        if let Some(usage) = &self.usage {
+            eprintln!("{:<12} {:.3} s", "user time", usage.user.as_secs_f64());
+            eprintln!("{:<12} {:.3} s", "system time", usage.system.as_secs_f64());
+            eprintln!(
+                "{:<12} {:.1} MiB",
+                "peak memory",
+                usage.peak_rss as f64 / (1 << 20) as f64
+            );
+        }
+    }
+}
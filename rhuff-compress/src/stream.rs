@@ -0,0 +1,76 @@
+//! `--flush-every`: compressing the input as it arrives instead of once it is all read, for
+//! pipes such as `rhuff-compress -c --flush-every 64K - - | nc host port`. Every block is
+//! trained on its own data and flushed as soon as it is full, so the reader can decode each
+//! one without waiting for the end of the input.
+use crate::error::{Context, Error};
+use crate::interrupt::Output;
+use crate::paths;
+use rhuffman::container::{self, HuffmanWriter};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+/// Size of the reads from the input, a smaller one than the blocks does not delay them
+const READ_SIZE: usize = 64 << 10;
+
+/// Flushes the writer after every write, which [HuffmanWriter] makes once per block
+struct Flushing<W: Write> {
+    out: W,
+    /// Bytes written so far
+    written: u64,
+}
+
+impl<W: Write> Write for Flushing<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.out.write(buf)?;
+        self.out.flush()?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Compresses the input, or stdin for `-`, to the output with the options, flushing every
+/// block. Returns the bytes read and written.
+pub fn compress(
+    input: &Path,
+    output: Option<&Path>,
+    options: &container::Options,
+) -> Result<(u64, u64), Error> {
+    let input_name = input.display();
+    let output_name = output.unwrap_or_else(|| Path::new("-")).display();
+    let mut reader: Box<dyn Read> = if input == Path::new("-") {
+        Box::new(io::stdin())
+    } else {
+        Box::new(File::open(paths::long(input)).context("file", &input_name)?)
+    };
+    let out = Flushing {
+        out: Output::create(output).context("file", &output_name)?,
+        written: 0,
+    };
+    let mut writer = HuffmanWriter::trained(out, options)
+        .context("operation", "compress")
+        .context("file", &input_name)?;
+
+    let mut buffer = vec![0; READ_SIZE];
+    let mut read = 0;
+    loop {
+        let n = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+            Err(error) => return Err(error).context("file", &input_name),
+        };
+        read += n as u64;
+        writer
+            .write_all(&buffer[..n])
+            .context("file", &output_name)?;
+    }
+    let out = writer.finish().context("file", &output_name)?;
+    let written = out.written;
+    out.out.commit().context("file", &output_name)?;
+    Ok((read, written))
+}
@@ -0,0 +1,37 @@
+//! Live metrics of the running job in the Prometheus textfile format, for the node exporter
+//! textfile collector or any orchestrator polling the file.
+use rhuffman::container::telemetry::Telemetry;
+use std::fs;
+use std::path::Path;
+
+/// Rewrites the textfile with the snapshot. The file is replaced atomically so readers never
+/// see it half written.
+pub fn write_textfile(path: &Path, operation: &str, telemetry: &Telemetry) {
+    let metrics = format!(
+        "# HELP rhuff_bytes_in Bytes consumed so far\n\
+         # TYPE rhuff_bytes_in gauge\n\
+         rhuff_bytes_in{{operation=\"{op}\"}} {}\n\
+         # HELP rhuff_bytes_out Bytes produced so far\n\
+         # TYPE rhuff_bytes_out gauge\n\
+         rhuff_bytes_out{{operation=\"{op}\"}} {}\n\
+         # HELP rhuff_ratio Bytes out per byte in\n\
+         # TYPE rhuff_ratio gauge\n\
+         rhuff_ratio{{operation=\"{op}\"}} {}\n\
+         # HELP rhuff_throughput_bytes_per_second Bytes in per second\n\
+         # TYPE rhuff_throughput_bytes_per_second gauge\n\
+         rhuff_throughput_bytes_per_second{{operation=\"{op}\"}} {}\n\
+         # HELP rhuff_done Whether the job has finished\n\
+         # TYPE rhuff_done gauge\n\
+         rhuff_done{{operation=\"{op}\"}} {}\n",
+        telemetry.bytes_in,
+        telemetry.bytes_out,
+        telemetry.ratio(),
+        telemetry.throughput(),
+        telemetry.done as u8,
+        op = operation,
+    );
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    fs::write(&temporary, metrics).expect("Unable to write the telemetry file");
+    fs::rename(&temporary, path).expect("Unable to write the telemetry file");
+}
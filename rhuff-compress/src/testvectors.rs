@@ -0,0 +1,61 @@
+//! `testvectors`: writing the conformance vectors of the library to a directory, for the
+//! bindings and other implementations to check their output against. Every vector is three
+//! files, `<name>.input`, `<name>.model` and `<name>.expected`, and `manifest.json` lists
+//! them with what they cover. Models are empty for containers, which embed their trees.
+use crate::error::{Context, Error};
+use rhuffman::container::testvectors::{self, Kind};
+use serde_json::{json, Value};
+use std::fs;
+use std::path::Path;
+
+/// What a reader needs to know of the vector besides its files
+fn describe(kind: &Kind) -> Value {
+    match kind {
+        Kind::Payload(layout) => json!({
+            "kind": "payload",
+            "byte_order": layout.byte_order.to_string(),
+            "padding": layout.padding.to_string(),
+        }),
+        Kind::Tree => json!({ "kind": "tree" }),
+        Kind::Container(options) => json!({
+            "kind": "container",
+            "filter": options.filter.to_string(),
+            "block_size": options.block_size.to_string(),
+            "content": options.content.to_string(),
+            "byte_order": options.byte_order.to_string(),
+            "padding": options.padding.to_string(),
+            "align": options.align,
+            "stored": options.stored,
+            "checksum": options.checksum.to_string(),
+            "block_checksums": options.block_checksums,
+        }),
+        _ => json!({ "kind": "unknown" }),
+    }
+}
+
+pub fn write(output: &Path) -> Result<(), Error> {
+    let vectors = testvectors::generate().context("operation", "testvectors")?;
+    fs::create_dir_all(output).context("file", output.display())?;
+    let mut manifest = vec![];
+    for vector in &vectors {
+        for (extension, contents) in [
+            ("input", &vector.input),
+            ("model", &vector.model),
+            ("expected", &vector.expected),
+        ] {
+            let path = output.join(format!("{}.{}", vector.name, extension));
+            fs::write(&path, contents).context("file", path.display())?;
+        }
+        let mut entry = describe(&vector.kind);
+        entry["name"] = json!(vector.name);
+        manifest.push(entry);
+    }
+    let path = output.join("manifest.json");
+    let manifest = json!({
+        "schema": rhuffman::report::SCHEMA_VERSION,
+        "vectors": manifest,
+    });
+    let manifest = serde_json::to_string_pretty(&manifest).expect("Manifests always serialize");
+    fs::write(&path, manifest + "\n").context("file", path.display())?;
+    Ok(())
+}
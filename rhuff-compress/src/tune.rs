@@ -0,0 +1,414 @@
+//! `tune`: picking the preset that best compresses files like those of a corpus. Every
+//! candidate model is cross-validated: the files are split in folds, and each fold is
+//! compressed with the model trained on the other folds. The model with the best held-out
+//! ratio is trained again on the whole corpus and written as a preset file.
+//!
+//! Presets code bytes, so models are over bytes or over nibbles, which presets code as bytes
+//! once the input is split with `--symbols nibble`. Word models are not candidates, no
+//! container can code them.
+use crate::error::{Context, Error};
+use crate::presets;
+use rhuffman::filters::nibbles;
+use rhuffman::huffman_tree::canonical;
+use rhuffman::huffman_tree::huffman_generator::{HuffmanGenerator, HuffmanTree};
+use rhuffman::report;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// Number of occurrences of each symbol
+type Counts = [u64; 256];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Alphabet {
+    Byte,
+    Nibble,
+}
+
+impl Alphabet {
+    fn size(self) -> usize {
+        match self {
+            Alphabet::Byte => 256,
+            Alphabet::Nibble => 16,
+        }
+    }
+
+    fn count(self, data: &[u8]) -> Counts {
+        let mut counts = [0; 256];
+        let symbols = match self {
+            Alphabet::Byte => data.to_vec(),
+            Alphabet::Nibble => nibbles::split(data),
+        };
+        for symbol in symbols {
+            counts[symbol as usize] += 1;
+        }
+        counts
+    }
+}
+
+/// Which symbols of the alphabet get a code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Coverage {
+    /// Those seen in the training data
+    Seen,
+    /// The most frequent of those seen
+    Top(usize),
+    /// All of them, unseen ones as if seen once, so that no input fails to compress
+    All,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Model {
+    alphabet: Alphabet,
+    coverage: Coverage,
+}
+
+impl Model {
+    fn train(self, counts: &Counts) -> Option<HuffmanTree<u8>> {
+        let mut counts: Vec<(u8, u64)> = (0..self.alphabet.size())
+            .map(|symbol| (symbol as u8, counts[symbol]))
+            .collect();
+        match self.coverage {
+            Coverage::Seen => {}
+            Coverage::Top(k) => {
+                counts.sort_by_key(|&(symbol, count)| (std::cmp::Reverse(count), symbol));
+                counts.truncate(k);
+            }
+            Coverage::All => counts.iter_mut().for_each(|(_, count)| *count += 1),
+        }
+        let mut gen = HuffmanGenerator::new();
+        for (symbol, count) in counts.into_iter().filter(|&(_, count)| count > 0) {
+            gen.add_occurences_to_symbol(&symbol, count as usize);
+        }
+        gen.into_huffman_tree()
+    }
+
+    /// The flags compressing with a preset of this model needs
+    fn flags(self) -> &'static str {
+        match self.alphabet {
+            Alphabet::Byte => "",
+            Alphabet::Nibble => " --symbols nibble",
+        }
+    }
+}
+
+impl fmt::Display for Model {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let alphabet = match self.alphabet {
+            Alphabet::Byte => "byte",
+            Alphabet::Nibble => "nibble",
+        };
+        match self.coverage {
+            Coverage::Seen => write!(f, "{}", alphabet),
+            Coverage::Top(k) => write!(f, "{}-top-{}", alphabet, k),
+            Coverage::All => write!(f, "{}-all", alphabet),
+        }
+    }
+}
+
+/// How a model did on the held-out folds
+struct Score {
+    model: Model,
+    /// Held-out bytes coded, and the bits they were coded in, over the folds the model could
+    /// code
+    bytes: u64,
+    bits: u64,
+    /// Folds with a symbol the model trained on the other folds has no code for
+    failed_folds: usize,
+}
+
+impl Score {
+    fn ratio(&self) -> f64 {
+        match self.bytes {
+            0 => 0.0,
+            bytes => self.bits as f64 / 8.0 / bytes as f64,
+        }
+    }
+}
+
+/// Bits the counted symbols take with the tree, None if one of them has no code
+fn coded_bits(tree: &HuffmanTree<u8>, counts: &Counts) -> Option<u64> {
+    let mut lengths = [None; 256];
+    for (symbol, len) in canonical::code_lengths(tree) {
+        lengths[symbol as usize] = Some(len as u64);
+    }
+    (0..256)
+        .filter(|&symbol| counts[symbol] > 0)
+        .map(|symbol| Some(lengths[symbol]? * counts[symbol]))
+        .sum()
+}
+
+fn add(total: &mut Counts, counts: &Counts) {
+    for (total, count) in total.iter_mut().zip(counts.iter()) {
+        *total += count;
+    }
+}
+
+/// Size and counts of a file of the corpus
+struct Sample {
+    bytes: u64,
+    byte_counts: Counts,
+    nibble_counts: Counts,
+}
+
+impl Sample {
+    fn new(data: &[u8]) -> Sample {
+        Sample {
+            bytes: data.len() as u64,
+            byte_counts: Alphabet::Byte.count(data),
+            nibble_counts: Alphabet::Nibble.count(data),
+        }
+    }
+
+    fn counts(&self, alphabet: Alphabet) -> &Counts {
+        match alphabet {
+            Alphabet::Byte => &self.byte_counts,
+            Alphabet::Nibble => &self.nibble_counts,
+        }
+    }
+}
+
+/// The candidate models, each alphabet with every coverage
+fn models(prune: &[usize]) -> Vec<Model> {
+    let mut models = vec![];
+    for alphabet in [Alphabet::Byte, Alphabet::Nibble] {
+        models.push(Model {
+            alphabet,
+            coverage: Coverage::Seen,
+        });
+        models.extend(
+            prune
+                .iter()
+                .filter(|&&k| k > 0 && k < alphabet.size())
+                .map(|&k| Model {
+                    alphabet,
+                    coverage: Coverage::Top(k),
+                }),
+        );
+        models.push(Model {
+            alphabet,
+            coverage: Coverage::All,
+        });
+    }
+    models
+}
+
+/// Counts of the training files and of the held-out ones, and the size of the held-out
+/// ones, for `fold` of `folds`. The files of fold i are i, i + folds, ...
+fn split(
+    samples: &[Sample],
+    alphabet: Alphabet,
+    folds: usize,
+    fold: usize,
+) -> (Counts, Counts, u64) {
+    let (mut training, mut held_out, mut held_out_bytes) = ([0; 256], [0; 256], 0);
+    for (index, sample) in samples.iter().enumerate() {
+        if index % folds == fold {
+            add(&mut held_out, sample.counts(alphabet));
+            held_out_bytes += sample.bytes;
+        } else {
+            add(&mut training, sample.counts(alphabet));
+        }
+    }
+    (training, held_out, held_out_bytes)
+}
+
+/// Scores the model on each fold held out in turn
+fn cross_validate(model: Model, samples: &[Sample], folds: usize) -> Score {
+    let mut score = Score {
+        model,
+        bytes: 0,
+        bits: 0,
+        failed_folds: 0,
+    };
+    for fold in 0..folds {
+        let (training, held_out, held_out_bytes) = split(samples, model.alphabet, folds, fold);
+        match model
+            .train(&training)
+            .and_then(|tree| coded_bits(&tree, &held_out))
+        {
+            Some(bits) => {
+                score.bytes += held_out_bytes;
+                score.bits += bits;
+            }
+            None => score.failed_folds += 1,
+        }
+    }
+    score
+}
+
+/// The score with the best ratio of those that coded every fold
+fn best(scores: &[Score]) -> Option<&Score> {
+    scores
+        .iter()
+        .filter(|score| score.failed_folds == 0)
+        .min_by(|a, b| a.ratio().total_cmp(&b.ratio()))
+}
+
+/// Cross-validates the models on the files of `corpus` split in `folds`, prints a report
+/// and writes the best model trained on the whole corpus to `output`
+pub fn tune(
+    corpus: &Path,
+    output: &Path,
+    folds: usize,
+    prune: &[usize],
+    json: bool,
+) -> Result<(), Error> {
+    let mut paths = vec![];
+    for entry in fs::read_dir(corpus).context("file", corpus.display())? {
+        let path = entry.context("file", corpus.display())?.path();
+        if path.is_file() {
+            paths.push(path);
+        }
+    }
+    // Sorted so that folds do not depend on the order the directory lists files in
+    paths.sort();
+    if paths.len() < 2 {
+        return Err(
+            Error::new("The corpus needs at least 2 files to hold some out")
+                .with("file", corpus.display()),
+        );
+    }
+    let folds = folds.clamp(2, paths.len());
+
+    let mut samples = vec![];
+    for path in &paths {
+        let data = fs::read(path).context("file", path.display())?;
+        samples.push(Sample::new(&data));
+    }
+    let scores: Vec<Score> = models(prune)
+        .into_iter()
+        .map(|model| cross_validate(model, &samples, folds))
+        .collect();
+    let best =
+        best(&scores).ok_or_else(|| Error::new("No model could code every held-out fold"))?;
+    report(&scores, best, folds, output, json);
+
+    let mut all = [0; 256];
+    for sample in &samples {
+        add(&mut all, sample.counts(best.model.alphabet));
+    }
+    let tree = best
+        .model
+        .train(&all)
+        .ok_or_else(|| Error::new("Cannot train a preset on an empty corpus"))?;
+    presets::write(&tree, output);
+    Ok(())
+}
+
+fn report(scores: &[Score], best: &Score, folds: usize, output: &Path, json: bool) {
+    if json {
+        let tune = report::Tune {
+            schema: report::SCHEMA_VERSION,
+            folds: folds as u64,
+            models: scores
+                .iter()
+                .map(|score| report::TunedModel {
+                    model: score.model.to_string(),
+                    ratio: score.ratio(),
+                    failed_folds: score.failed_folds as u64,
+                })
+                .collect(),
+            best: best.model.to_string(),
+        };
+        println!(
+            "{}",
+            serde_json::to_string(&tune).expect("Reports always serialize")
+        );
+        return;
+    }
+    println!("{:<16} held-out ratio over {} folds", "model", folds);
+    for score in scores {
+        match score.failed_folds {
+            0 => println!("{:<16} {:.4}", score.model.to_string(), score.ratio()),
+            failed => println!(
+                "{:<16} could not code {} of the folds",
+                score.model.to_string(),
+                failed
+            ),
+        }
+    }
+    println!(
+        "best: {}, written to {}, compress with{} --preset <id>",
+        best.model,
+        output.display(),
+        best.model.flags()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples(files: &[&[u8]]) -> Vec<Sample> {
+        files.iter().map(|data| Sample::new(data)).collect()
+    }
+
+    #[test]
+    fn folds_hold_out_every_nth_file() {
+        let samples = samples(&[b"a", b"bb", b"ccc", b"dddd", b"eeeee"]);
+        let (training, held_out, held_out_bytes) = split(&samples, Alphabet::Byte, 3, 0);
+        assert_eq!(held_out_bytes, 1 + 4);
+        assert_eq!((held_out[b'a' as usize], held_out[b'd' as usize]), (1, 4));
+        assert_eq!(held_out.iter().sum::<u64>(), 5);
+        assert_eq!(training.iter().sum::<u64>(), 2 + 3 + 5);
+
+        // Every file is held out exactly once
+        let mut held_out_total = 0;
+        for fold in 0..3 {
+            held_out_total += split(&samples, Alphabet::Byte, 3, fold).2;
+        }
+        assert_eq!(held_out_total, 15);
+
+        let (_, held_out, held_out_bytes) = split(&samples, Alphabet::Nibble, 3, 1);
+        assert_eq!(held_out_bytes, 2 + 5);
+        assert_eq!(held_out.iter().sum::<u64>(), 2 * (2 + 5));
+    }
+
+    #[test]
+    fn folds_with_unseen_symbols_fail() {
+        // The only z is held out with the third file
+        let samples = samples(&[b"abab", b"abba", b"abz", b"baab"]);
+        let seen = Model {
+            alphabet: Alphabet::Byte,
+            coverage: Coverage::Seen,
+        };
+        let score = cross_validate(seen, &samples, 4);
+        assert_eq!(score.failed_folds, 1);
+        // Only the three folds coded count, at one or two bits a byte
+        assert_eq!(score.bytes, 12);
+        assert!((12..=24).contains(&score.bits));
+
+        let all = Model {
+            coverage: Coverage::All,
+            ..seen
+        };
+        assert_eq!(cross_validate(all, &samples, 4).failed_folds, 0);
+        let top = Model {
+            coverage: Coverage::Top(1),
+            ..seen
+        };
+        assert_eq!(cross_validate(top, &samples, 2).failed_folds, 2);
+
+        let scores = [score, cross_validate(all, &samples, 4)];
+        assert_eq!(best(&scores).unwrap().model, all);
+        assert!(best(&scores[..1]).is_none());
+    }
+
+    #[test]
+    fn models_cover_both_alphabets() {
+        let names: Vec<String> = models(&[0, 4, 16]).iter().map(Model::to_string).collect();
+        assert_eq!(
+            names,
+            [
+                "byte",
+                "byte-top-4",
+                "byte-top-16",
+                "byte-all",
+                "nibble",
+                "nibble-top-4",
+                "nibble-all"
+            ]
+        );
+    }
+}
@@ -0,0 +1,216 @@
+//! Node.js bindings for rhuffman. Payloads are regular rhuffman containers, so a buffer
+//! compressed from JavaScript decompresses with the Rust crate or rhuff-compress, and the
+//! other way around.
+use napi::bindgen_prelude::Buffer;
+use napi::{Error, Result};
+use napi_derive::napi;
+
+use rhuffman::container::{self, BlockSize, ByteOrder, Checksum, Content, HeaderKind, Padding};
+use rhuffman::filters::Filter;
+use rhuffman::huffman_tree::huffman_generator::HuffmanGenerator;
+use rhuffman::presets::{self, Preset};
+use std::sync::Arc;
+
+/// How `compress()` should encode the data
+#[napi(object)]
+pub struct CompressOptions {
+    /// Preprocessing stage applied before entropy coding: "none", "timeseries", "nibbles",
+    /// "nibble-planes" or "rle"
+    pub filter: Option<String>,
+    /// Id of a registered `Codebook` to encode with
+    pub preset: Option<u32>,
+    /// "auto", "whole", or a number of bytes with an optional K or M suffix
+    pub block_size: Option<String>,
+    /// What the data is, recorded in the header: "raw" or "tar"
+    pub format: Option<String>,
+    /// How the encoded bits are packed in bytes: "msb", "lsb" or "words32le"
+    pub byte_order: Option<String>,
+    /// What the bits padding the encoded data are set to: "zeros" or "ones"
+    pub padding: Option<String>,
+    /// Start every block at a multiple of this many bytes, 0 packs them
+    pub align: Option<u32>,
+    /// Checksum of the data verified when decompressing: "none", "crc32", "xxhash64" or
+    /// "blake3"
+    pub checksum: Option<String>,
+    /// Also record the checksum of every block
+    pub block_checksums: Option<bool>,
+    /// "standard", or "minimal" to only record the preset
+    pub header: Option<String>,
+}
+
+fn to_error(message: &str) -> Error {
+    Error::from_reason(message.to_string())
+}
+
+fn container_options(options: Option<CompressOptions>) -> Result<container::Options> {
+    let options = match options {
+        Some(options) => options,
+        None => return Ok(container::Options::default()),
+    };
+    let filter = match options.filter {
+        Some(filter) => filter.parse::<Filter>().map_err(Error::from_reason)?,
+        None => Filter::None,
+    };
+    let block_size = match options.block_size {
+        Some(size) => size.parse::<BlockSize>().map_err(Error::from_reason)?,
+        None => BlockSize::Auto,
+    };
+    let content = match options.format {
+        Some(format) => format.parse::<Content>().map_err(Error::from_reason)?,
+        None => Content::Raw,
+    };
+    let byte_order = match options.byte_order {
+        Some(order) => order.parse::<ByteOrder>().map_err(Error::from_reason)?,
+        None => ByteOrder::MsbFirst,
+    };
+    let padding = match options.padding {
+        Some(padding) => padding.parse::<Padding>().map_err(Error::from_reason)?,
+        None => Padding::Zeros,
+    };
+    let checksum = match options.checksum {
+        Some(checksum) => checksum.parse::<Checksum>().map_err(Error::from_reason)?,
+        None => Checksum::None,
+    };
+    let header = match options.header {
+        Some(header) => header.parse::<HeaderKind>().map_err(Error::from_reason)?,
+        None => HeaderKind::Standard,
+    };
+    Ok(container::Options {
+        filter,
+        preset: options.preset,
+        backend: None,
+        block_size,
+        content,
+        byte_order,
+        padding,
+        align: options.align.unwrap_or(0) as usize,
+        stored: false,
+        checksum,
+        block_checksums: options.block_checksums.unwrap_or(false),
+        threads: 0,
+        header,
+        plugins: None,
+    })
+}
+
+#[napi]
+pub fn compress(data: Buffer, options: Option<CompressOptions>) -> Result<Buffer> {
+    let options = container_options(options)?;
+    container::compress(&data, &options)
+        .map(Buffer::from)
+        .map_err(to_error)
+}
+
+#[napi]
+pub fn decompress(data: Buffer) -> Result<Buffer> {
+    container::decompress(&data)
+        .map(Buffer::from)
+        .map_err(to_error)
+}
+
+/// A preset of the process-wide registry, shared with any Rust code running in the process
+#[napi]
+pub struct Codebook {
+    preset: Arc<Preset<u8>>,
+}
+
+#[napi]
+impl Codebook {
+    /// Trains a codebook on the sample and registers it under the id, replacing any codebook
+    /// already registered under it. The sample should contain every byte that will be
+    /// compressed with the codebook.
+    #[napi(factory)]
+    pub fn train(id: u32, sample: Buffer) -> Result<Codebook> {
+        Codebook::from_sample(id, &sample).map_err(to_error)
+    }
+
+    /// The codebook registered under the id
+    #[napi(factory)]
+    pub fn get(id: u32) -> Result<Codebook> {
+        let preset = presets::global()
+            .get(id)
+            .ok_or_else(|| to_error("No codebook is registered under this id"))?;
+        Ok(Codebook { preset })
+    }
+
+    #[napi(getter)]
+    pub fn id(&self) -> u32 {
+        self.preset.id()
+    }
+
+    /// The fingerprint of the codebook as 16 hexadecimal digits, since it does not fit in a
+    /// JavaScript number
+    #[napi(getter)]
+    pub fn fingerprint(&self) -> String {
+        format!("{:016x}", self.preset.fingerprint())
+    }
+
+    /// Compresses with this very codebook, even if another one has since been trained under
+    /// its id
+    #[napi]
+    pub fn compress(&self, data: Buffer, filter: Option<String>) -> Result<Buffer> {
+        let filter = match filter {
+            Some(filter) => filter.parse::<Filter>().map_err(Error::from_reason)?,
+            None => Filter::None,
+        };
+        self.compress_bytes(&data, filter)
+            .map(Buffer::from)
+            .map_err(to_error)
+    }
+}
+
+// Kept apart from the bindings so that tests run without a Node.js runtime
+impl Codebook {
+    fn from_sample(id: u32, sample: &[u8]) -> std::result::Result<Codebook, &'static str> {
+        let mut gen = HuffmanGenerator::new();
+        gen.add_occurences_from_iterator(&mut sample.iter());
+        let tree = gen.into_huffman_tree().ok_or("The sample is empty")?;
+        Ok(Codebook {
+            preset: presets::global().replace(id, tree),
+        })
+    }
+
+    fn compress_bytes(
+        &self,
+        data: &[u8],
+        filter: Filter,
+    ) -> std::result::Result<Vec<u8>, &'static str> {
+        let options = container::Options {
+            filter,
+            ..container::Options::default()
+        };
+        container::compress_with_preset(data, &self.preset, &options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codebooks_keep_compressing_with_their_own_tree() {
+        let first = Codebook::from_sample(200, b"abracadabra").unwrap();
+        let compressed = first.compress_bytes(b"abba", Filter::None).unwrap();
+        let second = Codebook::from_sample(200, b"aaaaaaaaaaaaaab").unwrap();
+        assert_ne!(first.fingerprint(), second.fingerprint());
+        assert_eq!(
+            presets::global().get(200).unwrap().fingerprint(),
+            second.preset.fingerprint()
+        );
+
+        // The first codebook still codes 'c' and 'd', which the second lacks
+        assert!(first.compress_bytes(b"cd", Filter::None).is_ok());
+        assert!(second.compress_bytes(b"cd", Filter::None).is_err());
+
+        // Containers record the codebook they were coded with
+        assert!(container::decompress(&compressed).is_err());
+        presets::global().replace(200, first.preset.tree().clone());
+        assert_eq!(container::decompress(&compressed).unwrap(), b"abba");
+    }
+
+    #[test]
+    fn empty_samples_are_rejected() {
+        assert!(Codebook::from_sample(201, b"").is_err());
+        assert!(presets::global().get(201).is_none());
+    }
+}
@@ -0,0 +1,43 @@
+//! Time taken by [HuffmanEncoder::encode()] for 100 MB of bytes, with counts skewed like those
+//! of text. Run with `cargo bench --bench encode`.
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use rhuffman::huffman_tree::huffman_encoder::HuffmanEncoder;
+
+const LEN: usize = 100_000_000;
+const ITERATIONS: u32 = 3;
+
+/// `len` bytes of 64 values, the small ones far more frequent, like the letters of text
+fn skewed_bytes(len: usize) -> Vec<u8> {
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    (0..len)
+        .map(|_| {
+            // xorshift, for the same data on every run
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            let rank = 1 + (state % 4096) as u32;
+            (4096 / rank).min(64) as u8 % 64
+        })
+        .collect()
+}
+
+fn main() {
+    let data = skewed_bytes(LEN);
+    let encoder = HuffmanEncoder::from_symbols_iterator(&mut data.iter()).unwrap();
+    let mut time = Duration::ZERO;
+    let mut bits = 0;
+    for _ in 0..ITERATIONS {
+        let start = Instant::now();
+        let encoded = black_box(encoder.encode(&mut data.iter()).unwrap());
+        time += start.elapsed();
+        bits = encoded.len();
+    }
+    println!(
+        "{} MB in {} MB: {:>8.2} s per encode",
+        LEN / 1_000_000,
+        bits / 8 / 1_000_000,
+        time.as_secs_f64() / f64::from(ITERATIONS)
+    );
+}
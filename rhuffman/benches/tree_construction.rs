@@ -0,0 +1,34 @@
+//! Time taken by [HuffmanGenerator::into_huffman_tree()] for word level alphabets, with
+//! counts spread like those of words in text. Run with `cargo bench --bench tree_construction`.
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use rhuffman::huffman_tree::huffman_generator::HuffmanGenerator;
+
+const ITERATIONS: u32 = 5;
+
+/// A generator counting `words` distinct words, the n-th most frequent one `words / n` times
+fn word_counts(words: usize) -> HuffmanGenerator<String> {
+    let mut gen = HuffmanGenerator::new();
+    for rank in 1..=words {
+        gen.add_occurences_to_symbol(&format!("word{}", rank), words / rank);
+    }
+    gen
+}
+
+fn main() {
+    for &words in &[100_000, 250_000, 1_000_000] {
+        let mut time = Duration::ZERO;
+        for _ in 0..ITERATIONS {
+            let gen = word_counts(words);
+            let start = Instant::now();
+            black_box(gen.into_huffman_tree());
+            time += start.elapsed();
+        }
+        println!(
+            "{:>9} symbols: {:>8.1} ms per tree",
+            words,
+            time.as_secs_f64() * 1000.0 / f64::from(ITERATIONS)
+        );
+    }
+}
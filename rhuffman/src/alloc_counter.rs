@@ -0,0 +1,75 @@
+//! Counting heap allocations, to check that encoding and decoding do not allocate for every
+//! symbol. Only built with the `debug-alloc` feature.
+//!
+//! Install [CountingAllocator] as the global allocator of your binary or test, then read
+//! [CompressReport::allocations](crate::container::CompressReport::allocations) and
+//! [DecodeStats::allocations](crate::container::DecodeStats::allocations). Allocations are
+//! counted per thread, so work running beside a call on other threads is not mixed in. Without
+//! the allocator installed, every count is 0.
+//! ## Examples
+//! ```
+//! # use rhuffman::alloc_counter::CountingAllocator;
+//! # use rhuffman::container::{self, DecodeOptions, Options};
+//! #[global_allocator]
+//! static ALLOCATOR: CountingAllocator = CountingAllocator;
+//!
+//! let data: Vec<u8> = (0..100_000u64).map(|i| (i * i % 251) as u8).collect();
+//! let (small, small_report) = container::compress_with_report(&data[..1_000], &Options::default()).unwrap();
+//! let (large, large_report) = container::compress_with_report(&data, &Options::default()).unwrap();
+//! assert!(small_report.allocations > 0);
+//! // Not one allocation per byte
+//! assert!(large_report.allocations < small_report.allocations + 100);
+//!
+//! let options = DecodeOptions::default();
+//! let small = container::decompress_with_stats(&small, &options).unwrap().1;
+//! let large = container::decompress_with_stats(&large, &options).unwrap().1;
+//! assert!(large.allocations < small.allocations + 100);
+//! ```
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+thread_local! {
+    static ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+}
+
+/// The [System] allocator, counting the allocations and reallocations of each thread
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CountingAllocator;
+
+impl CountingAllocator {
+    fn count() {
+        // Threads being torn down have no counter left, their allocations are not counted
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+    }
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        CountingAllocator::count();
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        CountingAllocator::count();
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        CountingAllocator::count();
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+/// Allocations made on this thread so far
+pub fn allocations() -> u64 {
+    ALLOCATIONS.try_with(Cell::get).unwrap_or(0)
+}
+
+/// Counts allocations made on behalf of this thread by another, e.g. a worker it waited on
+pub(crate) fn add(allocations: u64) {
+    let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + allocations));
+}
@@ -0,0 +1,117 @@
+//! Compression without choosing options: [compress()] looks at the data and picks the method
+//! most likely to shrink it. The container records what was picked, through its filter and
+//! the tree kind of its blocks, so it decompresses with [container::decompress()] like any
+//! other.
+use crate::container::{self, Options};
+use crate::filters::{rle, Filter};
+use crate::huffman_tree::analysis::analyze;
+
+/// Data this short is stored, the tree would take more room than coding saves
+pub const SMALL: usize = 64;
+
+/// The ways [compress()] can encode data
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[non_exhaustive]
+pub enum Method {
+    /// The bytes are copied as they are
+    Stored,
+    /// Huffman coding of the bytes
+    Huffman,
+    /// The [run-length filter](rle), then Huffman coding
+    RleHuffman,
+}
+
+impl Method {
+    /// Probes the size, runs and entropy of the data for the method to compress it with
+    /// ## Examples
+    /// ```
+    /// # use rhuffman::auto::Method;
+    /// assert_eq!(Method::choose(b"tiny"), Method::Stored);
+    /// assert_eq!(Method::choose(&[b'a'; 1000]), Method::RleHuffman);
+    /// let text = b"a man a plan a canal panama ".repeat(10);
+    /// assert_eq!(Method::choose(&text), Method::Huffman);
+    /// ```
+    pub fn choose(data: &[u8]) -> Method {
+        if data.len() < SMALL {
+            return Method::Stored;
+        }
+        if rle::has_long_runs(data) {
+            return Method::RleHuffman;
+        }
+        let mut counts = [0u64; 256];
+        for &byte in data {
+            counts[usize::from(byte)] += 1;
+        }
+        let counts: Vec<(u8, u64)> = (0..=255).zip(counts.iter().copied()).collect();
+        match analyze(&counts) {
+            Some(analysis) if analysis.looks_incompressible(8) => Method::Stored,
+            _ => Method::Huffman,
+        }
+    }
+
+    /// The container options of the method, left to their defaults otherwise
+    pub fn options(self) -> Options {
+        match self {
+            Method::Stored => Options {
+                stored: true,
+                ..Options::default()
+            },
+            Method::Huffman => Options::default(),
+            Method::RleHuffman => Options {
+                filter: Filter::Rle,
+                ..Options::default()
+            },
+        }
+    }
+}
+
+/// Compresses the data with the method [Method::choose()] picks for it.
+/// ## Errors
+/// Returns Err if the data can not be compressed, which does not happen with the options of
+/// any method.
+/// ## Examples
+/// ```
+/// # use rhuffman::{auto, container};
+/// let data = vec![0u8; 10_000];
+/// let compressed = auto::compress(&data).unwrap();
+/// assert!(compressed.len() < 100);
+/// assert_eq!(container::decompress(&compressed).unwrap(), data);
+/// ```
+pub fn compress(data: &[u8]) -> Result<Vec<u8>, &'static str> {
+    container::compress(data, &Method::choose(data).options())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::{decompress, inspect, TreeSource};
+
+    #[test]
+    fn incompressible_data_is_stored() {
+        let random: Vec<u8> = (0..50_000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+        assert_eq!(Method::choose(&random), Method::Stored);
+        let compressed = compress(&random).unwrap();
+        assert!(compressed.len() < random.len() + 32);
+        assert!(inspect(&compressed)
+            .unwrap()
+            .blocks
+            .iter()
+            .all(|block| block.tree == TreeSource::Stored));
+        assert_eq!(decompress(&compressed).unwrap(), random);
+    }
+
+    #[test]
+    fn every_method_round_trips() {
+        let data = b"some bytes, then runs of them\0\0\0\0".repeat(100);
+        for method in [Method::Stored, Method::Huffman, Method::RleHuffman] {
+            let compressed = container::compress(&data, &method.options()).unwrap();
+            assert_eq!(decompress(&compressed).unwrap(), data);
+        }
+        assert_eq!(
+            decompress(&compress(&[]).unwrap()).unwrap(),
+            Vec::<u8>::new()
+        );
+    }
+}
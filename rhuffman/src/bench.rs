@@ -0,0 +1,96 @@
+//! Measuring how fast a decoder decodes data in the environment it runs in, e.g. to compare
+//! codebooks, or [lookup table](crate::huffman_tree::huffman_decoder::HuffmanDecoder::with_lookup_table)
+//! widths, on the machine and data they will be used with rather than going by defaults.
+//! ## Examples
+//! ```
+//! # use rhuffman::bench;
+//! # use rhuffman::huffman_tree::huffman_decoder::HuffmanDecoder;
+//! # use rhuffman::huffman_tree::huffman_generator::HuffmanGenerator;
+//! let data = b"the quick brown fox jumps over the lazy dog".repeat(100);
+//! let mut gen = HuffmanGenerator::new();
+//! gen.add_occurences_from_iterator(&mut data.iter());
+//! let tree = gen.into_huffman_tree().unwrap();
+//!
+//! let narrow = HuffmanDecoder::new(tree.clone()).with_lookup_table(6);
+//! let wide = HuffmanDecoder::new(tree).with_lookup_table(12);
+//! let narrow = bench::measure(&narrow, &data, 10).unwrap();
+//! let wide = bench::measure(&wide, &data, 10).unwrap();
+//! println!("{:.1} MB/s with 6 bits, {:.1} MB/s with 12", narrow.mb_per_sec, wide.mb_per_sec);
+//! ```
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use crate::huffman_tree::huffman_decoder::HuffmanDecoder;
+use crate::huffman_tree::huffman_encoder::HuffmanEncoder;
+
+/// How fast [measure()] decoded the data
+#[derive(PartialEq, Debug, Clone, Copy)]
+#[must_use]
+pub struct Throughput {
+    /// Time spent decoding, over every iteration
+    pub time: Duration,
+    pub symbols_per_sec: f64,
+    /// Millions of decoded bytes per second
+    pub mb_per_sec: f64,
+}
+
+/// Encodes `data` with the code of the codebook, then decodes it `iterations` times,
+/// reusing the output buffer, and returns the decoding throughput. Encoding is not timed.
+/// ## Errors
+/// Returns Err if `iterations` is 0, or if `data` holds a byte that has no code in the
+/// codebook.
+pub fn measure(
+    codebook: &HuffmanDecoder<u8>,
+    data: &[u8],
+    iterations: u32,
+) -> Result<Throughput, &'static str> {
+    if iterations == 0 {
+        return Err("Measuring takes at least one iteration");
+    }
+    let encoder = HuffmanEncoder::from_tree(&codebook.get_tree());
+    let bits = encoder
+        .encode(&mut data.iter())
+        .map_err(|_| "Data holds a byte with no code in the codebook")?;
+
+    let mut decoded = Vec::with_capacity(data.len());
+    let start = Instant::now();
+    for _ in 0..iterations {
+        decoded.clear();
+        codebook
+            .decode_into(black_box(&bits), &mut decoded)
+            .map_err(<&str>::from)?;
+        black_box(&decoded);
+    }
+    let time = start.elapsed();
+
+    let decoded = data.len() as f64 * f64::from(iterations);
+    // Too fast to time, e.g. empty data
+    let secs = time.as_secs_f64().max(f64::MIN_POSITIVE);
+    Ok(Throughput {
+        time,
+        symbols_per_sec: decoded / secs,
+        mb_per_sec: decoded / secs / 1_000_000.0,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::huffman_tree::huffman_generator::HuffmanGenerator;
+
+    #[test]
+    fn decoding_is_measured() {
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i * i % 37) as u8).collect();
+        let mut gen = HuffmanGenerator::new();
+        gen.add_occurences_from_iterator(&mut data.iter());
+        let decoder = gen.into_encoder_decoder_pair().unwrap().1;
+
+        let throughput = measure(&decoder, &data, 3).unwrap();
+        assert!(throughput.time > Duration::ZERO);
+        assert!(throughput.symbols_per_sec > 0.0);
+        assert!((throughput.symbols_per_sec / throughput.mb_per_sec - 1e6).abs() < 1.0);
+
+        assert!(measure(&decoder, &data, 0).is_err());
+        assert!(measure(&decoder, &[200], 1).is_err());
+    }
+}
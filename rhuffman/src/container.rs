@@ -0,0 +1,2799 @@
+//! The self-describing byte format produced by [compress()] and read by [decompress()]: a
+//! header carrying everything needed to decode the payload, followed by the encoded bits.
+//!
+//! | Field        | Encoding                                                     |
+//! |--------------|--------------------------------------------------------------|
+//! | magic        | `RHF`                                                        |
+//! | version      | `u8`, currently 7                                            |
+//! | filter       | `u8`, see [Filter]                                           |
+//! | content      | `u8`, see [Content]                                          |
+//! | byte order   | `u8`, see [ByteOrder]                                        |
+//! | padding      | `u8`, see [Padding]                                          |
+//! | block size   | varint, 0 when the data was not split or blocks vary in size |
+//! | block count  | varint                                                       |
+//! | alignment    | varint, 0 when blocks follow each other                      |
+//! | flags        | `u8`, 0x01 if the container is streamed, 0x02 if it has plugins |
+//! | plugins      | if flagged, the `u64` LE fingerprint of [Options::plugins]    |
+//! | checksum     | `u8`, see [Checksum], plus 0x80 if blocks have a digest too  |
+//! | digest       | the checksum of the original data, of its length             |
+//! | offset map   | if aligned, the `u64` LE offset of every block then of the end |
+//! | blocks       | one after the other, see below                               |
+//!
+//! The filtered data is split in blocks of the block size, the last one possibly shorter, so
+//! that each gets a tree fitted to its own statistics. Each block is:
+//!
+//! | Field        | Encoding                                                     |
+//! |--------------|--------------------------------------------------------------|
+//! | tree         | `u8` kind, then a tree, preset id + fingerprint, or backend  |
+//! | symbol count | varint                                                       |
+//! | bit length   | varint                                                       |
+//! | digest       | if blocks have one, the checksum of the data of the block    |
+//! | payload      | the encoded bits, packed in the byte order and padded        |
+//!
+//! Embedded trees are stored as the code length of each byte, run-length coded against a
+//! fixed meta-code, and readers rebuild the canonical tree for those lengths. Stored blocks
+//! have no tree, each byte being its own 8 bit code. Blocks coded by an
+//! [entropy backend](Options::backend) record its id as a varint, then the length of its
+//! model as a varint and the model.
+//!
+//! Aligned containers start every block at a multiple of the alignment from the start of the
+//! container, and end at one too, the gaps filled with zeros. Offsets are from the start of
+//! the container, and a block may end before the next offset once rewritten in place with
+//! [rewrite_block()].
+//!
+//! Streamed containers are written by a [HuffmanWriter] before the size of the data is known.
+//! Their header has a block count of 0 and no digest, and they are never aligned. The last
+//! block is followed by a `5` byte where the tree kind of the next would be, then the block
+//! count as a varint and the digest.
+//!
+//! Version 6 containers have no flags. Version 5 containers have no checksum or digest either.
+//! Version 4 containers have no alignment or offset map. Version 3 containers have no byte
+//! order or padding, their payloads are
+//! [ByteOrder::MsbFirst] padded with [Padding::Zeros]. Version 2 containers have no content
+//! either, which is then [Content::Raw]. Version 1 containers hold a single block and have no
+//! block size or block count either.
+//!
+//! Containers with a [minimal](HeaderKind::Minimal) header only record the preset the data
+//! is coded with, see [minimal].
+//!
+//! Files written by earlier versions of rhuff-compress are MessagePack blobs with no magic;
+//! [decompress()] reads them when the `legacy-format` feature is enabled.
+pub mod batch;
+mod block_size;
+pub mod channels;
+pub mod checksum;
+mod chunks;
+pub mod delta;
+#[cfg(feature = "legacy-format")]
+pub mod legacy;
+pub mod merge;
+pub mod minimal;
+pub mod packing;
+mod reader;
+mod rewrite;
+pub mod scratch;
+pub mod tar;
+pub mod telemetry;
+pub mod testvectors;
+mod transcode;
+pub(crate) mod tree;
+mod writer;
+pub mod zip;
+
+use bit_vec::BitVec;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "debug-alloc")]
+use crate::alloc_counter;
+use crate::entropy::{self, Backend, BackendId};
+use crate::filters::utf8::Utf8Policy;
+use crate::filters::Filter;
+use crate::huffman_tree::canonical;
+use crate::huffman_tree::huffman_decoder::HuffmanDecoder;
+use crate::huffman_tree::huffman_encoder::HuffmanEncoder;
+use crate::huffman_tree::huffman_generator::{HuffmanGenerator, HuffmanTree};
+use crate::huffman_tree::huffman_node::HuffmanNode;
+use crate::presets::{self, Preset, PresetId};
+use crate::varint;
+use telemetry::{Reporter, Telemetry};
+
+pub use checksum::Checksum;
+pub use chunks::{decode_chunks, DecodeChunks};
+use packing::Layout;
+pub use packing::{ByteOrder, Padding};
+pub use reader::HuffmanReader;
+pub use rewrite::rewrite_block;
+pub use transcode::transcode;
+pub use writer::{HuffmanWriter, DEFAULT_STREAM_BLOCK_SIZE};
+
+pub const MAGIC: &[u8; 3] = b"RHF";
+pub const VERSION: u8 = 7;
+const VERSION_6: u8 = 6;
+const VERSION_5: u8 = 5;
+const VERSION_4: u8 = 4;
+const VERSION_3: u8 = 3;
+const VERSION_2: u8 = 2;
+const VERSION_1: u8 = 1;
+
+/// Pre-order tree, only written by earlier versions
+const TREE_PREORDER: u8 = 0;
+const TREE_PRESET: u8 = 1;
+const TREE_NONE: u8 = 2;
+const TREE_LENGTHS: u8 = 3;
+const TREE_STORED: u8 = 4;
+/// Where the tree kind of the next block would be, the end of the blocks of a streamed
+/// container
+const STREAM_END: u8 = 5;
+const TREE_BACKEND: u8 = 6;
+
+/// [Frame::streamed]
+const FLAG_STREAMED: u8 = 0x01;
+/// [Frame::plugins]
+const FLAG_PLUGINS: u8 = 0x02;
+
+/// The formats [decompress()] knows how to read
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[non_exhaustive]
+pub enum ContainerFormat {
+    /// The single block format written before blocks were introduced
+    V1,
+    /// The format written before the content was recorded
+    V2,
+    /// The format written before the byte order was recorded
+    V3,
+    /// The format written before blocks could be aligned
+    V4,
+    /// The format written before the data could be checksummed
+    V5,
+    /// The format written before containers could be streamed
+    V6,
+    /// The versioned format described in the [module documentation](self)
+    V7,
+    /// The format with a [minimal](HeaderKind::Minimal) header
+    Minimal,
+    /// The MessagePack format written by earlier versions of rhuff-compress
+    Legacy,
+}
+
+impl ContainerFormat {
+    /// Guesses the format of the data from its first bytes. Returns None if it is not one
+    /// of the known formats.
+    pub fn detect(data: &[u8]) -> Option<ContainerFormat> {
+        if data.starts_with(MAGIC) {
+            match data.get(MAGIC.len()) {
+                Some(&VERSION_1) => Some(ContainerFormat::V1),
+                Some(&VERSION_2) => Some(ContainerFormat::V2),
+                Some(&VERSION_3) => Some(ContainerFormat::V3),
+                Some(&VERSION_4) => Some(ContainerFormat::V4),
+                Some(&VERSION_5) => Some(ContainerFormat::V5),
+                Some(&VERSION_6) => Some(ContainerFormat::V6),
+                Some(&VERSION) => Some(ContainerFormat::V7),
+                _ => None,
+            }
+        } else {
+            // Legacy files are a MessagePack array of 3 or 4 fields
+            match data.first() {
+                Some(0x93) | Some(0x94) => Some(ContainerFormat::Legacy),
+                Some(&minimal::MAGIC) => Some(ContainerFormat::Minimal),
+                _ => None,
+            }
+        }
+    }
+}
+
+/// How [compress()] should encode the data
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct Options {
+    /// Preprocessing stage applied before entropy coding
+    pub filter: Filter,
+    /// Encode with this preset of the [global registry](presets::global) and only store its
+    /// id in the header, instead of training and embedding a tree
+    pub preset: Option<PresetId>,
+    /// Code every block with the backend registered under this id in the
+    /// [global registry](entropy::global), recording its model in the block, instead of
+    /// embedding a tree. Can not be combined with a preset or stored blocks.
+    pub backend: Option<BackendId>,
+    pub block_size: BlockSize,
+    /// What the data is, recorded in the header for readers. It does not change how the
+    /// data is encoded.
+    pub content: Content,
+    /// How the payload bits are packed in bytes, recorded in the header
+    pub byte_order: ByteOrder,
+    /// What the bits after each payload are set to, recorded in the header and checked by
+    /// [strict](DecodeOptions::strict) decoding
+    pub padding: Padding,
+    /// Start every block at a multiple of this many bytes, e.g. the page size, and record
+    /// where each starts so blocks can be replaced in place with [rewrite_block()]. 0 packs
+    /// blocks one after the other.
+    pub align: usize,
+    /// Copy the bytes of each block as they are instead of coding them, for data that would
+    /// not shrink
+    pub stored: bool,
+    /// Record a digest of the data in the header, checked when decompressing
+    pub checksum: Checksum,
+    /// Also record a digest of the data of every block, so that damage is pinned to the
+    /// blocks it hit and the others can still be [recovered](recover). Ignored without a
+    /// checksum.
+    pub block_checksums: bool,
+    /// Number of threads encoding blocks at once, 0 or 1 to encode them on the calling thread.
+    /// The output is the same whatever the number of threads.
+    pub threads: usize,
+    /// Which header to write. A minimal header needs a preset, and leaves out everything
+    /// else: the other options must be left to their defaults.
+    pub header: HeaderKind,
+    /// Fingerprint of the stages the data went through before being compressed that this
+    /// crate knows nothing of, e.g. the plugins of rhuff-compress. Recorded in the header,
+    /// and containers are only decoded with the same [DecodeOptions::plugins].
+    pub plugins: Option<u64>,
+}
+
+impl Options {
+    fn layout(&self) -> Layout {
+        Layout {
+            byte_order: self.byte_order,
+            padding: self.padding,
+        }
+    }
+
+    /// The checksum of every block
+    fn block_checksum(&self) -> Checksum {
+        match self.block_checksums {
+            true => self.checksum,
+            false => Checksum::None,
+        }
+    }
+}
+
+/// What kind of data a container holds
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[non_exhaustive]
+pub enum Content {
+    /// Any bytes
+    #[default]
+    Raw,
+    /// A tar archive, whose members can be listed with [tar::members()]
+    Tar,
+}
+
+impl FromStr for Content {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "raw" => Ok(Content::Raw),
+            "tar" => Ok(Content::Tar),
+            _ => Err(format!("Unknown format \"{}\"", s)),
+        }
+    }
+}
+
+impl fmt::Display for Content {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Content::Raw => write!(f, "raw"),
+            Content::Tar => write!(f, "tar"),
+        }
+    }
+}
+
+/// The header a container is written with
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[non_exhaustive]
+pub enum HeaderKind {
+    /// The header described in the [module documentation](self)
+    #[default]
+    Standard,
+    /// A magic byte and the preset id, see [minimal]
+    Minimal,
+}
+
+impl FromStr for HeaderKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "standard" => Ok(HeaderKind::Standard),
+            "minimal" => Ok(HeaderKind::Minimal),
+            _ => Err(format!("Unknown header \"{}\"", s)),
+        }
+    }
+}
+
+impl fmt::Display for HeaderKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeaderKind::Standard => write!(f, "standard"),
+            HeaderKind::Minimal => write!(f, "minimal"),
+        }
+    }
+}
+
+/// How the filtered data is split in blocks, each with its own tree
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum BlockSize {
+    /// Probe a few block sizes on the start of the data, weighing the cost of the extra
+    /// headers against the better fit of smaller blocks, and keep the cheapest
+    #[default]
+    Auto,
+    /// A single block
+    Whole,
+    /// Blocks of this many bytes, which must not be zero
+    Fixed(usize),
+}
+
+impl BlockSize {
+    /// The block size to split the data with, 0 for a single block
+    fn resolve(self, data: &[u8]) -> usize {
+        match self {
+            BlockSize::Auto => block_size::probe(data),
+            BlockSize::Whole => 0,
+            BlockSize::Fixed(size) => size,
+        }
+    }
+}
+
+impl FromStr for BlockSize {
+    type Err = String;
+
+    /// Parses `auto`, `whole`, or a number of bytes with an optional `K` or `M` suffix
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => return Ok(BlockSize::Auto),
+            "whole" => return Ok(BlockSize::Whole),
+            _ => {}
+        }
+        let (digits, unit) = match s.char_indices().last() {
+            Some((i, 'K')) | Some((i, 'k')) => (&s[..i], 1 << 10),
+            Some((i, 'M')) | Some((i, 'm')) => (&s[..i], 1 << 20),
+            _ => (s, 1),
+        };
+        match digits
+            .parse::<usize>()
+            .ok()
+            .and_then(|n| n.checked_mul(unit))
+        {
+            Some(0) | None => Err(format!("Invalid block size \"{}\"", s)),
+            Some(size) => Ok(BlockSize::Fixed(size)),
+        }
+    }
+}
+
+impl fmt::Display for BlockSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlockSize::Auto => write!(f, "auto"),
+            BlockSize::Whole => write!(f, "whole"),
+            BlockSize::Fixed(size) => write!(f, "{}", size),
+        }
+    }
+}
+
+/// Compresses the data into a self-describing container.
+/// ## Errors
+/// Returns Err if the preset is not registered, or lacks a code for one of the bytes.
+/// ## Examples
+/// ```
+/// # use rhuffman::container::{self, Options};
+/// let data = b"a man a plan a canal panama";
+/// let compressed = container::compress(data, &Options::default()).unwrap();
+/// assert_eq!(container::decompress(&compressed).unwrap(), data);
+/// ```
+pub fn compress(data: &[u8], options: &Options) -> Result<Vec<u8>, &'static str> {
+    let mut out = vec![];
+    compress_into(data, options, None, &mut BitVec::new(), &mut out, None)?;
+    Ok(out)
+}
+
+/// Same as [compress()], coding with the preset rather than the one registered under its id,
+/// which may have been [replaced](presets::Registry::replace) since the preset was obtained.
+/// The preset of the options is ignored, the container records the id and fingerprint of
+/// this one.
+/// ## Errors
+/// Returns Err if the preset lacks a code for one of the bytes, or the blocks are stored.
+/// ## Examples
+/// ```
+/// # use rhuffman::container::{self, Options};
+/// # use rhuffman::huffman_tree::huffman_generator::HuffmanGenerator;
+/// # use rhuffman::presets;
+/// let mut gen = HuffmanGenerator::new();
+/// gen.add_occurences_from_iterator(&mut b"abracadabra".iter());
+/// let preset = presets::global().replace(7, gen.into_huffman_tree().unwrap());
+/// let compressed = container::compress_with_preset(b"abba", &preset, &Options::default()).unwrap();
+/// assert_eq!(container::decompress(&compressed).unwrap(), b"abba");
+/// ```
+pub fn compress_with_preset(
+    data: &[u8],
+    preset: &Arc<Preset<u8>>,
+    options: &Options,
+) -> Result<Vec<u8>, &'static str> {
+    let options = Options {
+        preset: Some(preset.id()),
+        ..*options
+    };
+    let mut out = vec![];
+    compress_into(
+        data,
+        &options,
+        Some(preset),
+        &mut BitVec::new(),
+        &mut out,
+        None,
+    )?;
+    Ok(out)
+}
+
+/// Same as [compress()], also returning how long each phase of the compression took.
+/// ## Errors
+/// Returns Err if the preset is not registered, or lacks a code for one of the bytes.
+/// ## Examples
+/// ```
+/// # use rhuffman::container::{self, Options};
+/// let (compressed, report) = container::compress_with_report(b"abracadabra", &Options::default()).unwrap();
+/// assert!(report.total() >= report.encode);
+/// # assert_eq!(container::decompress(&compressed).unwrap(), b"abracadabra");
+/// ```
+pub fn compress_with_report(
+    data: &[u8],
+    options: &Options,
+) -> Result<(Vec<u8>, CompressReport), &'static str> {
+    let mut out = vec![];
+    #[cfg(feature = "debug-alloc")]
+    let start = alloc_counter::allocations();
+    let report = compress_into(data, options, None, &mut BitVec::new(), &mut out, None)?;
+    #[cfg(feature = "debug-alloc")]
+    let report = CompressReport {
+        // Workers counted their own
+        allocations: report.allocations + alloc_counter::allocations() - start,
+        ..report
+    };
+    Ok((out, report))
+}
+
+/// Time spent in each phase of a compression, see [compress_with_report()]
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct CompressReport {
+    /// Applying the filter
+    pub filter: Duration,
+    /// Counting byte frequencies, including probing for a block size
+    pub freq_pass: Duration,
+    /// Building the trees and their encoders
+    pub tree_build: Duration,
+    /// Encoding the bytes into bits
+    pub encode: Duration,
+    /// Writing headers, trees and payloads to the output
+    pub io: Duration,
+    /// Heap allocations made by the whole compression, on every thread, see
+    /// [alloc_counter](crate::alloc_counter)
+    #[cfg(feature = "debug-alloc")]
+    pub allocations: u64,
+}
+
+impl CompressReport {
+    pub fn total(&self) -> Duration {
+        self.filter + self.freq_pass + self.tree_build + self.encode + self.io
+    }
+
+    /// Adds the time of another report, e.g. of another thread
+    fn add(&mut self, other: &CompressReport) {
+        self.filter += other.filter;
+        self.freq_pass += other.freq_pass;
+        self.tree_build += other.tree_build;
+        self.encode += other.encode;
+        self.io += other.io;
+        #[cfg(feature = "debug-alloc")]
+        {
+            self.allocations += other.allocations;
+        }
+    }
+}
+
+/// Adds the time `f` takes to `total`
+fn timed<R>(total: &mut Duration, f: impl FnOnce() -> R) -> R {
+    let start = Instant::now();
+    let result = f();
+    *total += start.elapsed();
+    result
+}
+
+/// Same as [compress()], calling `callback` with the progress of the job at most once per
+/// `interval`, after a block is done, and once more at the end.
+/// ## Errors
+/// Returns Err if the preset is not registered, or lacks a code for one of the bytes.
+pub fn compress_with_telemetry(
+    data: &[u8],
+    options: &Options,
+    interval: Duration,
+    callback: &mut dyn FnMut(&Telemetry),
+) -> Result<Vec<u8>, &'static str> {
+    let mut out = vec![];
+    let mut reporter = Reporter::new(interval, callback);
+    compress_into(
+        data,
+        options,
+        None,
+        &mut BitVec::new(),
+        &mut out,
+        Some(&mut reporter),
+    )?;
+    Ok(out)
+}
+
+/// Appends the container of the data to `out`, returning how long each phase took. Codes
+/// with the `held` preset if any, see [registered_preset()]. `bits` is scratch space for the
+/// payload.
+fn compress_into(
+    data: &[u8],
+    options: &Options,
+    held: Option<&Arc<Preset<u8>>>,
+    bits: &mut BitVec,
+    out: &mut Vec<u8>,
+    mut reporter: Option<&mut Reporter>,
+) -> Result<CompressReport, &'static str> {
+    if options.header == HeaderKind::Minimal {
+        let start = out.len();
+        let report = minimal::compress_into(data, options, held, bits, out)?;
+        if let Some(reporter) = reporter {
+            reporter.finish(data.len() as u64, (out.len() - start) as u64);
+        }
+        return Ok(report);
+    }
+    let mut report = CompressReport::default();
+    let input_len = data.len() as u64;
+    let start = out.len();
+    let digest = options.checksum.digest(data);
+    let filtered;
+    let data = match options.filter {
+        Filter::None => data,
+        filter => {
+            filtered = timed(&mut report.filter, || filter.apply(data))?;
+            &filtered
+        }
+    };
+    let block_size = match (options.filter, options.block_size) {
+        // A block, and so a tree, per plane
+        (Filter::NibblePlanes, BlockSize::Auto) => data.len() / 2,
+        (_, block_size) => timed(&mut report.freq_pass, || block_size.resolve(data)),
+    };
+    let blocks: Vec<&[u8]> = if block_size == 0 || data.len() <= block_size {
+        vec![data]
+    } else {
+        data.chunks(block_size).collect()
+    };
+
+    let mut aligner = timed(&mut report.io, || {
+        write_header(options, &digest, block_size, blocks.len(), out)
+    });
+    let coder = registered_coder(options, held)?;
+    let (layout, checksum) = (options.layout(), options.block_checksum());
+    let encode = |block: &[u8], bits: &mut BitVec, out: &mut Vec<u8>, report: &mut _| {
+        if options.stored {
+            write_block(
+                block,
+                &Codebook::Stored,
+                layout,
+                checksum,
+                bits,
+                out,
+                report,
+            )
+        } else {
+            encode_block(block, &coder, layout, checksum, bits, out, report)
+        }
+    };
+    let threads = options.threads.min(blocks.len());
+    let encoded = match threads {
+        0 | 1 => None,
+        threads => Some(encode_in_parallel(&blocks, threads, &encode, &mut report)?),
+    };
+    let mut done = 0;
+    for (index, block) in blocks.iter().enumerate() {
+        aligner.next(out);
+        match &encoded {
+            Some(encoded) => out.extend_from_slice(&encoded[index]),
+            None => encode(block, bits, out, &mut report)?,
+        }
+        done += block.len();
+        if let Some(reporter) = reporter.as_mut() {
+            // Progress through the filtered data, scaled back to the input
+            let bytes_in = input_len * done as u64 / (data.len() as u64).max(1);
+            reporter.update(bytes_in, (out.len() - start) as u64);
+        }
+    }
+    aligner.next(out);
+    if let Some(reporter) = reporter {
+        reporter.finish(input_len, (out.len() - start) as u64);
+    }
+    Ok(report)
+}
+
+/// Encodes the blocks on `threads` threads, each taking a run of consecutive blocks, and
+/// returns them in order. The time spent is added up over all threads.
+fn encode_in_parallel<F>(
+    blocks: &[&[u8]],
+    threads: usize,
+    encode: &F,
+    report: &mut CompressReport,
+) -> Result<Vec<Vec<u8>>, &'static str>
+where
+    F: Fn(&[u8], &mut BitVec, &mut Vec<u8>, &mut CompressReport) -> Result<(), &'static str> + Sync,
+{
+    let run = blocks.len().div_ceil(threads);
+    type Run = Result<(Vec<Vec<u8>>, CompressReport), &'static str>;
+    let runs: Vec<Run> = thread::scope(|scope| {
+        let workers: Vec<_> = blocks
+            .chunks(run)
+            .map(|blocks| {
+                scope.spawn(move || {
+                    #[cfg(feature = "debug-alloc")]
+                    let start = alloc_counter::allocations();
+                    let (mut bits, mut report) = (BitVec::new(), CompressReport::default());
+                    let mut encoded = Vec::with_capacity(blocks.len());
+                    for block in blocks {
+                        let mut out = vec![];
+                        encode(block, &mut bits, &mut out, &mut report)?;
+                        encoded.push(out);
+                    }
+                    #[cfg(feature = "debug-alloc")]
+                    {
+                        report.allocations = alloc_counter::allocations() - start;
+                    }
+                    Ok((encoded, report))
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .map(|worker| {
+                worker
+                    .join()
+                    .unwrap_or(Err("A compression worker panicked"))
+            })
+            .collect()
+    });
+    let mut encoded = Vec::with_capacity(blocks.len());
+    for run in runs {
+        let (blocks, run_report) = run?;
+        encoded.extend(blocks);
+        report.add(&run_report);
+    }
+    Ok(encoded)
+}
+
+/// The preset of the options: the `held` one if any, or else the one registered under the id
+/// of the options. Err if it is not registered or the blocks are stored.
+fn registered_preset(
+    options: &Options,
+    held: Option<&Arc<Preset<u8>>>,
+) -> Result<Option<Arc<Preset<u8>>>, &'static str> {
+    match options.preset {
+        Some(_) if options.stored => Err("Stored blocks are not coded with a preset"),
+        Some(_) if held.is_some() => Ok(held.cloned()),
+        Some(id) => presets::global()
+            .get(id)
+            .map(Some)
+            .ok_or("The preset is not registered"),
+        None => Ok(None),
+    }
+}
+
+/// The coder of the options: the preset or backend they name, or else a tree trained on
+/// each block. Err if it is not registered, or combined with stored blocks or another coder.
+fn registered_coder(
+    options: &Options,
+    held: Option<&Arc<Preset<u8>>>,
+) -> Result<Coder, &'static str> {
+    let preset = registered_preset(options, held)?;
+    match (options.backend, preset) {
+        (Some(_), Some(_)) => Err("Blocks are not coded with both a preset and a backend"),
+        (Some(_), None) if options.stored => Err("Stored blocks are not coded with a backend"),
+        (Some(id), None) => entropy::global()
+            .get(id)
+            .map(|backend| Coder::Backend(id, backend))
+            .ok_or("The backend is not registered"),
+        (None, Some(preset)) => Ok(Coder::Preset(preset)),
+        (None, None) => Ok(Coder::Trained),
+    }
+}
+
+/// What blocks are coded with, see [registered_coder()]
+enum Coder {
+    /// A tree trained on each block
+    Trained,
+    Preset(Arc<Preset<u8>>),
+    Backend(BackendId, Arc<dyn Backend>),
+}
+
+/// Appends the block to `out`, encoded by the coder
+fn encode_block(
+    block: &[u8],
+    coder: &Coder,
+    layout: Layout,
+    checksum: Checksum,
+    bits: &mut BitVec,
+    out: &mut Vec<u8>,
+    report: &mut CompressReport,
+) -> Result<(), &'static str> {
+    match coder {
+        Coder::Preset(preset) => {
+            let codebook = &Codebook::Preset(preset);
+            return write_block(block, codebook, layout, checksum, bits, out, report);
+        }
+        // Backends train on nothing for an empty block
+        Coder::Backend(..) if block.is_empty() => {
+            return write_block(block, &Codebook::Empty, layout, checksum, bits, out, report);
+        }
+        Coder::Backend(id, backend) => {
+            let codebook = &Codebook::Backend(*id, backend.as_ref());
+            return write_block(block, codebook, layout, checksum, bits, out, report);
+        }
+        Coder::Trained => {}
+    }
+    let counts = timed(&mut report.freq_pass, || count(&[block]));
+    let trained = timed(&mut report.tree_build, || {
+        build(counts).map(|tree| {
+            let encoder = HuffmanEncoder::from_tree(&tree);
+            (tree, encoder)
+        })
+    });
+    match trained {
+        Some((tree, encoder)) => {
+            let codebook = Codebook::Embedded(&tree, &encoder);
+            write_block(block, &codebook, layout, checksum, bits, out, report)
+        }
+        None => write_block(block, &Codebook::Empty, layout, checksum, bits, out, report),
+    }
+}
+
+/// The tree a block is encoded with
+enum Codebook<'a> {
+    Preset(&'a Preset<u8>),
+    Embedded(&'a HuffmanTree<u8>, &'a HuffmanEncoder<u8>),
+    /// Only valid for empty data
+    Empty,
+    /// Every byte is its own code
+    Stored,
+    /// The backend trains on the block and records its model
+    Backend(BackendId, &'a dyn Backend),
+}
+
+/// The canonical tree trained on every buffer, None if they are all empty
+fn train(buffers: &[&[u8]]) -> Option<HuffmanTree<u8>> {
+    build(count(buffers))
+}
+
+fn count(buffers: &[&[u8]]) -> HuffmanGenerator<u8> {
+    let mut gen = HuffmanGenerator::new();
+    for buffer in buffers {
+        gen.add_occurences_from_bytes(buffer);
+    }
+    gen
+}
+
+fn build(gen: HuffmanGenerator<u8>) -> Option<HuffmanTree<u8>> {
+    // Only code lengths are stored, so encode with the tree readers will rebuild from them
+    let tree = gen.into_huffman_tree()?;
+    canonical::from_lengths(&canonical::code_lengths(&tree))
+}
+
+/// Appends a single block container of already filtered data to `out`, with the digest of the
+/// original data. `bits` is scratch space for the payload.
+fn write_container(
+    data: &[u8],
+    digest: &[u8],
+    options: &Options,
+    codebook: &Codebook,
+    bits: &mut BitVec,
+    out: &mut Vec<u8>,
+) -> Result<(), &'static str> {
+    let mut aligner = write_header(options, digest, 0, 1, out);
+    let report = &mut CompressReport::default();
+    aligner.next(out);
+    let (layout, checksum) = (options.layout(), options.block_checksum());
+    write_block(data, codebook, layout, checksum, bits, out, report)?;
+    aligner.next(out);
+    Ok(())
+}
+
+/// Writes the header with the digest of the original data by the checksum of the options,
+/// leaving room for the offset map of aligned blocks. Call [Aligner::next()] before each
+/// block and once after the last one.
+fn write_header(
+    options: &Options,
+    digest: &[u8],
+    block_size: usize,
+    block_count: usize,
+    out: &mut Vec<u8>,
+) -> Aligner {
+    let start = out.len();
+    write_fields(options, block_size, block_count, 0, out);
+    out.extend_from_slice(digest);
+    let map = out.len();
+    if options.align > 0 {
+        out.resize(map + (block_count + 1) * 8, 0);
+    }
+    Aligner {
+        align: options.align,
+        start,
+        map,
+        index: 0,
+    }
+}
+
+/// Writes the header of a streamed container, which is never aligned. The block count and
+/// digest are written after the blocks by [write_stream_end()].
+fn write_streamed_header(options: &Options, block_size: usize, out: &mut Vec<u8>) {
+    write_fields(options, block_size, 0, FLAG_STREAMED, out);
+}
+
+/// Writes the end of the blocks of a streamed container
+fn write_stream_end(block_count: u64, digest: &[u8], out: &mut Vec<u8>) {
+    out.push(STREAM_END);
+    varint::write(block_count, out);
+    out.extend_from_slice(digest);
+}
+
+/// Writes the header up to the digest
+fn write_fields(
+    options: &Options,
+    block_size: usize,
+    block_count: usize,
+    flags: u8,
+    out: &mut Vec<u8>,
+) {
+    out.extend_from_slice(MAGIC);
+    out.push(VERSION);
+    out.push(filter_tag(options.filter));
+    out.push(content_tag(options.content));
+    out.push(packing::tag(options.byte_order));
+    out.push(packing::padding_tag(options.padding));
+    varint::write(block_size as u64, out);
+    varint::write(block_count as u64, out);
+    varint::write(options.align as u64, out);
+    match options.plugins {
+        Some(plugins) => {
+            out.push(flags | FLAG_PLUGINS);
+            out.extend_from_slice(&plugins.to_le_bytes());
+        }
+        None => out.push(flags),
+    }
+    out.push(match options.block_checksum() {
+        Checksum::None => checksum::tag(options.checksum),
+        _ => checksum::tag(options.checksum) | checksum::BLOCKS,
+    });
+}
+
+/// Pads aligned blocks and fills the offset map as they are written
+struct Aligner {
+    align: usize,
+    /// Where the container starts in the output
+    start: usize,
+    /// Where the offset map starts in the output
+    map: usize,
+    /// Index of the next block, or of the end once every block is written
+    index: usize,
+}
+
+impl Aligner {
+    /// Pads the output to where the next block, or the end of the container, starts and
+    /// records its offset. Does nothing when blocks are not aligned.
+    fn next(&mut self, out: &mut Vec<u8>) {
+        if self.align == 0 {
+            return;
+        }
+        let offset = (out.len() - self.start).next_multiple_of(self.align);
+        out.resize(self.start + offset, 0);
+        let entry = self.map + self.index * 8;
+        out[entry..entry + 8].copy_from_slice(&(offset as u64).to_le_bytes());
+        self.index += 1;
+    }
+}
+
+/// Appends the block to `out` with its digest by the checksum, adding the time spent encoding
+/// and writing to the report
+fn write_block(
+    data: &[u8],
+    codebook: &Codebook,
+    layout: Layout,
+    checksum: Checksum,
+    bits: &mut BitVec,
+    out: &mut Vec<u8>,
+    report: &mut CompressReport,
+) -> Result<(), &'static str> {
+    let start = Instant::now();
+    let mut encoding = Duration::default();
+    bits.truncate(0);
+    match codebook {
+        Codebook::Preset(preset) => {
+            out.push(TREE_PRESET);
+            varint::write(u64::from(preset.id()), out);
+            out.extend_from_slice(&preset.fingerprint().to_le_bytes());
+            timed(&mut encoding, || {
+                preset.encoder().encode_bytes_append(data, bits)
+            })
+            .map_err(|_| "The data contains a byte the preset has no code for")?;
+        }
+        Codebook::Embedded(tree, encoder) => {
+            out.push(TREE_LENGTHS);
+            tree::write_lengths(tree, out);
+            timed(&mut encoding, || encoder.encode_bytes_append(data, bits))
+                .map_err(|_| "The data contains a byte the tree has no code for")?;
+        }
+        Codebook::Stored => {
+            out.push(TREE_STORED);
+            timed(&mut encoding, || bits.append(&mut BitVec::from_bytes(data)));
+        }
+        Codebook::Backend(id, backend) => {
+            out.push(TREE_BACKEND);
+            varint::write(u64::from(*id), out);
+            let mut model = vec![];
+            timed(&mut encoding, || {
+                backend.encode_block(data, &mut model, bits)
+            })?;
+            varint::write(model.len() as u64, out);
+            out.extend_from_slice(&model);
+        }
+        Codebook::Empty if data.is_empty() => out.push(TREE_NONE),
+        Codebook::Empty => return Err("Missing tree"),
+    }
+
+    varint::write(data.len() as u64, out);
+    varint::write(bits.len() as u64, out);
+    out.extend_from_slice(&checksum.digest(data));
+    packing::pack_into(bits, layout, out);
+    report.encode += encoding;
+    report.io += start.elapsed() - encoding;
+    Ok(())
+}
+
+/// Decompresses data produced by [compress()], or by earlier versions of rhuff-compress if the
+/// `legacy-format` feature is enabled.
+/// ## Errors
+/// Returns Err if the data is not in a known format, is corrupted, or references a preset
+/// that is not registered in the [global registry](presets::global).
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, &'static str> {
+    decompress_with_options(data, &DecodeOptions::default())
+}
+
+/// How [decompress_with_options()] should treat data that decodes fine but was not written
+/// exactly as [compress()] writes it
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct DecodeOptions {
+    /// Reject payloads whose padding does not match the fill recorded in the header, zeros
+    /// before version 4, and bytes trailing the container. Those are ignored otherwise, so
+    /// that truncation or corruption at the end of a file may go unnoticed.
+    pub strict: bool,
+    /// Number of threads decoding the blocks of a container at once, 0 or 1 to decode them
+    /// on the calling thread. The output is the same whatever the number of threads.
+    pub threads: usize,
+    /// Fingerprint of the stages the caller runs on the output, which must be the
+    /// [Options::plugins] the container was written with
+    pub plugins: Option<u64>,
+    /// Longest code a block may decode, in bits, see
+    /// [decode_with_stall_limit()](HuffmanDecoder::decode_with_stall_limit). None for no
+    /// limit.
+    pub stall_bits: Option<usize>,
+    /// Most bytes a container may decode to, None for no limit. Checked against the symbol
+    /// counts the blocks record before anything is allocated for them, and as the filter is
+    /// reversed, so that a small file can not claim gigabytes of output.
+    pub max_output: Option<u64>,
+}
+
+/// Same as [decompress()], with the checks of the options.
+/// ## Errors
+/// Returns Err if the data is not in a known format, is corrupted, or references a preset
+/// that is not registered in the [global registry](presets::global).
+/// ## Examples
+/// ```
+/// # use rhuffman::container::{self, DecodeOptions, Options};
+/// let mut compressed = container::compress(b"hello", &Options::default()).unwrap();
+/// compressed.push(0);
+/// let strict = DecodeOptions {
+///     strict: true,
+///     ..DecodeOptions::default()
+/// };
+/// assert!(container::decompress_with_options(&compressed, &strict).is_err());
+/// assert_eq!(container::decompress(&compressed).unwrap(), b"hello");
+/// ```
+pub fn decompress_with_options(
+    data: &[u8],
+    options: &DecodeOptions,
+) -> Result<Vec<u8>, &'static str> {
+    let mut out = vec![];
+    decompress_into(data, options, &mut BitVec::new(), &mut out, None)?;
+    Ok(out)
+}
+
+/// What a decompression took, see [decompress_with_stats()]
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[must_use]
+pub struct DecodeStats {
+    /// The whole decompression
+    pub time: Duration,
+    /// Heap allocations made by the whole decompression, see
+    /// [alloc_counter](crate::alloc_counter)
+    #[cfg(feature = "debug-alloc")]
+    pub allocations: u64,
+}
+
+/// Same as [decompress_with_options()], also returning what the decompression took.
+/// ## Errors
+/// Returns Err if the data is not in a known format, is corrupted, or references a preset
+/// that is not registered in the [global registry](presets::global).
+/// ## Examples
+/// ```
+/// # use rhuffman::container::{self, DecodeOptions, Options};
+/// let compressed = container::compress(b"abracadabra", &Options::default()).unwrap();
+/// let (data, stats) =
+///     container::decompress_with_stats(&compressed, &DecodeOptions::default()).unwrap();
+/// assert_eq!(data, b"abracadabra");
+/// # let _ = stats.time;
+/// ```
+pub fn decompress_with_stats(
+    data: &[u8],
+    options: &DecodeOptions,
+) -> Result<(Vec<u8>, DecodeStats), &'static str> {
+    let mut stats = DecodeStats::default();
+    let mut out = vec![];
+    #[cfg(feature = "debug-alloc")]
+    let start = alloc_counter::allocations();
+    timed(&mut stats.time, || {
+        decompress_into(data, options, &mut BitVec::new(), &mut out, None)
+    })?;
+    #[cfg(feature = "debug-alloc")]
+    {
+        stats.allocations = alloc_counter::allocations() - start;
+    }
+    Ok((out, stats))
+}
+
+/// Same as [decompress_with_options()], calling `callback` with the progress of the job at
+/// most once per `interval`, after a block is done, and once more at the end.
+/// ## Errors
+/// Returns Err if the data is not in a known format, is corrupted, or references a preset
+/// that is not registered in the [global registry](presets::global).
+pub fn decompress_with_telemetry(
+    data: &[u8],
+    options: &DecodeOptions,
+    interval: Duration,
+    callback: &mut dyn FnMut(&Telemetry),
+) -> Result<Vec<u8>, &'static str> {
+    let mut out = vec![];
+    let mut reporter = Reporter::new(interval, callback);
+    decompress_into(
+        data,
+        options,
+        &mut BitVec::new(),
+        &mut out,
+        Some(&mut reporter),
+    )?;
+    Ok(out)
+}
+
+/// Appends the decompressed data to `out`. `bits` is scratch space for the payload.
+fn decompress_into(
+    data: &[u8],
+    options: &DecodeOptions,
+    bits: &mut BitVec,
+    out: &mut Vec<u8>,
+    mut reporter: Option<&mut Reporter>,
+) -> Result<(), &'static str> {
+    let start = out.len();
+    match ContainerFormat::detect(data) {
+        Some(ContainerFormat::V1)
+        | Some(ContainerFormat::V2)
+        | Some(ContainerFormat::V3)
+        | Some(ContainerFormat::V4)
+        | Some(ContainerFormat::V5)
+        | Some(ContainerFormat::V6)
+        | Some(ContainerFormat::V7) => {
+            let Container {
+                filter,
+                checksum,
+                digest,
+                plugins,
+                blocks,
+                len,
+                ..
+            } = read_container(data)?;
+            check_plugins(plugins, options.plugins)?;
+            if options.strict && len != data.len() {
+                return Err("Trailing bytes after the container");
+            }
+            let symbols = blocks.iter().fold(0, |symbols: u64, block| {
+                symbols.saturating_add(block.header.symbol_count)
+            });
+            check_output(symbols, options)?;
+            let consumed: Vec<usize> = blocks
+                .iter()
+                .map(|block| {
+                    block.payload.as_ptr() as usize + block.payload.len() - data.as_ptr() as usize
+                })
+                .collect();
+            let mut progress = |consumed: usize, decoded: usize| {
+                if let Some(reporter) = reporter.as_mut() {
+                    reporter.update(consumed as u64, decoded as u64);
+                }
+            };
+            match options.threads.min(blocks.len()) {
+                0 | 1 => {
+                    for (block, consumed) in blocks.into_iter().zip(consumed) {
+                        decode_block(block, options, bits, out)?;
+                        progress(consumed, out.len() - start);
+                    }
+                }
+                threads => {
+                    // The bytes of each block, the header counted with the first one
+                    let spans = consumed.iter().scan(0, |end, &consumed| {
+                        Some(consumed - std::mem::replace(end, consumed))
+                    });
+                    let blocks = blocks.into_iter().zip(spans).collect();
+                    for block in decode_in_parallel(blocks, threads, options, &mut progress)? {
+                        out.extend_from_slice(&block);
+                    }
+                }
+            }
+            if filter != Filter::None {
+                let max_len = options.max_output.unwrap_or(u64::MAX);
+                let reversed = filter.reverse_within(&out[start..], max_len)?;
+                out.truncate(start);
+                out.extend(reversed);
+            }
+            if checksum.digest(&out[start..]) != digest {
+                return Err("Checksum mismatch");
+            }
+        }
+        Some(ContainerFormat::Minimal) => {
+            check_plugins(None, options.plugins)?;
+            minimal::decompress_into(data, options, bits, out)?
+        }
+        #[cfg(feature = "legacy-format")]
+        Some(ContainerFormat::Legacy) => {
+            check_plugins(None, options.plugins)?;
+            let max_len = options.max_output.unwrap_or(u64::MAX);
+            out.extend(legacy::decompress_within(data, max_len)?)
+        }
+        #[cfg(not(feature = "legacy-format"))]
+        Some(ContainerFormat::Legacy) => {
+            return Err("Legacy files can only be read with the legacy-format feature")
+        }
+        None => return Err("Not a compressed file"),
+    }
+    if let Some(reporter) = reporter {
+        reporter.finish(data.len() as u64, (out.len() - start) as u64);
+    }
+    Ok(())
+}
+
+/// Decodes the blocks on `threads` threads, each taking a run of consecutive blocks, and
+/// returns them in order, without reversing the filter. Each block comes with its length in
+/// the container, and `progress` is called with the bytes consumed and decoded so far as the
+/// blocks are done, in whatever order. The allocations of the threads are counted as those
+/// of the calling thread.
+fn decode_in_parallel(
+    blocks: Vec<(Block, usize)>,
+    threads: usize,
+    options: &DecodeOptions,
+    progress: &mut dyn FnMut(usize, usize),
+) -> Result<Vec<Vec<u8>>, &'static str> {
+    let count = blocks.len();
+    let run = count.div_ceil(threads);
+    let mut runs: Vec<Vec<(Block, usize)>> = Vec::with_capacity(threads);
+    let mut blocks = blocks.into_iter().peekable();
+    while blocks.peek().is_some() {
+        runs.push(blocks.by_ref().take(run).collect());
+    }
+    #[cfg(feature = "debug-alloc")]
+    let allocations = &std::sync::atomic::AtomicU64::new(0);
+    let (done, finished) = mpsc::channel();
+    let runs: Vec<Result<Vec<Vec<u8>>, &'static str>> = thread::scope(|scope| {
+        let workers: Vec<_> = runs
+            .into_iter()
+            .map(|blocks| {
+                let done = done.clone();
+                scope.spawn(move || {
+                    #[cfg(feature = "debug-alloc")]
+                    let start = alloc_counter::allocations();
+                    let mut bits = BitVec::new();
+                    let mut decoded = Vec::with_capacity(blocks.len());
+                    for (block, span) in blocks {
+                        let mut out = vec![];
+                        decode_block(block, options, &mut bits, &mut out)?;
+                        // The calling thread may have stopped listening after a panic
+                        let _ = done.send((span, out.len()));
+                        decoded.push(out);
+                    }
+                    #[cfg(feature = "debug-alloc")]
+                    allocations.fetch_add(
+                        alloc_counter::allocations() - start,
+                        std::sync::atomic::Ordering::Relaxed,
+                    );
+                    Ok(decoded)
+                })
+            })
+            .collect();
+        // Ends once every worker is done and dropped its sender
+        drop(done);
+        let (mut consumed, mut decoded) = (0, 0);
+        for (span, len) in finished {
+            consumed += span;
+            decoded += len;
+            progress(consumed, decoded);
+        }
+        workers
+            .into_iter()
+            .map(|worker| {
+                worker
+                    .join()
+                    .unwrap_or(Err("A decompression worker panicked"))
+            })
+            .collect()
+    });
+    #[cfg(feature = "debug-alloc")]
+    alloc_counter::add(allocations.load(std::sync::atomic::Ordering::Relaxed));
+    let mut decoded = Vec::with_capacity(count);
+    for run in runs {
+        decoded.extend(run?);
+    }
+    Ok(decoded)
+}
+
+/// Where decompressing a container fails, see [diagnose()]
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[must_use]
+pub struct Diagnosis {
+    /// The error [decompress()] returns
+    pub message: &'static str,
+    /// Index of the block that could not be read or decoded, None for the header or the
+    /// filter
+    pub block: Option<usize>,
+    /// Byte offset in the container of the failing block, or of the failing header field
+    pub offset: u64,
+}
+
+/// Finds where decompressing the data with the options fails, to tell which part of a
+/// damaged file is corrupted. Returns None if it decompresses fine. Decompresses the whole
+/// data, so only call it once [decompress_with_options()] has failed.
+pub fn diagnose(data: &[u8], options: &DecodeOptions) -> Option<Diagnosis> {
+    let located = |message, block, offset: usize| {
+        Some(Diagnosis {
+            message,
+            block,
+            offset: offset as u64,
+        })
+    };
+    match ContainerFormat::detect(data) {
+        Some(ContainerFormat::V1)
+        | Some(ContainerFormat::V2)
+        | Some(ContainerFormat::V3)
+        | Some(ContainerFormat::V4)
+        | Some(ContainerFormat::V5)
+        | Some(ContainerFormat::V6)
+        | Some(ContainerFormat::V7) => {}
+        Some(ContainerFormat::Minimal) | Some(ContainerFormat::Legacy) => {
+            return decompress_with_options(data, options)
+                .err()
+                .and_then(|message| located(message, None, 0))
+        }
+        None => return located("Not a compressed file", None, 0),
+    }
+
+    let mut pos = 0;
+    let frame = match read_header(data, &mut pos) {
+        Ok(frame) => frame,
+        Err(message) => return located(message, None, pos),
+    };
+    let (mut bits, mut out) = (BitVec::new(), vec![]);
+    for index in 0..frame.block_count as usize {
+        let start = pos;
+        let decoded = frame
+            .seek(index, &mut pos)
+            .and_then(|_| read_block(data, &mut pos, frame.layout, frame.block_checksum))
+            .and_then(|block| decode_block(block, options, &mut bits, &mut out));
+        if let Err(message) = decoded {
+            return located(message, Some(index), start);
+        }
+    }
+    if let Err(message) = frame.seek(frame.block_count as usize, &mut pos) {
+        return located(message, None, pos);
+    }
+    if options.strict && pos != data.len() {
+        return located("Trailing bytes after the container", None, pos);
+    }
+    match frame
+        .filter
+        .reverse_within(&out, options.max_output.unwrap_or(u64::MAX))
+    {
+        Ok(data) if frame.checksum.digest(&data) != frame.digest => {
+            located("Checksum mismatch", None, pos)
+        }
+        Ok(_) => None,
+        Err(message) => located(message, None, pos),
+    }
+}
+
+/// What [recover()] could decode of a damaged container
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+#[must_use]
+pub struct Recovered {
+    /// The data of every block that decoded, one after the other
+    pub data: Vec<u8>,
+    /// Index of every block that could not be read or decoded, or whose digest does not
+    /// match
+    pub damaged: Vec<usize>,
+}
+
+/// Decodes every block of the container that still can be, skipping damaged blocks instead
+/// of stopping at the first. Blocks are only known to be intact when they have a digest, see
+/// [Options::block_checksums]; otherwise damage may go unnoticed until the checksum of the
+/// whole data, which is not checked here. Blocks past one that can not be read are lost too,
+/// unless the container is aligned and records where each block starts.
+/// ## Errors
+/// Returns Err if the header can not be read, or the container has a filter, which can only
+/// be reversed on the whole data.
+/// ## Examples
+/// ```
+/// # use rhuffman::container::{self, BlockSize, Checksum, Options};
+/// let options = Options {
+///     block_size: BlockSize::Fixed(100),
+///     checksum: Checksum::Crc32,
+///     block_checksums: true,
+///     ..Options::default()
+/// };
+/// let data: Vec<u8> = (0..300u32).map(|i| (i % 7) as u8).collect();
+/// let mut compressed = container::compress(&data, &options).unwrap();
+/// let last = compressed.len() - 1;
+/// compressed[last] ^= 0x80;
+///
+/// let recovered = container::recover(&compressed).unwrap();
+/// assert_eq!(recovered.damaged, vec![2]);
+/// assert_eq!(recovered.data, &data[..200]);
+/// ```
+pub fn recover(data: &[u8]) -> Result<Recovered, &'static str> {
+    match ContainerFormat::detect(data) {
+        Some(ContainerFormat::Legacy) => return Err("Legacy files have no blocks to recover"),
+        Some(ContainerFormat::Minimal) => {
+            return Err("Minimal containers have no blocks to recover")
+        }
+        None => return Err("Not a compressed file"),
+        Some(_) => {}
+    }
+    let mut pos = 0;
+    let frame = read_header(data, &mut pos)?;
+    check_plugins(frame.plugins, None)?;
+    if frame.filter != Filter::None {
+        return Err("The blocks of filtered containers can not be recovered on their own");
+    }
+    let mut recovered = Recovered::default();
+    let (mut bits, mut lost) = (BitVec::new(), false);
+    for index in 0..frame.block_count as usize {
+        if lost || frame.seek(index, &mut pos).is_err() {
+            recovered.damaged.push(index);
+            continue;
+        }
+        let block = match read_block(data, &mut pos, frame.layout, frame.block_checksum) {
+            Ok(block) => block,
+            Err(_) => {
+                // Where the next block starts is only known from the offset map
+                lost = frame.offsets.is_empty();
+                recovered.damaged.push(index);
+                continue;
+            }
+        };
+        let start = recovered.data.len();
+        let options = &DecodeOptions::default();
+        if decode_block(block, options, &mut bits, &mut recovered.data).is_err() {
+            recovered.data.truncate(start);
+            recovered.damaged.push(index);
+        }
+    }
+    Ok(recovered)
+}
+
+/// Where the tree of a block comes from
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[non_exhaustive]
+pub enum TreeSource {
+    /// The tree is stored in the header
+    Embedded,
+    /// The tree is a preset that must be registered to decode the payload
+    Preset { id: PresetId, fingerprint: u64 },
+    /// The block holds no data
+    None,
+    /// The bytes of the block are copied as they are, see [Options::stored]
+    Stored,
+    /// The block is coded by a backend that must be registered to decode the payload, see
+    /// [Options::backend]
+    Backend { id: BackendId },
+}
+
+/// The header of a container, as described in the [module documentation](self)
+#[derive(PartialEq, Eq, Debug, Clone)]
+#[must_use]
+pub struct Header {
+    pub version: u8,
+    pub filter: Filter,
+    pub content: Content,
+    pub byte_order: ByteOrder,
+    pub padding: Padding,
+    /// Size of the blocks the filtered data was split in, the last one possibly shorter. 0 if
+    /// it was not split, or for merged containers whose blocks vary in size.
+    pub block_size: u64,
+    /// Multiple of bytes every block starts at, 0 if they follow each other
+    pub align: u64,
+    pub checksum: Checksum,
+    /// The checksum of the original data, empty for [Checksum::None]
+    pub digest: Vec<u8>,
+    /// Whether every block also has a digest of its data, see [Options::block_checksums]
+    pub block_checksums: bool,
+    /// Offset of every block then of the end of the container when blocks are aligned, empty
+    /// otherwise
+    pub offsets: Vec<u64>,
+    /// Whether the container was written by a [HuffmanWriter], the block count and digest
+    /// following the blocks
+    pub streamed: bool,
+    /// See [Options::plugins]
+    pub plugins: Option<u64>,
+    pub blocks: Vec<BlockHeader>,
+}
+
+/// The header of one block of a container
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct BlockHeader {
+    pub tree: TreeSource,
+    /// Number of bytes the payload decodes to, before the filter is reversed
+    pub symbol_count: u64,
+    /// Number of meaningful bits in the payload
+    pub bit_len: u64,
+}
+
+/// Reads the header of a container without decoding its payload, e.g. to find out what is
+/// needed to decompress it.
+/// ## Errors
+/// Returns Err if the data is not a container in the versioned format or its header is
+/// corrupted. Legacy files have no header to read.
+pub fn inspect(data: &[u8]) -> Result<Header, &'static str> {
+    match ContainerFormat::detect(data) {
+        Some(ContainerFormat::V1)
+        | Some(ContainerFormat::V2)
+        | Some(ContainerFormat::V3)
+        | Some(ContainerFormat::V4)
+        | Some(ContainerFormat::V5)
+        | Some(ContainerFormat::V6)
+        | Some(ContainerFormat::V7) => {
+            let container = read_container(data)?;
+            Ok(Header {
+                version: data[MAGIC.len()],
+                filter: container.filter,
+                content: container.content,
+                byte_order: container.layout.byte_order,
+                padding: container.layout.padding,
+                block_size: container.block_size,
+                align: container.align,
+                checksum: container.checksum,
+                digest: container.digest,
+                block_checksums: container.block_checksum != Checksum::None,
+                offsets: container.offsets,
+                streamed: container.streamed,
+                plugins: container.plugins,
+                blocks: container.blocks.iter().map(|block| block.header).collect(),
+            })
+        }
+        Some(ContainerFormat::Minimal) => Err("Minimal containers have no header to inspect"),
+        Some(ContainerFormat::Legacy) => Err("Legacy files have no header"),
+        None => Err("Not a compressed file"),
+    }
+}
+
+/// A block as read from a container, with its embedded tree if any
+struct Block<'a> {
+    header: BlockHeader,
+    tree: Option<HuffmanTree<u8>>,
+    /// The model of the backend the block is coded by, empty for other blocks
+    model: &'a [u8],
+    layout: Layout,
+    checksum: Checksum,
+    /// The checksum of the decoded block, empty for [Checksum::None]
+    digest: &'a [u8],
+    payload: &'a [u8],
+}
+
+/// A versioned container as read from its header
+struct Container<'a> {
+    filter: Filter,
+    content: Content,
+    layout: Layout,
+    block_size: u64,
+    align: u64,
+    checksum: Checksum,
+    digest: Vec<u8>,
+    block_checksum: Checksum,
+    offsets: Vec<u64>,
+    streamed: bool,
+    plugins: Option<u64>,
+    blocks: Vec<Block<'a>>,
+    /// Number of bytes of the data the container takes
+    len: usize,
+}
+
+fn read_container(data: &[u8]) -> Result<Container<'_>, &'static str> {
+    let mut pos = 0;
+    let frame = read_header(data, &mut pos)?;
+    let mut blocks = vec![];
+    for index in 0..frame.block_count as usize {
+        frame.seek(index, &mut pos)?;
+        blocks.push(read_block(
+            data,
+            &mut pos,
+            frame.layout,
+            frame.block_checksum,
+        )?);
+    }
+    frame.seek(frame.block_count as usize, &mut pos)?;
+    Ok(Container {
+        filter: frame.filter,
+        content: frame.content,
+        layout: frame.layout,
+        block_size: frame.block_size,
+        align: frame.align,
+        checksum: frame.checksum,
+        digest: frame.digest,
+        block_checksum: frame.block_checksum,
+        offsets: frame.offsets,
+        streamed: frame.streamed,
+        plugins: frame.plugins,
+        blocks,
+        len: pos,
+    })
+}
+
+/// The header of a versioned container up to its first block
+struct Frame {
+    filter: Filter,
+    content: Content,
+    layout: Layout,
+    block_size: u64,
+    block_count: u64,
+    align: u64,
+    checksum: Checksum,
+    /// The checksum of the original data
+    digest: Vec<u8>,
+    /// The checksum of the data of every block
+    block_checksum: Checksum,
+    /// Offset of every block then of the end, empty unless blocks are aligned
+    offsets: Vec<u64>,
+    /// Whether the block count and digest follow the blocks, see [write_streamed_header()]
+    streamed: bool,
+    /// See [Options::plugins]
+    plugins: Option<u64>,
+    /// Where a streamed container ends, past the block count and digest
+    end: Option<usize>,
+}
+
+/// Err unless the plugins the caller runs on the output are the ones the data went through,
+/// see [Options::plugins]
+fn check_plugins(recorded: Option<u64>, given: Option<u64>) -> Result<(), &'static str> {
+    match (recorded, given) {
+        (Some(_), None) => Err("The data went through plugins, which must be given to decode it"),
+        (None, Some(_)) => Err("The data did not go through plugins"),
+        (Some(recorded), Some(given)) if recorded != given => {
+            Err("The data went through other plugins")
+        }
+        _ => Ok(()),
+    }
+}
+
+impl Frame {
+    /// Moves `pos` from the end of the previous block to the start of block `index`, or to the
+    /// end of the container past the last block
+    fn seek(&self, index: usize, pos: &mut usize) -> Result<(), &'static str> {
+        let offset = match (self.offsets.get(index), self.end) {
+            (Some(&offset), _) => usize::try_from(offset).map_err(|_| "Invalid block offset")?,
+            (None, Some(end)) if index as u64 == self.block_count => end,
+            _ => return Ok(()),
+        };
+        if offset < *pos {
+            return Err("Block overflows into the next one");
+        }
+        *pos = offset;
+        Ok(())
+    }
+}
+
+/// Reads the header of a versioned container up to its first block
+fn read_header(data: &[u8], pos: &mut usize) -> Result<Frame, &'static str> {
+    let mut frame = read_frame(data, pos)?;
+    if frame.streamed {
+        read_stream_end(data, *pos, &mut frame)?;
+    }
+    Ok(frame)
+}
+
+/// Same as [read_header()], leaving the block count and digest of a streamed container
+/// unknown, as they follow the blocks
+fn read_frame(data: &[u8], pos: &mut usize) -> Result<Frame, &'static str> {
+    let version = data[MAGIC.len()];
+    *pos = MAGIC.len() + 1;
+    let filter = filter_from_tag(*data.get(*pos).ok_or("Truncated header")?)?;
+    *pos += 1;
+    let mut content = Content::Raw;
+    if version >= VERSION_3 {
+        content = content_from_tag(*data.get(*pos).ok_or("Truncated header")?)?;
+        *pos += 1;
+    }
+    let mut layout = Layout::default();
+    if version >= VERSION_4 {
+        let tags = data.get(*pos..*pos + 2).ok_or("Truncated header")?;
+        layout.byte_order = packing::from_tag(tags[0])?;
+        layout.padding = packing::padding_from_tag(tags[1])?;
+        *pos += 2;
+    }
+    let mut frame = Frame {
+        filter,
+        content,
+        layout,
+        block_size: 0,
+        block_count: 1,
+        align: 0,
+        checksum: Checksum::None,
+        digest: vec![],
+        block_checksum: Checksum::None,
+        offsets: vec![],
+        streamed: false,
+        plugins: None,
+        end: None,
+    };
+    if version == VERSION_1 {
+        return Ok(frame);
+    }
+    frame.block_size = varint::read(data, pos)?;
+    frame.block_count = varint::read(data, pos)?;
+    if version < VERSION_5 {
+        return Ok(frame);
+    }
+    frame.align = varint::read(data, pos)?;
+    if version >= VERSION {
+        let flags = *data.get(*pos).ok_or("Truncated header")?;
+        if flags & !(FLAG_STREAMED | FLAG_PLUGINS) != 0 {
+            return Err("Unknown header flags");
+        }
+        frame.streamed = flags & FLAG_STREAMED != 0;
+        if frame.streamed && frame.align > 0 {
+            return Err("Streamed containers can not be aligned");
+        }
+        *pos += 1;
+        if flags & FLAG_PLUGINS != 0 {
+            let bytes = data.get(*pos..*pos + 8).ok_or("Truncated header")?;
+            frame.plugins = Some(u64_le(bytes));
+            *pos += 8;
+        }
+    }
+    if version >= VERSION_6 {
+        let tag = *data.get(*pos).ok_or("Truncated header")?;
+        frame.checksum = checksum::from_tag(tag & !checksum::BLOCKS)?;
+        if tag & checksum::BLOCKS != 0 {
+            frame.block_checksum = frame.checksum;
+        }
+        *pos += 1;
+        if frame.streamed {
+            return Ok(frame);
+        }
+        let len = frame.checksum.digest_len();
+        frame.digest = data
+            .get(*pos..*pos + len)
+            .ok_or("Truncated header")?
+            .to_vec();
+        *pos += len;
+    }
+    if frame.align > 0 {
+        let len = usize::try_from(frame.block_count)
+            .ok()
+            .and_then(|count| count.checked_add(1)?.checked_mul(8))
+            .ok_or("Truncated header")?;
+        let map = data
+            .get(*pos..pos.saturating_add(len))
+            .ok_or("Truncated header")?;
+        *pos += len;
+        frame.offsets = map.chunks_exact(8).map(u64_le).collect();
+    }
+    Ok(frame)
+}
+
+/// Fills the block count, digest and end of a streamed container by skipping over its blocks
+/// from `pos`, where the first one starts
+fn read_stream_end(data: &[u8], mut pos: usize, frame: &mut Frame) -> Result<(), &'static str> {
+    let mut block_count = 0u64;
+    while *data.get(pos).ok_or("Truncated stream")? != STREAM_END {
+        read_block(data, &mut pos, frame.layout, frame.block_checksum)?;
+        block_count += 1;
+    }
+    frame.digest = read_stream_digest(data, &mut pos, block_count, frame.checksum)?.to_vec();
+    frame.block_count = block_count;
+    frame.end = Some(pos);
+    Ok(())
+}
+
+/// Reads the end of a stream at `pos`, checking it counts `block_count` blocks, and returns
+/// the digest of the data
+fn read_stream_digest<'a>(
+    data: &'a [u8],
+    pos: &mut usize,
+    block_count: u64,
+    checksum: Checksum,
+) -> Result<&'a [u8], &'static str> {
+    if data.get(*pos) != Some(&STREAM_END) {
+        return Err("Truncated stream");
+    }
+    *pos += 1;
+    if varint::read(data, pos)? != block_count {
+        return Err("Block count does not match the end of the stream");
+    }
+    let len = checksum.digest_len();
+    let digest = data.get(*pos..*pos + len).ok_or("Truncated stream")?;
+    *pos += len;
+    Ok(digest)
+}
+
+fn read_block<'a>(
+    data: &'a [u8],
+    pos: &mut usize,
+    layout: Layout,
+    checksum: Checksum,
+) -> Result<Block<'a>, &'static str> {
+    let tree_kind = *data.get(*pos).ok_or("Truncated header")?;
+    *pos += 1;
+    let mut embedded = None;
+    let mut model: &[u8] = &[];
+    let tree = match tree_kind {
+        TREE_LENGTHS => {
+            embedded = Some(tree::read_lengths(data, pos)?);
+            TreeSource::Embedded
+        }
+        TREE_PREORDER => {
+            embedded = Some(tree::read_preorder(data, pos, 0)?);
+            TreeSource::Embedded
+        }
+        TREE_PRESET => {
+            let id = varint::read(data, pos)?;
+            let id = PresetId::try_from(id).map_err(|_| "Invalid preset id")?;
+            let fingerprint = data.get(*pos..*pos + 8).ok_or("Truncated header")?;
+            *pos += 8;
+            TreeSource::Preset {
+                id,
+                fingerprint: u64_le(fingerprint),
+            }
+        }
+        TREE_NONE => TreeSource::None,
+        TREE_STORED => TreeSource::Stored,
+        TREE_BACKEND => {
+            let id = varint::read(data, pos)?;
+            let id = BackendId::try_from(id).map_err(|_| "Invalid backend id")?;
+            let len = usize::try_from(varint::read(data, pos)?).map_err(|_| "Truncated header")?;
+            model = data
+                .get(*pos..pos.saturating_add(len))
+                .ok_or("Truncated header")?;
+            *pos += len;
+            TreeSource::Backend { id }
+        }
+        _ => return Err("Unknown tree kind"),
+    };
+
+    let symbol_count = varint::read(data, pos)?;
+    let bit_len = varint::read(data, pos)?;
+    let digest = data
+        .get(*pos..*pos + checksum.digest_len())
+        .ok_or("Truncated header")?;
+    *pos += digest.len();
+    let payload_len = usize::try_from(bit_len)
+        .map(|bit_len| layout.byte_order.packed_len(bit_len))
+        .map_err(|_| "Truncated payload")?;
+    let payload = data
+        .get(*pos..pos.saturating_add(payload_len))
+        .ok_or("Truncated payload")?;
+    *pos += payload_len;
+    Ok(Block {
+        header: BlockHeader {
+            tree,
+            symbol_count,
+            bit_len,
+        },
+        tree: embedded,
+        model,
+        layout,
+        checksum,
+        digest,
+        payload,
+    })
+}
+
+/// Err if decoding that many symbols would exceed the [output
+/// limit](DecodeOptions::max_output)
+fn check_output(symbols: u64, options: &DecodeOptions) -> Result<(), &'static str> {
+    match options.max_output {
+        Some(limit) if symbols > limit => Err("The data decodes to more than the output limit"),
+        _ => Ok(()),
+    }
+}
+
+/// Takes the decoded bytes off the [output limit](DecodeOptions::max_output), for readers
+/// decoding one block at a time
+fn spend_output(options: &mut DecodeOptions, len: usize) {
+    if let Some(limit) = &mut options.max_output {
+        *limit = limit.saturating_sub(len as u64);
+    }
+}
+
+/// Appends the decoded block to `out`, without reversing the filter
+fn decode_block(
+    block: Block,
+    options: &DecodeOptions,
+    bits: &mut BitVec,
+    out: &mut Vec<u8>,
+) -> Result<(), &'static str> {
+    let embedded = block.tree.map(HuffmanDecoder::new);
+    let preset;
+    let mut backend = None;
+    let decoder = match block.header.tree {
+        TreeSource::Embedded => embedded.as_ref(),
+        TreeSource::Preset { id, fingerprint } => {
+            preset = presets::global()
+                .get(id)
+                .ok_or("The file was compressed with a preset that is not registered")?;
+            if preset.fingerprint() != fingerprint {
+                return Err("The preset has changed since the file was compressed");
+            }
+            Some(preset.decoder())
+        }
+        TreeSource::Backend { id } => {
+            backend = Some(
+                entropy::global()
+                    .get(id)
+                    .ok_or("The file was compressed with a backend that is not registered")?,
+            );
+            None
+        }
+        TreeSource::None | TreeSource::Stored => None,
+    };
+
+    check_output(block.header.symbol_count, options)?;
+    let symbol_count = block.header.symbol_count as usize;
+    let bit_len = block.header.bit_len as usize;
+    packing::unpack_into(block.payload, bit_len, block.layout.byte_order, bits);
+    if options.strict {
+        packing::check_padding(block.payload, bit_len, block.layout)?;
+    }
+
+    let start = out.len();
+    match decoder {
+        None if block.header.tree == TreeSource::Stored => {
+            if bit_len != symbol_count.saturating_mul(8) {
+                return Err("Decoded symbol count does not match the header");
+            }
+            out.extend(bits.to_bytes());
+        }
+        None if symbol_count == 0 => {}
+        None => match &backend {
+            Some(backend) => backend.decode_block(block.model, bits, symbol_count, out)?,
+            None => return Err("Missing tree"),
+        },
+        Some(decoder) => match decoder.lone_symbol() {
+            // A lone symbol is coded with zero bits
+            Some(&symbol) => {
+                out.try_reserve(symbol_count)
+                    .map_err(|_| "Block is too large to decode")?;
+                out.resize(start + symbol_count, symbol)
+            }
+            None => match options.stall_bits {
+                Some(stall_bits) => decoder.decode_into_with_stall_limit(bits, stall_bits, out)?,
+                None => decoder.decode_into(bits, out)?,
+            },
+        },
+    };
+    if out.len() - start != symbol_count {
+        return Err("Decoded symbol count does not match the header");
+    }
+    if block.checksum.digest(&out[start..]) != block.digest {
+        return Err("Block checksum mismatch");
+    }
+    Ok(())
+}
+
+/// The `u64` stored little-endian in the 8 bytes
+fn u64_le(bytes: &[u8]) -> u64 {
+    let mut le = [0; 8];
+    le.copy_from_slice(bytes);
+    u64::from_le_bytes(le)
+}
+
+fn filter_tag(filter: Filter) -> u8 {
+    match filter {
+        Filter::None => 0,
+        Filter::Timeseries => 1,
+        Filter::Nibbles => 2,
+        Filter::NibblePlanes => 3,
+        Filter::Rle => 4,
+        Filter::Chars(Utf8Policy::Fail) => 5,
+        Filter::Chars(Utf8Policy::Replace) => 6,
+        Filter::Chars(Utf8Policy::Escape) => 7,
+    }
+}
+
+fn filter_from_tag(tag: u8) -> Result<Filter, &'static str> {
+    match tag {
+        0 => Ok(Filter::None),
+        1 => Ok(Filter::Timeseries),
+        2 => Ok(Filter::Nibbles),
+        3 => Ok(Filter::NibblePlanes),
+        4 => Ok(Filter::Rle),
+        5 => Ok(Filter::Chars(Utf8Policy::Fail)),
+        6 => Ok(Filter::Chars(Utf8Policy::Replace)),
+        7 => Ok(Filter::Chars(Utf8Policy::Escape)),
+        _ => Err("Unknown filter"),
+    }
+}
+
+fn content_tag(content: Content) -> u8 {
+    match content {
+        Content::Raw => 0,
+        Content::Tar => 1,
+    }
+}
+
+fn content_from_tag(tag: u8) -> Result<Content, &'static str> {
+    match tag {
+        0 => Ok(Content::Raw),
+        1 => Ok(Content::Tar),
+        _ => Err("Unknown content"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_edge_case_inputs() {
+        for data in &[&b""[..], b"a", b"aaaa", b"ab", &[0, 255, 0, 255, 7]] {
+            let compressed = compress(data, &Options::default()).unwrap();
+            assert_eq!(
+                ContainerFormat::detect(&compressed),
+                Some(ContainerFormat::V7)
+            );
+            assert_eq!(decompress(&compressed).unwrap(), *data);
+        }
+    }
+
+    #[test]
+    fn round_trips_with_filter() {
+        let data: Vec<u8> = (0..100u64).flat_map(|i| (i * 30).to_le_bytes()).collect();
+        let options = Options {
+            filter: Filter::Timeseries,
+            ..Options::default()
+        };
+        let compressed = compress(&data, &options).unwrap();
+        assert_eq!(decompress(&compressed).unwrap(), data);
+    }
+
+    #[test]
+    fn nibble_planes_get_a_tree_each() {
+        // BCD digits, high nibbles only ever 0 to 3
+        let data: Vec<u8> = (0..1000u32)
+            .map(|i| (((i % 4) << 4) | (i % 10)) as u8)
+            .collect();
+        for &filter in &[Filter::Nibbles, Filter::NibblePlanes] {
+            let options = Options {
+                filter,
+                ..Options::default()
+            };
+            let compressed = compress(&data, &options).unwrap();
+            assert_eq!(decompress(&compressed).unwrap(), data);
+        }
+        let planes = Options {
+            filter: Filter::NibblePlanes,
+            ..Options::default()
+        };
+        let header = inspect(&compress(&data, &planes).unwrap()).unwrap();
+        assert_eq!(header.block_size, data.len() as u64);
+        assert_eq!(header.blocks.len(), 2);
+    }
+
+    #[test]
+    fn round_trips_with_preset() {
+        let mut gen = HuffmanGenerator::new();
+        gen.add_occurences_from_iterator(&mut (0..=255u8).collect::<Vec<_>>().iter());
+        presets::global()
+            .register(0xc0_47a1, gen.into_huffman_tree().unwrap())
+            .unwrap();
+
+        let options = Options {
+            preset: Some(0xc0_47a1),
+            ..Options::default()
+        };
+        let compressed = compress(b"hello", &options).unwrap();
+        assert_eq!(decompress(&compressed).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn preorder_trees_are_still_read() {
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&[VERSION_1, 0, TREE_PREORDER]);
+        data.extend_from_slice(&[0, 1, b'a', 1, b'b']);
+        data.extend_from_slice(&[3, 3, 0b0100_0000]);
+        assert_eq!(decompress(&data).unwrap(), b"aba");
+    }
+
+    #[test]
+    fn version_2_is_still_read() {
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&[VERSION_2, 0, 0, 1, TREE_NONE, 0, 0]);
+        let header = inspect(&data).unwrap();
+        assert_eq!(header.content, Content::Raw);
+        assert_eq!(decompress(&data).unwrap(), b"");
+    }
+
+    #[test]
+    fn earlier_versions_are_still_read() {
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&[VERSION_3, 0, 1, 0, 1, TREE_NONE, 0, 0]);
+        let header = inspect(&data).unwrap();
+        assert_eq!(header.content, Content::Tar);
+        assert_eq!(header.byte_order, ByteOrder::MsbFirst);
+        assert_eq!(decompress(&data).unwrap(), b"");
+
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&[VERSION_4, 0, 0, 1, 0, 0, 1, TREE_NONE, 0, 0]);
+        let header = inspect(&data).unwrap();
+        assert_eq!(header.byte_order, ByteOrder::LsbFirst);
+        assert_eq!(header.align, 0);
+        assert_eq!(decompress(&data).unwrap(), b"");
+
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&[VERSION_5, 0, 0, 0, 0, 0, 1, 0, TREE_NONE, 0, 0]);
+        let header = inspect(&data).unwrap();
+        assert_eq!(header.checksum, Checksum::None);
+        assert_eq!(decompress(&data).unwrap(), b"");
+    }
+
+    #[test]
+    fn checksums_catch_corrupted_data() {
+        let data: Vec<u8> = (0..3000u32).map(|i| (i % 7 * i % 13) as u8).collect();
+        for checksum in [Checksum::Crc32, Checksum::XxHash64, Checksum::Blake3] {
+            let options = Options {
+                filter: Filter::Timeseries,
+                block_size: BlockSize::Whole,
+                checksum,
+                ..Options::default()
+            };
+            let mut compressed = compress(&data, &options).unwrap();
+            let header = inspect(&compressed).unwrap();
+            assert_eq!(header.checksum, checksum);
+            assert_eq!(header.digest, checksum.digest(&data));
+            assert_eq!(decompress(&compressed).unwrap(), data);
+
+            // Flip the last byte of the digest, the blocks still decode
+            let digest_end = MAGIC.len() + 9 + checksum.digest_len();
+            compressed[digest_end - 1] ^= 1;
+            assert_eq!(decompress(&compressed), Err("Checksum mismatch"));
+            let diagnosis = diagnose(&compressed, &DecodeOptions::default()).unwrap();
+            assert_eq!(diagnosis.message, "Checksum mismatch");
+            assert_eq!(diagnosis.block, None);
+        }
+    }
+
+    #[test]
+    fn threads_do_not_change_the_output() {
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i * i % 241) as u8 % 19).collect();
+        let options = Options {
+            block_size: BlockSize::Fixed(900),
+            align: 512,
+            checksum: Checksum::Crc32,
+            block_checksums: true,
+            ..Options::default()
+        };
+        let sequential = compress(&data, &options).unwrap();
+        for &threads in &[2, 3, 11, 64] {
+            let parallel = compress(&data, &Options { threads, ..options }).unwrap();
+            assert_eq!(parallel, sequential);
+        }
+        let stored = Options {
+            stored: true,
+            threads: 4,
+            ..options
+        };
+        assert_eq!(
+            decompress(&compress(&data, &stored).unwrap()).unwrap(),
+            data
+        );
+    }
+
+    #[test]
+    fn threads_decode_blocks_in_order() {
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i * i % 241) as u8 % 19).collect();
+        let options = Options {
+            filter: Filter::Nibbles,
+            block_size: BlockSize::Fixed(900),
+            checksum: Checksum::Crc32,
+            block_checksums: true,
+            ..Options::default()
+        };
+        let mut compressed = compress(&data, &options).unwrap();
+        for &threads in &[2, 3, 64] {
+            let options = DecodeOptions {
+                threads,
+                ..DecodeOptions::default()
+            };
+            assert_eq!(
+                decompress_with_options(&compressed, &options).unwrap(),
+                data
+            );
+        }
+        let single = compress(&data, &Options::default()).unwrap();
+        let options = DecodeOptions {
+            threads: 4,
+            ..DecodeOptions::default()
+        };
+        assert_eq!(decompress_with_options(&single, &options).unwrap(), data);
+
+        // The first damaged block fails the whole container, as when decoding in turn
+        let mut pos = 0;
+        let frame = read_header(&compressed, &mut pos).unwrap();
+        for _ in 0..14 {
+            read_block(&compressed, &mut pos, frame.layout, frame.block_checksum).unwrap();
+        }
+        let block = read_block(&compressed, &mut pos, frame.layout, frame.block_checksum).unwrap();
+        let payload = block.payload.as_ptr() as usize - compressed.as_ptr() as usize;
+        compressed[payload + 10] ^= 0x10;
+        assert_eq!(
+            decompress_with_options(&compressed, &options),
+            decompress(&compressed)
+        );
+        assert!(decompress(&compressed).is_err());
+    }
+
+    #[test]
+    fn block_checksums_pin_damage_to_blocks() {
+        let data: Vec<u8> = (0..4000u32).map(|i| (i % 7 * i % 13) as u8).collect();
+        let options = Options {
+            block_size: BlockSize::Fixed(1000),
+            checksum: Checksum::XxHash64,
+            block_checksums: true,
+            ..Options::default()
+        };
+        let mut compressed = compress(&data, &options).unwrap();
+        assert!(inspect(&compressed).unwrap().block_checksums);
+        assert_eq!(decompress(&compressed).unwrap(), data);
+
+        // Flip a payload bit of block 1, which still decodes to as many bytes
+        let mut pos = 0;
+        let frame = read_header(&compressed, &mut pos).unwrap();
+        read_block(&compressed, &mut pos, frame.layout, frame.block_checksum).unwrap();
+        let block = read_block(&compressed, &mut pos, frame.layout, frame.block_checksum).unwrap();
+        let payload = block.payload.as_ptr() as usize - compressed.as_ptr() as usize;
+        compressed[payload + 100] ^= 0x10;
+
+        let diagnosis = diagnose(&compressed, &DecodeOptions::default()).unwrap();
+        assert_eq!(diagnosis.block, Some(1));
+        let recovered = recover(&compressed).unwrap();
+        assert_eq!(recovered.damaged, vec![1]);
+        let mut intact = data[..1000].to_vec();
+        intact.extend_from_slice(&data[2000..]);
+        assert_eq!(recovered.data, intact);
+
+        // Without an offset map, the blocks after one that can not be read are lost
+        let mut truncated = compress(&data, &options).unwrap();
+        truncated.truncate(payload);
+        assert_eq!(recover(&truncated).unwrap().damaged, vec![1, 2, 3]);
+        let aligned = Options {
+            align: 1024,
+            ..options
+        };
+        let mut compressed = compress(&data, &aligned).unwrap();
+        let header = inspect(&compressed).unwrap();
+        compressed[header.offsets[2] as usize] = 0xff;
+        assert_eq!(recover(&compressed).unwrap().damaged, vec![2]);
+        assert!(recover(
+            &compress(
+                &data,
+                &Options {
+                    filter: Filter::Rle,
+                    ..options
+                }
+            )
+            .unwrap()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn round_trips_in_every_byte_order() {
+        let data = b"the byte order only changes how the payload is laid out";
+        for &byte_order in &[
+            ByteOrder::MsbFirst,
+            ByteOrder::LsbFirst,
+            ByteOrder::Words32Le,
+        ] {
+            let options = Options {
+                byte_order,
+                block_size: BlockSize::Fixed(20),
+                ..Options::default()
+            };
+            let compressed = compress(data, &options).unwrap();
+            assert_eq!(inspect(&compressed).unwrap().byte_order, byte_order);
+            assert_eq!(decompress(&compressed).unwrap(), &data[..]);
+
+            let stored = Options {
+                stored: true,
+                ..options
+            };
+            let compressed = compress(data, &stored).unwrap();
+            let strict = DecodeOptions {
+                strict: true,
+                ..DecodeOptions::default()
+            };
+            assert_eq!(
+                decompress_with_options(&compressed, &strict).unwrap(),
+                &data[..]
+            );
+        }
+        let stored_preset = Options {
+            stored: true,
+            preset: Some(0xdead),
+            ..Options::default()
+        };
+        assert!(compress(data, &stored_preset).is_err());
+    }
+
+    #[test]
+    fn padding_is_recorded_and_checked() {
+        let options = Options {
+            padding: Padding::Ones,
+            block_size: BlockSize::Whole,
+            ..Options::default()
+        };
+        let mut compressed = compress(b"aaab", &options).unwrap();
+        let header = inspect(&compressed).unwrap();
+        assert_eq!(header.padding, Padding::Ones);
+        assert_eq!(decompress(&compressed).unwrap(), b"aaab");
+
+        // The 4 bits of payload leave 4 bits of padding
+        assert_eq!(header.blocks[0].bit_len, 4);
+        assert_eq!(*compressed.last().unwrap() & 0xf, 0xf);
+        *compressed.last_mut().unwrap() ^= 1;
+        let strict = DecodeOptions {
+            strict: true,
+            ..DecodeOptions::default()
+        };
+        assert!(decompress_with_options(&compressed, &strict).is_err());
+        assert_eq!(decompress(&compressed).unwrap(), b"aaab");
+    }
+
+    #[test]
+    fn strict_mode_rejects_trailing_bytes() {
+        let strict = DecodeOptions {
+            strict: true,
+            ..DecodeOptions::default()
+        };
+        let mut compressed = compress(b"hello world", &Options::default()).unwrap();
+        assert_eq!(
+            decompress_with_options(&compressed, &strict).unwrap(),
+            b"hello world"
+        );
+        compressed.extend_from_slice(b"junk");
+        assert!(decompress_with_options(&compressed, &strict).is_err());
+        let diagnosis = diagnose(&compressed, &strict).unwrap();
+        assert_eq!(diagnosis.message, "Trailing bytes after the container");
+        assert_eq!(diagnosis.offset, compressed.len() as u64 - 4);
+        assert_eq!(decompress(&compressed).unwrap(), b"hello world");
+    }
+
+    #[test]
+    fn plugins_must_match_to_decode() {
+        let plugins = |plugins| DecodeOptions {
+            plugins,
+            ..DecodeOptions::default()
+        };
+        let options = Options {
+            block_size: BlockSize::Fixed(4),
+            plugins: Some(42),
+            ..Options::default()
+        };
+        let compressed = compress(b"hello world", &options).unwrap();
+        assert_eq!(inspect(&compressed).unwrap().plugins, Some(42));
+        assert_eq!(
+            decompress_with_options(&compressed, &plugins(Some(42))).unwrap(),
+            b"hello world"
+        );
+        assert!(decompress(&compressed).is_err());
+        assert!(decompress_with_options(&compressed, &plugins(Some(7))).is_err());
+        let mut out = vec![];
+        let mut reader = HuffmanReader::new(&compressed[..]);
+        assert!(std::io::Read::read_to_end(&mut reader, &mut out).is_err());
+        assert!(recover(&compressed).is_err());
+
+        let plain = compress(b"hello world", &Options::default()).unwrap();
+        assert!(decompress_with_options(&plain, &plugins(Some(42))).is_err());
+    }
+
+    #[test]
+    fn chars_record_their_utf8_policy() {
+        let text = b"caf\xe9 au lait, \xff\xfe and caf\xc3\xa9";
+        let chars = |policy| Options {
+            filter: Filter::Chars(policy),
+            ..Options::default()
+        };
+        assert!(compress(text, &chars(Utf8Policy::Fail)).is_err());
+
+        let escaped = compress(text, &chars(Utf8Policy::Escape)).unwrap();
+        assert_eq!(
+            inspect(&escaped).unwrap().filter,
+            Filter::Chars(Utf8Policy::Escape)
+        );
+        assert_eq!(decompress(&escaped).unwrap(), text);
+
+        let replaced = compress(text, &chars(Utf8Policy::Replace)).unwrap();
+        assert_eq!(
+            decompress(&replaced).unwrap(),
+            "caf\u{fffd} au lait, \u{fffd}\u{fffd} and café".as_bytes()
+        );
+        let valid = compress("café".as_bytes(), &chars(Utf8Policy::Fail)).unwrap();
+        assert_eq!(decompress(&valid).unwrap(), "café".as_bytes());
+    }
+
+    #[test]
+    fn output_limits_are_checked_before_decoding() {
+        // Blocks of a lone symbol, which code any length in no bits
+        let data = vec![b'a'; 10_000];
+        let options = Options {
+            block_size: BlockSize::Fixed(4_000),
+            ..Options::default()
+        };
+        let compressed = compress(&data, &options).unwrap();
+        for threads in &[0, 2] {
+            let limit = |max_output| DecodeOptions {
+                threads: *threads,
+                max_output: Some(max_output),
+                ..DecodeOptions::default()
+            };
+            assert_eq!(
+                decompress_with_options(&compressed, &limit(10_000)).unwrap(),
+                data
+            );
+            assert!(decompress_with_options(&compressed, &limit(9_999)).is_err());
+            // Every block fits, the whole output does not
+            let mut reader = HuffmanReader::with_options(&compressed[..], &limit(9_999));
+            assert!(std::io::Read::read_to_end(&mut reader, &mut vec![]).is_err());
+            let mut chunks = chunks::decode_chunks(&compressed, &limit(9_999), 1 << 16).unwrap();
+            let mut results = vec![];
+            while let Some(chunk) = chunks.next_chunk() {
+                results.push(chunk.map(<[u8]>::len));
+            }
+            assert_eq!(results[..2], [Ok(4_000), Ok(4_000)]);
+            assert!(results[2].is_err());
+        }
+    }
+
+    #[test]
+    fn output_limits_cover_the_filter() {
+        // A few bytes of runs decode to a megabyte
+        let data = vec![0; 1 << 20];
+        let options = Options {
+            filter: Filter::Rle,
+            ..Options::default()
+        };
+        let compressed = compress(&data, &options).unwrap();
+        assert!(compressed.len() < 100);
+        let limit = |max_output| DecodeOptions {
+            max_output: Some(max_output),
+            ..DecodeOptions::default()
+        };
+        assert_eq!(
+            decompress_with_options(&compressed, &limit(1 << 20)).unwrap(),
+            data
+        );
+        let over = limit(1 << 10);
+        assert!(decompress_with_options(&compressed, &over).is_err());
+        let diagnosis = diagnose(&compressed, &over).unwrap();
+        assert_eq!(
+            diagnosis.message,
+            "The runs decode to more than the output limit"
+        );
+        let mut reader = HuffmanReader::with_options(&compressed[..], &over);
+        assert!(std::io::Read::read_to_end(&mut reader, &mut vec![]).is_err());
+        let mut chunks = chunks::decode_chunks(&compressed, &over, 1 << 16).unwrap();
+        assert!(chunks.next_chunk().unwrap().is_err());
+    }
+
+    #[test]
+    fn inspect_reads_the_header() {
+        let options = Options {
+            filter: Filter::Timeseries,
+            ..Options::default()
+        };
+        let header = inspect(&compress(b"hello", &options).unwrap()).unwrap();
+        assert_eq!(header.version, VERSION);
+        assert_eq!(header.filter, Filter::Timeseries);
+        assert_eq!(header.blocks.len(), 1);
+        assert_eq!(header.blocks[0].tree, TreeSource::Embedded);
+
+        let header = inspect(&compress(b"", &Options::default()).unwrap()).unwrap();
+        assert_eq!(header.blocks[0].tree, TreeSource::None);
+        assert_eq!(header.blocks[0].symbol_count, 0);
+        assert!(inspect(b"garbage").is_err());
+    }
+
+    #[test]
+    fn round_trips_in_blocks() {
+        let data = b"aaaaaaaabbbbbbbbccccccccdddddddde";
+        for size in 1..=data.len() + 1 {
+            let options = Options {
+                block_size: BlockSize::Fixed(size),
+                ..Options::default()
+            };
+            let compressed = compress(data, &options).unwrap();
+            let header = inspect(&compressed).unwrap();
+            assert_eq!(header.block_size, size as u64);
+            assert_eq!(header.blocks.len(), data.len().div_ceil(size));
+            assert_eq!(decompress(&compressed).unwrap(), &data[..]);
+        }
+    }
+
+    #[test]
+    fn telemetry_reports_every_block_and_the_end() {
+        let data = vec![7u8; 1000];
+        let options = Options {
+            block_size: BlockSize::Fixed(100),
+            ..Options::default()
+        };
+        let mut snapshots = vec![];
+        let compressed =
+            compress_with_telemetry(&data, &options, Duration::from_secs(0), &mut |t| {
+                snapshots.push(*t)
+            })
+            .unwrap();
+        assert_eq!(snapshots.len(), 11);
+        assert_eq!(snapshots[0].bytes_in, 100);
+        let last = snapshots.last().unwrap();
+        assert!(last.done);
+        assert_eq!(last.bytes_in, 1000);
+        assert_eq!(last.bytes_out, compressed.len() as u64);
+
+        snapshots.clear();
+        let decompressed = decompress_with_telemetry(
+            &compressed,
+            &DecodeOptions::default(),
+            Duration::from_secs(0),
+            &mut |t| snapshots.push(*t),
+        )
+        .unwrap();
+        assert_eq!(decompressed, data);
+        assert_eq!(snapshots.len(), 11);
+        assert_eq!(snapshots.last().unwrap().bytes_out, 1000);
+
+        // Threads report each block as it is done, whatever the order
+        let serial = std::mem::take(&mut snapshots);
+        let threaded = DecodeOptions {
+            threads: 3,
+            ..DecodeOptions::default()
+        };
+        let decompressed =
+            decompress_with_telemetry(&compressed, &threaded, Duration::from_secs(0), &mut |t| {
+                snapshots.push(*t)
+            })
+            .unwrap();
+        assert_eq!(decompressed, data);
+        assert_eq!(snapshots.len(), 11);
+        for (block, snapshot) in snapshots[..10].iter().enumerate() {
+            assert!(!snapshot.done);
+            assert_eq!(snapshot.bytes_out, 100 * (block as u64 + 1));
+        }
+        assert_eq!(snapshots[9].bytes_in, serial[9].bytes_in);
+    }
+
+    #[test]
+    fn report_accounts_for_every_phase() {
+        let data: Vec<u8> = (0..100u64).flat_map(|i| (i * 30).to_le_bytes()).collect();
+        let options = Options {
+            filter: Filter::Timeseries,
+            ..Options::default()
+        };
+        let (compressed, report) = compress_with_report(&data, &options).unwrap();
+        assert_eq!(compressed, compress(&data, &options).unwrap());
+        assert!(report.filter > Duration::default());
+        assert!(report.encode > Duration::default());
+        assert!(report.io > Duration::default());
+        assert!(report.total() >= report.filter + report.encode);
+    }
+
+    #[test]
+    fn block_size_parses() {
+        assert_eq!("auto".parse(), Ok(BlockSize::Auto));
+        assert_eq!("whole".parse(), Ok(BlockSize::Whole));
+        assert_eq!("300".parse(), Ok(BlockSize::Fixed(300)));
+        assert_eq!("64K".parse(), Ok(BlockSize::Fixed(64 << 10)));
+        assert_eq!("2m".parse(), Ok(BlockSize::Fixed(2 << 20)));
+        assert!("0".parse::<BlockSize>().is_err());
+        assert!("K".parse::<BlockSize>().is_err());
+    }
+
+    #[test]
+    fn unregistered_preset_is_an_error() {
+        let options = Options {
+            preset: Some(0xdead),
+            ..Options::default()
+        };
+        assert!(compress(b"hello", &options).is_err());
+    }
+
+    /// A coder from outside the crate: every byte less the smallest one, on 8 bits
+    struct Offset;
+
+    impl entropy::EntropyCoder<u8> for Offset {
+        type Model = u8;
+
+        fn train<S: crate::huffman_tree::frequency_source::FrequencySource<u8> + ?Sized>(
+            &self,
+            source: &S,
+        ) -> Result<u8, &'static str> {
+            let tree = crate::huffman_tree::frequency_source::huffman_tree(source);
+            let mut symbols = vec![];
+            if let Some(tree) = tree {
+                tree.visit(&mut |_: &[bool], &symbol: &u8| symbols.push(symbol));
+            }
+            symbols
+                .into_iter()
+                .min()
+                .ok_or("No symbols to train the model on")
+        }
+
+        fn encode(&self, model: &u8, symbols: &[u8], out: &mut BitVec) -> Result<(), &'static str> {
+            for &symbol in symbols {
+                let offset = symbol.checked_sub(*model).ok_or("Below the model")?;
+                out.append(&mut BitVec::from_bytes(&[offset]));
+            }
+            Ok(())
+        }
+
+        fn decode(
+            &self,
+            model: &u8,
+            bits: &BitVec,
+            count: usize,
+            out: &mut Vec<u8>,
+        ) -> Result<(), &'static str> {
+            if bits.len() != count * 8 {
+                return Err("The bits do not code the number of symbols");
+            }
+            for offset in bits.to_bytes() {
+                out.push(offset.checked_add(*model).ok_or("Past the last byte")?);
+            }
+            Ok(())
+        }
+    }
+
+    impl entropy::ModelFormat<u8> for Offset {
+        fn write_model(&self, model: &u8, out: &mut Vec<u8>) {
+            out.push(*model);
+        }
+
+        fn read_model(&self, data: &[u8]) -> Result<u8, &'static str> {
+            match data {
+                [model] => Ok(*model),
+                _ => Err("Invalid model"),
+            }
+        }
+    }
+
+    #[test]
+    fn round_trips_with_backend() {
+        let _ = entropy::global().register(0xbac, Offset);
+        let data: Vec<u8> = (0..3000u32).map(|i| b'a' + (i * i % 26) as u8).collect();
+        let options = Options {
+            backend: Some(0xbac),
+            block_size: BlockSize::Fixed(1000),
+            checksum: Checksum::Crc32,
+            block_checksums: true,
+            ..Options::default()
+        };
+        let compressed = compress(&data, &options).unwrap();
+        let header = inspect(&compressed).unwrap();
+        assert_eq!(header.blocks.len(), 3);
+        assert!(header
+            .blocks
+            .iter()
+            .all(|block| block.tree == TreeSource::Backend { id: 0xbac }));
+        assert_eq!(decompress(&compressed).unwrap(), data);
+        assert_eq!(decompress(&compress(b"", &options).unwrap()).unwrap(), b"");
+
+        let threaded = Options {
+            threads: 3,
+            ..options
+        };
+        assert_eq!(compress(&data, &threaded).unwrap(), compressed);
+        let huffman = Options {
+            backend: Some(entropy::HUFFMAN),
+            ..options
+        };
+        let compressed = compress(&data, &huffman).unwrap();
+        assert_eq!(decompress(&compressed).unwrap(), data);
+        assert_eq!(
+            transcode(&compressed, &options).map(|t| decompress(&t)),
+            Ok(Ok(data))
+        );
+    }
+
+    #[test]
+    fn unregistered_backend_is_an_error() {
+        let options = Options {
+            backend: Some(0xdead),
+            ..Options::default()
+        };
+        assert_eq!(
+            compress(b"hello", &options),
+            Err("The backend is not registered")
+        );
+        for options in [
+            Options {
+                preset: Some(0xdead),
+                backend: Some(entropy::HUFFMAN),
+                ..Options::default()
+            },
+            Options {
+                stored: true,
+                backend: Some(entropy::HUFFMAN),
+                ..Options::default()
+            },
+        ] {
+            assert!(compress(b"hello", &options).is_err());
+        }
+
+        // A block naming a backend no reader registered
+        let huffman = Options {
+            backend: Some(entropy::HUFFMAN),
+            block_size: BlockSize::Whole,
+            ..Options::default()
+        };
+        let mut compressed = compress(b"hello", &huffman).unwrap();
+        // No byte of the header before it is a 6
+        let kind = compressed
+            .iter()
+            .position(|&byte| byte == TREE_BACKEND)
+            .unwrap();
+        assert_eq!(compressed[kind + 1], 0);
+        compressed[kind + 1] = 0x7f;
+        assert_eq!(
+            decompress(&compressed),
+            Err("The file was compressed with a backend that is not registered")
+        );
+    }
+
+    #[test]
+    fn corrupted_data_is_rejected() {
+        let compressed = compress(b"hello world", &Options::default()).unwrap();
+        assert!(decompress(&compressed[..compressed.len() - 1]).is_err());
+        assert!(decompress(&compressed[..10]).is_err());
+        assert!(decompress(b"garbage").is_err());
+    }
+
+    #[test]
+    fn diagnosis_points_at_the_corrupted_block() {
+        let data: Vec<u8> = (0..3000u32).map(|i| (i % 7 * i % 13) as u8).collect();
+        let options = Options {
+            block_size: BlockSize::Fixed(1000),
+            ..Options::default()
+        };
+        let mut compressed = compress(&data, &options).unwrap();
+        assert_eq!(diagnose(&compressed, &DecodeOptions::default()), None);
+
+        let mut pos = 0;
+        read_header(&compressed, &mut pos).unwrap();
+        read_block(&compressed, &mut pos, Layout::default(), Checksum::None).unwrap();
+        compressed[pos] = 0xff;
+        let diagnosis = diagnose(&compressed, &DecodeOptions::default()).unwrap();
+        assert_eq!(diagnosis.message, "Unknown tree kind");
+        assert_eq!(diagnosis.block, Some(1));
+        assert_eq!(diagnosis.offset, pos as u64);
+        assert_eq!(
+            diagnose(b"garbage", &DecodeOptions::default())
+                .unwrap()
+                .block,
+            None
+        );
+    }
+
+    /// Valid containers of every kind, then each of them truncated and with random bytes
+    /// overwritten, as a fuzzer would start from
+    fn corpus() -> Vec<Vec<u8>> {
+        let data: Vec<u8> = (0..2000u32).map(|i| (i % 7 * i % 13) as u8).collect();
+        let mut runs = vec![0u8; 500];
+        runs.extend_from_slice(&data[..500]);
+        let seeds: Vec<Vec<u8>> = [
+            (&data[..], Options::default()),
+            (&data[..3], Options::default()),
+            (b"aaaaaaaa", Options::default()),
+            (
+                &data,
+                Options {
+                    filter: Filter::Timeseries,
+                    block_size: BlockSize::Fixed(300),
+                    byte_order: ByteOrder::Words32Le,
+                    padding: Padding::Ones,
+                    ..Options::default()
+                },
+            ),
+            (
+                &runs,
+                Options {
+                    filter: Filter::Rle,
+                    align: 64,
+                    ..Options::default()
+                },
+            ),
+            (
+                &data,
+                Options {
+                    filter: Filter::NibblePlanes,
+                    ..Options::default()
+                },
+            ),
+            (
+                &data[..100],
+                Options {
+                    stored: true,
+                    byte_order: ByteOrder::LsbFirst,
+                    ..Options::default()
+                },
+            ),
+            (
+                &data[..500],
+                Options {
+                    checksum: Checksum::XxHash64,
+                    block_size: BlockSize::Fixed(200),
+                    block_checksums: true,
+                    ..Options::default()
+                },
+            ),
+        ]
+        .iter()
+        .map(|(data, options)| compress(data, options).unwrap())
+        .collect();
+
+        // xorshift, the corpus must be the same on every run
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut random = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        let mut corpus = seeds.clone();
+        for seed in &seeds {
+            corpus.extend(
+                (0..seed.len())
+                    .filter(|len| *len < 64 || len % 16 == 0)
+                    .map(|len| seed[..len].to_vec()),
+            );
+            for _ in 0..100 {
+                let mut mutated = seed.clone();
+                for _ in 0..1 + random() % 4 {
+                    let pos = random() as usize % mutated.len();
+                    mutated[pos] = random() as u8;
+                }
+                corpus.push(mutated);
+            }
+        }
+        corpus
+    }
+
+    #[test]
+    fn no_input_makes_reading_panic() {
+        for input in corpus() {
+            for (_, read) in crate::regression::READERS {
+                read(&input);
+            }
+        }
+    }
+}
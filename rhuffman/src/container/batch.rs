@@ -0,0 +1,203 @@
+//! Compressing many small independent buffers at once, for serving layers that compress
+//! thousands of responses per second. Every output is a regular container that
+//! [decompress()](super::decompress) reads on its own.
+use bit_vec::BitVec;
+use std::thread;
+
+use super::{train, write_container, Codebook, Options};
+use crate::huffman_tree::huffman_encoder::HuffmanEncoder;
+use crate::presets;
+
+/// Where the tree of each item of a batch comes from. Ignored when a preset is set in the
+/// [Options].
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum BatchTree {
+    /// Train a single tree on the whole batch and embed it in every item. Training is only
+    /// paid once, at the cost of slightly worse codes for items that differ from the rest.
+    #[default]
+    Shared,
+    /// Train a tree on each item, as [compress()](super::compress) would
+    PerItem,
+}
+
+/// How [compress_batch()] should encode the batch
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct BatchOptions {
+    /// Options applied to every item
+    pub options: Options,
+    pub tree: BatchTree,
+    /// Number of worker threads, 0 to use the available parallelism
+    pub threads: usize,
+}
+
+/// Compresses each buffer into its own container, spreading the work over several threads.
+/// Outputs are in the same order as the inputs.
+/// ## Errors
+/// Returns Err if the preset is not registered, or lacks a code for one of the bytes.
+/// ## Examples
+/// ```
+/// # use rhuffman::container::{self, batch::{self, BatchOptions}};
+/// let responses = [&b"{\"id\": 1}"[..], b"{\"id\": 2}", b"{\"id\": 3}"];
+/// let compressed = batch::compress_batch(&responses, &BatchOptions::default()).unwrap();
+/// for (response, compressed) in responses.iter().zip(&compressed) {
+///     assert_eq!(&container::decompress(compressed).unwrap(), response);
+/// }
+/// ```
+pub fn compress_batch(
+    buffers: &[&[u8]],
+    options: &BatchOptions,
+) -> Result<Vec<Vec<u8>>, &'static str> {
+    let filter = options.options.filter;
+    let filtered: Vec<Vec<u8>> = buffers
+        .iter()
+        .map(|data| filter.apply(data))
+        .collect::<Result<_, _>>()?;
+
+    let preset = match options.options.preset {
+        Some(id) => Some(
+            presets::global()
+                .get(id)
+                .ok_or("The preset is not registered")?,
+        ),
+        None => None,
+    };
+    let shared = match (&preset, options.tree) {
+        (None, BatchTree::Shared) => {
+            let all: Vec<&[u8]> = filtered.iter().map(Vec::as_slice).collect();
+            train(&all).map(|tree| {
+                let encoder = HuffmanEncoder::from_tree(&tree);
+                (tree, encoder)
+            })
+        }
+        _ => None,
+    };
+
+    let compress_one = |original: &[u8], data: &[u8], bits: &mut BitVec| {
+        let digest = options.options.checksum.digest(original);
+        let write = |codebook: &Codebook, bits: &mut BitVec, out: &mut Vec<u8>| {
+            write_container(data, &digest, &options.options, codebook, bits, out)
+        };
+        let mut out = vec![];
+        match (&preset, options.tree) {
+            (Some(preset), _) => write(&Codebook::Preset(preset), bits, &mut out)?,
+            (None, BatchTree::Shared) => {
+                let codebook = match &shared {
+                    Some((tree, encoder)) => Codebook::Embedded(tree, encoder),
+                    None => Codebook::Empty,
+                };
+                write(&codebook, bits, &mut out)?
+            }
+            (None, BatchTree::PerItem) => match train(&[data]) {
+                Some(tree) => {
+                    let encoder = HuffmanEncoder::from_tree(&tree);
+                    let codebook = Codebook::Embedded(&tree, &encoder);
+                    write(&codebook, bits, &mut out)?
+                }
+                None => write(&Codebook::Empty, bits, &mut out)?,
+            },
+        }
+        Ok(out)
+    };
+
+    let threads = match options.threads {
+        0 => thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    };
+    let chunk_size = filtered.len().div_ceil(threads).max(1);
+    let compress_one = &compress_one;
+    let chunks: Vec<Result<Vec<Vec<u8>>, &'static str>> = thread::scope(|scope| {
+        let workers: Vec<_> = buffers
+            .chunks(chunk_size)
+            .zip(filtered.chunks(chunk_size))
+            .map(|(originals, chunk)| {
+                scope.spawn(move || {
+                    // Each worker reuses one payload buffer for all of its items
+                    let mut bits = BitVec::new();
+                    originals
+                        .iter()
+                        .zip(chunk)
+                        .map(|(original, data)| compress_one(original, data, &mut bits))
+                        .collect()
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .map(|worker| {
+                worker
+                    .join()
+                    .unwrap_or(Err("A compression worker panicked"))
+            })
+            .collect()
+    });
+
+    let mut out = Vec::with_capacity(buffers.len());
+    for chunk in chunks {
+        out.extend(chunk?);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{decompress, Checksum};
+    use super::*;
+    use crate::filters::Filter;
+
+    fn round_trips(buffers: &[&[u8]], options: &BatchOptions) {
+        let compressed = compress_batch(buffers, options).unwrap();
+        assert_eq!(compressed.len(), buffers.len());
+        for (data, compressed) in buffers.iter().zip(&compressed) {
+            assert_eq!(&decompress(compressed).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn batches_round_trip_with_either_tree_choice() {
+        let buffers = [&b"hello"[..], b"", b"world", b"zzzz", b"hello world"];
+        for &tree in &[BatchTree::Shared, BatchTree::PerItem] {
+            for &threads in &[0, 1, 2, 16] {
+                round_trips(
+                    &buffers,
+                    &BatchOptions {
+                        tree,
+                        threads,
+                        ..BatchOptions::default()
+                    },
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn empty_batches_round_trip() {
+        round_trips(&[], &BatchOptions::default());
+        round_trips(&[b"", b""], &BatchOptions::default());
+    }
+
+    #[test]
+    fn filter_and_checksum_apply_to_every_item() {
+        let series: Vec<u8> = (0..50u64).flat_map(|i| (i * 10).to_le_bytes()).collect();
+        let options = BatchOptions {
+            options: Options {
+                filter: Filter::Timeseries,
+                checksum: Checksum::Crc32,
+                ..Options::default()
+            },
+            ..BatchOptions::default()
+        };
+        round_trips(&[&series, &series[8..], b"abc"], &options);
+    }
+
+    #[test]
+    fn unregistered_preset_is_an_error() {
+        let options = BatchOptions {
+            options: Options {
+                preset: Some(0xdead),
+                ..Options::default()
+            },
+            ..BatchOptions::default()
+        };
+        assert!(compress_batch(&[b"hello"], &options).is_err());
+    }
+}
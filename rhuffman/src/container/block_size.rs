@@ -0,0 +1,79 @@
+//! Choosing a block size for [BlockSize::Auto](super::BlockSize::Auto).
+//!
+//! Smaller blocks let each tree follow local changes in the statistics of the data, but each
+//! block pays for its own header. Every candidate size is scored on the start of the data by
+//! the entropy of each block plus an estimate of its header, which is much cheaper than
+//! actually compressing the sample with each size.
+
+/// Block sizes tried besides a single block
+const CANDIDATES: [usize; 4] = [16 << 10, 64 << 10, 256 << 10, 1 << 20];
+/// How much of the data is probed
+pub const PROBE_LEN: usize = 4 << 20;
+
+/// The cheapest block size for the data, 0 for a single block
+pub fn probe(data: &[u8]) -> usize {
+    let sample = &data[..data.len().min(PROBE_LEN)];
+    let mut best = (estimate(sample, sample.len().max(1)), 0);
+    for &size in CANDIDATES.iter().filter(|&&size| size < sample.len()) {
+        let cost = estimate(sample, size);
+        if cost < best.0 {
+            best = (cost, size);
+        }
+    }
+    best.1
+}
+
+/// Estimated compressed size in bytes of the sample split in blocks of `size` bytes
+fn estimate(sample: &[u8], size: usize) -> f64 {
+    sample
+        .chunks(size)
+        .map(|block| {
+            let mut counts = [0u64; 256];
+            for &byte in block {
+                counts[byte as usize] += 1;
+            }
+            let len = block.len() as f64;
+            let mut bits = 0.0;
+            let mut distinct = 0;
+            for &count in counts.iter().filter(|&&count| count > 0) {
+                bits += count as f64 * (len / count as f64).log2();
+                distinct += 1;
+            }
+            // About 5 bits per code length, plus the block's tree kind and counts
+            bits / 8.0 + (distinct * 5) as f64 / 8.0 + 6.0
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pseudo-random bytes drawn from `alphabet`
+    fn noise(len: usize, alphabet: &[u8], seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6_364_136_223_846_793_005)
+                    .wrapping_add(1_442_695_040_888_963_407);
+                alphabet[(state >> 33) as usize % alphabet.len()]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn uniform_data_stays_whole() {
+        assert_eq!(probe(&noise(1 << 20, b"0123456789abcdef", 1)), 0);
+        assert_eq!(probe(b"short"), 0);
+        assert_eq!(probe(b""), 0);
+    }
+
+    #[test]
+    fn changing_statistics_get_blocks() {
+        let mut data = noise(256 << 10, b"0123456789abcdef", 1);
+        data.extend(noise(256 << 10, b"ghijklmnopqrstuv", 2));
+        let size = probe(&data);
+        assert!(size > 0 && size <= 256 << 10);
+    }
+}
@@ -0,0 +1,266 @@
+//! Files carrying several named channels, e.g. `literals`, `lengths` and `tokens`, each
+//! coded with its own tree, for schemes mixing alphabets with different statistics.
+//!
+//! Data is pushed as segments tagged with their channel, and read back as the same segments
+//! in the same order, so the channels can be interleaved as they are produced.
+//!
+//! | Field          | Encoding                                                   |
+//! |----------------|------------------------------------------------------------|
+//! | magic          | `RHC`                                                      |
+//! | version        | `u8`, currently 1                                          |
+//! | channel count  | varint                                                     |
+//! | channels       | varint name length, UTF-8 name, tree as in containers      |
+//! | segment count  | varint                                                     |
+//! | segments       | varint channel index, then a block without its tree        |
+//!
+//! A channel's tree is trained on all of its segments. Segments are written like the blocks
+//! of a container: symbol count, bit length, then the payload, most significant bit first
+//! and padded with zeros.
+use bit_vec::BitVec;
+
+use super::packing::{self, ByteOrder, Layout};
+use super::{check_output, spend_output, train, tree, DecodeOptions, TREE_LENGTHS, TREE_NONE};
+use crate::huffman_tree::huffman_decoder::HuffmanDecoder;
+use crate::huffman_tree::huffman_encoder::HuffmanEncoder;
+use crate::varint;
+
+pub const MAGIC: &[u8; 3] = b"RHC";
+pub const VERSION: u8 = 1;
+
+/// Data of one channel, in the order it was pushed
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Segment {
+    /// Index of the channel in [Channels::names]
+    pub channel: usize,
+    pub data: Vec<u8>,
+}
+
+/// Collects segments of named channels, and writes them with a tree per channel
+#[derive(Default, Debug, Clone)]
+pub struct Channels {
+    /// In order of first use
+    pub names: Vec<String>,
+    pub segments: Vec<Segment>,
+}
+
+impl Channels {
+    pub fn new() -> Channels {
+        Channels::default()
+    }
+
+    /// Appends a segment to the channel, which is created on first use
+    pub fn push(&mut self, channel: &str, data: &[u8]) {
+        let channel = match self.names.iter().position(|name| name == channel) {
+            Some(index) => index,
+            None => {
+                self.names.push(channel.to_owned());
+                self.names.len() - 1
+            }
+        };
+        self.segments.push(Segment {
+            channel,
+            data: data.to_vec(),
+        });
+    }
+
+    /// The data of every segment of the channel, one after the other, None if there is no
+    /// such channel
+    pub fn channel(&self, name: &str) -> Option<Vec<u8>> {
+        let index = self.names.iter().position(|n| n == name)?;
+        Some(
+            self.segments
+                .iter()
+                .filter(|segment| segment.channel == index)
+                .flat_map(|segment| segment.data.iter().copied())
+                .collect(),
+        )
+    }
+
+    /// Writes the channels and their segments in the format described in the
+    /// [module](self) documentation
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.push(VERSION);
+        varint::write(self.names.len() as u64, &mut out);
+        let mut encoders = vec![];
+        for (index, name) in self.names.iter().enumerate() {
+            varint::write(name.len() as u64, &mut out);
+            out.extend_from_slice(name.as_bytes());
+            let buffers: Vec<&[u8]> = self
+                .segments
+                .iter()
+                .filter(|segment| segment.channel == index)
+                .map(|segment| &segment.data[..])
+                .collect();
+            match train(&buffers) {
+                Some(tree) => {
+                    out.push(TREE_LENGTHS);
+                    tree::write_lengths(&tree, &mut out);
+                    encoders.push(Some(HuffmanEncoder::from_tree(&tree)));
+                }
+                None => {
+                    out.push(TREE_NONE);
+                    encoders.push(None);
+                }
+            }
+        }
+
+        varint::write(self.segments.len() as u64, &mut out);
+        let mut bits = BitVec::new();
+        for segment in &self.segments {
+            bits.truncate(0);
+            if let Some(encoder) = &encoders[segment.channel] {
+                // Trained on this data, every byte has a code
+                #[allow(clippy::expect_used)]
+                encoder
+                    .encode_bytes_append(&segment.data, &mut bits)
+                    .expect("The channel tree lacks a code");
+            }
+            varint::write(segment.channel as u64, &mut out);
+            varint::write(segment.data.len() as u64, &mut out);
+            varint::write(bits.len() as u64, &mut out);
+            packing::pack_into(&bits, Layout::default(), &mut out);
+        }
+        out
+    }
+
+    /// Reads channels written by [to_bytes()](Channels::to_bytes)
+    /// ## Errors
+    /// Returns Err if the data is not a channels file or is corrupted.
+    pub fn from_bytes(data: &[u8]) -> Result<Channels, &'static str> {
+        Channels::from_bytes_with_options(data, &DecodeOptions::default())
+    }
+
+    /// Same as [from_bytes()](Channels::from_bytes), with the [output
+    /// limit](DecodeOptions::max_output) and [stall limit](DecodeOptions::stall_bits) of the
+    /// options, the others do not apply to channels
+    /// ## Errors
+    /// Returns Err if the data is not a channels file, is corrupted, or exceeds a limit.
+    pub fn from_bytes_with_options(
+        data: &[u8],
+        options: &DecodeOptions,
+    ) -> Result<Channels, &'static str> {
+        let mut options = *options;
+        if !data.starts_with(MAGIC) {
+            return Err("Not a channels file");
+        }
+        let mut pos = MAGIC.len();
+        if *data.get(pos).ok_or("Truncated header")? != VERSION {
+            return Err("Unknown channels version");
+        }
+        pos += 1;
+
+        let channel_count = varint::read(data, &mut pos)?;
+        let mut names = vec![];
+        let mut decoders = vec![];
+        for _ in 0..channel_count {
+            let len = varint::read(data, &mut pos)? as usize;
+            let name = data
+                .get(pos..pos.saturating_add(len))
+                .ok_or("Truncated header")?;
+            pos += len;
+            names.push(String::from_utf8(name.to_vec()).map_err(|_| "Invalid channel name")?);
+            let tree_kind = *data.get(pos).ok_or("Truncated header")?;
+            pos += 1;
+            decoders.push(match tree_kind {
+                TREE_LENGTHS => Some(HuffmanDecoder::new(tree::read_lengths(data, &mut pos)?)),
+                TREE_NONE => None,
+                _ => return Err("Unknown tree kind"),
+            });
+        }
+
+        let segment_count = varint::read(data, &mut pos)?;
+        let mut segments = vec![];
+        let mut bits = BitVec::new();
+        for _ in 0..segment_count {
+            let channel = varint::read(data, &mut pos)? as usize;
+            let decoder = decoders.get(channel).ok_or("Unknown channel")?;
+            let symbol_count = varint::read(data, &mut pos)?;
+            check_output(symbol_count, &options)?;
+            let symbol_count = symbol_count as usize;
+            let bit_len = varint::read(data, &mut pos)? as usize;
+            let payload = data
+                .get(pos..pos.saturating_add(bit_len.div_ceil(8)))
+                .ok_or("Truncated payload")?;
+            pos += payload.len();
+            packing::unpack_into(payload, bit_len, ByteOrder::MsbFirst, &mut bits);
+
+            let data = match decoder {
+                None if symbol_count == 0 => vec![],
+                None => return Err("Missing tree"),
+                Some(decoder) => match decoder.lone_symbol() {
+                    // A lone symbol is coded with zero bits
+                    Some(&symbol) => {
+                        let mut data = vec![];
+                        data.try_reserve(symbol_count)
+                            .map_err(|_| "Segment is too large to decode")?;
+                        data.resize(symbol_count, symbol);
+                        data
+                    }
+                    None => match options.stall_bits {
+                        Some(stall_bits) => decoder.decode_with_stall_limit(&bits, stall_bits)?,
+                        None => decoder.decode_unbounded(&bits)?,
+                    },
+                },
+            };
+            if data.len() != symbol_count {
+                return Err("Decoded symbol count does not match the header");
+            }
+            spend_output(&mut options, data.len());
+            segments.push(Segment { channel, data });
+        }
+        Ok(Channels { names, segments })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interleaved_channels_round_trip() {
+        let mut channels = Channels::new();
+        channels.push("literals", b"hello world");
+        channels.push("lengths", &[3, 3, 4]);
+        channels.push("literals", b"hello again");
+        channels.push("empty", b"");
+        channels.push("lengths", &[3; 50]);
+
+        let bytes = channels.to_bytes();
+        let read = Channels::from_bytes(&bytes).unwrap();
+        assert_eq!(read.names, ["literals", "lengths", "empty"]);
+        assert_eq!(read.segments, channels.segments);
+        assert_eq!(
+            read.channel("literals").unwrap(),
+            b"hello worldhello again".to_vec()
+        );
+        assert_eq!(read.channel("empty").unwrap(), Vec::<u8>::new());
+        assert_eq!(read.channel("tokens"), None);
+    }
+
+    #[test]
+    fn output_limits_span_the_segments() {
+        let mut channels = Channels::new();
+        channels.push("runs", &[0; 600]);
+        channels.push("runs", &[0; 400]);
+        let bytes = channels.to_bytes();
+        let limit = |max_output| DecodeOptions {
+            max_output: Some(max_output),
+            ..DecodeOptions::default()
+        };
+        let read = Channels::from_bytes_with_options(&bytes, &limit(1_000)).unwrap();
+        assert_eq!(read.segments, channels.segments);
+        assert!(Channels::from_bytes_with_options(&bytes, &limit(999)).is_err());
+        assert!(Channels::from_bytes_with_options(&bytes, &limit(500)).is_err());
+    }
+
+    #[test]
+    fn truncated_files_are_rejected() {
+        let mut channels = Channels::new();
+        channels.push("literals", b"some literal text");
+        let bytes = channels.to_bytes();
+        for len in 0..bytes.len() {
+            assert!(Channels::from_bytes(&bytes[..len]).is_err());
+        }
+    }
+}
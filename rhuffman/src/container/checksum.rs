@@ -0,0 +1,579 @@
+//! Checksums of the original data, recorded in the container header and checked once the
+//! data is decompressed. The algorithms trade speed for strength: [Checksum::Crc32] and
+//! [Checksum::XxHash64] catch accidental corruption of temporary files, [Checksum::Blake3] is
+//! a cryptographic hash for archives kept for a long time.
+//!
+//! Digests are stored in the byte order their reference implementations print them in.
+use std::fmt;
+use std::str::FromStr;
+
+/// The algorithm checksumming a container, see [Options::checksum](super::Options::checksum)
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[non_exhaustive]
+pub enum Checksum {
+    #[default]
+    None,
+    /// The CRC-32 of zip and gzip
+    Crc32,
+    /// xxHash64 with a seed of 0
+    XxHash64,
+    /// BLAKE3 with its default 32 byte output
+    Blake3,
+}
+
+impl Checksum {
+    /// Number of bytes the digest takes in the header
+    pub fn digest_len(self) -> usize {
+        match self {
+            Checksum::None => 0,
+            Checksum::Crc32 => 4,
+            Checksum::XxHash64 => 8,
+            Checksum::Blake3 => 32,
+        }
+    }
+
+    /// The digest of the data, empty for [Checksum::None]
+    /// ## Examples
+    /// ```
+    /// # use rhuffman::container::Checksum;
+    /// assert_eq!(Checksum::Crc32.digest(b"123456789"), [0xcb, 0xf4, 0x39, 0x26]);
+    /// assert!(Checksum::None.digest(b"123456789").is_empty());
+    /// ```
+    pub fn digest(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Checksum::None => vec![],
+            Checksum::Crc32 => crc32(data).to_be_bytes().to_vec(),
+            Checksum::XxHash64 => xxhash64(data).to_be_bytes().to_vec(),
+            Checksum::Blake3 => blake3(data).to_vec(),
+        }
+    }
+}
+
+impl FromStr for Checksum {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Checksum::None),
+            "crc32" => Ok(Checksum::Crc32),
+            "xxhash64" => Ok(Checksum::XxHash64),
+            "blake3" => Ok(Checksum::Blake3),
+            _ => Err(format!("Unknown checksum \"{}\"", s)),
+        }
+    }
+}
+
+impl fmt::Display for Checksum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Checksum::None => write!(f, "none"),
+            Checksum::Crc32 => write!(f, "crc32"),
+            Checksum::XxHash64 => write!(f, "xxhash64"),
+            Checksum::Blake3 => write!(f, "blake3"),
+        }
+    }
+}
+
+/// Set in the tag of containers whose blocks have a digest too
+pub(super) const BLOCKS: u8 = 0x80;
+
+pub(super) fn tag(checksum: Checksum) -> u8 {
+    match checksum {
+        Checksum::None => 0,
+        Checksum::Crc32 => 1,
+        Checksum::XxHash64 => 2,
+        Checksum::Blake3 => 3,
+    }
+}
+
+pub(super) fn from_tag(tag: u8) -> Result<Checksum, &'static str> {
+    match tag {
+        0 => Ok(Checksum::None),
+        1 => Ok(Checksum::Crc32),
+        2 => Ok(Checksum::XxHash64),
+        3 => Ok(Checksum::Blake3),
+        _ => Err("Unknown checksum"),
+    }
+}
+
+/// The CRC-32 of zip, bit by bit as it is only used on small data or for integrity
+pub(super) fn crc32(data: &[u8]) -> u32 {
+    crc32_update(0, data)
+}
+
+/// The CRC-32 of the data that came before, with a CRC of `crc`, followed by `data`
+fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+const XXH_PRIME_1: u64 = 0x9e37_79b1_85eb_ca87;
+const XXH_PRIME_2: u64 = 0xc2b2_ae3d_27d4_eb4f;
+const XXH_PRIME_3: u64 = 0x1656_67b1_9e37_79f9;
+const XXH_PRIME_4: u64 = 0x85eb_ca77_c2b2_ae63;
+const XXH_PRIME_5: u64 = 0x27d4_eb2f_1656_67c5;
+
+fn xxh_round(acc: u64, input: u64) -> u64 {
+    acc.wrapping_add(input.wrapping_mul(XXH_PRIME_2))
+        .rotate_left(31)
+        .wrapping_mul(XXH_PRIME_1)
+}
+
+fn xxh_merge(acc: u64, value: u64) -> u64 {
+    (acc ^ xxh_round(0, value))
+        .wrapping_mul(XXH_PRIME_1)
+        .wrapping_add(XXH_PRIME_4)
+}
+
+fn u64_le(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .rev()
+        .fold(0, |value, &byte| value << 8 | u64::from(byte))
+}
+
+const XXH_LANES: [u64; 4] = [
+    XXH_PRIME_1.wrapping_add(XXH_PRIME_2),
+    XXH_PRIME_2,
+    0,
+    XXH_PRIME_1.wrapping_neg(),
+];
+
+fn xxh_stripe(acc: &mut [u64; 4], stripe: &[u8]) {
+    for (lane, word) in acc.iter_mut().zip(stripe.chunks_exact(8)) {
+        *lane = xxh_round(*lane, u64_le(word));
+    }
+}
+
+fn xxh_converge(acc: &[u64; 4]) -> u64 {
+    let hash = acc[0]
+        .rotate_left(1)
+        .wrapping_add(acc[1].rotate_left(7))
+        .wrapping_add(acc[2].rotate_left(12))
+        .wrapping_add(acc[3].rotate_left(18));
+    acc.iter().fold(hash, |hash, &lane| xxh_merge(hash, lane))
+}
+
+/// xxHash64 with a seed of 0
+fn xxhash64(data: &[u8]) -> u64 {
+    let stripes = data.chunks_exact(32);
+    let rest = stripes.remainder();
+    let hash = if data.len() >= 32 {
+        let mut acc = XXH_LANES;
+        for stripe in stripes {
+            xxh_stripe(&mut acc, stripe);
+        }
+        xxh_converge(&acc)
+    } else {
+        XXH_PRIME_5
+    };
+    xxh_finish(hash.wrapping_add(data.len() as u64), rest)
+}
+
+/// Mixes in the bytes left after the last stripe, fewer than 32
+fn xxh_finish(mut hash: u64, mut rest: &[u8]) -> u64 {
+    while rest.len() >= 8 {
+        hash ^= xxh_round(0, u64_le(&rest[..8]));
+        hash = hash
+            .rotate_left(27)
+            .wrapping_mul(XXH_PRIME_1)
+            .wrapping_add(XXH_PRIME_4);
+        rest = &rest[8..];
+    }
+    if rest.len() >= 4 {
+        hash ^= u64_le(&rest[..4]).wrapping_mul(XXH_PRIME_1);
+        hash = hash
+            .rotate_left(23)
+            .wrapping_mul(XXH_PRIME_2)
+            .wrapping_add(XXH_PRIME_3);
+        rest = &rest[4..];
+    }
+    for &byte in rest {
+        hash ^= u64::from(byte).wrapping_mul(XXH_PRIME_5);
+        hash = hash.rotate_left(11).wrapping_mul(XXH_PRIME_1);
+    }
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(XXH_PRIME_2);
+    hash ^= hash >> 29;
+    hash = hash.wrapping_mul(XXH_PRIME_3);
+    hash ^ hash >> 32
+}
+
+const BLAKE3_IV: [u32; 8] = [
+    0x6a09_e667,
+    0xbb67_ae85,
+    0x3c6e_f372,
+    0xa54f_f53a,
+    0x510e_527f,
+    0x9b05_688c,
+    0x1f83_d9ab,
+    0x5be0_cd19,
+];
+const BLAKE3_PERMUTATION: [usize; 16] = [2, 6, 3, 10, 7, 0, 4, 13, 1, 11, 12, 5, 9, 14, 15, 8];
+const BLAKE3_BLOCK_LEN: usize = 64;
+const BLAKE3_CHUNK_LEN: usize = 1024;
+const CHUNK_START: u32 = 1;
+const CHUNK_END: u32 = 2;
+const PARENT: u32 = 4;
+const ROOT: u32 = 8;
+
+fn blake3_g(state: &mut [u32; 16], [a, b, c, d]: [usize; 4], x: u32, y: u32) {
+    state[a] = state[a].wrapping_add(state[b]).wrapping_add(x);
+    state[d] = (state[d] ^ state[a]).rotate_right(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_right(12);
+    state[a] = state[a].wrapping_add(state[b]).wrapping_add(y);
+    state[d] = (state[d] ^ state[a]).rotate_right(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_right(7);
+}
+
+fn blake3_compress(
+    cv: &[u32; 8],
+    block: &[u32; 16],
+    counter: u64,
+    len: u32,
+    flags: u32,
+) -> [u32; 16] {
+    let mut state = [0; 16];
+    state[..8].copy_from_slice(cv);
+    state[8..12].copy_from_slice(&BLAKE3_IV[..4]);
+    state[12] = counter as u32;
+    state[13] = (counter >> 32) as u32;
+    state[14] = len;
+    state[15] = flags;
+    let mut m = *block;
+    for round in 0..7 {
+        blake3_g(&mut state, [0, 4, 8, 12], m[0], m[1]);
+        blake3_g(&mut state, [1, 5, 9, 13], m[2], m[3]);
+        blake3_g(&mut state, [2, 6, 10, 14], m[4], m[5]);
+        blake3_g(&mut state, [3, 7, 11, 15], m[6], m[7]);
+        blake3_g(&mut state, [0, 5, 10, 15], m[8], m[9]);
+        blake3_g(&mut state, [1, 6, 11, 12], m[10], m[11]);
+        blake3_g(&mut state, [2, 7, 8, 13], m[12], m[13]);
+        blake3_g(&mut state, [3, 4, 9, 14], m[14], m[15]);
+        if round < 6 {
+            let previous = m;
+            for (word, &from) in m.iter_mut().zip(BLAKE3_PERMUTATION.iter()) {
+                *word = previous[from];
+            }
+        }
+    }
+    for i in 0..8 {
+        state[i] ^= state[i + 8];
+        state[i + 8] ^= cv[i];
+    }
+    state
+}
+
+/// The last compression of a chunk or parent, left undone until it is known whether it is
+/// the root
+struct Blake3Output {
+    cv: [u32; 8],
+    block: [u32; 16],
+    counter: u64,
+    len: u32,
+    flags: u32,
+}
+
+impl Blake3Output {
+    fn chaining_value(&self) -> [u32; 8] {
+        let state = blake3_compress(&self.cv, &self.block, self.counter, self.len, self.flags);
+        let mut cv = [0; 8];
+        cv.copy_from_slice(&state[..8]);
+        cv
+    }
+
+    fn root(&self) -> [u8; 32] {
+        let state = blake3_compress(&self.cv, &self.block, 0, self.len, self.flags | ROOT);
+        let mut hash = [0; 32];
+        for (bytes, word) in hash.chunks_exact_mut(4).zip(state.iter()) {
+            bytes.copy_from_slice(&word.to_le_bytes());
+        }
+        hash
+    }
+}
+
+fn blake3_words(bytes: &[u8]) -> [u32; 16] {
+    let mut words = [0; 16];
+    for (word, bytes) in words.iter_mut().zip(bytes.chunks(4)) {
+        *word = u64_le(bytes) as u32;
+    }
+    words
+}
+
+/// A chunk of at most [BLAKE3_CHUNK_LEN] bytes, the `counter`th of the input
+fn blake3_chunk(chunk: &[u8], counter: u64) -> Blake3Output {
+    let mut cv = BLAKE3_IV;
+    let mut blocks = chunk.chunks(BLAKE3_BLOCK_LEN).peekable();
+    let mut flags = CHUNK_START;
+    while let Some(block) = blocks.next() {
+        let words = blake3_words(block);
+        if blocks.peek().is_none() {
+            return Blake3Output {
+                cv,
+                block: words,
+                counter,
+                len: block.len() as u32,
+                flags: flags | CHUNK_END,
+            };
+        }
+        let state = blake3_compress(&cv, &words, counter, BLAKE3_BLOCK_LEN as u32, flags);
+        cv.copy_from_slice(&state[..8]);
+        flags = 0;
+    }
+    // Only the empty input has no block
+    Blake3Output {
+        cv,
+        block: [0; 16],
+        counter,
+        len: 0,
+        flags: CHUNK_START | CHUNK_END,
+    }
+}
+
+/// The subtree of the input starting at chunk `counter`, whose left subtree holds the largest
+/// power of 2 of chunks that leaves some input to the right one
+fn blake3_subtree(data: &[u8], counter: u64) -> Blake3Output {
+    if data.len() <= BLAKE3_CHUNK_LEN {
+        return blake3_chunk(data, counter);
+    }
+    let chunks = (data.len() - 1) / BLAKE3_CHUNK_LEN;
+    let left_chunks = 1 << (usize::BITS - 1 - chunks.leading_zeros());
+    let (left, right) = data.split_at(left_chunks * BLAKE3_CHUNK_LEN);
+    let left = blake3_subtree(left, counter).chaining_value();
+    let right = blake3_subtree(right, counter + left_chunks as u64).chaining_value();
+    blake3_parent(&left, &right)
+}
+
+fn blake3_parent(left: &[u32; 8], right: &[u32; 8]) -> Blake3Output {
+    let mut block = [0; 16];
+    block[..8].copy_from_slice(left);
+    block[8..].copy_from_slice(right);
+    Blake3Output {
+        cv: BLAKE3_IV,
+        block,
+        counter: 0,
+        len: BLAKE3_BLOCK_LEN as u32,
+        flags: PARENT,
+    }
+}
+
+fn blake3(data: &[u8]) -> [u8; 32] {
+    blake3_subtree(data, 0).root()
+}
+
+/// A digest computed over data fed a piece at a time, for writers that never hold all of it.
+/// Gives the same digest as [Checksum::digest()] of the pieces put together.
+pub(super) enum Digester {
+    None,
+    Crc32(u32),
+    XxHash64 {
+        acc: [u64; 4],
+        /// Bytes not yet making a whole stripe
+        pending: Vec<u8>,
+        len: u64,
+    },
+    Blake3 {
+        /// Bytes of the last chunk, only compressed once more data shows it is not the root
+        chunk: Vec<u8>,
+        /// Index of the last chunk
+        counter: u64,
+        /// Chaining values of the complete subtrees to the left, largest first
+        stack: Vec<[u32; 8]>,
+    },
+}
+
+impl Digester {
+    pub(super) fn new(checksum: Checksum) -> Digester {
+        match checksum {
+            Checksum::None => Digester::None,
+            Checksum::Crc32 => Digester::Crc32(0),
+            Checksum::XxHash64 => Digester::XxHash64 {
+                acc: XXH_LANES,
+                pending: Vec::with_capacity(32),
+                len: 0,
+            },
+            Checksum::Blake3 => Digester::Blake3 {
+                chunk: Vec::with_capacity(BLAKE3_CHUNK_LEN),
+                counter: 0,
+                stack: vec![],
+            },
+        }
+    }
+
+    pub(super) fn update(&mut self, mut data: &[u8]) {
+        match self {
+            Digester::None => {}
+            Digester::Crc32(crc) => *crc = crc32_update(*crc, data),
+            Digester::XxHash64 { acc, pending, len } => {
+                *len += data.len() as u64;
+                if !pending.is_empty() {
+                    let take = data.len().min(32 - pending.len());
+                    pending.extend_from_slice(&data[..take]);
+                    data = &data[take..];
+                    if pending.len() < 32 {
+                        return;
+                    }
+                    xxh_stripe(acc, pending);
+                    pending.clear();
+                }
+                let stripes = data.chunks_exact(32);
+                pending.extend_from_slice(stripes.remainder());
+                for stripe in stripes {
+                    xxh_stripe(acc, stripe);
+                }
+            }
+            Digester::Blake3 {
+                chunk,
+                counter,
+                stack,
+            } => {
+                while !data.is_empty() {
+                    if chunk.len() == BLAKE3_CHUNK_LEN {
+                        let mut cv = blake3_chunk(chunk, *counter).chaining_value();
+                        *counter += 1;
+                        // Every trailing zero of the chunk count completes a subtree
+                        let mut total = *counter;
+                        while total & 1 == 0 {
+                            if let Some(left) = stack.pop() {
+                                cv = blake3_parent(&left, &cv).chaining_value();
+                            }
+                            total >>= 1;
+                        }
+                        stack.push(cv);
+                        chunk.clear();
+                    }
+                    let take = data.len().min(BLAKE3_CHUNK_LEN - chunk.len());
+                    chunk.extend_from_slice(&data[..take]);
+                    data = &data[take..];
+                }
+            }
+        }
+    }
+
+    pub(super) fn finish(self) -> Vec<u8> {
+        match self {
+            Digester::None => vec![],
+            Digester::Crc32(crc) => crc.to_be_bytes().to_vec(),
+            Digester::XxHash64 { acc, pending, len } => {
+                let hash = match len {
+                    0..=31 => XXH_PRIME_5,
+                    _ => xxh_converge(&acc),
+                };
+                xxh_finish(hash.wrapping_add(len), &pending)
+                    .to_be_bytes()
+                    .to_vec()
+            }
+            Digester::Blake3 {
+                chunk,
+                counter,
+                stack,
+            } => {
+                let mut output = blake3_chunk(&chunk, counter);
+                for left in stack.iter().rev() {
+                    output = blake3_parent(left, &output.chaining_value());
+                }
+                output.root().to_vec()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    #[test]
+    fn digests_match_reference_vectors() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b""), 0);
+
+        assert_eq!(xxhash64(b""), 0xef46_db37_51d8_e999);
+        assert_eq!(xxhash64(b"a"), 0xd24e_c4f1_a98c_6e5b);
+        assert_eq!(xxhash64(b"abc"), 0x44bc_2cf5_ad77_0999);
+        let stripes: Vec<u8> = (0..1000).map(|i| (i * 31 % 251) as u8).collect();
+        assert_eq!(xxhash64(&stripes[..31]), 0x5836_f086_07db_da19);
+        assert_eq!(xxhash64(&stripes[..33]), 0xc480_db42_8c35_ab3e);
+        assert_eq!(xxhash64(&stripes[..100]), 0xcd8c_2446_7b83_82ad);
+        assert_eq!(xxhash64(&stripes), 0xd1be_e8e4_f060_3bbf);
+
+        assert_eq!(
+            hex(&blake3(b"")),
+            "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"
+        );
+        assert_eq!(
+            hex(&blake3(b"abc")),
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"
+        );
+        // The official vectors hash bytes counting up modulo 251
+        let input: Vec<u8> = (0..2048).map(|i| (i % 251) as u8).collect();
+        for (len, expected) in [
+            (
+                1,
+                "2d3adedff11b61f14c886e35afa036736dcd87a74d27b5c1510225d0f592e213",
+            ),
+            (
+                1024,
+                "42214739f095a406f3fc83deb889744ac00df831c10daa55189b5d121c855af7",
+            ),
+            (
+                1025,
+                "d00278ae47eb27b34faecf67b4fe263f82d5412916c1ffd97c8cb7fb814b8444",
+            ),
+            (
+                2048,
+                "e776b6028c7cd22a4d0ba182a8bf62205d2ef576467e838ed6f2529b85fba24a",
+            ),
+        ] {
+            assert_eq!(hex(&blake3(&input[..len])), expected, "length {}", len);
+        }
+    }
+
+    #[test]
+    fn digests_fed_in_pieces_match() {
+        let data: Vec<u8> = (0..5000u32).map(|i| (i * 7 % 253) as u8).collect();
+        for checksum in [Checksum::Crc32, Checksum::XxHash64, Checksum::Blake3] {
+            for &len in &[0, 1, 31, 32, 33, 1024, 1025, 3072, 5000] {
+                for &piece in &[1, 7, 32, 1000, 5000] {
+                    let mut digester = Digester::new(checksum);
+                    for data in data[..len].chunks(piece) {
+                        digester.update(data);
+                    }
+                    assert_eq!(
+                        digester.finish(),
+                        checksum.digest(&data[..len]),
+                        "{} of {} bytes in pieces of {}",
+                        checksum,
+                        len,
+                        piece
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn names_and_tags_round_trip() {
+        for checksum in [
+            Checksum::None,
+            Checksum::Crc32,
+            Checksum::XxHash64,
+            Checksum::Blake3,
+        ] {
+            assert_eq!(checksum.to_string().parse(), Ok(checksum));
+            assert_eq!(from_tag(tag(checksum)), Ok(checksum));
+            assert_eq!(checksum.digest(b"data").len(), checksum.digest_len());
+        }
+    }
+}
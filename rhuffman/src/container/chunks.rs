@@ -0,0 +1,217 @@
+//! Decompressing a container a piece at a time, for callers such as bindings that pull the
+//! output through a fixed-size buffer instead of holding all of it at once.
+use bit_vec::BitVec;
+
+use super::{
+    check_plugins, decompress_into, read_block, read_header, Checksum, ContainerFormat,
+    DecodeOptions, Filter, Frame,
+};
+
+/// The decompressed data of a container, handed out in chunks of at most the chunk size by
+/// [next_chunk()](DecodeChunks::next_chunk). Blocks are decoded one at a time into a buffer
+/// that is reused, so only one block is held in memory, and no chunk allocates.
+///
+/// This is not an [Iterator], as each chunk borrows that buffer until the next call.
+/// Containers with a filter or a checksum are decoded whole on the first call instead, since
+/// the filter is reversed and the digest checked on the whole data.
+/// ## Examples
+/// ```
+/// # use rhuffman::container::{self, BlockSize, DecodeOptions, Options};
+/// let options = Options {
+///     block_size: BlockSize::Fixed(100),
+///     ..Options::default()
+/// };
+/// let data: Vec<u8> = (0..250u32).map(|i| (i % 7) as u8).collect();
+/// let compressed = container::compress(&data, &options).unwrap();
+///
+/// let mut chunks = container::decode_chunks(&compressed, &DecodeOptions::default(), 64).unwrap();
+/// let mut out = vec![];
+/// while let Some(chunk) = chunks.next_chunk() {
+///     let chunk = chunk.unwrap();
+///     assert!(chunk.len() <= 64);
+///     out.extend_from_slice(chunk);
+/// }
+/// assert_eq!(out, data);
+/// ```
+pub struct DecodeChunks<'a> {
+    data: &'a [u8],
+    options: DecodeOptions,
+    chunk_size: usize,
+    state: State,
+    /// Where the next block is read from
+    pos: usize,
+    next_block: usize,
+    bits: BitVec,
+    buffer: Vec<u8>,
+    /// How much of the buffer was already handed out
+    consumed: usize,
+}
+
+enum State {
+    /// Decoding one block at a time
+    Blocks(Frame),
+    /// Decoding the whole container at once
+    Whole,
+    /// Everything was decoded, or decoding failed
+    Done,
+}
+
+/// Starts decompressing the data, see [DecodeChunks].
+/// ## Errors
+/// Returns Err if the chunk size is 0, or the data is not a compressed file or its header is
+/// corrupted. Other errors are returned by [next_chunk()](DecodeChunks::next_chunk).
+pub fn decode_chunks<'a>(
+    data: &'a [u8],
+    options: &DecodeOptions,
+    chunk_size: usize,
+) -> Result<DecodeChunks<'a>, &'static str> {
+    if chunk_size == 0 {
+        return Err("Chunks can not be empty");
+    }
+    let mut pos = 0;
+    let state = match ContainerFormat::detect(data) {
+        None => return Err("Not a compressed file"),
+        Some(ContainerFormat::Minimal) | Some(ContainerFormat::Legacy) => State::Whole,
+        Some(_) => {
+            let frame = read_header(data, &mut pos)?;
+            check_plugins(frame.plugins, options.plugins)?;
+            if frame.filter != Filter::None || frame.checksum != Checksum::None {
+                State::Whole
+            } else {
+                State::Blocks(frame)
+            }
+        }
+    };
+    Ok(DecodeChunks {
+        data,
+        options: *options,
+        chunk_size,
+        state,
+        pos,
+        next_block: 0,
+        bits: BitVec::new(),
+        buffer: vec![],
+        consumed: 0,
+    })
+}
+
+impl<'a> DecodeChunks<'a> {
+    /// Returns the next chunk of the decompressed data, None once all of it was returned.
+    /// Chunks are never empty. After an error, returns None.
+    pub fn next_chunk(&mut self) -> Option<Result<&[u8], &'static str>> {
+        while self.consumed == self.buffer.len() {
+            self.buffer.clear();
+            self.consumed = 0;
+            match self.fill() {
+                Ok(true) => {}
+                Ok(false) => return None,
+                Err(message) => {
+                    self.state = State::Done;
+                    return Some(Err(message));
+                }
+            }
+        }
+        let start = self.consumed;
+        self.consumed = self.buffer.len().min(start + self.chunk_size);
+        Some(Ok(&self.buffer[start..self.consumed]))
+    }
+
+    /// Decodes the next block into the buffer, returns false if there is none left
+    fn fill(&mut self) -> Result<bool, &'static str> {
+        let frame = match &self.state {
+            State::Blocks(frame) => frame,
+            State::Whole => {
+                self.state = State::Done;
+                let options = &self.options;
+                decompress_into(self.data, options, &mut self.bits, &mut self.buffer, None)?;
+                return Ok(true);
+            }
+            State::Done => return Ok(false),
+        };
+        if self.next_block == frame.block_count as usize {
+            frame.seek(self.next_block, &mut self.pos)?;
+            if self.options.strict && self.pos != self.data.len() {
+                return Err("Trailing bytes after the container");
+            }
+            self.state = State::Done;
+            return Ok(false);
+        }
+        frame.seek(self.next_block, &mut self.pos)?;
+        let block = read_block(self.data, &mut self.pos, frame.layout, frame.block_checksum)?;
+        super::decode_block(block, &self.options, &mut self.bits, &mut self.buffer)?;
+        super::spend_output(&mut self.options, self.buffer.len());
+        self.next_block += 1;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{compress, decompress, BlockSize, Options};
+    use super::*;
+
+    fn collect(data: &[u8], chunk_size: usize) -> Result<Vec<u8>, &'static str> {
+        let mut chunks = decode_chunks(data, &DecodeOptions::default(), chunk_size)?;
+        let mut out = vec![];
+        while let Some(chunk) = chunks.next_chunk() {
+            let chunk = chunk?;
+            assert!(!chunk.is_empty() && chunk.len() <= chunk_size);
+            out.extend_from_slice(chunk);
+        }
+        Ok(out)
+    }
+
+    #[test]
+    fn chunks_add_up_to_the_data() {
+        let data: Vec<u8> = (0..5_000u32).map(|i| (i * i % 251) as u8 % 29).collect();
+        let blocks = Options {
+            block_size: BlockSize::Fixed(700),
+            ..Options::default()
+        };
+        let cases = [
+            blocks,
+            Options {
+                filter: Filter::Timeseries,
+                ..blocks
+            },
+            Options {
+                checksum: Checksum::Crc32,
+                block_checksums: true,
+                ..blocks
+            },
+            Options {
+                align: 256,
+                ..blocks
+            },
+        ];
+        for options in &cases {
+            let compressed = compress(&data, options).unwrap();
+            for &chunk_size in &[1, 13, 700, 1 << 20] {
+                assert_eq!(collect(&compressed, chunk_size).unwrap(), data);
+            }
+        }
+        let empty = compress(&[], &Options::default()).unwrap();
+        assert_eq!(collect(&empty, 5).unwrap(), b"");
+        assert!(decode_chunks(&empty, &DecodeOptions::default(), 0).is_err());
+    }
+
+    #[test]
+    fn errors_end_the_chunks() {
+        let data: Vec<u8> = (0..1000u32).map(|i| (i % 7) as u8).collect();
+        let options = Options {
+            block_size: BlockSize::Fixed(100),
+            ..Options::default()
+        };
+        let mut compressed = compress(&data, &options).unwrap();
+        compressed.truncate(compressed.len() - 1);
+        assert!(decompress(&compressed).is_err());
+
+        let mut chunks = decode_chunks(&compressed, &DecodeOptions::default(), 64).unwrap();
+        let mut results = vec![];
+        while let Some(chunk) = chunks.next_chunk() {
+            results.push(chunk.map(<[u8]>::len));
+        }
+        assert!(matches!(results.last(), Some(Err(_))));
+        assert!(chunks.next_chunk().is_none());
+    }
+}
@@ -0,0 +1,253 @@
+//! Incremental archives: a delta holds the blocks of new data that an older archive does not
+//! have, and refers to the blocks it does have, so backing up data that barely changed costs
+//! about as much as the changes.
+//!
+//! | Field        | Encoding                                                       |
+//! |--------------|----------------------------------------------------------------|
+//! | magic        | `RHD`                                                          |
+//! | version      | `u8`, currently 1                                              |
+//! | base         | `u64` LE FNV-1a hash of the old archive                        |
+//! | block size   | varint, as in containers                                       |
+//! | block count  | varint                                                         |
+//! | digest       | of the new data, by the checksum of the old archive            |
+//! | blocks       | `u8` 0 then the varint index of a block of the old archive, or |
+//! |              | `u8` 1 then a block written as in containers                   |
+//!
+//! The new data is filtered and split in blocks like the old archive was, and a block is
+//! reused when the old archive has one with the same data, whatever its position. Applying
+//! the delta to the old archive gives a container with its filter, content, layout and
+//! checksum.
+use bit_vec::BitVec;
+use std::collections::HashMap;
+use std::hash::Hasher;
+
+use super::{
+    check_plugins, decode_block, encode_block, read_block, read_header, write_header, Checksum,
+    Coder, CompressReport, ContainerFormat, DecodeOptions, Frame, Options,
+};
+use crate::presets::Fnv1a;
+use crate::varint;
+
+pub const MAGIC: &[u8; 3] = b"RHD";
+pub const VERSION: u8 = 1;
+
+const BLOCK_OLD: u8 = 0;
+const BLOCK_NEW: u8 = 1;
+
+/// Writes the delta turning the `old` archive into an archive of the `new` data.
+/// ## Errors
+/// Returns Err if the old archive can not be read or decoded, or is a legacy file.
+/// ## Examples
+/// ```
+/// # use rhuffman::container::{self, delta, BlockSize, Options};
+/// let options = Options {
+///     block_size: BlockSize::Fixed(1000),
+///     ..Options::default()
+/// };
+/// let mut data: Vec<u8> = (0..10_000u32).map(|i| (i % 97) as u8).collect();
+/// let old = container::compress(&data, &options).unwrap();
+///
+/// data[5000] = 0xff;
+/// let diff = delta::diff(&old, &data).unwrap();
+/// assert!(diff.len() < old.len() / 2);
+/// let new = delta::apply(&old, &diff).unwrap();
+/// assert_eq!(container::decompress(&new).unwrap(), data);
+/// ```
+pub fn diff(old: &[u8], new: &[u8]) -> Result<Vec<u8>, &'static str> {
+    let (frame, blocks) = read_blocks(old)?;
+    let mut bits = BitVec::new();
+    let mut by_hash: HashMap<u64, Vec<(usize, Vec<u8>)>> = HashMap::new();
+    for (index, bytes) in blocks.iter().enumerate() {
+        let mut decoded = vec![];
+        let block = read_block(bytes, &mut 0, frame.layout, frame.block_checksum)?;
+        decode_block(block, &DecodeOptions::default(), &mut bits, &mut decoded)?;
+        by_hash
+            .entry(hash(&decoded))
+            .or_default()
+            .push((index, decoded));
+    }
+
+    let filtered = frame.filter.apply(new)?;
+    let block_size = frame.block_size as usize;
+    let chunks: Vec<&[u8]> = if block_size == 0 || filtered.len() <= block_size {
+        vec![&filtered]
+    } else {
+        filtered.chunks(block_size).collect()
+    };
+
+    let mut out = MAGIC.to_vec();
+    out.push(VERSION);
+    out.extend_from_slice(&hash(old).to_le_bytes());
+    varint::write(frame.block_size, &mut out);
+    varint::write(chunks.len() as u64, &mut out);
+    out.extend_from_slice(&frame.checksum.digest(new));
+    let report = &mut CompressReport::default();
+    for chunk in chunks {
+        // Hashes only narrow down the candidates, the data is compared
+        let reused = by_hash.get(&hash(chunk)).and_then(|candidates| {
+            candidates
+                .iter()
+                .find(|(_, decoded)| decoded == chunk)
+                .map(|(index, _)| *index)
+        });
+        match reused {
+            Some(index) => {
+                out.push(BLOCK_OLD);
+                varint::write(index as u64, &mut out);
+            }
+            None => {
+                out.push(BLOCK_NEW);
+                let (layout, checksum) = (frame.layout, frame.block_checksum);
+                let coder = &Coder::Trained;
+                encode_block(chunk, coder, layout, checksum, &mut bits, &mut out, report)?;
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Writes the archive of the new data from the `old` archive the delta was made against.
+/// ## Errors
+/// Returns Err if the delta is corrupted or was made against another archive, or if the old
+/// archive can not be read.
+pub fn apply(old: &[u8], delta: &[u8]) -> Result<Vec<u8>, &'static str> {
+    if !delta.starts_with(MAGIC) {
+        return Err("Not a delta");
+    }
+    let mut pos = MAGIC.len();
+    if *delta.get(pos).ok_or("Truncated header")? != VERSION {
+        return Err("Unknown delta version");
+    }
+    pos += 1;
+    let base = delta.get(pos..pos + 8).ok_or("Truncated header")?;
+    pos += 8;
+    if base != hash(old).to_le_bytes() {
+        return Err("The delta was made against another archive");
+    }
+    let block_size = varint::read(delta, &mut pos)?;
+    let block_count = varint::read(delta, &mut pos)?;
+
+    let (frame, blocks) = read_blocks(old)?;
+    let len = frame.checksum.digest_len();
+    let digest = delta.get(pos..pos + len).ok_or("Truncated header")?;
+    pos += len;
+    let options = Options {
+        filter: frame.filter,
+        checksum: frame.checksum,
+        block_checksums: frame.block_checksum != Checksum::None,
+        content: frame.content,
+        byte_order: frame.layout.byte_order,
+        padding: frame.layout.padding,
+        ..Options::default()
+    };
+    let mut out = vec![];
+    let mut aligner = write_header(
+        &options,
+        digest,
+        block_size as usize,
+        block_count as usize,
+        &mut out,
+    );
+    for _ in 0..block_count {
+        aligner.next(&mut out);
+        let kind = *delta.get(pos).ok_or("Truncated block")?;
+        pos += 1;
+        match kind {
+            BLOCK_OLD => {
+                let index = varint::read(delta, &mut pos)? as usize;
+                out.extend_from_slice(blocks.get(index).ok_or("No such block in the archive")?);
+            }
+            BLOCK_NEW => {
+                let start = pos;
+                read_block(delta, &mut pos, frame.layout, frame.block_checksum)?;
+                out.extend_from_slice(&delta[start..pos]);
+            }
+            _ => return Err("Unknown block kind"),
+        }
+    }
+    aligner.next(&mut out);
+    Ok(out)
+}
+
+/// The header of a versioned container and the bytes of each of its blocks
+fn read_blocks(data: &[u8]) -> Result<(Frame, Vec<&[u8]>), &'static str> {
+    match ContainerFormat::detect(data) {
+        Some(ContainerFormat::Legacy) => return Err("Legacy files have no blocks to reuse"),
+        Some(ContainerFormat::Minimal) => return Err("Minimal containers have no blocks to reuse"),
+        None => return Err("Not a compressed file"),
+        Some(_) => {}
+    }
+    let mut pos = 0;
+    let frame = read_header(data, &mut pos)?;
+    check_plugins(frame.plugins, None)?;
+    let mut blocks = vec![];
+    for index in 0..frame.block_count as usize {
+        frame.seek(index, &mut pos)?;
+        let start = pos;
+        read_block(data, &mut pos, frame.layout, frame.block_checksum)?;
+        blocks.push(&data[start..pos]);
+    }
+    Ok((frame, blocks))
+}
+
+fn hash(data: &[u8]) -> u64 {
+    let mut hasher = Fnv1a::new();
+    hasher.write(data);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{compress, decompress, inspect, BlockSize, ByteOrder};
+    use super::*;
+    use crate::filters::Filter;
+
+    #[test]
+    fn deltas_reuse_unchanged_blocks() {
+        let options = Options {
+            block_size: BlockSize::Fixed(500),
+            byte_order: ByteOrder::LsbFirst,
+            align: 256,
+            checksum: Checksum::XxHash64,
+            block_checksums: true,
+            ..Options::default()
+        };
+        let data: Vec<u8> = (0..3000u32).map(|i| (i * i % 251) as u8).collect();
+        let old = compress(&data, &options).unwrap();
+        // One block changed, one moved, and more data
+        let mut new = data.clone();
+        new[700] ^= 1;
+        new[2500..3000].copy_from_slice(&data[0..500]);
+        new.extend_from_slice(b"appended");
+
+        let delta = diff(&old, &new).unwrap();
+        let applied = apply(&old, &delta).unwrap();
+        assert_eq!(decompress(&applied).unwrap(), new);
+        let header = inspect(&applied).unwrap();
+        assert_eq!(header.byte_order, ByteOrder::LsbFirst);
+        assert_eq!(header.blocks.len(), 7);
+        assert_eq!(header.block_size, 500);
+        assert_eq!(header.digest, Checksum::XxHash64.digest(&new));
+        assert!(header.block_checksums);
+
+        assert!(apply(&compress(b"other", &options).unwrap(), &delta).is_err());
+        assert!(apply(&old, &delta[..delta.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn filtered_archives_are_diffed_on_filtered_data() {
+        let options = Options {
+            filter: Filter::Rle,
+            block_size: BlockSize::Fixed(100),
+            ..Options::default()
+        };
+        let mut data = vec![0u8; 5000];
+        data.extend((0..1000u32).map(|i| (i % 7) as u8));
+        let old = compress(&data, &options).unwrap();
+        data.extend_from_slice(&[9; 100]);
+
+        let applied = apply(&old, &diff(&old, &data).unwrap()).unwrap();
+        assert_eq!(decompress(&applied).unwrap(), data);
+        assert!(diff(b"garbage", &data).is_err());
+    }
+}
@@ -0,0 +1,206 @@
+//! Reader for the MessagePack files written by rhuff-compress before the versioned
+//! [container](super) format existed, and [migrate()] to rewrite them as containers.
+use bit_vec::BitVec;
+use serde::{Deserialize, Serialize};
+
+use super::{write_container, BlockSize, Checksum, Codebook, Options};
+use crate::filters::Filter;
+use crate::huffman_tree::canonical;
+use crate::huffman_tree::huffman_decoder::HuffmanDecoder;
+use crate::huffman_tree::huffman_encoder::HuffmanEncoder;
+use crate::huffman_tree::huffman_generator::HuffmanTree;
+
+/// The legacy file layout, serialized with `rmp_serde`
+#[doc(hidden)]
+#[derive(Serialize, Deserialize)]
+pub struct Compressed<T: Eq> {
+    pub tree: HuffmanTree<T>,
+    #[serde(with = "serde_bytes")]
+    pub data: Vec<u8>,
+    /// Number of meaningful bits in `data`, the rest of the last byte is padding
+    pub data_len: usize,
+    #[serde(default)]
+    pub filter: Filter,
+}
+
+/// Decompresses a legacy file.
+/// ## Errors
+/// Returns Err if the data is not a legacy file or is corrupted.
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, &'static str> {
+    decompress_within(data, u64::MAX)
+}
+
+/// Same as [decompress()], failing if the data decodes to more than `max_len` bytes
+pub(super) fn decompress_within(data: &[u8], max_len: u64) -> Result<Vec<u8>, &'static str> {
+    let (compressed, bits) = read(data)?;
+    let decoder = HuffmanDecoder::new(compressed.tree);
+    compressed
+        .filter
+        .reverse_within(&decoder.decode_unbounded(&bits)?, max_len)
+}
+
+/// Parses a legacy file, returning it along with its payload
+fn read(data: &[u8]) -> Result<(Compressed<u8>, BitVec), &'static str> {
+    let compressed: Compressed<u8> =
+        rmp_serde::from_slice(data).map_err(|_| "Not a legacy compressed file")?;
+    if compressed.data_len > compressed.data.len() * 8 {
+        return Err("Truncated payload");
+    }
+    let mut bits = BitVec::from_bytes(&compressed.data);
+    // Restore bit length from bytes
+    bits.truncate(compressed.data_len);
+    Ok((compressed, bits))
+}
+
+/// A legacy file rewritten by [migrate()], and what changed
+#[derive(PartialEq, Eq, Debug, Clone)]
+#[must_use]
+pub struct Migration {
+    /// The container, holding a single block
+    pub container: Vec<u8>,
+    /// The filter of the legacy file, kept in the container
+    pub filter: Filter,
+    pub checksum: Checksum,
+    /// Whether the block holds the payload of the legacy file bit for bit. Containers only
+    /// embed the code lengths of a tree, so a legacy tree that is not the canonical tree of
+    /// its lengths is replaced by it: the data is then encoded again, in as many bits.
+    pub payload_kept: bool,
+    /// Size of the payload, in bits
+    pub payload_bits: u64,
+    /// Size of the decompressed data
+    pub data_len: u64,
+}
+
+/// Rewrites a legacy file as a [container](super) with the digest of the data by the
+/// checksum, also recorded for the block unless it is [None](Checksum::None). The payload is
+/// packed most significant bit first and padded with zeros, as legacy files were.
+/// ## Errors
+/// Returns Err if the data is not a legacy file or is corrupted.
+pub fn migrate(data: &[u8], checksum: Checksum) -> Result<Migration, &'static str> {
+    let (compressed, payload) = read(data)?;
+    let decoder = HuffmanDecoder::new(compressed.tree);
+    let symbols = decoder.decode_unbounded(&payload)?;
+    let decompressed = compressed.filter.reverse(&symbols)?;
+
+    let lengths = canonical::code_lengths(&decoder.get_tree());
+    let tree = canonical::from_lengths(&lengths).ok_or("Invalid tree")?;
+    let encoder = HuffmanEncoder::from_tree(&tree);
+    let options = Options {
+        filter: compressed.filter,
+        block_size: BlockSize::Whole,
+        checksum,
+        block_checksums: checksum != Checksum::None,
+        ..Options::default()
+    };
+    let (mut bits, mut container) = (BitVec::new(), vec![]);
+    write_container(
+        &symbols,
+        &checksum.digest(&decompressed),
+        &options,
+        &Codebook::Embedded(&tree, &encoder),
+        &mut bits,
+        &mut container,
+    )?;
+    Ok(Migration {
+        container,
+        filter: compressed.filter,
+        checksum,
+        payload_kept: bits == payload,
+        payload_bits: bits.len() as u64,
+        data_len: decompressed.len() as u64,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::{self, ContainerFormat};
+    use crate::huffman_tree::huffman_generator::HuffmanGenerator;
+    use crate::huffman_tree::huffman_node::{HuffmanBranch, HuffmanLeaf, HuffmanNode};
+
+    /// Builds a file the way rhuff-compress used to
+    fn legacy_file(contents: &[u8]) -> Vec<u8> {
+        let mut gen = HuffmanGenerator::new();
+        gen.add_occurences_from_iterator(&mut contents.iter());
+        legacy_file_with(gen.into_huffman_tree().unwrap(), contents)
+    }
+
+    fn legacy_file_with(tree: HuffmanTree<u8>, contents: &[u8]) -> Vec<u8> {
+        let bits = HuffmanEncoder::from_tree(&tree)
+            .encode(&mut contents.iter())
+            .unwrap();
+        rmp_serde::to_vec(&Compressed {
+            tree,
+            data: bits.to_bytes(),
+            data_len: bits.len(),
+            filter: Filter::None,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn legacy_files_are_detected_and_decoded() {
+        let contents = b"the quick brown fox jumps over the lazy dog";
+        let file = legacy_file(contents);
+        assert_eq!(
+            ContainerFormat::detect(&file),
+            Some(ContainerFormat::Legacy)
+        );
+        assert_eq!(container::decompress(&file).unwrap(), contents);
+    }
+
+    #[test]
+    fn padding_bits_are_trimmed() {
+        // 3 symbols of 1 or 2 bits never fill a whole byte
+        let contents = b"abc";
+        assert_eq!(decompress(&legacy_file(contents)).unwrap(), contents);
+    }
+
+    #[test]
+    fn migrated_files_keep_canonical_payloads() {
+        let contents = b"the quick brown fox jumps over the lazy dog".repeat(20);
+        let lengths = [(b'a', 2), (b'b', 2), (b'c', 1)];
+        let canonical_tree = canonical::from_lengths(&lengths).unwrap();
+        let file = legacy_file_with(canonical_tree, b"abcacb");
+        let migration = migrate(&file, Checksum::Crc32).unwrap();
+        assert!(migration.payload_kept);
+        assert_eq!((migration.payload_bits, migration.data_len), (10, 6));
+        assert_eq!(
+            ContainerFormat::detect(&migration.container),
+            Some(ContainerFormat::V7)
+        );
+        let header = container::inspect(&migration.container).unwrap();
+        assert_eq!(
+            (header.checksum, header.block_checksums),
+            (Checksum::Crc32, true)
+        );
+        assert_eq!(
+            container::decompress(&migration.container).unwrap(),
+            b"abcacb"
+        );
+
+        let migration = migrate(&legacy_file(&contents), Checksum::None).unwrap();
+        assert_eq!(
+            container::decompress(&migration.container).unwrap(),
+            contents
+        );
+        assert!(migrate(&contents, Checksum::None).is_err());
+    }
+
+    #[test]
+    fn non_canonical_trees_are_replaced() {
+        let leaf = |symbol| HuffmanNode::Leaf(HuffmanLeaf::new(symbol));
+        // c, the only 1 bit code, comes last instead of first
+        let tree = HuffmanNode::Branch(HuffmanBranch::new(
+            HuffmanNode::Branch(HuffmanBranch::new(leaf(b'a'), leaf(b'b'))),
+            leaf(b'c'),
+        ));
+        let migration = migrate(&legacy_file_with(tree, b"abcacb"), Checksum::None).unwrap();
+        assert!(!migration.payload_kept);
+        assert_eq!(migration.payload_bits, 10);
+        assert_eq!(
+            container::decompress(&migration.container).unwrap(),
+            b"abcacb"
+        );
+    }
+}
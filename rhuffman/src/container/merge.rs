@@ -0,0 +1,275 @@
+//! Merging containers into one holding their data one after the other, for consolidating many
+//! small archives.
+//!
+//! Blocks are copied as they are, only the header is rewritten, so merging costs about as
+//! much as copying the files. Filters work on the whole data though, so filtered containers
+//! and legacy files are decompressed and compressed again without a filter first.
+use bit_vec::BitVec;
+
+use super::packing::{self, Layout};
+use super::{
+    check_plugins, compress, decode_block, decompress, read_block, read_header, train, write_block,
+    write_header, Aligner, BlockSize, Checksum, Codebook, CompressReport, ContainerFormat, Content,
+    DecodeOptions, Options,
+};
+use crate::filters::Filter;
+use crate::huffman_tree::huffman_encoder::HuffmanEncoder;
+
+/// Which trees the blocks of a merged container are encoded with
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum MergeTree {
+    /// Copy every block with its own tree, without decoding anything
+    #[default]
+    Keep,
+    /// Decode every block and encode them all with a tree trained on all the data, which
+    /// pays off when the inputs are similar and their blocks small
+    Shared,
+}
+
+/// A block of one of the inputs
+struct Source<'a> {
+    /// Every byte of the block
+    bytes: &'a [u8],
+    /// Offset of the payload in the bytes
+    payload: usize,
+    layout: Layout,
+    /// The checksum of the block, whose digest comes right before the payload
+    checksum: Checksum,
+    symbol_count: u64,
+    bit_len: u64,
+}
+
+/// Merges the containers into one whose data is theirs one after the other. The merged
+/// container has the layout of the first one, and its content if they all have the same. It
+/// has no checksum, as blocks are copied without decoding the data.
+/// ## Errors
+/// Returns Err if there is no container or one of them can not be read, or when decoding
+/// blocks for a [MergeTree::Shared] tree fails.
+/// ## Examples
+/// ```
+/// # use rhuffman::container::{self, merge::{merge, MergeTree}, Options};
+/// let a = container::compress(b"first file, ", &Options::default()).unwrap();
+/// let b = container::compress(b"second file", &Options::default()).unwrap();
+/// let merged = merge(&[&a, &b], MergeTree::Keep).unwrap();
+/// assert_eq!(container::decompress(&merged).unwrap(), b"first file, second file");
+/// ```
+pub fn merge(containers: &[&[u8]], tree: MergeTree) -> Result<Vec<u8>, &'static str> {
+    if containers.is_empty() {
+        return Err("No containers to merge");
+    }
+    let mut recompressed = vec![];
+    for &data in containers {
+        recompressed.push(match ContainerFormat::detect(data) {
+            Some(ContainerFormat::Minimal) | Some(ContainerFormat::Legacy) | None => {
+                Some(recompress(data)?)
+            }
+            Some(_) => match read_header(data, &mut 0)?.filter {
+                Filter::None => None,
+                _ => Some(recompress(data)?),
+            },
+        });
+    }
+
+    let mut layout = None;
+    let mut contents = vec![];
+    let mut sources = vec![];
+    for (data, recompressed) in containers.iter().zip(&recompressed) {
+        let data = recompressed.as_deref().unwrap_or(data);
+        let mut pos = 0;
+        let frame = read_header(data, &mut pos)?;
+        check_plugins(frame.plugins, None)?;
+        let block_layout = frame.layout;
+        layout.get_or_insert(block_layout);
+        contents.push(frame.content);
+        for index in 0..frame.block_count as usize {
+            frame.seek(index, &mut pos)?;
+            let start = pos;
+            let block = read_block(data, &mut pos, block_layout, frame.block_checksum)?;
+            sources.push(Source {
+                bytes: &data[start..pos],
+                payload: block.payload.as_ptr() as usize - data[start..].as_ptr() as usize,
+                layout: block_layout,
+                checksum: frame.block_checksum,
+                symbol_count: block.header.symbol_count,
+                bit_len: block.header.bit_len,
+            });
+        }
+    }
+    // Empty blocks only take room, unless there is no data at all
+    if sources.iter().any(|source| source.symbol_count > 0) {
+        sources.retain(|source| source.symbol_count > 0);
+    } else {
+        sources.truncate(1);
+    }
+
+    let options = Options {
+        content: if contents.iter().all(|&content| content == contents[0]) {
+            contents[0]
+        } else {
+            Content::Raw
+        },
+        byte_order: layout.unwrap_or_default().byte_order,
+        padding: layout.unwrap_or_default().padding,
+        ..Options::default()
+    };
+    let sizes: Vec<u64> = sources.iter().map(|source| source.symbol_count).collect();
+    let mut out = vec![];
+    let mut aligner = write_header(&options, &[], block_size(&sizes), sources.len(), &mut out);
+    match tree {
+        MergeTree::Keep => {
+            for source in &sources {
+                aligner.next(&mut out);
+                copy_block(source, options.layout(), &mut out);
+            }
+        }
+        MergeTree::Shared => write_shared(&sources, options.layout(), &mut aligner, &mut out)?,
+    }
+    aligner.next(&mut out);
+    Ok(out)
+}
+
+/// The data as an unfiltered container whose blocks can be copied
+fn recompress(data: &[u8]) -> Result<Vec<u8>, &'static str> {
+    let options = Options {
+        block_size: BlockSize::Whole,
+        ..Options::default()
+    };
+    compress(&decompress(data)?, &options)
+}
+
+/// The block size of the header, the size of every block but the last one or 0 if they differ
+fn block_size(sizes: &[u64]) -> usize {
+    match sizes.split_last() {
+        Some((_, [])) | None => 0,
+        Some((_, full)) if full.iter().all(|&size| size == full[0]) => full[0] as usize,
+        Some(_) => 0,
+    }
+}
+
+/// Appends the block without its digest, repacking its payload if it is in another layout
+fn copy_block(source: &Source, layout: Layout, out: &mut Vec<u8>) {
+    if source.layout == layout && source.checksum == Checksum::None {
+        out.extend_from_slice(source.bytes);
+        return;
+    }
+    let digest_start = source.payload - source.checksum.digest_len();
+    out.extend_from_slice(&source.bytes[..digest_start]);
+    let payload = &source.bytes[source.payload..];
+    if source.layout == layout {
+        out.extend_from_slice(payload);
+        return;
+    }
+    let mut bits = BitVec::new();
+    packing::unpack_into(
+        payload,
+        source.bit_len as usize,
+        source.layout.byte_order,
+        &mut bits,
+    );
+    packing::pack_into(&bits, layout, out);
+}
+
+/// Appends every block encoded with a tree trained on all of them
+fn write_shared(
+    sources: &[Source],
+    layout: Layout,
+    aligner: &mut Aligner,
+    out: &mut Vec<u8>,
+) -> Result<(), &'static str> {
+    let mut bits = BitVec::new();
+    let mut decoded = vec![];
+    for source in sources {
+        let block = read_block(source.bytes, &mut 0, source.layout, source.checksum)?;
+        let mut data = vec![];
+        decode_block(block, &DecodeOptions::default(), &mut bits, &mut data)?;
+        decoded.push(data);
+    }
+    let buffers: Vec<&[u8]> = decoded.iter().map(Vec::as_slice).collect();
+    let shared = train(&buffers).map(|tree| {
+        let encoder = HuffmanEncoder::from_tree(&tree);
+        (tree, encoder)
+    });
+    let codebook = match &shared {
+        Some((tree, encoder)) => Codebook::Embedded(tree, encoder),
+        None => Codebook::Empty,
+    };
+    let report = &mut CompressReport::default();
+    for data in &decoded {
+        aligner.next(out);
+        write_block(
+            data,
+            &codebook,
+            layout,
+            Checksum::None,
+            &mut bits,
+            out,
+            report,
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{inspect, ByteOrder};
+    use super::*;
+
+    #[test]
+    fn merged_containers_hold_every_input() {
+        let inputs: [(&[u8], Options); 4] = [
+            (
+                b"aaaaaaaaaabbbbbbbbbbcccccccccc",
+                Options {
+                    block_size: BlockSize::Fixed(10),
+                    checksum: Checksum::XxHash64,
+                    block_checksums: true,
+                    ..Options::default()
+                },
+            ),
+            (b"", Options::default()),
+            (
+                &[1, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0],
+                Options {
+                    filter: Filter::Timeseries,
+                    ..Options::default()
+                },
+            ),
+            (
+                b"packed the other way around",
+                Options {
+                    byte_order: ByteOrder::LsbFirst,
+                    checksum: Checksum::Crc32,
+                    block_checksums: true,
+                    ..Options::default()
+                },
+            ),
+        ];
+        let containers: Vec<Vec<u8>> = inputs
+            .iter()
+            .map(|(data, options)| compress(data, options).unwrap())
+            .collect();
+        let containers: Vec<&[u8]> = containers.iter().map(Vec::as_slice).collect();
+        let expected: Vec<u8> = inputs.iter().flat_map(|(data, _)| data.to_vec()).collect();
+
+        for &tree in &[MergeTree::Keep, MergeTree::Shared] {
+            let merged = merge(&containers, tree).unwrap();
+            assert_eq!(decompress(&merged).unwrap(), expected);
+            let header = inspect(&merged).unwrap();
+            assert_eq!(header.filter, Filter::None);
+            assert_eq!(header.blocks.len(), 5);
+        }
+
+        let first = merge(&containers[..1], MergeTree::Keep).unwrap();
+        assert_eq!(inspect(&first).unwrap().block_size, 10);
+        assert_eq!(
+            merge(&[containers[1]], MergeTree::Keep).unwrap(),
+            containers[1]
+        );
+    }
+
+    #[test]
+    fn nothing_or_garbage_can_not_be_merged() {
+        assert!(merge(&[], MergeTree::Keep).is_err());
+        assert!(merge(&[b"garbage"], MergeTree::Keep).is_err());
+    }
+}
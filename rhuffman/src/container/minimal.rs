@@ -0,0 +1,162 @@
+//! Containers with a minimal header, for links where the standard header would dwarf a
+//! payload of a few dozen bytes, e.g. telemetry frames:
+//!
+//! | Field        | Encoding                                           |
+//! |--------------|----------------------------------------------------|
+//! | magic        | `u8`, `0xB7`                                       |
+//! | preset       | varint, the id of the preset the data is coded with |
+//! | bit length   | varint                                             |
+//! | payload      | the encoded bits, most significant bit first, padded with zeros |
+//!
+//! Nothing else is recorded: no filter, checksum or fingerprint of the preset, so the reader
+//! must have registered the very preset the writer used.
+use bit_vec::BitVec;
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+use super::packing::{self, ByteOrder, Layout};
+use super::{registered_preset, CompressReport, DecodeOptions, HuffmanNode, Options};
+use crate::presets::{self, Preset, PresetId};
+use crate::varint;
+
+/// First byte of a container with a minimal header
+pub(super) const MAGIC: u8 = 0xB7;
+
+/// Returns the id of the preset a container with a minimal header is coded with
+/// ## Errors
+/// Returns Err if the data does not start with a minimal header
+pub fn preset_id(data: &[u8]) -> Result<PresetId, &'static str> {
+    if data.first() != Some(&MAGIC) {
+        return Err("Not a minimal header");
+    }
+    let id = varint::read(data, &mut 1)?;
+    PresetId::try_from(id).map_err(|_| "Invalid preset id")
+}
+
+/// Appends the container of the data to `out`, coded with the `held` preset if any
+pub(super) fn compress_into(
+    data: &[u8],
+    options: &Options,
+    held: Option<&Arc<Preset<u8>>>,
+    bits: &mut BitVec,
+    out: &mut Vec<u8>,
+) -> Result<CompressReport, &'static str> {
+    // Only the preset is recorded, every other option must be one readers can assume
+    let recorded = Options {
+        preset: options.preset,
+        header: options.header,
+        block_size: options.block_size,
+        threads: options.threads,
+        ..Options::default()
+    };
+    if *options != recorded {
+        return Err("Minimal headers only record a preset, other options can not be set");
+    }
+    let preset = registered_preset(options, held)?.ok_or("Minimal headers need a preset")?;
+    if let HuffmanNode::Leaf(_) = preset.tree() {
+        return Err("Minimal headers need a preset of at least two symbols");
+    }
+    let mut report = CompressReport::default();
+    bits.truncate(0);
+    super::timed(&mut report.encode, || {
+        preset.encoder().encode_bytes_append(data, bits)
+    })
+    .map_err(|_| "The data contains a byte the preset has no code for")?;
+    out.push(MAGIC);
+    varint::write(u64::from(preset.id()), out);
+    varint::write(bits.len() as u64, out);
+    packing::pack_into(bits, Layout::default(), out);
+    Ok(report)
+}
+
+/// Appends the decompressed data to `out`. `bits` is scratch space for the payload.
+pub(super) fn decompress_into(
+    data: &[u8],
+    options: &DecodeOptions,
+    bits: &mut BitVec,
+    out: &mut Vec<u8>,
+) -> Result<(), &'static str> {
+    let id = preset_id(data)?;
+    let mut pos = 1;
+    varint::read(data, &mut pos)?;
+    let bit_len =
+        usize::try_from(varint::read(data, &mut pos)?).map_err(|_| "Truncated payload")?;
+    let payload = data
+        .get(pos..pos.saturating_add(ByteOrder::MsbFirst.packed_len(bit_len)))
+        .ok_or("Truncated payload")?;
+    if options.strict {
+        if pos + payload.len() != data.len() {
+            return Err("Trailing bytes after the container");
+        }
+        packing::check_padding(payload, bit_len, Layout::default())?;
+    }
+    let preset = presets::global()
+        .get(id)
+        .ok_or("The file was compressed with a preset that is not registered")?;
+    packing::unpack_into(payload, bit_len, ByteOrder::MsbFirst, bits);
+    let start = out.len();
+    preset.decoder().decode_into(bits, out)?;
+    // Symbols take a bit each at least, so the output is bounded by the payload
+    super::check_output((out.len() - start) as u64, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{compress, decompress, decompress_with_options, HeaderKind};
+    use super::*;
+    use crate::filters::Filter;
+    use crate::huffman_tree::huffman_generator::HuffmanGenerator;
+
+    #[test]
+    fn minimal_headers_round_trip() {
+        let frame = b"temp=21.5;hum=40;temp=21.6;hum=41".to_vec();
+        let mut gen = HuffmanGenerator::new();
+        gen.add_occurences_from_iterator(&mut frame.iter());
+        let id: PresetId = 0xb7_0001;
+        presets::global()
+            .register(id, gen.into_huffman_tree().unwrap())
+            .unwrap();
+
+        let options = Options {
+            preset: Some(id),
+            header: HeaderKind::Minimal,
+            ..Options::default()
+        };
+        let compressed = compress(&frame, &options).unwrap();
+        let standard = compress(
+            &frame,
+            &Options {
+                header: HeaderKind::Standard,
+                ..options
+            },
+        )
+        .unwrap();
+        assert!(compressed.len() + 10 < standard.len());
+        assert_eq!(decompress(&compressed).unwrap(), frame);
+        assert_eq!(preset_id(&compressed), Ok(id));
+        assert_eq!(
+            compress(b"", &options).map(|c| decompress(&c)),
+            Ok(Ok(vec![]))
+        );
+
+        let strict = DecodeOptions {
+            strict: true,
+            ..DecodeOptions::default()
+        };
+        let mut trailing = compressed.clone();
+        trailing.push(0);
+        assert!(decompress_with_options(&trailing, &strict).is_err());
+        assert!(decompress(&compressed[..compressed.len() - 1]).is_err());
+
+        let filtered = Options {
+            filter: Filter::Rle,
+            ..options
+        };
+        assert!(compress(&frame, &filtered).is_err());
+        let untrained = Options {
+            preset: None,
+            ..options
+        };
+        assert!(compress(&frame, &untrained).is_err());
+    }
+}
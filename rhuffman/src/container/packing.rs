@@ -0,0 +1,285 @@
+//! How the bits of a payload are laid out in bytes, recorded in the container header so that
+//! consumers with fixed expectations, e.g. hardware decoders, can read payloads in place.
+//!
+//! The bits left over in the last byte or word are padding, filled as the [Padding] says.
+use bit_vec::BitVec;
+use std::fmt;
+use std::str::FromStr;
+
+/// The order bits are packed in, see [pack()]
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[non_exhaustive]
+pub enum ByteOrder {
+    /// The first bit in the most significant bit of each byte, the padding in the low bits of
+    /// the last byte
+    #[default]
+    MsbFirst,
+    /// The first bit in the least significant bit of each byte as in DEFLATE, the padding in
+    /// the high bits of the last byte
+    LsbFirst,
+    /// The first bit in the most significant bit of 32 bit words stored little-endian, the
+    /// padding in the low bits of the last word. Suits readers loading whole words on
+    /// little-endian machines.
+    Words32Le,
+}
+
+/// What the bits padding the last byte or word are set to
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[non_exhaustive]
+pub enum Padding {
+    #[default]
+    Zeros,
+    /// As required by some formats, e.g. the end of string padding of HPACK
+    Ones,
+}
+
+/// The byte order and padding of packed bits
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct Layout {
+    pub byte_order: ByteOrder,
+    pub padding: Padding,
+}
+
+impl ByteOrder {
+    /// Number of bytes `bit_len` bits take once packed
+    pub fn packed_len(self, bit_len: usize) -> usize {
+        match self {
+            ByteOrder::MsbFirst | ByteOrder::LsbFirst => bit_len.div_ceil(8),
+            ByteOrder::Words32Le => bit_len.div_ceil(32) * 4,
+        }
+    }
+
+    /// The byte holding the bit at `index`, and the mask of that bit in the byte
+    fn locate(self, index: usize) -> (usize, u8) {
+        match self {
+            ByteOrder::MsbFirst => (index / 8, 0x80 >> (index % 8)),
+            ByteOrder::LsbFirst => (index / 8, 1 << (index % 8)),
+            ByteOrder::Words32Le => {
+                let shift = 31 - index % 32;
+                (index / 32 * 4 + shift / 8, 1 << (shift % 8))
+            }
+        }
+    }
+}
+
+impl FromStr for ByteOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "msb" => Ok(ByteOrder::MsbFirst),
+            "lsb" => Ok(ByteOrder::LsbFirst),
+            "words32le" => Ok(ByteOrder::Words32Le),
+            _ => Err(format!("Unknown byte order \"{}\"", s)),
+        }
+    }
+}
+
+impl FromStr for Padding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "zeros" => Ok(Padding::Zeros),
+            "ones" => Ok(Padding::Ones),
+            _ => Err(format!("Unknown padding \"{}\"", s)),
+        }
+    }
+}
+
+impl fmt::Display for Padding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Padding::Zeros => write!(f, "zeros"),
+            Padding::Ones => write!(f, "ones"),
+        }
+    }
+}
+
+impl fmt::Display for ByteOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ByteOrder::MsbFirst => write!(f, "msb"),
+            ByteOrder::LsbFirst => write!(f, "lsb"),
+            ByteOrder::Words32Le => write!(f, "words32le"),
+        }
+    }
+}
+
+/// Packs the bits in [packed_len()](ByteOrder::packed_len) bytes.
+/// ## Examples
+/// ```
+/// # use bit_vec::BitVec;
+/// # use rhuffman::container::packing::{pack, ByteOrder, Layout, Padding};
+/// let bits = BitVec::from_fn(3, |i| i == 0);
+/// let layout = |byte_order, padding| Layout { byte_order, padding };
+/// assert_eq!(pack(&bits, layout(ByteOrder::MsbFirst, Padding::Zeros)), vec![0b1000_0000]);
+/// assert_eq!(pack(&bits, layout(ByteOrder::LsbFirst, Padding::Ones)), vec![0b1111_1001]);
+/// assert_eq!(
+///     pack(&bits, layout(ByteOrder::Words32Le, Padding::Zeros)),
+///     vec![0, 0, 0, 0b1000_0000]
+/// );
+/// ```
+pub fn pack(bits: &BitVec, layout: Layout) -> Vec<u8> {
+    let mut out = vec![];
+    pack_into(bits, layout, &mut out);
+    out
+}
+
+/// Unpacks the first `bit_len` bits of data written by [pack()].
+/// ## Errors
+/// Returns Err if the data is shorter than `bit_len` bits take once packed, or its padding
+/// does not match the layout.
+pub fn unpack(data: &[u8], bit_len: usize, layout: Layout) -> Result<BitVec, &'static str> {
+    if data.len() < layout.byte_order.packed_len(bit_len) {
+        return Err("Truncated payload");
+    }
+    let mut bits = BitVec::new();
+    unpack_into(data, bit_len, layout.byte_order, &mut bits);
+    check_padding(data, bit_len, layout)?;
+    Ok(bits)
+}
+
+/// Appends the packed bits to `out`
+pub(super) fn pack_into(bits: &BitVec, layout: Layout, out: &mut Vec<u8>) {
+    let order = layout.byte_order;
+    let start = out.len();
+    let len = order.packed_len(bits.len());
+    out.resize(start + len, 0);
+    for (i, bit) in bits.iter().enumerate() {
+        let (byte, mask) = order.locate(i);
+        if bit {
+            out[start + byte] |= mask;
+        }
+    }
+    if layout.padding == Padding::Ones {
+        for i in bits.len()..len * 8 {
+            let (byte, mask) = order.locate(i);
+            out[start + byte] |= mask;
+        }
+    }
+}
+
+/// Replaces the contents of `bits` with the first `bit_len` bits of the packed data, which
+/// must be long enough
+pub(super) fn unpack_into(data: &[u8], bit_len: usize, order: ByteOrder, bits: &mut BitVec) {
+    bits.truncate(0);
+    bits.reserve(bit_len);
+    for i in 0..bit_len {
+        let (byte, mask) = order.locate(i);
+        bits.push(data[byte] & mask != 0);
+    }
+}
+
+/// Checks the padding after the first `bit_len` bits of the packed data, which must be long
+/// enough
+pub(super) fn check_padding(
+    data: &[u8],
+    bit_len: usize,
+    layout: Layout,
+) -> Result<(), &'static str> {
+    let order = layout.byte_order;
+    let fill = layout.padding == Padding::Ones;
+    for i in bit_len..order.packed_len(bit_len) * 8 {
+        let (byte, mask) = order.locate(i);
+        if (data[byte] & mask != 0) != fill {
+            return Err("Padding does not match the header");
+        }
+    }
+    Ok(())
+}
+
+pub(super) fn padding_tag(padding: Padding) -> u8 {
+    match padding {
+        Padding::Zeros => 0,
+        Padding::Ones => 1,
+    }
+}
+
+pub(super) fn padding_from_tag(tag: u8) -> Result<Padding, &'static str> {
+    match tag {
+        0 => Ok(Padding::Zeros),
+        1 => Ok(Padding::Ones),
+        _ => Err("Unknown padding"),
+    }
+}
+
+pub(super) fn tag(order: ByteOrder) -> u8 {
+    match order {
+        ByteOrder::MsbFirst => 0,
+        ByteOrder::LsbFirst => 1,
+        ByteOrder::Words32Le => 2,
+    }
+}
+
+pub(super) fn from_tag(tag: u8) -> Result<ByteOrder, &'static str> {
+    match tag {
+        0 => Ok(ByteOrder::MsbFirst),
+        1 => Ok(ByteOrder::LsbFirst),
+        2 => Ok(ByteOrder::Words32Le),
+        _ => Err("Unknown byte order"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORDERS: [ByteOrder; 3] = [
+        ByteOrder::MsbFirst,
+        ByteOrder::LsbFirst,
+        ByteOrder::Words32Le,
+    ];
+
+    #[test]
+    fn every_order_round_trips() {
+        for len in 0..80 {
+            let bits = BitVec::from_fn(len, |i| (i * 7 + len) % 3 == 0);
+            for &byte_order in &ORDERS {
+                for &padding in &[Padding::Zeros, Padding::Ones] {
+                    let layout = Layout {
+                        byte_order,
+                        padding,
+                    };
+                    let packed = pack(&bits, layout);
+                    assert_eq!(packed.len(), byte_order.packed_len(len));
+                    assert_eq!(unpack(&packed, len, layout).unwrap(), bits);
+                    assert_eq!(padding.to_string().parse(), Ok(padding));
+                    assert_eq!(padding_from_tag(padding_tag(padding)), Ok(padding));
+                }
+                assert_eq!(byte_order.to_string().parse(), Ok(byte_order));
+                assert_eq!(from_tag(tag(byte_order)), Ok(byte_order));
+            }
+        }
+    }
+
+    #[test]
+    fn words_are_little_endian() {
+        // 0x12345678 then the first bits of the next word
+        let value = 0x1234_5678u32;
+        let bits = BitVec::from_fn(34, |i| i < 32 && value & (1 << (31 - i)) != 0 || i == 32);
+        let layout = Layout {
+            byte_order: ByteOrder::Words32Le,
+            padding: Padding::Zeros,
+        };
+        assert_eq!(
+            pack(&bits, layout),
+            vec![0x78, 0x56, 0x34, 0x12, 0, 0, 0, 0x80]
+        );
+        assert!(unpack(&[0x78, 0x56, 0x34], 20, layout).is_err());
+    }
+
+    #[test]
+    fn mismatched_padding_is_rejected() {
+        let bits = BitVec::from_fn(5, |i| i % 2 == 0);
+        let zeros = Layout::default();
+        let ones = Layout {
+            padding: Padding::Ones,
+            ..zeros
+        };
+        assert_eq!(pack(&bits, zeros), vec![0b1010_1000]);
+        assert_eq!(pack(&bits, ones), vec![0b1010_1111]);
+        assert!(unpack(&[0b1010_1000], 5, ones).is_err());
+        assert!(unpack(&[0b1010_1001], 5, zeros).is_err());
+    }
+}
@@ -0,0 +1,401 @@
+//! Decompressing data as it is read, the counterpart of [HuffmanWriter](super::HuffmanWriter).
+use bit_vec::BitVec;
+use std::io::{self, Read};
+
+use super::checksum::Digester;
+use super::{
+    check_plugins, decode_block, decompress_into, read_block, read_frame, read_stream_digest,
+    spend_output, ContainerFormat, DecodeOptions, Filter, Frame, STREAM_END,
+};
+
+/// Compressed bytes asked of the inner reader at least at a time
+const READ_CHUNK: usize = 8 << 10;
+
+/// Decompresses a container as it is read from the inner reader, a block at a time, so that
+/// only one block of compressed and decompressed data is held in memory. Reads both the
+/// containers of [compress()](super::compress) and the streamed ones of
+/// [HuffmanWriter](super::HuffmanWriter).
+///
+/// The checksum of the whole data is checked once the last block is read: the data returned
+/// before a read fails can not be trusted. Containers with a filter or a minimal header, and
+/// legacy files, are read whole on the first read instead.
+/// ## Examples
+/// ```
+/// # use rhuffman::container::{self, HuffmanReader, Options};
+/// # use std::io::Read;
+/// let input = b"the quick brown fox jumps over the lazy dog".repeat(100);
+/// let compressed = container::compress(&input, &Options::default()).unwrap();
+///
+/// let mut decompressed = vec![];
+/// HuffmanReader::new(&compressed[..])
+///     .read_to_end(&mut decompressed)
+///     .unwrap();
+/// assert_eq!(decompressed, input);
+/// ```
+pub struct HuffmanReader<R: Read> {
+    inner: R,
+    options: DecodeOptions,
+    /// Bytes read from `inner` and not parsed yet
+    input: Vec<u8>,
+    /// Offset of the first byte of `input` in the container
+    offset: usize,
+    /// Whether `inner` has no bytes left
+    eof: bool,
+    state: State,
+    bits: BitVec,
+    /// Decompressed data of the last block
+    buffer: Vec<u8>,
+    /// How much of the buffer was already returned
+    consumed: usize,
+}
+
+enum State {
+    /// Nothing was read yet
+    Start,
+    /// Decoding one block at a time
+    Blocks {
+        frame: Frame,
+        next_block: u64,
+        digester: Digester,
+    },
+    /// Everything was decoded
+    Done,
+    /// Decoding failed, every read returns the error again
+    Failed(io::ErrorKind, String),
+}
+
+/// What is next in the unparsed input
+enum Step {
+    /// A block, starting at the offset
+    Block(usize),
+    /// The end of the container, with the digest of the data
+    End(Vec<u8>),
+}
+
+fn invalid(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl<R: Read> HuffmanReader<R> {
+    /// Decompresses what is read from `inner` with the [default options](DecodeOptions).
+    /// Reads fail with [InvalidData](io::ErrorKind::InvalidData) if it is not a compressed
+    /// file or is corrupted.
+    pub fn new(inner: R) -> HuffmanReader<R> {
+        HuffmanReader::with_options(inner, &DecodeOptions::default())
+    }
+
+    /// Same as [new()](HuffmanReader::new), with the checks of the options. With
+    /// [strict](DecodeOptions::strict), the inner reader is read to its end to check that
+    /// nothing trails the container.
+    pub fn with_options(inner: R, options: &DecodeOptions) -> HuffmanReader<R> {
+        HuffmanReader {
+            inner,
+            options: *options,
+            input: vec![],
+            offset: 0,
+            eof: false,
+            state: State::Start,
+            bits: BitVec::new(),
+            buffer: vec![],
+            consumed: 0,
+        }
+    }
+
+    /// Returns the inner reader. Bytes already read from it but not decoded are lost.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Decodes the next block into the buffer, returns false if there is none left
+    fn fill(&mut self) -> io::Result<bool> {
+        let (frame, next_block, mut digester) =
+            match std::mem::replace(&mut self.state, State::Done) {
+                State::Start => return self.start(),
+                State::Blocks {
+                    frame,
+                    next_block,
+                    digester,
+                } => (frame, next_block, digester),
+                State::Done => return Ok(false),
+                State::Failed(kind, message) => {
+                    self.state = State::Failed(kind, message.clone());
+                    return Err(io::Error::new(kind, message));
+                }
+            };
+        let offset = self.offset;
+        let (step, end) = self.parse(|data, pos| {
+            // Offsets of aligned blocks are from the start of the container
+            let mut seeked = offset;
+            frame.seek(next_block as usize, &mut seeked)?;
+            *pos = seeked - offset;
+            if *pos > data.len() {
+                return Err("Truncated payload");
+            }
+            if frame.streamed && data.get(*pos) == Some(&STREAM_END) {
+                let digest = read_stream_digest(data, pos, next_block, frame.checksum)?;
+                return Ok(Step::End(digest.to_vec()));
+            }
+            if !frame.streamed && next_block == frame.block_count {
+                return Ok(Step::End(frame.digest.clone()));
+            }
+            let start = *pos;
+            read_block(data, pos, frame.layout, frame.block_checksum)?;
+            Ok(Step::Block(start))
+        })?;
+        match step {
+            Step::Block(mut start) => {
+                let block = read_block(&self.input, &mut start, frame.layout, frame.block_checksum)
+                    .map_err(invalid)?;
+                decode_block(block, &self.options, &mut self.bits, &mut self.buffer)
+                    .map_err(invalid)?;
+                spend_output(&mut self.options, self.buffer.len());
+                digester.update(&self.buffer);
+                self.consume(end);
+                self.state = State::Blocks {
+                    frame,
+                    next_block: next_block + 1,
+                    digester,
+                };
+                Ok(true)
+            }
+            Step::End(digest) => {
+                self.consume(end);
+                if digester.finish() != digest {
+                    return Err(invalid("Checksum mismatch"));
+                }
+                if self.options.strict {
+                    while self.input.is_empty() && !self.eof {
+                        self.read_input()?;
+                    }
+                    if !self.input.is_empty() {
+                        return Err(invalid("Trailing bytes after the container"));
+                    }
+                }
+                Ok(false)
+            }
+        }
+    }
+
+    /// Reads the header, and decodes the whole container if it can not be read in blocks
+    fn start(&mut self) -> io::Result<bool> {
+        let (frame, end) = self.parse(|data, pos| match ContainerFormat::detect(data) {
+            None => Err("Not a compressed file"),
+            Some(ContainerFormat::Minimal) | Some(ContainerFormat::Legacy) => Ok(None),
+            Some(_) => read_frame(data, pos).map(Some),
+        })?;
+        match frame {
+            Some(frame) if frame.filter == Filter::None => {
+                check_plugins(frame.plugins, self.options.plugins).map_err(invalid)?;
+                self.consume(end);
+                self.state = State::Blocks {
+                    digester: Digester::new(frame.checksum),
+                    frame,
+                    next_block: 0,
+                };
+                self.fill()
+            }
+            _ => {
+                self.inner.read_to_end(&mut self.input)?;
+                let options = &self.options;
+                decompress_into(&self.input, options, &mut self.bits, &mut self.buffer, None)
+                    .map_err(invalid)?;
+                self.input = vec![];
+                Ok(true)
+            }
+        }
+    }
+
+    /// Runs `parse` on the unparsed input, reading more of it for as long as `parse` fails
+    /// and the inner reader has some left. Returns what `parse` returned and the position it
+    /// left in the input.
+    fn parse<T>(
+        &mut self,
+        parse: impl Fn(&[u8], &mut usize) -> Result<T, &'static str>,
+    ) -> io::Result<(T, usize)> {
+        loop {
+            let mut pos = 0;
+            match parse(&self.input, &mut pos) {
+                Ok(value) => return Ok((value, pos)),
+                Err(message) if self.eof => return Err(invalid(message)),
+                // The input may only be truncated so far
+                Err(_) => self.read_input()?,
+            }
+        }
+    }
+
+    /// Reads at least as many bytes as there are unparsed, so that parsing a large block
+    /// is retried a logarithmic number of times
+    fn read_input(&mut self) -> io::Result<()> {
+        let len = self.input.len();
+        self.input.resize(len + len.max(READ_CHUNK), 0);
+        let read = loop {
+            match self.inner.read(&mut self.input[len..]) {
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+                result => break result,
+            }
+        };
+        self.input
+            .truncate(len + read.as_ref().map_or(0, |&read| read));
+        self.eof = read? == 0;
+        Ok(())
+    }
+
+    /// Drops the first `len` bytes of the unparsed input
+    fn consume(&mut self, len: usize) {
+        self.input.drain(..len);
+        self.offset += len;
+    }
+}
+
+impl<R: Read> Read for HuffmanReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.consumed == self.buffer.len() {
+            self.buffer.clear();
+            self.consumed = 0;
+            match self.fill() {
+                Ok(true) => {}
+                Ok(false) => return Ok(0),
+                Err(error) => {
+                    if !matches!(self.state, State::Failed(..)) {
+                        self.state = State::Failed(error.kind(), error.to_string());
+                    }
+                    return Err(error);
+                }
+            }
+        }
+        let len = buf.len().min(self.buffer.len() - self.consumed);
+        buf[..len].copy_from_slice(&self.buffer[self.consumed..self.consumed + len]);
+        self.consumed += len;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        compress, decompress_with_options, BlockSize, Checksum, HuffmanWriter, Options,
+    };
+    use super::*;
+    use crate::huffman_tree::huffman_generator::HuffmanGenerator;
+    use std::io::Write;
+
+    /// Hands out the data a few bytes at a time
+    struct Trickle<'a>(&'a [u8], usize);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.1 = self.1 % 7 + 1;
+            let len = buf.len().min(self.1).min(self.0.len());
+            buf[..len].copy_from_slice(&self.0[..len]);
+            self.0 = &self.0[len..];
+            Ok(len)
+        }
+    }
+
+    fn read(compressed: &[u8], options: &DecodeOptions) -> io::Result<Vec<u8>> {
+        let mut out = vec![];
+        HuffmanReader::with_options(Trickle(compressed, 0), options).read_to_end(&mut out)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn reads_every_kind_of_container() {
+        let data: Vec<u8> = (0..5_000u32).map(|i| (i * i % 251) as u8 % 29).collect();
+        let blocks = Options {
+            block_size: BlockSize::Fixed(700),
+            ..Options::default()
+        };
+        let cases = [
+            blocks,
+            Options {
+                filter: Filter::Rle,
+                ..blocks
+            },
+            Options {
+                checksum: Checksum::Blake3,
+                block_checksums: true,
+                ..blocks
+            },
+            Options {
+                align: 256,
+                checksum: Checksum::Crc32,
+                ..blocks
+            },
+        ];
+        let strict = DecodeOptions {
+            strict: true,
+            ..DecodeOptions::default()
+        };
+        for options in &cases {
+            let compressed = compress(&data, options).unwrap();
+            assert_eq!(read(&compressed, &strict).unwrap(), data);
+        }
+
+        let mut gen = HuffmanGenerator::new();
+        gen.add_occurences_from_iterator(&mut data.iter());
+        let options = Options {
+            checksum: Checksum::XxHash64,
+            ..blocks
+        };
+        let mut writer =
+            HuffmanWriter::with_options(vec![], gen.into_huffman_tree().unwrap(), &options)
+                .unwrap();
+        writer.write_all(&data).unwrap();
+        let streamed = writer.finish().unwrap();
+        assert_eq!(read(&streamed, &strict).unwrap(), data);
+
+        let empty = compress(&[], &Options::default()).unwrap();
+        assert_eq!(read(&empty, &strict).unwrap(), b"");
+    }
+
+    #[test]
+    fn corrupted_streams_fail() {
+        let data: Vec<u8> = (0..3_000u32).map(|i| (i % 13) as u8).collect();
+        let options = Options {
+            block_size: BlockSize::Fixed(500),
+            checksum: Checksum::Crc32,
+            ..Options::default()
+        };
+        let compressed = compress(&data, &options).unwrap();
+        let lenient = DecodeOptions::default();
+        assert!(read(&compressed[..compressed.len() - 1], &lenient).is_err());
+        assert!(read(b"not compressed", &lenient).is_err());
+
+        let mut trailing = compressed.clone();
+        trailing.push(0);
+        assert_eq!(read(&trailing, &lenient).unwrap(), data);
+        assert!(read(
+            &trailing,
+            &DecodeOptions {
+                strict: true,
+                ..DecodeOptions::default()
+            }
+        )
+        .is_err());
+
+        // The digest follows the block size and count of a version 7 header
+        let mut mismatch = compressed;
+        mismatch[13] ^= 1;
+        let mut reader = HuffmanReader::new(&mismatch[..]);
+        let error = reader.read_to_end(&mut vec![]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(reader.read(&mut [0; 16]).is_err());
+    }
+
+    #[test]
+    fn stall_limits_reject_deep_codes() {
+        // Doubling counts make codes as deep as there are symbols
+        let data: Vec<u8> = (0..12u8)
+            .flat_map(|symbol| (0..1 << symbol).map(move |_| symbol))
+            .collect();
+        let compressed = compress(&data, &Options::default()).unwrap();
+        let limit = |stall_bits| DecodeOptions {
+            stall_bits: Some(stall_bits),
+            ..DecodeOptions::default()
+        };
+        assert_eq!(read(&compressed, &limit(11)).unwrap(), data);
+        let error = read(&compressed, &limit(10)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(decompress_with_options(&compressed, &limit(10)).is_err());
+    }
+}
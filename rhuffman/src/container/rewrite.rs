@@ -0,0 +1,125 @@
+//! Replacing a block of an aligned container in place, for append-mostly datasets whose
+//! occasional corrections should not cost rewriting the whole archive.
+use bit_vec::BitVec;
+use std::ops::Range;
+
+use super::{encode_block, read_header, Checksum, Coder, CompressReport, ContainerFormat, Filter};
+
+/// Replaces the data of block `index` of a container written with
+/// [Options::align](super::Options::align) set, and returns the range of bytes that changed,
+/// so that only those need to be written back to disk. The new block gets a tree of its own
+/// and must fit where the old one was: the bytes up to the next block offset. The rest of
+/// that room is zeroed.
+///
+/// The block size in the header is left as it was, even if the new data is of another size.
+/// ## Errors
+/// Returns Err if the container is not aligned, has a filter or a checksum, or has no such
+/// block, or if the new block does not fit.
+/// ## Examples
+/// ```
+/// # use rhuffman::container::{self, BlockSize, Options};
+/// let options = Options {
+///     block_size: BlockSize::Fixed(100),
+///     align: 512,
+///     ..Options::default()
+/// };
+/// let mut compressed = container::compress(&[b'a'; 300], &options).unwrap();
+/// let changed = container::rewrite_block(&mut compressed, 1, &[b'b'; 100]).unwrap();
+/// assert_eq!(changed.start % 512, 0);
+///
+/// let mut expected = vec![b'a'; 300];
+/// expected[100..200].fill(b'b');
+/// assert_eq!(container::decompress(&compressed).unwrap(), expected);
+/// ```
+pub fn rewrite_block(
+    container: &mut [u8],
+    index: usize,
+    data: &[u8],
+) -> Result<Range<usize>, &'static str> {
+    match ContainerFormat::detect(container) {
+        Some(ContainerFormat::V5) | Some(ContainerFormat::V6) | Some(ContainerFormat::V7) => {}
+        _ => return Err("Only containers of version 5 or later can be rewritten"),
+    }
+    let frame = read_header(container, &mut 0)?;
+    if frame.checksum != Checksum::None {
+        // The digest covers the whole data, which is not decoded here
+        return Err("The blocks of checksummed containers can not be rewritten");
+    }
+    if frame.align == 0 {
+        return Err("The blocks of the container are not aligned");
+    }
+    if frame.filter != Filter::None {
+        return Err("The blocks of filtered containers can not be rewritten");
+    }
+    let slot = match (frame.offsets.get(index), frame.offsets.get(index + 1)) {
+        (Some(&start), Some(&end)) if start <= end && end <= container.len() as u64 => {
+            start as usize..end as usize
+        }
+        (Some(_), Some(_)) => return Err("Invalid block offset"),
+        _ => return Err("No such block"),
+    };
+
+    let mut block = vec![];
+    let report = &mut CompressReport::default();
+    encode_block(
+        data,
+        &Coder::Trained,
+        frame.layout,
+        Checksum::None,
+        &mut BitVec::new(),
+        &mut block,
+        report,
+    )?;
+    if block.len() > slot.len() {
+        return Err("The new block does not fit where the old one was");
+    }
+    block.resize(slot.len(), 0);
+    container[slot.clone()].copy_from_slice(&block);
+    Ok(slot)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{compress, decompress, inspect, BlockSize, Options};
+    use super::*;
+
+    fn aligned(data: &[u8]) -> Vec<u8> {
+        let options = Options {
+            block_size: BlockSize::Fixed(1000),
+            align: 4096,
+            ..Options::default()
+        };
+        compress(data, &options).unwrap()
+    }
+
+    #[test]
+    fn rewritten_blocks_replace_the_old_data() {
+        let data: Vec<u8> = (0..2500u32).map(|i| (i % 13) as u8).collect();
+        let mut compressed = aligned(&data);
+        let header = inspect(&compressed).unwrap();
+        assert_eq!(header.align, 4096);
+        assert_eq!(header.offsets, vec![4096, 8192, 12288, 16384]);
+        assert_eq!(compressed.len(), 16384);
+
+        // Shorter and smaller, the slot keeps its offset
+        let changed = rewrite_block(&mut compressed, 2, b"fixed").unwrap();
+        assert_eq!(changed, 12288..16384);
+        let mut expected = data[..2000].to_vec();
+        expected.extend_from_slice(b"fixed");
+        assert_eq!(decompress(&compressed).unwrap(), expected);
+        assert_eq!(inspect(&compressed).unwrap().offsets, header.offsets);
+    }
+
+    #[test]
+    fn only_fitting_blocks_of_aligned_containers_are_rewritten() {
+        let data = vec![1u8; 2500];
+        let mut compressed = aligned(&data);
+        let random: Vec<u8> = (0..10_000u32).map(|i| (i * 7919 % 251) as u8).collect();
+        assert!(rewrite_block(&mut compressed, 0, &random).is_err());
+        assert!(rewrite_block(&mut compressed, 3, b"x").is_err());
+        assert_eq!(decompress(&compressed).unwrap(), data);
+
+        let mut packed = compress(&data, &Options::default()).unwrap();
+        assert!(rewrite_block(&mut packed, 0, b"x").is_err());
+    }
+}
@@ -0,0 +1,174 @@
+//! Reusable working memory for servers that compress or decompress on every request.
+//!
+//! A [CodecScratch] keeps the buffers [compress()](super::compress) and
+//! [decompress()](super::decompress) would otherwise allocate on each call. Combined with a
+//! preset and output buffers owned by the caller, steady-state encoding and decoding do not
+//! allocate at all. Trees trained or read per call, and filters, still allocate.
+use bit_vec::BitVec;
+use std::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+
+use super::{compress_into, decompress_into, DecodeOptions, Options};
+
+/// Buffers reused across calls to [compress()](CodecScratch::compress) and
+/// [decompress()](CodecScratch::decompress). Check one out of a [ScratchPool] per request.
+#[derive(Debug, Default)]
+pub struct CodecScratch {
+    bits: BitVec,
+}
+
+impl CodecScratch {
+    pub fn new() -> CodecScratch {
+        CodecScratch::default()
+    }
+
+    /// Same as [container::compress()](super::compress), appending the container to `out`.
+    /// ## Errors
+    /// Returns Err if the preset is not registered, or lacks a code for one of the bytes.
+    /// `out` may then hold a partial container.
+    pub fn compress(
+        &mut self,
+        data: &[u8],
+        options: &Options,
+        out: &mut Vec<u8>,
+    ) -> Result<(), &'static str> {
+        compress_into(data, options, None, &mut self.bits, out, None)?;
+        Ok(())
+    }
+
+    /// Same as [container::decompress()](super::decompress), appending the data to `out`.
+    /// ## Errors
+    /// Returns Err if the data is not in a known format, is corrupted, or references a preset
+    /// that is not registered. `out` may then hold partially decoded data.
+    pub fn decompress(&mut self, data: &[u8], out: &mut Vec<u8>) -> Result<(), &'static str> {
+        decompress_into(data, &DecodeOptions::default(), &mut self.bits, out, None)
+    }
+}
+
+/// A thread-safe pool of [CodecScratch]. Scratches are created on demand and returned to the
+/// pool when the [PooledScratch] checked out is dropped.
+/// ## Examples
+/// ```
+/// # use rhuffman::container::{Options, scratch::ScratchPool};
+/// let pool = ScratchPool::new();
+/// let (mut compressed, mut decompressed) = (vec![], vec![]);
+/// for request in &[&b"GET /"[..], b"GET /index.html"] {
+///     let mut scratch = pool.checkout();
+///     compressed.clear();
+///     scratch.compress(request, &Options::default(), &mut compressed).unwrap();
+///     decompressed.clear();
+///     scratch.decompress(&compressed, &mut decompressed).unwrap();
+///     assert_eq!(&decompressed, request);
+/// }
+/// ```
+#[derive(Debug, Default)]
+pub struct ScratchPool {
+    free: Mutex<Vec<CodecScratch>>,
+}
+
+impl ScratchPool {
+    pub fn new() -> ScratchPool {
+        ScratchPool::default()
+    }
+
+    pub fn checkout(&self) -> PooledScratch<'_> {
+        let scratch = self
+            .free
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .pop()
+            .unwrap_or_default();
+        PooledScratch {
+            pool: self,
+            scratch,
+        }
+    }
+
+    /// Number of scratches waiting in the pool
+    pub fn idle(&self) -> usize {
+        self.free.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+}
+
+/// A [CodecScratch] checked out of a [ScratchPool], returned to it on drop
+#[derive(Debug)]
+pub struct PooledScratch<'a> {
+    pool: &'a ScratchPool,
+    scratch: CodecScratch,
+}
+
+impl Deref for PooledScratch<'_> {
+    type Target = CodecScratch;
+
+    fn deref(&self) -> &CodecScratch {
+        &self.scratch
+    }
+}
+
+impl DerefMut for PooledScratch<'_> {
+    fn deref_mut(&mut self) -> &mut CodecScratch {
+        &mut self.scratch
+    }
+}
+
+impl Drop for PooledScratch<'_> {
+    fn drop(&mut self) {
+        let scratch = std::mem::take(&mut self.scratch);
+        let mut free = self.pool.free.lock().unwrap_or_else(|e| e.into_inner());
+        free.push(scratch);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::{compress, decompress};
+    use crate::filters::Filter;
+
+    #[test]
+    fn scratch_matches_one_shot_functions() {
+        let mut scratch = CodecScratch::new();
+        let series: Vec<u8> = (0..20u64).flat_map(|i| (i * 7).to_le_bytes()).collect();
+        let timeseries = Options {
+            filter: Filter::Timeseries,
+            ..Options::default()
+        };
+        for (data, options) in &[
+            (&b"hello world"[..], Options::default()),
+            (b"", Options::default()),
+            (b"a", Options::default()),
+            (&series, timeseries),
+        ] {
+            let mut compressed = vec![];
+            scratch.compress(data, options, &mut compressed).unwrap();
+            assert_eq!(compressed, compress(data, options).unwrap());
+
+            let mut decompressed = vec![];
+            scratch.decompress(&compressed, &mut decompressed).unwrap();
+            assert_eq!(&decompressed, data);
+            assert_eq!(decompressed, decompress(&compressed).unwrap());
+        }
+    }
+
+    #[test]
+    fn output_is_appended() {
+        let mut scratch = CodecScratch::new();
+        let compressed = compress(b"world", &Options::default()).unwrap();
+        let mut out = b"hello ".to_vec();
+        scratch.decompress(&compressed, &mut out).unwrap();
+        assert_eq!(out, b"hello world");
+    }
+
+    #[test]
+    fn pool_reuses_returned_scratches() {
+        let pool = ScratchPool::new();
+        {
+            let _first = pool.checkout();
+            let _second = pool.checkout();
+            assert_eq!(pool.idle(), 0);
+        }
+        assert_eq!(pool.idle(), 2);
+        let _again = pool.checkout();
+        assert_eq!(pool.idle(), 1);
+    }
+}
@@ -0,0 +1,374 @@
+//! Tar archives stored in containers, usually compressed with [Content::Tar](super::Content).
+//!
+//! [members()] lists an archive without decompressing all of it: when the data was split in
+//! blocks and not filtered, only the blocks holding member headers are decoded, and the
+//! contents of the members in between are skipped.
+use bit_vec::BitVec;
+use std::collections::hash_map::{Entry, HashMap};
+use std::convert::TryFrom;
+
+use super::{
+    check_plugins, decode_block, decompress, read_container, Block, Checksum, Container,
+    ContainerFormat, DecodeOptions,
+};
+use crate::filters::Filter;
+
+/// Tar archives are made of records of this size
+const RECORD: usize = 512;
+/// Longest GNU long name or pax header read, they are only ever a few hundred bytes
+const MAX_METADATA: u64 = 1 << 20;
+
+/// A member of a tar archive
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Member {
+    /// Path of the member, invalid UTF-8 replaced
+    pub name: String,
+    /// Size of the contents of the member
+    pub size: u64,
+    /// The tar type flag, e.g. `b'0'` for a file or `b'5'` for a directory
+    pub kind: u8,
+}
+
+/// Whether the data starts with a valid ustar or GNU tar header
+pub fn is_tar(data: &[u8]) -> bool {
+    match data.get(..RECORD) {
+        Some(header) => &header[257..262] == b"ustar" && checksum_matches(header),
+        None => false,
+    }
+}
+
+/// Lists the members of the tar archive in the container, decoding as little as possible.
+/// ## Errors
+/// Returns Err if the container can not be decompressed, or does not hold a tar archive.
+pub fn members(data: &[u8]) -> Result<Vec<Member>, &'static str> {
+    let mut archive = Archive::open(data)?;
+    let mut members = vec![];
+    let mut offset = 0u64;
+    let mut long_name = None;
+    let mut pax = Pax::default();
+    while offset < archive.len() {
+        let header = archive.read(offset, RECORD as u64)?;
+        // The archive ends with zero records
+        if header.iter().all(|&byte| byte == 0) {
+            break;
+        }
+        if !checksum_matches(&header) {
+            return Err("Invalid tar header");
+        }
+        let size = parse_number(&header[124..136])?;
+        let kind = header[156];
+        offset += RECORD as u64;
+        let size = match kind {
+            // GNU long name of the next member
+            b'L' => {
+                long_name = Some(text(&archive.read(offset, size.min(MAX_METADATA))?));
+                size
+            }
+            // pax extended header of the next member
+            b'x' => {
+                pax = Pax::parse(&archive.read(offset, size.min(MAX_METADATA))?)?;
+                size
+            }
+            // pax global header
+            b'g' => size,
+            _ => {
+                let name = match (pax.path.take(), long_name.take()) {
+                    (Some(path), _) | (None, Some(path)) => path,
+                    (None, None) => ustar_name(&header),
+                };
+                // pax sizes hold members too large for the header field
+                let size = pax.size.take().unwrap_or(size);
+                members.push(Member { name, size, kind });
+                size
+            }
+        };
+        offset = offset
+            .checked_add(size.div_ceil(RECORD as u64) * RECORD as u64)
+            .ok_or("Invalid tar header")?;
+    }
+    Ok(members)
+}
+
+/// The decompressed archive, decoded block by block as it is read when possible
+enum Archive<'a> {
+    Decoded(Vec<u8>),
+    Blocks {
+        block_size: u64,
+        len: u64,
+        /// Taken when decoded
+        blocks: Vec<Option<Block<'a>>>,
+        decoded: HashMap<usize, Vec<u8>>,
+        bits: BitVec,
+    },
+}
+
+impl<'a> Archive<'a> {
+    fn open(data: &'a [u8]) -> Result<Archive<'a>, &'static str> {
+        match ContainerFormat::detect(data) {
+            Some(ContainerFormat::V2)
+            | Some(ContainerFormat::V3)
+            | Some(ContainerFormat::V4)
+            | Some(ContainerFormat::V5)
+            | Some(ContainerFormat::V6)
+            | Some(ContainerFormat::V7) => {}
+            _ => return Ok(Archive::Decoded(decompress(data)?)),
+        }
+        let Container {
+            filter,
+            checksum,
+            block_size,
+            plugins,
+            blocks,
+            ..
+        } = read_container(data)?;
+        check_plugins(plugins, None)?;
+        let full_blocks = blocks.len().saturating_sub(1);
+        // The checksum covers the whole data, so only verifies once it is all decoded
+        let lazy = filter == Filter::None
+            && checksum == Checksum::None
+            && block_size > 0
+            && blocks[..full_blocks]
+                .iter()
+                .all(|block| block.header.symbol_count == block_size)
+            && blocks
+                .last()
+                .is_some_and(|block| block.header.symbol_count <= block_size);
+        if !lazy {
+            return Ok(Archive::Decoded(decompress(data)?));
+        }
+        Ok(Archive::Blocks {
+            block_size,
+            len: blocks.iter().map(|block| block.header.symbol_count).sum(),
+            blocks: blocks.into_iter().map(Some).collect(),
+            decoded: HashMap::new(),
+            bits: BitVec::new(),
+        })
+    }
+
+    fn len(&self) -> u64 {
+        match self {
+            Archive::Decoded(data) => data.len() as u64,
+            Archive::Blocks { len, .. } => *len,
+        }
+    }
+
+    /// The `len` bytes at `offset`, all of which must be in the archive
+    fn read(&mut self, offset: u64, len: u64) -> Result<Vec<u8>, &'static str> {
+        let end = offset
+            .checked_add(len)
+            .filter(|&end| end <= self.len())
+            .ok_or("Truncated tar archive")?;
+        match self {
+            Archive::Decoded(data) => Ok(data[offset as usize..end as usize].to_vec()),
+            Archive::Blocks {
+                block_size,
+                blocks,
+                decoded,
+                bits,
+                ..
+            } => {
+                let mut out = vec![];
+                let mut pos = offset;
+                while pos < end {
+                    let index =
+                        usize::try_from(pos / *block_size).map_err(|_| "Truncated tar archive")?;
+                    if let Entry::Vacant(entry) = decoded.entry(index) {
+                        let block = blocks
+                            .get_mut(index)
+                            .and_then(Option::take)
+                            .ok_or("Truncated tar archive")?;
+                        let mut block_data = vec![];
+                        decode_block(block, &DecodeOptions::default(), bits, &mut block_data)?;
+                        entry.insert(block_data);
+                    }
+                    let block_start = index as u64 * *block_size;
+                    let from = (pos - block_start) as usize;
+                    let to = (end - block_start).min(*block_size) as usize;
+                    out.extend_from_slice(&decoded[&index][from..to]);
+                    pos = block_start + to as u64;
+                }
+                Ok(out)
+            }
+        }
+    }
+}
+
+/// The pax extended header fields that change how a member is listed
+#[derive(Default)]
+struct Pax {
+    path: Option<String>,
+    size: Option<u64>,
+}
+
+impl Pax {
+    /// Parses `<length> <key>=<value>\n` records
+    fn parse(mut data: &[u8]) -> Result<Pax, &'static str> {
+        let mut pax = Pax::default();
+        while !data.is_empty() {
+            let space = data
+                .iter()
+                .position(|&byte| byte == b' ')
+                .ok_or("Invalid pax header")?;
+            let len: usize = std::str::from_utf8(&data[..space])
+                .ok()
+                .and_then(|len| len.parse().ok())
+                .filter(|&len| len > space + 1 && len <= data.len())
+                .ok_or("Invalid pax header")?;
+            let record = &data[space + 1..len - 1];
+            if let Some(equals) = record.iter().position(|&byte| byte == b'=') {
+                let value = &record[equals + 1..];
+                match &record[..equals] {
+                    b"path" => pax.path = Some(String::from_utf8_lossy(value).into_owned()),
+                    b"size" => {
+                        let size = std::str::from_utf8(value).ok().and_then(|s| s.parse().ok());
+                        pax.size = Some(size.ok_or("Invalid pax header")?);
+                    }
+                    _ => {}
+                }
+            }
+            data = &data[len..];
+        }
+        Ok(pax)
+    }
+}
+
+/// The checksum is the sum of the header bytes, its own field counted as spaces
+fn checksum_matches(header: &[u8]) -> bool {
+    let sum: u64 = header
+        .iter()
+        .enumerate()
+        .map(|(i, &byte)| {
+            if (148..156).contains(&i) {
+                u64::from(b' ')
+            } else {
+                u64::from(byte)
+            }
+        })
+        .sum();
+    parse_number(&header[148..156]) == Ok(sum)
+}
+
+/// Octal digits padded with spaces or NULs, or big-endian base-256 when the high bit of the
+/// first byte is set
+fn parse_number(field: &[u8]) -> Result<u64, &'static str> {
+    if field[0] & 0x80 != 0 {
+        return field[1..]
+            .iter()
+            .try_fold(u64::from(field[0] & 0x7f), |n, &byte| {
+                n.checked_mul(256)
+                    .map(|n| n | u64::from(byte))
+                    .ok_or("Invalid tar header")
+            });
+    }
+    let digits = field
+        .iter()
+        .skip_while(|&&byte| byte == b' ')
+        .take_while(|&&byte| byte != 0 && byte != b' ');
+    let mut n = 0u64;
+    for &digit in digits {
+        if !(b'0'..=b'7').contains(&digit) {
+            return Err("Invalid tar header");
+        }
+        n = n
+            .checked_mul(8)
+            .map(|n| n | u64::from(digit - b'0'))
+            .ok_or("Invalid tar header")?;
+    }
+    Ok(n)
+}
+
+fn text(field: &[u8]) -> String {
+    let end = field
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+fn ustar_name(header: &[u8]) -> String {
+    let name = text(&header[..100]);
+    // POSIX ustar splits long paths in a prefix and a name, GNU uses that space for times
+    if &header[257..263] == b"ustar\0" && header[345] != 0 {
+        format!("{}/{}", text(&header[345..500]), name)
+    } else {
+        name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{compress, BlockSize, Content, Options};
+    use super::*;
+
+    fn header(name: &str, size: usize, kind: u8) -> Vec<u8> {
+        let mut header = vec![0u8; RECORD];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[124..135].copy_from_slice(format!("{:011o}", size).as_bytes());
+        header[156] = kind;
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        header[148..156].copy_from_slice(b"        ");
+        let sum: u32 = header.iter().map(|&byte| u32::from(byte)).sum();
+        header[148..155].copy_from_slice(format!("{:06o}\0", sum).as_bytes());
+        header
+    }
+
+    fn member(archive: &mut Vec<u8>, name: &str, contents: &[u8], kind: u8) {
+        archive.extend(header(name, contents.len(), kind));
+        archive.extend_from_slice(contents);
+        archive.resize(archive.len().div_ceil(RECORD) * RECORD, 0);
+    }
+
+    #[test]
+    fn members_are_listed() {
+        let mut archive = vec![];
+        member(&mut archive, "dir/", b"", b'5');
+        member(&mut archive, "dir/big", &vec![b'x'; 5000], b'0');
+        let long = "long/".repeat(30) + "name";
+        member(&mut archive, "././@LongLink", long.as_bytes(), b'L');
+        member(&mut archive, "truncated", b"hi", b'0');
+        member(&mut archive, "", b"12 path=pax\n", b'x');
+        member(&mut archive, "ignored", b"!", b'0');
+        archive.resize(archive.len() + 2 * RECORD, 0);
+        assert!(is_tar(&archive));
+
+        let expected = vec![
+            Member {
+                name: "dir/".into(),
+                size: 0,
+                kind: b'5',
+            },
+            Member {
+                name: "dir/big".into(),
+                size: 5000,
+                kind: b'0',
+            },
+            Member {
+                name: long,
+                size: 2,
+                kind: b'0',
+            },
+            Member {
+                name: "pax".into(),
+                size: 1,
+                kind: b'0',
+            },
+        ];
+        for block_size in &[BlockSize::Whole, BlockSize::Fixed(700)] {
+            let options = Options {
+                block_size: *block_size,
+                content: Content::Tar,
+                ..Options::default()
+            };
+            let compressed = compress(&archive, &options).unwrap();
+            assert_eq!(members(&compressed).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn non_tar_data_is_rejected() {
+        let compressed = compress(&[7u8; 2000], &Options::default()).unwrap();
+        assert!(members(&compressed).is_err());
+        assert!(!is_tar(&[7u8; 2000]));
+    }
+}
@@ -0,0 +1,102 @@
+//! Live progress of long compression and decompression jobs, for orchestrators exporting
+//! metrics while the job runs instead of waiting for it to finish.
+use std::time::{Duration, Instant};
+
+/// A snapshot of a running job. Passed to the callback of
+/// [compress_with_telemetry()](super::compress_with_telemetry) and
+/// [decompress_with_telemetry()](super::decompress_with_telemetry).
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Telemetry {
+    /// Bytes consumed so far. When compressing with a filter, this is estimated from the
+    /// progress through the filtered data until the job is done.
+    pub bytes_in: u64,
+    /// Bytes produced so far
+    pub bytes_out: u64,
+    /// Time since the job started
+    pub elapsed: Duration,
+    /// Whether this is the last snapshot of the job
+    pub done: bool,
+}
+
+impl Telemetry {
+    /// Bytes out per byte in, 0 before anything was consumed
+    pub fn ratio(&self) -> f64 {
+        if self.bytes_in == 0 {
+            0.0
+        } else {
+            self.bytes_out as f64 / self.bytes_in as f64
+        }
+    }
+
+    /// Bytes in per second
+    pub fn throughput(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds == 0.0 {
+            0.0
+        } else {
+            self.bytes_in as f64 / seconds
+        }
+    }
+}
+
+/// Calls the callback at most once per interval as a job progresses, and once at its end
+pub(crate) struct Reporter<'a> {
+    callback: &'a mut dyn FnMut(&Telemetry),
+    interval: Duration,
+    start: Instant,
+    last: Instant,
+}
+
+impl<'a> Reporter<'a> {
+    pub fn new(interval: Duration, callback: &'a mut dyn FnMut(&Telemetry)) -> Reporter<'a> {
+        let now = Instant::now();
+        Reporter {
+            callback,
+            interval,
+            start: now,
+            last: now,
+        }
+    }
+
+    pub fn update(&mut self, bytes_in: u64, bytes_out: u64) {
+        let now = Instant::now();
+        if now.duration_since(self.last) >= self.interval {
+            self.last = now;
+            self.emit(bytes_in, bytes_out, false);
+        }
+    }
+
+    pub fn finish(&mut self, bytes_in: u64, bytes_out: u64) {
+        self.emit(bytes_in, bytes_out, true);
+    }
+
+    fn emit(&mut self, bytes_in: u64, bytes_out: u64, done: bool) {
+        (self.callback)(&Telemetry {
+            bytes_in,
+            bytes_out,
+            elapsed: self.start.elapsed(),
+            done,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reporter_throttles_updates_but_always_finishes() {
+        let mut snapshots = vec![];
+        {
+            let mut callback = |telemetry: &Telemetry| snapshots.push(*telemetry);
+            let mut reporter = Reporter::new(Duration::from_secs(3600), &mut callback);
+            reporter.update(10, 5);
+            reporter.update(20, 10);
+            reporter.finish(40, 10);
+        }
+
+        assert_eq!(snapshots.len(), 1);
+        assert!(snapshots[0].done);
+        assert_eq!(snapshots[0].ratio(), 0.25);
+    }
+}
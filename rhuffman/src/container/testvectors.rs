@@ -0,0 +1,285 @@
+//! Conformance vectors: inputs along with the exact bytes this crate writes for them, for
+//! the bindings and other implementations of the format to check their own output against.
+//!
+//! Each [TestVector] exercises one part of the format:
+//!
+//! - [payload](Kind::Payload) vectors code the input with the model and pack the bits in
+//!   every byte order and padding,
+//! - [tree](Kind::Tree) vectors serialize the model as trees are embedded in block headers,
+//! - [container](Kind::Container) vectors compress the input with the options, covering
+//!   filters, block framing, alignment, checksums and stored blocks.
+//!
+//! Models are the code length of every byte, 0 for bytes without a code, from which the
+//! [canonical](crate::huffman_tree::canonical) code is rebuilt. Containers coded with a
+//! preset are not covered, as they depend on what the reader registered.
+use std::convert::TryFrom;
+use std::iter;
+
+use super::packing::{self, ByteOrder, Layout, Padding};
+use super::{compress, tree, BlockSize, Checksum, Options};
+use crate::filters::Filter;
+use crate::huffman_tree::huffman_encoder::HuffmanEncoder;
+use crate::huffman_tree::huffman_generator::HuffmanGenerator;
+use crate::huffman_tree::{canonical, fibonacci_counts};
+
+/// What the expected bytes of a vector are
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[non_exhaustive]
+pub enum Kind {
+    /// The input coded with the model, packed with the layout
+    Payload(Layout),
+    /// The model written as an embedded tree. The input is the data the model was trained
+    /// on.
+    Tree,
+    /// The container of the input, with the trees embedded. The model is empty.
+    Container(Options),
+}
+
+/// An input and the bytes expected for it
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct TestVector {
+    /// Unique among the vectors, usable as a file name
+    pub name: String,
+    pub kind: Kind,
+    pub input: Vec<u8>,
+    /// The code length of every byte, empty for containers
+    pub model: Vec<u8>,
+    pub expected: Vec<u8>,
+}
+
+/// Returns every vector, always the same for a given version of the format
+/// ## Errors
+/// Returns Err if compressing one of the inputs fails, which is a bug.
+/// ## Examples
+/// ```
+/// # use rhuffman::container::{self, testvectors::{self, Kind}};
+/// for vector in testvectors::generate().unwrap() {
+///     if let Kind::Container(_) = vector.kind {
+///         assert_eq!(container::decompress(&vector.expected).unwrap(), vector.input);
+///     }
+/// }
+/// ```
+pub fn generate() -> Result<Vec<TestVector>, &'static str> {
+    let mut vectors = vec![];
+    for (name, input) in &[
+        ("text", text()),
+        ("skewed", skewed()),
+        ("bytes", all_bytes()),
+    ] {
+        let model = model(input)?;
+        let lengths: Vec<(u8, usize)> = (0..=255u8)
+            .zip(model.iter().map(|&len| usize::from(len)))
+            .filter(|&(_, len)| len > 0)
+            .collect();
+        let bits = HuffmanEncoder::from_code_lengths(&lengths)?
+            .encode(&mut input.iter())
+            .map_err(|_| "The model has no code for a byte of its input")?;
+        for &byte_order in &[
+            ByteOrder::MsbFirst,
+            ByteOrder::LsbFirst,
+            ByteOrder::Words32Le,
+        ] {
+            for &padding in &[Padding::Zeros, Padding::Ones] {
+                let layout = Layout {
+                    byte_order,
+                    padding,
+                };
+                vectors.push(TestVector {
+                    name: format!("payload-{}-{}-{}", name, byte_order, padding),
+                    kind: Kind::Payload(layout),
+                    input: input.clone(),
+                    model: model.clone(),
+                    expected: packing::pack(&bits, layout),
+                });
+            }
+        }
+        let tree = canonical::from_lengths(&lengths).ok_or("Invalid model")?;
+        let mut expected = vec![];
+        tree::write_lengths(&tree, &mut expected);
+        vectors.push(TestVector {
+            name: format!("tree-{}", name),
+            kind: Kind::Tree,
+            input: input.clone(),
+            model,
+            expected,
+        });
+    }
+
+    let blocks = Options {
+        block_size: BlockSize::Fixed(64),
+        ..Options::default()
+    };
+    let containers = [
+        ("empty", vec![], Options::default()),
+        ("single-symbol", vec![b'a'; 40], Options::default()),
+        ("text", text(), Options::default()),
+        ("blocks", text(), blocks),
+        (
+            "lsb-ones",
+            text(),
+            Options {
+                byte_order: ByteOrder::LsbFirst,
+                padding: Padding::Ones,
+                ..blocks
+            },
+        ),
+        (
+            "words32le",
+            skewed(),
+            Options {
+                byte_order: ByteOrder::Words32Le,
+                ..blocks
+            },
+        ),
+        (
+            "aligned",
+            text(),
+            Options {
+                align: 32,
+                ..blocks
+            },
+        ),
+        (
+            "stored",
+            all_bytes(),
+            Options {
+                stored: true,
+                ..blocks
+            },
+        ),
+        (
+            "crc32",
+            text(),
+            Options {
+                checksum: Checksum::Crc32,
+                ..Options::default()
+            },
+        ),
+        (
+            "xxhash64-blocks",
+            text(),
+            Options {
+                checksum: Checksum::XxHash64,
+                block_checksums: true,
+                ..blocks
+            },
+        ),
+        (
+            "blake3",
+            skewed(),
+            Options {
+                checksum: Checksum::Blake3,
+                ..Options::default()
+            },
+        ),
+        ("timeseries", ramp(), filtered(Filter::Timeseries)),
+        ("nibbles", all_bytes(), filtered(Filter::Nibbles)),
+        ("nibble-planes", all_bytes(), filtered(Filter::NibblePlanes)),
+        ("rle", runs(), filtered(Filter::Rle)),
+    ];
+    for (name, input, options) in containers.iter() {
+        vectors.push(TestVector {
+            name: format!("container-{}", name),
+            kind: Kind::Container(*options),
+            input: input.clone(),
+            model: vec![],
+            expected: compress(input, options)?,
+        });
+    }
+    Ok(vectors)
+}
+
+fn filtered(filter: Filter) -> Options {
+    Options {
+        filter,
+        ..Options::default()
+    }
+}
+
+/// The code length of every byte of the tree trained on the input
+fn model(input: &[u8]) -> Result<Vec<u8>, &'static str> {
+    let mut gen = HuffmanGenerator::new();
+    gen.add_occurences_from_iterator(&mut input.iter());
+    let tree = gen.into_huffman_tree().ok_or("Empty input")?;
+    let mut model = vec![0; 256];
+    for (symbol, len) in canonical::code_lengths(&tree) {
+        model[usize::from(symbol)] = u8::try_from(len).map_err(|_| "Code too long")?;
+    }
+    Ok(model)
+}
+
+fn text() -> Vec<u8> {
+    b"The quick brown fox jumps over the lazy dog; pack my box with five dozen liquor jugs!"
+        .repeat(3)
+}
+
+/// Byte `i` occurs the `i`th Fibonacci number of times, for codes of up to 15 bits
+fn skewed() -> Vec<u8> {
+    let mut data = vec![];
+    for (symbol, count) in (0..16u8).zip(fibonacci_counts(16)) {
+        data.extend(iter::repeat_n(symbol, count));
+    }
+    // Interleaved, so the payload is not a few long runs
+    (0..data.len()).map(|i| data[i * 11 % data.len()]).collect()
+}
+
+/// Every byte, each occurring 1 to 7 times
+fn all_bytes() -> Vec<u8> {
+    (0..=255u8)
+        .flat_map(|byte| iter::repeat_n(byte, usize::from(byte) % 7 + 1))
+        .collect()
+}
+
+/// Slowly increasing samples
+fn ramp() -> Vec<u8> {
+    (0..600u32).map(|i| (i / 5 + i % 3) as u8).collect()
+}
+
+fn runs() -> Vec<u8> {
+    [(b'x', 300), (b'y', 2), (b'z', 150), (b'x', 1), (0, 500)]
+        .iter()
+        .flat_map(|&(byte, len)| iter::repeat_n(byte, len))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::decompress;
+    use super::*;
+    use crate::huffman_tree::huffman_decoder::HuffmanDecoder;
+    use std::collections::HashSet;
+
+    #[test]
+    fn vectors_decode_to_their_input() {
+        let vectors = generate().unwrap();
+        assert_eq!(vectors, generate().unwrap());
+        let names: HashSet<&str> = vectors.iter().map(|vector| vector.name.as_str()).collect();
+        assert_eq!(names.len(), vectors.len());
+
+        for vector in &vectors {
+            let lengths: Vec<(u8, usize)> = (0..=255u8)
+                .zip(vector.model.iter().map(|&len| usize::from(len)))
+                .filter(|&(_, len)| len > 0)
+                .collect();
+            match vector.kind {
+                Kind::Payload(layout) => {
+                    let decoder = HuffmanDecoder::from_code_lengths(&lengths).unwrap();
+                    let bit_len = vector
+                        .input
+                        .iter()
+                        .map(|&byte| usize::from(vector.model[usize::from(byte)]))
+                        .sum();
+                    let bits = packing::unpack(&vector.expected, bit_len, layout).unwrap();
+                    assert_eq!(decoder.decode_unbounded(&bits).unwrap(), vector.input);
+                }
+                Kind::Tree => {
+                    let tree = tree::read_lengths(&vector.expected, &mut 0).unwrap();
+                    assert_eq!(Some(tree), canonical::from_lengths(&lengths));
+                }
+                Kind::Container(_) => {
+                    assert_eq!(decompress(&vector.expected).unwrap(), vector.input)
+                }
+            }
+        }
+    }
+}
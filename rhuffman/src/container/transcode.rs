@@ -0,0 +1,198 @@
+//! Re-encoding a container with other options, e.g. to move an archive to a newer format
+//! version or another block size.
+//!
+//! Blocks are decoded one at a time and re-encoded as soon as enough data is decoded to fill
+//! a block of the new size, so only about a block of each size is ever decoded at once
+//! instead of the whole data. Filters and checksums work on the whole data though: when
+//! either side has one, the data is decompressed then compressed again.
+use bit_vec::BitVec;
+
+use super::block_size::{self, PROBE_LEN};
+use super::{
+    check_plugins, compress, decode_block, decompress, encode_block, read_container,
+    registered_coder, write_block, write_header, BlockSize, Checksum, Codebook, CompressReport,
+    Container, ContainerFormat, DecodeOptions, Options,
+};
+use crate::filters::Filter;
+
+/// Re-encodes a container with the options, giving the same container as compressing the
+/// decompressed data with them.
+/// ## Errors
+/// Returns Err if the data can not be decompressed, or the preset of the options is not
+/// registered or lacks a code for one of the bytes.
+/// ## Examples
+/// ```
+/// # use rhuffman::container::{self, BlockSize, Options};
+/// let data = vec![7u8; 10_000];
+/// let compressed = container::compress(&data, &Options::default()).unwrap();
+/// let options = Options {
+///     block_size: BlockSize::Fixed(1000),
+///     ..Options::default()
+/// };
+/// let transcoded = container::transcode(&compressed, &options).unwrap();
+/// assert_eq!(container::inspect(&transcoded).unwrap().blocks.len(), 10);
+/// assert_eq!(container::decompress(&transcoded).unwrap(), data);
+/// ```
+pub fn transcode(data: &[u8], options: &Options) -> Result<Vec<u8>, &'static str> {
+    let source = match ContainerFormat::detect(data) {
+        Some(ContainerFormat::Minimal) | Some(ContainerFormat::Legacy) | None => None,
+        Some(_) => Some(read_container(data)?),
+    };
+    if let Some(source) = &source {
+        // The data is not decoded past the entropy coding, the plugins are left as they are
+        check_plugins(source.plugins, options.plugins)?;
+    }
+    let blocks = match source {
+        Some(Container {
+            filter: Filter::None,
+            checksum: Checksum::None,
+            blocks,
+            ..
+        }) if options.filter == Filter::None && options.checksum == Checksum::None => blocks,
+        _ => return compress(&decompress(data)?, options),
+    };
+
+    let total: u64 = blocks.iter().map(|block| block.header.symbol_count).sum();
+    let mut blocks = blocks.into_iter();
+    let (mut bits, mut pending) = (BitVec::new(), vec![]);
+    let decode_options = DecodeOptions::default();
+    // Decodes blocks until `len` bytes are pending or every block is decoded
+    let mut fill = |pending: &mut Vec<u8>, len: usize| -> Result<(), &'static str> {
+        while pending.len() < len {
+            match blocks.next() {
+                Some(block) => decode_block(block, &decode_options, &mut bits, pending)?,
+                None => break,
+            }
+        }
+        Ok(())
+    };
+
+    let block_size = match options.block_size {
+        BlockSize::Auto => {
+            // Only the start of the data is probed
+            fill(&mut pending, PROBE_LEN)?;
+            block_size::probe(&pending)
+        }
+        BlockSize::Whole => 0,
+        BlockSize::Fixed(size) => size,
+    };
+    let block_count = if block_size == 0 || total <= block_size as u64 {
+        1
+    } else {
+        total.div_ceil(block_size as u64) as usize
+    };
+
+    let (mut out, mut scratch) = (vec![], BitVec::new());
+    let mut aligner = write_header(options, &[], block_size, block_count, &mut out);
+    let coder = registered_coder(options, None)?;
+    let mut report = CompressReport::default();
+    let (layout, checksum) = (options.layout(), options.block_checksum());
+    for _ in 0..block_count {
+        let len = match block_count {
+            1 => usize::MAX,
+            _ => block_size,
+        };
+        fill(&mut pending, len)?;
+        let end = pending.len().min(len);
+        let block = &pending[..end];
+        aligner.next(&mut out);
+        if options.stored {
+            let codebook = &Codebook::Stored;
+            write_block(
+                block,
+                codebook,
+                layout,
+                checksum,
+                &mut scratch,
+                &mut out,
+                &mut report,
+            )?;
+        } else {
+            encode_block(
+                block,
+                &coder,
+                layout,
+                checksum,
+                &mut scratch,
+                &mut out,
+                &mut report,
+            )?;
+        }
+        pending.drain(..end);
+    }
+    aligner.next(&mut out);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{ByteOrder, Content};
+    use super::*;
+
+    #[test]
+    fn transcoding_matches_compressing_again() {
+        let data: Vec<u8> = (0..20_000u32).map(|i| (i % 251 * i % 17) as u8).collect();
+        let sources = [
+            Options::default(),
+            Options {
+                block_size: BlockSize::Fixed(3000),
+                ..Options::default()
+            },
+            Options {
+                filter: Filter::Timeseries,
+                ..Options::default()
+            },
+            Options {
+                checksum: Checksum::Crc32,
+                ..Options::default()
+            },
+        ];
+        let targets = [
+            Options::default(),
+            Options {
+                block_size: BlockSize::Fixed(7000),
+                byte_order: ByteOrder::Words32Le,
+                content: Content::Tar,
+                ..Options::default()
+            },
+            Options {
+                block_size: BlockSize::Whole,
+                ..Options::default()
+            },
+            Options {
+                block_size: BlockSize::Fixed(5000),
+                align: 512,
+                ..Options::default()
+            },
+            Options {
+                filter: Filter::Timeseries,
+                ..Options::default()
+            },
+            Options {
+                checksum: Checksum::Blake3,
+                ..Options::default()
+            },
+        ];
+        for source in &sources {
+            let compressed = compress(&data, source).unwrap();
+            for target in &targets {
+                assert_eq!(
+                    transcode(&compressed, target).unwrap(),
+                    compress(&data, target).unwrap()
+                );
+            }
+        }
+        let empty = compress(b"", &Options::default()).unwrap();
+        assert_eq!(
+            transcode(&empty, &targets[1]).unwrap(),
+            compress(b"", &targets[1]).unwrap()
+        );
+    }
+
+    #[test]
+    fn corrupted_sources_are_rejected() {
+        let compressed = compress(b"hello world", &Options::default()).unwrap();
+        assert!(transcode(&compressed[..compressed.len() - 1], &Options::default()).is_err());
+        assert!(transcode(b"garbage", &Options::default()).is_err());
+    }
+}
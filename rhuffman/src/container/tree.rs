@@ -0,0 +1,201 @@
+//! Encodings of the trees embedded in container headers.
+//!
+//! Trees are written as the code length of every byte, from which the reader rebuilds the
+//! [canonical](crate::huffman_tree::canonical) tree. The 256 lengths are split in runs of
+//! equal lengths, each written against a fixed meta-code, much like DEFLATE's code length
+//! code:
+//!
+//! - a `0` bit then the run length for a run of absent bytes,
+//! - a `1` bit, the change from the previous present length, then the run length.
+//!
+//! Run lengths, and zigzagged length changes plus one, are written as Elias gamma codes. The
+//! bits are padded with zeros to a whole byte.
+//!
+//! Containers written before lengths were used store the tree in pre-order instead, a `0`
+//! byte for a branch and a `1` byte followed by the symbol for a leaf.
+use bit_vec::BitVec;
+
+use crate::huffman_tree::canonical;
+use crate::huffman_tree::huffman_generator::HuffmanTree;
+use crate::huffman_tree::huffman_node::{HuffmanBranch, HuffmanLeaf, HuffmanNode};
+
+const NODE_BRANCH: u8 = 0;
+const NODE_LEAF: u8 = 1;
+
+const ALPHABET: usize = 256;
+
+/// Writes the code lengths of the tree. Readers get back the canonical tree for those
+/// lengths, so the payload must be encoded with [canonical::from_lengths()] of them.
+pub fn write_lengths(tree: &HuffmanTree<u8>, out: &mut Vec<u8>) {
+    let mut lengths = [0usize; ALPHABET];
+    for (symbol, len) in canonical::code_lengths(tree) {
+        // A lone leaf has an empty code, but zero marks absent bytes
+        lengths[symbol as usize] = len.max(1);
+    }
+
+    let mut bits = BitVec::new();
+    let mut previous = 0;
+    let mut start = 0;
+    while start < ALPHABET {
+        let len = lengths[start];
+        let run = lengths[start..].iter().take_while(|&&l| l == len).count();
+        if len == 0 {
+            bits.push(false);
+        } else {
+            bits.push(true);
+            let change = len as i64 - previous as i64;
+            write_gamma(((change << 1) ^ (change >> 63)) as u64 + 1, &mut bits);
+            previous = len;
+        }
+        write_gamma(run as u64, &mut bits);
+        start += run;
+    }
+    out.extend_from_slice(&bits.to_bytes());
+}
+
+/// Reads the code lengths written by [write_lengths()] and rebuilds the canonical tree
+pub fn read_lengths(data: &[u8], pos: &mut usize) -> Result<HuffmanTree<u8>, &'static str> {
+    let mut reader = BitReader {
+        data: &data[(*pos).min(data.len())..],
+        pos: 0,
+    };
+    let mut lengths = vec![];
+    let mut previous = 0i64;
+    let mut symbol = 0;
+    while symbol < ALPHABET {
+        let present = reader.read_bit()?;
+        let len = if present {
+            let zigzag = reader.read_gamma()? - 1;
+            let change = ((zigzag >> 1) as i64) ^ -((zigzag & 1) as i64);
+            previous += change;
+            // A tree of byte symbols can not be deeper than this
+            if !(1..=255).contains(&previous) {
+                return Err("Invalid tree");
+            }
+            previous as usize
+        } else {
+            0
+        };
+        let run = reader.read_gamma()? as usize;
+        if run > ALPHABET - symbol {
+            return Err("Invalid tree");
+        }
+        if len > 0 {
+            lengths.extend((symbol..symbol + run).map(|s| (s as u8, len)));
+        }
+        symbol += run;
+    }
+    *pos += reader.pos.div_ceil(8);
+    canonical::from_lengths(&lengths).ok_or("Invalid tree")
+}
+
+/// Reads a tree stored in pre-order
+pub fn read_preorder(
+    data: &[u8],
+    pos: &mut usize,
+    depth: usize,
+) -> Result<HuffmanTree<u8>, &'static str> {
+    // A tree of byte symbols can not be deeper than this
+    if depth > 255 {
+        return Err("Invalid tree");
+    }
+    let tag = *data.get(*pos).ok_or("Truncated tree")?;
+    *pos += 1;
+    match tag {
+        NODE_LEAF => {
+            let symbol = *data.get(*pos).ok_or("Truncated tree")?;
+            *pos += 1;
+            Ok(HuffmanNode::Leaf(HuffmanLeaf::new(symbol)))
+        }
+        NODE_BRANCH => {
+            let left = read_preorder(data, pos, depth + 1)?;
+            let right = read_preorder(data, pos, depth + 1)?;
+            Ok(HuffmanNode::Branch(HuffmanBranch::new(left, right)))
+        }
+        _ => Err("Invalid tree"),
+    }
+}
+
+/// Elias gamma code of `value`, which must not be zero
+fn write_gamma(value: u64, bits: &mut BitVec) {
+    let width = 64 - value.leading_zeros();
+    bits.grow(width as usize - 1, false);
+    for shift in (0..width).rev() {
+        bits.push((value >> shift) & 1 == 1);
+    }
+}
+
+/// Reads bits most significant first, like `BitVec::to_bytes()` writes them
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl BitReader<'_> {
+    fn read_bit(&mut self) -> Result<bool, &'static str> {
+        let byte = self.data.get(self.pos / 8).ok_or("Truncated tree")?;
+        let bit = byte & (0x80 >> (self.pos % 8)) != 0;
+        self.pos += 1;
+        Ok(bit)
+    }
+
+    fn read_gamma(&mut self) -> Result<u64, &'static str> {
+        let mut width = 1;
+        while !self.read_bit()? {
+            width += 1;
+            if width > 64 {
+                return Err("Invalid tree");
+            }
+        }
+        let mut value = 1u64;
+        for _ in 1..width {
+            value = (value << 1) | self.read_bit()? as u64;
+        }
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::huffman_tree::huffman_generator::HuffmanGenerator;
+
+    fn canonical_tree(data: &[u8]) -> HuffmanTree<u8> {
+        let mut gen = HuffmanGenerator::new();
+        gen.add_occurences_from_iterator(&mut data.iter());
+        let tree = gen.into_huffman_tree().unwrap();
+        canonical::from_lengths(&canonical::code_lengths(&tree)).unwrap()
+    }
+
+    #[test]
+    fn lengths_round_trip() {
+        let every_byte: Vec<u8> = (0..=255u8).chain(0..10).collect();
+        for data in &[&b"x"[..], b"ab", b"hello world", &every_byte] {
+            let tree = canonical_tree(data);
+            let mut out = vec![0xaa];
+            write_lengths(&tree, &mut out);
+            let written = out.len();
+            out.push(0x55);
+
+            let mut pos = 1;
+            assert_eq!(read_lengths(&out, &mut pos).unwrap(), tree);
+            assert_eq!(pos, written);
+        }
+    }
+
+    #[test]
+    fn lengths_are_smaller_than_preorder() {
+        let text = b"The quick brown fox jumps over the lazy dog, again and again.";
+        let mut out = vec![];
+        write_lengths(&canonical_tree(text), &mut out);
+        // Pre-order takes a byte per branch and two per leaf
+        let preorder = canonical::code_lengths(&canonical_tree(text)).len() * 3 - 1;
+        assert!(out.len() * 2 < preorder);
+    }
+
+    #[test]
+    fn garbage_is_rejected() {
+        assert!(read_lengths(&[0xff; 4], &mut 0).is_err());
+        assert!(read_lengths(&[], &mut 0).is_err());
+    }
+}
@@ -0,0 +1,335 @@
+//! Compressing data as it is written, for pipelines that never hold all of it. The output is
+//! a streamed container, see the [module documentation](super).
+use bit_vec::BitVec;
+use std::io::{self, Write};
+
+use super::checksum::Digester;
+use super::{
+    train, write_block, write_stream_end, write_streamed_header, BlockSize, Codebook,
+    CompressReport, Filter, HeaderKind, Options,
+};
+use crate::huffman_tree::canonical;
+use crate::huffman_tree::huffman_encoder::HuffmanEncoder;
+use crate::huffman_tree::huffman_generator::HuffmanTree;
+
+/// Size of the blocks of a [HuffmanWriter] whose options do not give a fixed one
+pub const DEFAULT_STREAM_BLOCK_SIZE: usize = 1 << 20;
+
+/// Compresses the bytes written to it with a tree chosen up front, writing a block to the
+/// inner writer each time the block size is reached. The tree is embedded in every block.
+/// [finish()](HuffmanWriter::finish) must be called once everything is written: it writes
+/// the last block and the end of the container, without which readers reject it.
+/// ## Examples
+/// ```
+/// # use rhuffman::container::{self, HuffmanWriter};
+/// # use rhuffman::huffman_tree::huffman_generator::HuffmanGenerator;
+/// # use std::io;
+/// let input = b"the quick brown fox jumps over the lazy dog".repeat(100);
+/// let mut gen = HuffmanGenerator::new();
+/// gen.add_occurences_from_iterator(&mut input.iter());
+/// let tree = gen.into_huffman_tree().unwrap();
+///
+/// let mut writer = HuffmanWriter::new(vec![], tree);
+/// io::copy(&mut &input[..], &mut writer).unwrap();
+/// let compressed = writer.finish().unwrap();
+/// assert_eq!(container::decompress(&compressed).unwrap(), input);
+/// ```
+pub struct HuffmanWriter<W: Write> {
+    out: W,
+    trees: Trees,
+    options: Options,
+    block_size: usize,
+    /// Data of the block being filled
+    block: Vec<u8>,
+    block_count: u64,
+    digester: Digester,
+    bits: BitVec,
+    /// Bytes not yet written to `out`, starting with the header
+    encoded: Vec<u8>,
+}
+
+/// The trees the blocks of a [HuffmanWriter] are coded with
+enum Trees {
+    /// The tree the writer was created with, for every block
+    Fixed {
+        tree: HuffmanTree<u8>,
+        encoder: HuffmanEncoder<u8>,
+        /// Bytes the tree has a code for
+        coded: Box<[bool; 256]>,
+    },
+    /// A tree trained on the data of each block
+    PerBlock,
+}
+
+impl<W: Write> HuffmanWriter<W> {
+    /// Writes a container with the [default options](Options::default) to `out`, in blocks
+    /// of [DEFAULT_STREAM_BLOCK_SIZE] bytes. Writing a byte the tree has no code for fails.
+    pub fn new(out: W, tree: HuffmanTree<u8>) -> HuffmanWriter<W> {
+        // Readers rebuild the canonical tree from the code lengths embedded in each block
+        let tree = canonical::from_lengths(&canonical::code_lengths(&tree)).unwrap_or(tree);
+        let mut coded = [false; 256];
+        tree.visit(&mut |_: &[bool], &symbol: &u8| coded[symbol as usize] = true);
+        let trees = Trees::Fixed {
+            encoder: HuffmanEncoder::from_tree(&tree),
+            tree,
+            coded: Box::new(coded),
+        };
+        HuffmanWriter::build(out, trees, &Options::default(), DEFAULT_STREAM_BLOCK_SIZE)
+    }
+
+    /// Same as [new()](HuffmanWriter::new) with the options. The block size is the fixed one
+    /// of the options if any, [DEFAULT_STREAM_BLOCK_SIZE] otherwise.
+    /// ## Errors
+    /// Returns Err if the options have a filter, a preset, a backend, aligned or stored blocks,
+    /// or a minimal header, none of which can be streamed.
+    pub fn with_options(
+        out: W,
+        tree: HuffmanTree<u8>,
+        options: &Options,
+    ) -> Result<HuffmanWriter<W>, &'static str> {
+        let block_size = stream_block_size(options)?;
+        let mut writer = HuffmanWriter::new(out, tree);
+        writer.encoded.clear();
+        write_streamed_header(options, block_size, &mut writer.encoded);
+        writer.options = *options;
+        writer.block_size = block_size;
+        writer.digester = Digester::new(options.checksum);
+        Ok(writer)
+    }
+
+    /// Same as [with_options()](HuffmanWriter::with_options), coding each block with a tree
+    /// trained on its own data, as [compress()](super::compress) does, for data that is not
+    /// known up front. Every byte can be written.
+    /// ## Errors
+    /// Returns Err for the options [with_options()](HuffmanWriter::with_options) rejects.
+    /// ## Examples
+    /// ```
+    /// # use rhuffman::container::{self, BlockSize, HuffmanWriter, Options};
+    /// # use std::io::Write;
+    /// let options = Options {
+    ///     block_size: BlockSize::Fixed(1000),
+    ///     ..Options::default()
+    /// };
+    /// let mut writer = HuffmanWriter::trained(vec![], &options).unwrap();
+    /// writer.write_all(&[b'a'; 1500]).unwrap();
+    /// writer.write_all(&b"any byte at all".repeat(100)).unwrap();
+    /// let compressed = writer.finish().unwrap();
+    /// assert_eq!(container::inspect(&compressed).unwrap().blocks.len(), 3);
+    /// ```
+    pub fn trained(out: W, options: &Options) -> Result<HuffmanWriter<W>, &'static str> {
+        let block_size = stream_block_size(options)?;
+        Ok(HuffmanWriter::build(
+            out,
+            Trees::PerBlock,
+            options,
+            block_size,
+        ))
+    }
+
+    fn build(out: W, trees: Trees, options: &Options, block_size: usize) -> HuffmanWriter<W> {
+        let mut encoded = vec![];
+        write_streamed_header(options, block_size, &mut encoded);
+        HuffmanWriter {
+            out,
+            trees,
+            options: *options,
+            block_size,
+            block: vec![],
+            block_count: 0,
+            digester: Digester::new(options.checksum),
+            bits: BitVec::new(),
+            encoded,
+        }
+    }
+
+    /// Writes the last block and the end of the container, and returns the inner writer
+    pub fn finish(mut self) -> io::Result<W> {
+        if !self.block.is_empty() {
+            self.encode_block()?;
+        }
+        let digester = std::mem::replace(&mut self.digester, Digester::None);
+        write_stream_end(self.block_count, &digester.finish(), &mut self.encoded);
+        self.out.write_all(&self.encoded)?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    fn encode_block(&mut self) -> io::Result<()> {
+        let trained;
+        let codebook = match &self.trees {
+            Trees::Fixed { tree, encoder, .. } => Codebook::Embedded(tree, encoder),
+            Trees::PerBlock => {
+                // Blocks are never empty, so they always have a tree
+                let tree = train(&[&self.block]).ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidInput, "Empty blocks have no tree")
+                })?;
+                let encoder = HuffmanEncoder::from_tree(&tree);
+                trained = (tree, encoder);
+                Codebook::Embedded(&trained.0, &trained.1)
+            }
+        };
+        let (layout, checksum) = (self.options.layout(), self.options.block_checksum());
+        let report = &mut CompressReport::default();
+        let bits = &mut self.bits;
+        write_block(
+            &self.block,
+            &codebook,
+            layout,
+            checksum,
+            bits,
+            &mut self.encoded,
+            report,
+        )
+        .map_err(|message| io::Error::new(io::ErrorKind::InvalidInput, message))?;
+        self.block.clear();
+        self.block_count += 1;
+        self.out.write_all(&self.encoded)?;
+        self.encoded.clear();
+        Ok(())
+    }
+}
+
+/// The block size of a streamed container with the options
+fn stream_block_size(options: &Options) -> Result<usize, &'static str> {
+    if options.filter != Filter::None {
+        return Err("Filters need the whole data, it can not be streamed");
+    }
+    if options.align > 0 {
+        return Err("Streamed containers can not be aligned");
+    }
+    if options.preset.is_some() || options.backend.is_some() || options.stored {
+        return Err("Streamed blocks are coded with the tree of the writer");
+    }
+    if options.header == HeaderKind::Minimal {
+        return Err("Minimal headers can not be streamed");
+    }
+    Ok(match options.block_size {
+        BlockSize::Fixed(size) if size > 0 => size,
+        _ => DEFAULT_STREAM_BLOCK_SIZE,
+    })
+}
+
+impl<W: Write> Write for HuffmanWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let room = self.block_size - self.block.len();
+        let buf = &buf[..buf.len().min(room)];
+        let uncoded = match &self.trees {
+            Trees::Fixed { coded, .. } => buf.iter().any(|&byte| !coded[byte as usize]),
+            Trees::PerBlock => false,
+        };
+        if uncoded {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The data contains a byte the tree has no code for",
+            ));
+        }
+        self.digester.update(buf);
+        self.block.extend_from_slice(buf);
+        if self.block.len() == self.block_size {
+            self.encode_block()?;
+        }
+        Ok(buf.len())
+    }
+
+    /// Flushes the inner writer. The block being filled is only written once full or on
+    /// [finish()](HuffmanWriter::finish).
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{decompress, inspect, Checksum};
+    use super::*;
+    use crate::huffman_tree::huffman_generator::HuffmanGenerator;
+
+    fn tree_of(data: &[u8]) -> HuffmanTree<u8> {
+        let mut gen = HuffmanGenerator::new();
+        gen.add_occurences_from_iterator(&mut data.iter());
+        gen.into_huffman_tree().unwrap()
+    }
+
+    #[test]
+    fn streamed_containers_round_trip() {
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i * i % 251) as u8 % 23).collect();
+        for checksum in [Checksum::None, Checksum::XxHash64, Checksum::Blake3] {
+            let options = Options {
+                block_size: BlockSize::Fixed(999),
+                checksum,
+                block_checksums: true,
+                ..Options::default()
+            };
+            let mut writer = HuffmanWriter::with_options(vec![], tree_of(&data), &options).unwrap();
+            for piece in data.chunks(777) {
+                writer.write_all(piece).unwrap();
+            }
+            let compressed = writer.finish().unwrap();
+            assert_eq!(decompress(&compressed).unwrap(), data);
+
+            let header = inspect(&compressed).unwrap();
+            assert!(header.streamed);
+            assert_eq!(header.blocks.len(), 11);
+            assert_eq!(header.digest, checksum.digest(&data));
+        }
+
+        let empty = HuffmanWriter::new(vec![], tree_of(b"a")).finish().unwrap();
+        assert_eq!(decompress(&empty).unwrap(), b"");
+    }
+
+    #[test]
+    fn trained_blocks_round_trip() {
+        // Each block has bytes the others do not
+        let data: Vec<u8> = (0..5_000u32)
+            .map(|i| (i / 1000 * 50 + i % 7) as u8)
+            .collect();
+        let options = Options {
+            block_size: BlockSize::Fixed(1000),
+            checksum: Checksum::Crc32,
+            ..Options::default()
+        };
+        let mut writer = HuffmanWriter::trained(vec![], &options).unwrap();
+        for piece in data.chunks(333) {
+            writer.write_all(piece).unwrap();
+        }
+        let compressed = writer.finish().unwrap();
+        assert_eq!(decompress(&compressed).unwrap(), data);
+        assert_eq!(inspect(&compressed).unwrap().blocks.len(), 5);
+
+        let empty = HuffmanWriter::trained(vec![], &options).unwrap();
+        assert_eq!(decompress(&empty.finish().unwrap()).unwrap(), b"");
+    }
+
+    #[test]
+    fn unfinished_or_uncoded_streams_fail() {
+        let data = b"abcabcabc".repeat(50);
+        let mut writer = HuffmanWriter::with_options(
+            vec![],
+            tree_of(&data),
+            &Options {
+                block_size: BlockSize::Fixed(100),
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        writer.write_all(&data).unwrap();
+        assert!(writer.write_all(b"abz").is_err());
+        let mut compressed = writer.finish().unwrap();
+        assert_eq!(decompress(&compressed).unwrap(), data);
+
+        // Without its end, a stream is rejected rather than read as shorter
+        compressed.truncate(compressed.len() - 2);
+        assert!(decompress(&compressed).is_err());
+
+        let filtered = Options {
+            filter: Filter::Rle,
+            ..Options::default()
+        };
+        assert!(HuffmanWriter::with_options(vec![], tree_of(&data), &filtered).is_err());
+        let minimal = Options {
+            header: HeaderKind::Minimal,
+            ..Options::default()
+        };
+        assert!(HuffmanWriter::with_options(vec![], tree_of(&data), &minimal).is_err());
+    }
+}
@@ -0,0 +1,315 @@
+//! ZIP archives whose entries are containers, so that standard zip tools can still list
+//! them.
+//!
+//! Entries are stored uncompressed as far as zip is concerned, their data being the
+//! container of the original file. An extra field marks them and records the original size:
+//!
+//! | Field         | Encoding                         |
+//! |---------------|----------------------------------|
+//! | header id     | `u16` [EXTRA_ID], `RH` on disk   |
+//! | data size     | `u16`, 9                         |
+//! | version       | `u8`, 1                          |
+//! | original size | `u64`                            |
+//!
+//! Zip tools list the names and the container sizes, and extract the containers; [ZipReader]
+//! extracts the original files. Archives are limited to 65535 entries of less than 4 GiB, as
+//! zip64 is not supported.
+use std::convert::{TryFrom, TryInto};
+
+use super::checksum::crc32;
+use super::{compress, decompress, Options};
+
+/// Id of the extra field marking entries holding a container
+pub const EXTRA_ID: u16 = u16::from_le_bytes(*b"RH");
+const EXTRA_VERSION: u8 = 1;
+const EXTRA_LEN: u16 = 9;
+
+const LOCAL_HEADER: u32 = 0x0403_4b50;
+const CENTRAL_HEADER: u32 = 0x0201_4b50;
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x0605_4b50;
+const LOCAL_HEADER_LEN: usize = 30;
+const CENTRAL_HEADER_LEN: usize = 46;
+const END_OF_CENTRAL_DIRECTORY_LEN: usize = 22;
+
+/// Zip 1.0, enough for stored entries
+const VERSION_NEEDED: u16 = 10;
+/// Names are UTF-8
+const FLAG_UTF8: u16 = 1 << 11;
+const METHOD_STORED: u16 = 0;
+/// 1980-01-01, the earliest date zip can store
+const DOS_DATE: u16 = (1 << 5) | 1;
+
+/// Builds a zip archive in memory.
+/// ## Examples
+/// ```
+/// # use rhuffman::container::{Options, zip::{ZipReader, ZipWriter}};
+/// let mut writer = ZipWriter::new();
+/// writer.add("notes.txt", b"to be or not to be", &Options::default()).unwrap();
+/// let archive = writer.finish();
+///
+/// let reader = ZipReader::new(&archive).unwrap();
+/// let entry = &reader.entries()[0];
+/// assert_eq!(entry.name, "notes.txt");
+/// assert_eq!(reader.read(entry).unwrap(), b"to be or not to be");
+/// ```
+#[derive(Debug, Default)]
+pub struct ZipWriter {
+    out: Vec<u8>,
+    central_directory: Vec<u8>,
+    entries: u16,
+}
+
+impl ZipWriter {
+    pub fn new() -> ZipWriter {
+        ZipWriter::default()
+    }
+
+    /// Compresses the data into a container and appends it as an entry named `name`
+    /// ## Errors
+    /// Returns Err if the data can not be compressed with the options, the archive is full,
+    /// or the name or the container are too large for a zip archive.
+    pub fn add(&mut self, name: &str, data: &[u8], options: &Options) -> Result<(), &'static str> {
+        let container = compress(data, options)?;
+        let mut extra = vec![];
+        extra.extend_from_slice(&EXTRA_ID.to_le_bytes());
+        extra.extend_from_slice(&EXTRA_LEN.to_le_bytes());
+        extra.push(EXTRA_VERSION);
+        extra.extend_from_slice(&(data.len() as u64).to_le_bytes());
+
+        let too_large = "The entry is too large for a zip archive";
+        let size = u32::try_from(container.len()).map_err(|_| too_large)?;
+        let name_len = u16::try_from(name.len()).map_err(|_| "The name is too long")?;
+        let offset = u32::try_from(self.out.len()).map_err(|_| too_large)?;
+        let entries = self
+            .entries
+            .checked_add(1)
+            .ok_or("The archive has too many entries")?;
+        let crc = crc32(&container);
+
+        let out = &mut self.out;
+        out.extend_from_slice(&LOCAL_HEADER.to_le_bytes());
+        for field in &[VERSION_NEEDED, FLAG_UTF8, METHOD_STORED, 0, DOS_DATE] {
+            out.extend_from_slice(&field.to_le_bytes());
+        }
+        for field in &[crc, size, size] {
+            out.extend_from_slice(&field.to_le_bytes());
+        }
+        out.extend_from_slice(&name_len.to_le_bytes());
+        out.extend_from_slice(&(extra.len() as u16).to_le_bytes());
+        out.extend_from_slice(name.as_bytes());
+        out.extend_from_slice(&extra);
+        out.extend_from_slice(&container);
+
+        let central = &mut self.central_directory;
+        central.extend_from_slice(&CENTRAL_HEADER.to_le_bytes());
+        for field in &[
+            VERSION_NEEDED,
+            VERSION_NEEDED,
+            FLAG_UTF8,
+            METHOD_STORED,
+            0,
+            DOS_DATE,
+        ] {
+            central.extend_from_slice(&field.to_le_bytes());
+        }
+        for field in &[crc, size, size] {
+            central.extend_from_slice(&field.to_le_bytes());
+        }
+        for field in &[name_len, extra.len() as u16, 0, 0, 0] {
+            central.extend_from_slice(&field.to_le_bytes());
+        }
+        central.extend_from_slice(&0u32.to_le_bytes());
+        central.extend_from_slice(&offset.to_le_bytes());
+        central.extend_from_slice(name.as_bytes());
+        central.extend_from_slice(&extra);
+
+        self.entries = entries;
+        Ok(())
+    }
+
+    /// Appends the central directory and returns the archive
+    pub fn finish(self) -> Vec<u8> {
+        let mut out = self.out;
+        let offset = out.len() as u32;
+        out.extend_from_slice(&self.central_directory);
+        out.extend_from_slice(&END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+        for field in &[0, 0, self.entries, self.entries] {
+            out.extend_from_slice(&field.to_le_bytes());
+        }
+        out.extend_from_slice(&(self.central_directory.len() as u32).to_le_bytes());
+        out.extend_from_slice(&offset.to_le_bytes());
+        out.extend_from_slice(&0u16.to_le_bytes());
+        out
+    }
+}
+
+/// An entry of a zip archive, as listed by its central directory
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct ZipEntry {
+    /// Name of the entry, invalid UTF-8 replaced
+    pub name: String,
+    /// Size of the file the entry extracts to
+    pub size: u64,
+    /// Size of the entry in the archive
+    pub stored_size: u64,
+    /// Whether the entry holds a container, otherwise it is stored as is
+    pub container: bool,
+    crc: u32,
+    method: u16,
+    offset: usize,
+}
+
+/// Reads zip archives written by [ZipWriter], or any archive of stored entries
+pub struct ZipReader<'a> {
+    data: &'a [u8],
+    entries: Vec<ZipEntry>,
+}
+
+impl<'a> ZipReader<'a> {
+    /// Reads the central directory of the archive
+    /// ## Errors
+    /// Returns Err if the data is not a zip archive or its central directory is corrupted.
+    pub fn new(data: &'a [u8]) -> Result<ZipReader<'a>, &'static str> {
+        // The end of central directory record is followed by a comment of up to 64 KiB
+        let end = (0..=data.len().saturating_sub(END_OF_CENTRAL_DIRECTORY_LEN))
+            .rev()
+            .take(1 << 16)
+            .find(|&pos| u32_at(data, pos) == Some(END_OF_CENTRAL_DIRECTORY))
+            .ok_or("Not a zip archive")?;
+        let count = u16_at(data, end + 10).ok_or("Truncated zip archive")?;
+        let mut pos = u32_at(data, end + 16).ok_or("Truncated zip archive")? as usize;
+
+        let mut entries = vec![];
+        for _ in 0..count {
+            let truncated = "Truncated central directory";
+            if u32_at(data, pos) != Some(CENTRAL_HEADER) {
+                return Err("Invalid central directory");
+            }
+            let method = u16_at(data, pos + 10).ok_or(truncated)?;
+            let crc = u32_at(data, pos + 16).ok_or(truncated)?;
+            let stored_size = u32_at(data, pos + 20).ok_or(truncated)?;
+            let size = u32_at(data, pos + 24).ok_or(truncated)?;
+            let name_len = u16_at(data, pos + 28).ok_or(truncated)? as usize;
+            let extra_len = u16_at(data, pos + 30).ok_or(truncated)? as usize;
+            let comment_len = u16_at(data, pos + 32).ok_or(truncated)? as usize;
+            let offset = u32_at(data, pos + 42).ok_or(truncated)?;
+            let name_start = pos + CENTRAL_HEADER_LEN;
+            let name = data
+                .get(name_start..name_start + name_len)
+                .ok_or(truncated)?;
+            let extra = data
+                .get(name_start + name_len..name_start + name_len + extra_len)
+                .ok_or(truncated)?;
+
+            let original_size = marker(extra);
+            entries.push(ZipEntry {
+                name: String::from_utf8_lossy(name).into_owned(),
+                size: original_size.unwrap_or(u64::from(size)),
+                stored_size: u64::from(stored_size),
+                container: original_size.is_some(),
+                crc,
+                method,
+                offset: offset as usize,
+            });
+            pos = name_start + name_len + extra_len + comment_len;
+        }
+        Ok(ZipReader { data, entries })
+    }
+
+    pub fn entries(&self) -> &[ZipEntry] {
+        &self.entries
+    }
+
+    /// Extracts the entry, decompressing its container if it holds one
+    /// ## Errors
+    /// Returns Err if the entry is not stored, is corrupted, or its container can not be
+    /// decompressed.
+    pub fn read(&self, entry: &ZipEntry) -> Result<Vec<u8>, &'static str> {
+        if entry.method != METHOD_STORED {
+            return Err("Only stored zip entries can be read");
+        }
+        let truncated = "Truncated zip entry";
+        if u32_at(self.data, entry.offset) != Some(LOCAL_HEADER) {
+            return Err("Invalid zip entry");
+        }
+        let name_len = u16_at(self.data, entry.offset + 26).ok_or(truncated)? as usize;
+        let extra_len = u16_at(self.data, entry.offset + 28).ok_or(truncated)? as usize;
+        let start = entry.offset + LOCAL_HEADER_LEN + name_len + extra_len;
+        let stored = self
+            .data
+            .get(start..start + entry.stored_size as usize)
+            .ok_or(truncated)?;
+        if crc32(stored) != entry.crc {
+            return Err("Zip entry checksum mismatch");
+        }
+        if entry.container {
+            decompress(stored)
+        } else {
+            Ok(stored.to_vec())
+        }
+    }
+}
+
+/// The original size recorded by the marker in the extra fields, if there is one
+fn marker(mut extra: &[u8]) -> Option<u64> {
+    while extra.len() >= 4 {
+        let id = u16::from_le_bytes([extra[0], extra[1]]);
+        let len = u16::from_le_bytes([extra[2], extra[3]]) as usize;
+        let field = extra.get(4..4 + len)?;
+        if id == EXTRA_ID && len == EXTRA_LEN as usize && field[0] == EXTRA_VERSION {
+            return Some(u64::from_le_bytes(field[1..].try_into().ok()?));
+        }
+        extra = &extra[4 + len..];
+    }
+    None
+}
+
+fn u16_at(data: &[u8], pos: usize) -> Option<u16> {
+    let bytes = data.get(pos..pos.checked_add(2)?)?;
+    Some(u16::from_le_bytes(bytes.try_into().ok()?))
+}
+
+fn u32_at(data: &[u8], pos: usize) -> Option<u32> {
+    let bytes = data.get(pos..pos.checked_add(4)?)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn archives_round_trip() {
+        let files: Vec<(&str, Vec<u8>)> = vec![
+            ("empty", vec![]),
+            ("dir/text.txt", b"hello hello hello world".to_vec()),
+            ("bytes.bin", (0..=255u8).cycle().take(3000).collect()),
+        ];
+        let mut writer = ZipWriter::new();
+        for (name, data) in &files {
+            writer.add(name, data, &Options::default()).unwrap();
+        }
+        let archive = writer.finish();
+
+        let reader = ZipReader::new(&archive).unwrap();
+        assert_eq!(reader.entries().len(), files.len());
+        for (entry, (name, data)) in reader.entries().iter().zip(&files) {
+            assert_eq!(entry.name, *name);
+            assert_eq!(entry.size, data.len() as u64);
+            assert!(entry.container);
+            assert_eq!(&reader.read(entry).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn corruption_is_detected() {
+        let mut writer = ZipWriter::new();
+        writer.add("a", b"some data", &Options::default()).unwrap();
+        let mut archive = writer.finish();
+        assert!(ZipReader::new(&archive[..10]).is_err());
+
+        archive[LOCAL_HEADER_LEN + 1 + 4 + EXTRA_LEN as usize + 6] ^= 1;
+        let reader = ZipReader::new(&archive).unwrap();
+        assert!(reader.read(&reader.entries()[0]).is_err());
+    }
+}
@@ -0,0 +1,385 @@
+//! The interface entropy coders implement: a model is trained on symbol frequencies, then
+//! codes symbols to bits and bits back to symbols. Code written against [EntropyCoder] works
+//! with any coder, including ones defined in other crates.
+//!
+//! Coders of bytes that can also write their models, see [ModelFormat], code the blocks of
+//! [containers](crate::container) once [registered](Registry::register) in the [global()]
+//! registry: [Options::backend](crate::container::Options::backend) picks one by id, and
+//! each block records the id and the model it was coded with. [Huffman] is registered under
+//! [HUFFMAN]. Without a backend, containers code blocks with trees of their own.
+//! ## Examples
+//! ```
+//! # use rhuffman::container::{self, Options};
+//! # use rhuffman::entropy;
+//! let options = Options {
+//!     backend: Some(entropy::HUFFMAN),
+//!     ..Options::default()
+//! };
+//! let compressed = container::compress(b"abracadabra", &options).unwrap();
+//! assert_eq!(container::decompress(&compressed).unwrap(), b"abracadabra");
+//! ```
+use bit_vec::BitVec;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, OnceLock, RwLock};
+
+use crate::container::tree;
+use crate::huffman_tree::canonical;
+use crate::huffman_tree::frequency_source::{self, FrequencySource};
+use crate::huffman_tree::huffman_decoder::HuffmanDecoder;
+use crate::huffman_tree::huffman_encoder::HuffmanEncoder;
+use crate::huffman_tree::HuffmanTree;
+
+/// Codes symbols of type `T` with models trained on their frequencies.
+/// ## Examples
+/// ```
+/// # use rhuffman::entropy::{EntropyCoder, Huffman};
+/// # use bit_vec::BitVec;
+/// fn round_trip<C: EntropyCoder<u8>>(coder: &C, data: &[u8]) -> Vec<u8> {
+///     let counts: Vec<(u8, u64)> = data.iter().map(|&byte| (byte, 1)).collect();
+///     let model = coder.train(&counts).unwrap();
+///     let mut bits = BitVec::new();
+///     coder.encode(&model, data, &mut bits).unwrap();
+///     let mut decoded = vec![];
+///     coder.decode(&model, &bits, data.len(), &mut decoded).unwrap();
+///     decoded
+/// }
+///
+/// assert_eq!(round_trip(&Huffman, b"abracadabra"), b"abracadabra");
+/// ```
+pub trait EntropyCoder<T> {
+    /// What symbols are coded with, e.g. a tree
+    type Model;
+
+    /// Builds the model of the frequencies of the source
+    /// ## Errors
+    /// Returns Err if the source lists no symbol.
+    fn train<S: FrequencySource<T> + ?Sized>(
+        &self,
+        source: &S,
+    ) -> Result<Self::Model, &'static str>;
+
+    /// Appends the bits coding the symbols to `out`
+    /// ## Errors
+    /// Returns Err if the model can not code one of the symbols.
+    fn encode(
+        &self,
+        model: &Self::Model,
+        symbols: &[T],
+        out: &mut BitVec,
+    ) -> Result<(), &'static str>;
+
+    /// Appends the `count` symbols coded by the bits to `out`
+    /// ## Errors
+    /// Returns Err if the bits do not code `count` symbols with the model.
+    fn decode(
+        &self,
+        model: &Self::Model,
+        bits: &BitVec,
+        count: usize,
+        out: &mut Vec<T>,
+    ) -> Result<(), &'static str>;
+}
+
+/// Coders whose models can be written next to the bits they code, as containers need to
+/// decode blocks coded by a [registered](Registry::register) coder
+pub trait ModelFormat<T>: EntropyCoder<T> {
+    /// Appends the model to `out`
+    fn write_model(&self, model: &Self::Model, out: &mut Vec<u8>);
+
+    /// Reads back a model written by [write_model()](ModelFormat::write_model)
+    /// ## Errors
+    /// Returns Err if the data is not a model of this coder.
+    fn read_model(&self, data: &[u8]) -> Result<Self::Model, &'static str>;
+}
+
+/// Id a backend is registered under in a [Registry], and recorded under in containers
+pub type BackendId = u32;
+
+/// The id [Huffman] is registered under in the [global()] registry
+pub const HUFFMAN: BackendId = 0;
+
+/// A coder of bytes as containers use it, trained on each block and recording its model.
+/// Implemented for every [ModelFormat] of bytes.
+pub trait Backend: Send + Sync {
+    /// Appends the model trained on the block to `model`, and the bits coding the block with
+    /// it to `bits`
+    /// ## Errors
+    /// Returns Err if the block is empty or the coder fails to code it.
+    fn encode_block(
+        &self,
+        block: &[u8],
+        model: &mut Vec<u8>,
+        bits: &mut BitVec,
+    ) -> Result<(), &'static str>;
+
+    /// Appends the `count` bytes coded by the bits with the model to `out`
+    /// ## Errors
+    /// Returns Err if the model is invalid or the bits do not code `count` bytes with it.
+    fn decode_block(
+        &self,
+        model: &[u8],
+        bits: &BitVec,
+        count: usize,
+        out: &mut Vec<u8>,
+    ) -> Result<(), &'static str>;
+}
+
+impl<C: ModelFormat<u8> + Send + Sync> Backend for C {
+    fn encode_block(
+        &self,
+        block: &[u8],
+        model: &mut Vec<u8>,
+        bits: &mut BitVec,
+    ) -> Result<(), &'static str> {
+        let mut counts = [0u64; 256];
+        for &byte in block {
+            counts[usize::from(byte)] += 1;
+        }
+        let counts: Vec<(u8, u64)> = (0..=255u8)
+            .zip(counts)
+            .filter(|&(_, count)| count > 0)
+            .collect();
+        let trained = self.train(&counts[..])?;
+        self.write_model(&trained, model);
+        self.encode(&trained, block, bits)
+    }
+
+    fn decode_block(
+        &self,
+        model: &[u8],
+        bits: &BitVec,
+        count: usize,
+        out: &mut Vec<u8>,
+    ) -> Result<(), &'static str> {
+        let model = self.read_model(model)?;
+        self.decode(&model, bits, count, out)
+    }
+}
+
+/// A thread-safe map from [BackendId] to [Backend], like the [presets](crate::presets) of
+/// trees
+pub struct Registry {
+    backends: RwLock<HashMap<BackendId, Arc<dyn Backend>>>,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Registry::new()
+    }
+}
+
+impl Registry {
+    /// An empty registry, without even [Huffman]
+    pub fn new() -> Registry {
+        Registry {
+            backends: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Registers the coder under the given id.
+    /// ## Errors
+    /// Returns Err if another coder is already registered under that id.
+    pub fn register<B: Backend + 'static>(
+        &self,
+        id: BackendId,
+        backend: B,
+    ) -> Result<(), &'static str> {
+        let mut backends = self.backends.write().unwrap_or_else(|e| e.into_inner());
+        if backends.contains_key(&id) {
+            return Err("A backend is already registered under this id");
+        }
+        backends.insert(id, Arc::new(backend));
+        Ok(())
+    }
+
+    pub fn get(&self, id: BackendId) -> Option<Arc<dyn Backend>> {
+        let backends = self.backends.read().unwrap_or_else(|e| e.into_inner());
+        backends.get(&id).cloned()
+    }
+
+    /// The ids of every registered coder, in increasing order
+    pub fn ids(&self) -> Vec<BackendId> {
+        let backends = self.backends.read().unwrap_or_else(|e| e.into_inner());
+        let mut ids: Vec<BackendId> = backends.keys().copied().collect();
+        ids.sort_unstable();
+        ids
+    }
+}
+
+/// The process-wide registry containers look backends up in, holding [Huffman] under
+/// [HUFFMAN] from the start
+pub fn global() -> &'static Registry {
+    static GLOBAL: OnceLock<Registry> = OnceLock::new();
+    GLOBAL.get_or_init(|| {
+        let registry = Registry::new();
+        let _ = registry.register(HUFFMAN, Huffman);
+        registry
+    })
+}
+
+/// Huffman coding, see [huffman_tree](crate::huffman_tree)
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct Huffman;
+
+/// The tree of a [Huffman] coder, with its encoder and decoder
+pub struct HuffmanModel<T: Eq + Hash + Clone + Ord> {
+    encoder: HuffmanEncoder<T>,
+    decoder: HuffmanDecoder<T>,
+}
+
+impl<T: Eq + Hash + Clone + Ord> HuffmanModel<T> {
+    pub fn new(tree: HuffmanTree<T>) -> HuffmanModel<T> {
+        HuffmanModel {
+            encoder: HuffmanEncoder::from_tree(&tree),
+            decoder: HuffmanDecoder::new(tree),
+        }
+    }
+
+    /// The tree of the model, rebuilt from the decoder on every call
+    pub fn tree(&self) -> HuffmanTree<T> {
+        self.decoder.get_tree()
+    }
+}
+
+impl<T: Eq + Hash + Clone + Ord> EntropyCoder<T> for Huffman {
+    type Model = HuffmanModel<T>;
+
+    fn train<S: FrequencySource<T> + ?Sized>(
+        &self,
+        source: &S,
+    ) -> Result<HuffmanModel<T>, &'static str> {
+        let tree =
+            frequency_source::huffman_tree(source).ok_or("No symbols to train the model on")?;
+        // Canonical, so that the code lengths are enough to write the model
+        let tree = canonical::from_lengths(&canonical::code_lengths(&tree)).unwrap_or(tree);
+        Ok(HuffmanModel::new(tree))
+    }
+
+    fn encode(
+        &self,
+        model: &HuffmanModel<T>,
+        symbols: &[T],
+        out: &mut BitVec,
+    ) -> Result<(), &'static str> {
+        model
+            .encoder
+            .encode_append(&mut symbols.iter(), out)
+            .map_err(|_| "A symbol has no code in the model")
+    }
+
+    fn decode(
+        &self,
+        model: &HuffmanModel<T>,
+        bits: &BitVec,
+        count: usize,
+        out: &mut Vec<T>,
+    ) -> Result<(), &'static str> {
+        let start = out.len();
+        match model.decoder.lone_symbol() {
+            // A lone symbol is coded with zero bits
+            Some(symbol) if bits.is_empty() => {
+                out.try_reserve(count)
+                    .map_err(|_| "Too many symbols to decode")?;
+                out.resize(start + count, symbol.clone());
+            }
+            _ => model.decoder.decode_into(bits, out)?,
+        }
+        match out.len() - start == count {
+            true => Ok(()),
+            false => Err("The bits do not code the number of symbols"),
+        }
+    }
+}
+
+/// Models are written as the code lengths of their tree, as containers embed trees
+impl ModelFormat<u8> for Huffman {
+    fn write_model(&self, model: &HuffmanModel<u8>, out: &mut Vec<u8>) {
+        tree::write_lengths(&model.tree(), out);
+    }
+
+    fn read_model(&self, data: &[u8]) -> Result<HuffmanModel<u8>, &'static str> {
+        let mut pos = 0;
+        let tree = tree::read_lengths(data, &mut pos)?;
+        if pos != data.len() {
+            return Err("Trailing bytes after the model");
+        }
+        Ok(HuffmanModel::new(tree))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn huffman_codes_through_the_trait() {
+        let data: Vec<u16> = (0..3_000u32).map(|i| (i * i % 1013) as u16 % 40).collect();
+        let counts: Vec<(u16, u64)> = data.iter().map(|&symbol| (symbol, 1)).collect();
+        let model = Huffman.train(&counts).unwrap();
+
+        let mut bits = BitVec::new();
+        Huffman.encode(&model, &data, &mut bits).unwrap();
+        let mut decoded = vec![7];
+        Huffman
+            .decode(&model, &bits, data.len(), &mut decoded)
+            .unwrap();
+        assert_eq!(decoded[1..], data[..]);
+        assert!(Huffman
+            .decode(&model, &bits, data.len() + 1, &mut vec![])
+            .is_err());
+        assert!(Huffman
+            .decode(&model, &bits, data.len() - 1, &mut vec![])
+            .is_err());
+        // Bits left over, even less than a byte of them, are an error
+        let mut padded = bits.clone();
+        padded.push(false);
+        assert!(Huffman
+            .decode(&model, &padded, data.len(), &mut vec![])
+            .is_err());
+        assert!(Huffman.encode(&model, &[999], &mut bits).is_err());
+
+        let lone = Huffman.train(&vec![(5u16, 3u64)]).unwrap();
+        let mut bits = BitVec::new();
+        Huffman.encode(&lone, &[5, 5], &mut bits).unwrap();
+        assert!(bits.is_empty());
+        let mut decoded = vec![];
+        Huffman.decode(&lone, &bits, 2, &mut decoded).unwrap();
+        assert_eq!(decoded, [5, 5]);
+        assert!(Huffman
+            .decode(&lone, &BitVec::from_elem(1, false), 2, &mut vec![])
+            .is_err());
+        assert!(Huffman.train(&Vec::<(u16, u64)>::new()).is_err());
+    }
+
+    #[test]
+    fn huffman_models_are_written_as_code_lengths() {
+        let counts: Vec<(u8, u64)> = (0..=255u8)
+            .map(|byte| (byte, 1 + u64::from(byte % 9)))
+            .collect();
+        let model = Huffman.train(&counts[..]).unwrap();
+        let mut written = vec![];
+        Huffman.write_model(&model, &mut written);
+        assert_eq!(Huffman.read_model(&written).unwrap().tree(), model.tree());
+        written.push(0);
+        assert!(Huffman.read_model(&written).is_err());
+        assert!(Huffman.read_model(&[]).is_err());
+
+        let (mut model, mut bits, mut decoded) = (vec![], BitVec::new(), vec![]);
+        Backend::encode_block(&Huffman, b"abracadabra", &mut model, &mut bits).unwrap();
+        Backend::decode_block(&Huffman, &model, &bits, 11, &mut decoded).unwrap();
+        assert_eq!(decoded, b"abracadabra");
+        assert!(Backend::encode_block(&Huffman, b"", &mut model, &mut bits).is_err());
+    }
+
+    #[test]
+    fn backends_are_registered_once() {
+        let registry = Registry::new();
+        assert!(registry.get(HUFFMAN).is_none());
+        registry.register(7, Huffman).unwrap();
+        registry.register(HUFFMAN, Huffman).unwrap();
+        assert!(registry.register(7, Huffman).is_err());
+        assert_eq!(registry.ids(), [HUFFMAN, 7]);
+        assert!(global().get(HUFFMAN).is_some());
+    }
+}
@@ -0,0 +1,143 @@
+//! Reversible preprocessing stages applied to the raw bytes before they are
+//! handed to the entropy coder, and undone after decoding.
+pub mod nibbles;
+pub mod rle;
+pub mod timeseries;
+pub mod utf8;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+use utf8::Utf8Policy;
+
+/// The preprocessing stage selected for a payload. It is meant to be stored next to the
+/// compressed data so that decompression knows which stage to undo.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[non_exhaustive]
+pub enum Filter {
+    /// Bytes are passed through untouched
+    #[default]
+    None,
+    /// Bytes are little-endian `u64` timestamps, see [timeseries]
+    Timeseries,
+    /// Bytes are coded as their two nibbles, interleaved, see [nibbles]
+    Nibbles,
+    /// Bytes are coded as their two nibbles, every high nibble first, see [nibbles]. Unless
+    /// told otherwise, containers code each plane in a block with its own tree.
+    NibblePlanes,
+    /// Long runs of the same byte are coded as their length, see [rle]
+    Rle,
+    /// Bytes are UTF-8 text, coded as its chars, with the policy for bytes that are not, see
+    /// [utf8]
+    Chars(Utf8Policy),
+}
+
+impl Filter {
+    /// Transforms the data before entropy coding.
+    /// ## Errors
+    /// Returns Err if the filter can not code the data, which only [Chars](Filter::Chars)
+    /// does, for data that is not UTF-8 with [Utf8Policy::Fail].
+    pub fn apply(&self, data: &[u8]) -> Result<Vec<u8>, &'static str> {
+        match self {
+            Filter::None => Ok(data.to_vec()),
+            Filter::Timeseries => Ok(timeseries::encode_bytes(data)),
+            Filter::Nibbles => Ok(nibbles::split(data)),
+            Filter::NibblePlanes => Ok(nibbles::split_planes(data)),
+            Filter::Rle => Ok(rle::encode(data)),
+            Filter::Chars(policy) => utf8::encode(data, *policy),
+        }
+    }
+
+    /// Undoes [apply()](Filter::apply) after entropy decoding.
+    /// ## Errors
+    /// Returns Err if the data could not have been produced by this filter.
+    pub fn reverse(&self, data: &[u8]) -> Result<Vec<u8>, &'static str> {
+        match self {
+            Filter::None => Ok(data.to_vec()),
+            Filter::Timeseries => timeseries::decode_bytes(data),
+            Filter::Nibbles => nibbles::join(data),
+            Filter::NibblePlanes => nibbles::join_planes(data),
+            Filter::Rle => rle::decode(data),
+            Filter::Chars(policy) => utf8::decode(data, *policy),
+        }
+    }
+
+    /// Same as [reverse()](Filter::reverse), failing once the data would be longer than
+    /// `max_len` bytes. Runs are checked before they are allocated, the other filters give
+    /// back at most 8 bytes per byte.
+    /// ## Errors
+    /// Returns Err if the data could not have been produced by this filter or decodes to more
+    /// than `max_len` bytes.
+    pub fn reverse_within(&self, data: &[u8], max_len: u64) -> Result<Vec<u8>, &'static str> {
+        let reversed = match self {
+            Filter::Rle => return rle::decode_within(data, max_len),
+            filter => filter.reverse(data)?,
+        };
+        if reversed.len() as u64 > max_len {
+            return Err("The data decodes to more than the output limit");
+        }
+        Ok(reversed)
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Filter::None),
+            "timeseries" => Ok(Filter::Timeseries),
+            "nibbles" => Ok(Filter::Nibbles),
+            "nibble-planes" => Ok(Filter::NibblePlanes),
+            "rle" => Ok(Filter::Rle),
+            "chars" => Ok(Filter::Chars(Utf8Policy::Fail)),
+            "chars-replace" => Ok(Filter::Chars(Utf8Policy::Replace)),
+            "chars-escape" => Ok(Filter::Chars(Utf8Policy::Escape)),
+            _ => Err(format!("Unknown filter \"{}\"", s)),
+        }
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Filter::None => write!(f, "none"),
+            Filter::Timeseries => write!(f, "timeseries"),
+            Filter::Nibbles => write!(f, "nibbles"),
+            Filter::NibblePlanes => write!(f, "nibble-planes"),
+            Filter::Rle => write!(f, "rle"),
+            Filter::Chars(Utf8Policy::Fail) => write!(f, "chars"),
+            Filter::Chars(policy) => write!(f, "chars-{}", policy),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filter_names_round_trip() {
+        for filter in &[
+            Filter::None,
+            Filter::Timeseries,
+            Filter::Nibbles,
+            Filter::NibblePlanes,
+            Filter::Rle,
+            Filter::Chars(Utf8Policy::Fail),
+            Filter::Chars(Utf8Policy::Replace),
+            Filter::Chars(Utf8Policy::Escape),
+        ] {
+            assert_eq!(filter.to_string().parse::<Filter>(), Ok(*filter));
+        }
+    }
+
+    #[test]
+    fn timeseries_filter_is_reversible() {
+        let data: Vec<u8> = (0..64u64).flat_map(|i| (i * 15).to_le_bytes()).collect();
+        let filtered = Filter::Timeseries.apply(&data).unwrap();
+        assert!(filtered.len() < data.len());
+        assert_eq!(Filter::Timeseries.reverse(&filtered).unwrap(), data);
+    }
+}
@@ -0,0 +1,87 @@
+//! Splitting bytes into their 4 bit halves, so the entropy coder sees an alphabet of 16
+//! nibbles instead of 256 bytes. Formats with structure at the nibble level, e.g. BCD, hex
+//! dumps or some sensor encodings, compress better that way.
+//!
+//! Nibbles are either interleaved, each byte's high nibble then its low nibble, or laid out
+//! in planes, every high nibble then every low nibble so that a block of each plane gets a
+//! tree of its own.
+
+/// Each byte's high nibble then its low nibble, each in a byte of its own
+/// ## Examples
+/// ```
+/// # use rhuffman::filters::nibbles;
+/// assert_eq!(nibbles::split(&[0x12, 0x34]), vec![1, 2, 3, 4]);
+/// ```
+pub fn split(data: &[u8]) -> Vec<u8> {
+    data.iter()
+        .flat_map(|&byte| [byte >> 4, byte & 0xf])
+        .collect()
+}
+
+/// Reverses [split()]
+/// ## Errors
+/// Returns Err if there is an odd number of nibbles or one of them is over 15.
+pub fn join(nibbles: &[u8]) -> Result<Vec<u8>, &'static str> {
+    if !nibbles.len().is_multiple_of(2) {
+        return Err("Odd number of nibbles");
+    }
+    nibbles
+        .chunks_exact(2)
+        .map(|pair| byte(pair[0], pair[1]))
+        .collect()
+}
+
+/// Every high nibble then every low nibble, each in a byte of its own
+/// ## Examples
+/// ```
+/// # use rhuffman::filters::nibbles;
+/// assert_eq!(nibbles::split_planes(&[0x12, 0x34]), vec![1, 3, 2, 4]);
+/// ```
+pub fn split_planes(data: &[u8]) -> Vec<u8> {
+    data.iter()
+        .map(|&byte| byte >> 4)
+        .chain(data.iter().map(|&byte| byte & 0xf))
+        .collect()
+}
+
+/// Reverses [split_planes()]
+/// ## Errors
+/// Returns Err if there is an odd number of nibbles or one of them is over 15.
+pub fn join_planes(nibbles: &[u8]) -> Result<Vec<u8>, &'static str> {
+    if !nibbles.len().is_multiple_of(2) {
+        return Err("Odd number of nibbles");
+    }
+    let (high, low) = nibbles.split_at(nibbles.len() / 2);
+    high.iter()
+        .zip(low)
+        .map(|(&high, &low)| byte(high, low))
+        .collect()
+}
+
+fn byte(high: u8, low: u8) -> Result<u8, &'static str> {
+    if high > 0xf || low > 0xf {
+        return Err("Nibble over 15");
+    }
+    Ok((high << 4) | low)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nibbles_round_trip() {
+        let data: Vec<u8> = (0..=255).collect();
+        assert_eq!(join(&split(&data)).unwrap(), data);
+        assert_eq!(join_planes(&split_planes(&data)).unwrap(), data);
+        assert!(split(&data).iter().all(|&nibble| nibble < 16));
+    }
+
+    #[test]
+    fn invalid_nibbles_are_rejected() {
+        assert!(join(&[1, 2, 3]).is_err());
+        assert!(join(&[1, 16]).is_err());
+        assert!(join_planes(&[0x10, 0]).is_err());
+        assert_eq!(join(&[]), Ok(vec![]));
+    }
+}
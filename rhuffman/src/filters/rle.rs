@@ -0,0 +1,168 @@
+//! Run-length coding of long repeats, so that zero-filled regions and the like take a few
+//! bytes instead of a code per repeated byte.
+//!
+//! The output starts with the escape byte, the least frequent byte of the input. Runs of at
+//! least [MIN_RUN] bytes are written as the escape byte, the run length less
+//! `MIN_RUN - 1` as a varint, then the repeated byte. A lone escape byte in the input is
+//! written as the escape byte followed by a 0. Every other byte is copied as is.
+use std::convert::TryFrom;
+
+use crate::varint;
+
+/// Shortest run coded as a run, shorter ones are cheaper to copy
+pub const MIN_RUN: usize = 4;
+
+/// Runs at least this long are the ones [has_long_runs()] looks for
+pub const LONG_RUN: usize = 32;
+
+/// Whether runs of at least [LONG_RUN] bytes make up an eighth of the data or more, in which
+/// case [encode()] is worth running.
+/// ## Examples
+/// ```
+/// # use rhuffman::filters::rle;
+/// let mut sparse = vec![0u8; 4096];
+/// sparse.extend(b"some text between zero-filled regions");
+/// assert!(rle::has_long_runs(&sparse));
+/// assert!(!rle::has_long_runs(b"no runs at all"));
+/// ```
+pub fn has_long_runs(data: &[u8]) -> bool {
+    let in_runs: usize = runs(data)
+        .map(|(_, len)| len)
+        .filter(|&len| len >= LONG_RUN)
+        .sum();
+    !data.is_empty() && in_runs >= data.len() / 8
+}
+
+/// Codes the runs of the data, see the [module](self) documentation
+pub fn encode(data: &[u8]) -> Vec<u8> {
+    let mut counts = [0usize; 256];
+    for &byte in data {
+        counts[usize::from(byte)] += 1;
+    }
+    let escape = (0..=255u8)
+        .min_by_key(|&byte| counts[usize::from(byte)])
+        .unwrap_or(0);
+
+    let mut out = vec![escape];
+    for (byte, len) in runs(data) {
+        if len >= MIN_RUN {
+            out.push(escape);
+            varint::write((len - (MIN_RUN - 1)) as u64, &mut out);
+            out.push(byte);
+        } else if byte == escape {
+            for _ in 0..len {
+                out.extend_from_slice(&[escape, 0]);
+            }
+        } else {
+            out.resize(out.len() + len, byte);
+        }
+    }
+    out
+}
+
+/// Reverses [encode()]
+/// ## Errors
+/// Returns Err if the data ends in the middle of a run or a run is too long to be held in
+/// memory.
+pub fn decode(data: &[u8]) -> Result<Vec<u8>, &'static str> {
+    decode_within(data, u64::MAX)
+}
+
+/// Same as [decode()], failing as soon as the output would be longer than `max_len` bytes,
+/// before allocating for it. A few bytes of input can code runs of any length.
+/// ## Errors
+/// Returns Err if the data ends in the middle of a run, or decodes to more than `max_len`
+/// bytes or to more than memory can hold.
+pub fn decode_within(data: &[u8], max_len: u64) -> Result<Vec<u8>, &'static str> {
+    const OVER_LIMIT: &str = "The runs decode to more than the output limit";
+    let (&escape, data) = match data.split_first() {
+        Some(split) => split,
+        None => return Ok(vec![]),
+    };
+    let mut out = Vec::with_capacity(
+        data.len()
+            .min(usize::try_from(max_len).unwrap_or(usize::MAX)),
+    );
+    let mut pos = 0;
+    while let Some(&byte) = data.get(pos) {
+        pos += 1;
+        if out.len() as u64 >= max_len {
+            return Err(OVER_LIMIT);
+        }
+        if byte != escape {
+            out.push(byte);
+            continue;
+        }
+        let len = varint::read(data, &mut pos)?;
+        if len == 0 {
+            out.push(escape);
+            continue;
+        }
+        let len = usize::try_from(len)
+            .ok()
+            .and_then(|len| len.checked_add(MIN_RUN - 1))
+            .ok_or("Run is too long")?;
+        let byte = *data.get(pos).ok_or("Truncated run")?;
+        pos += 1;
+        if (out.len() as u64).saturating_add(len as u64) > max_len {
+            return Err(OVER_LIMIT);
+        }
+        out.try_reserve(len).map_err(|_| "Run is too long")?;
+        out.resize(out.len() + len, byte);
+    }
+    Ok(out)
+}
+
+/// Every run of the data with its length, runs of a single byte included
+fn runs(data: &[u8]) -> impl Iterator<Item = (u8, usize)> + '_ {
+    let mut pos = 0;
+    std::iter::from_fn(move || {
+        let byte = *data.get(pos)?;
+        let len = data[pos..].iter().take_while(|&&b| b == byte).count();
+        pos += len;
+        Some((byte, len))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_and_escapes_round_trip() {
+        let mut data: Vec<u8> = (0..=255).collect();
+        data.extend(vec![0; 1000]);
+        data.extend(&[7, 7, 7, 7, 7, 1, 1, 1]);
+        // Every byte occurs, so the escape byte itself occurs too
+        let encoded = encode(&data);
+        assert!(encoded.len() < 300);
+        assert_eq!(decode(&encoded).unwrap(), data);
+
+        let escapes = vec![encode(&data)[0]; 3];
+        assert_eq!(decode(&encode(&escapes)).unwrap(), escapes);
+        assert_eq!(decode(&encode(&[])).unwrap(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn truncated_runs_are_rejected() {
+        let encoded = encode(&[5; 100]);
+        assert_eq!(encoded.len(), 4);
+        assert!(decode(&encoded[..3]).is_err());
+        assert!(decode(&encoded[..2]).is_err());
+    }
+
+    #[test]
+    fn runs_past_the_limit_are_rejected() {
+        let mut data = vec![0; 100_000];
+        data.extend(b"tail");
+        let encoded = encode(&data);
+        assert_eq!(decode_within(&encoded, 100_004).unwrap(), data);
+        assert!(decode_within(&encoded, 100_003).is_err());
+        assert!(decode_within(&encoded, 99_999).is_err());
+        // A run of 2^62 bytes in a dozen
+        let mut huge = vec![1, 1];
+        varint::write(1 << 62, &mut huge);
+        huge.push(0);
+        assert!(decode_within(&huge, 1 << 20).is_err());
+    }
+}
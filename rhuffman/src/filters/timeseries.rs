@@ -0,0 +1,134 @@
+//! Delta-of-delta transform for monotonic `u64` timestamps, in the spirit of
+//! the Gorilla time series encoding. Regularly sampled timestamps collapse to
+//! a stream of zeros, which the Huffman stage then codes in about one bit each.
+use crate::varint;
+
+/// Transforms a sequence of timestamps into their delta-of-delta form.
+///
+/// The first value is emitted as-is (its delta and delta-of-delta are taken
+/// against an implicit zero), every following value as the change of its
+/// delta. Arithmetic wraps, so any `u64` sequence round-trips, not only
+/// monotonic ones.
+/// ## Examples
+/// ```
+/// # use rhuffman::filters::timeseries;
+/// let timestamps = [1000, 1010, 1020, 1030, 1041];
+/// assert_eq!(timeseries::encode(&timestamps), vec![1000, -990, 0, 0, 1]);
+/// ```
+pub fn encode(timestamps: &[u64]) -> Vec<i64> {
+    let mut previous = 0u64;
+    let mut previous_delta = 0u64;
+    timestamps
+        .iter()
+        .map(|&timestamp| {
+            let delta = timestamp.wrapping_sub(previous);
+            let delta_of_delta = delta.wrapping_sub(previous_delta);
+            previous = timestamp;
+            previous_delta = delta;
+            delta_of_delta as i64
+        })
+        .collect()
+}
+
+/// Reverses [encode()](encode), restoring the original timestamps.
+pub fn decode(deltas_of_deltas: &[i64]) -> Vec<u64> {
+    let mut previous = 0u64;
+    let mut previous_delta = 0u64;
+    deltas_of_deltas
+        .iter()
+        .map(|&delta_of_delta| {
+            previous_delta = previous_delta.wrapping_add(delta_of_delta as u64);
+            previous = previous.wrapping_add(previous_delta);
+            previous
+        })
+        .collect()
+}
+
+/// Byte-level form of [encode()](encode) used by [Filter::Timeseries](super::Filter::Timeseries).
+///
+/// The input is read as little-endian `u64` timestamps. The output holds the number of
+/// timestamps followed by each delta-of-delta, all as zigzag varints, then any trailing
+/// bytes that did not fill a whole timestamp, verbatim.
+pub fn encode_bytes(data: &[u8]) -> Vec<u8> {
+    let chunks = data.chunks_exact(8);
+    let remainder = chunks.remainder();
+    let timestamps: Vec<u64> = chunks
+        .map(|chunk| {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(chunk);
+            u64::from_le_bytes(bytes)
+        })
+        .collect();
+
+    let mut out = Vec::with_capacity(data.len() / 4);
+    varint::write(timestamps.len() as u64, &mut out);
+    for delta_of_delta in encode(&timestamps) {
+        varint::write(zigzag(delta_of_delta), &mut out);
+    }
+    out.extend_from_slice(remainder);
+    out
+}
+
+/// Reverses [encode_bytes()](encode_bytes).
+/// ## Errors
+/// Returns Err if the data ends in the middle of the varint section.
+pub fn decode_bytes(data: &[u8]) -> Result<Vec<u8>, &'static str> {
+    let mut pos = 0;
+    let count = varint::read(data, &mut pos)?;
+    let mut deltas_of_deltas = Vec::new();
+    for _ in 0..count {
+        deltas_of_deltas.push(unzigzag(varint::read(data, &mut pos)?));
+    }
+
+    let mut out = Vec::with_capacity(deltas_of_deltas.len() * 8 + data.len() - pos);
+    for timestamp in decode(&deltas_of_deltas) {
+        out.extend_from_slice(&timestamp.to_le_bytes());
+    }
+    out.extend_from_slice(&data[pos..]);
+    Ok(out)
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn unzigzag(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn regular_timestamps_become_zeros() {
+        let timestamps: Vec<u64> = (0..100).map(|i| 1_600_000_000 + i * 60).collect();
+        let encoded = encode(&timestamps);
+        assert!(encoded[2..].iter().all(|&dod| dod == 0));
+        assert_eq!(decode(&encoded), timestamps);
+    }
+
+    #[test]
+    fn non_monotonic_timestamps_round_trip() {
+        let timestamps = [u64::MAX, 0, 5, 3, u64::MAX / 2, 1];
+        assert_eq!(decode(&encode(&timestamps)), timestamps);
+    }
+
+    #[test]
+    fn bytes_with_trailing_remainder_round_trip() {
+        let mut data = Vec::new();
+        for timestamp in &[10u64, 20, 30, 45] {
+            data.extend_from_slice(&timestamp.to_le_bytes());
+        }
+        data.extend_from_slice(&[1, 2, 3]);
+
+        let encoded = encode_bytes(&data);
+        assert_eq!(decode_bytes(&encoded).unwrap(), data);
+    }
+
+    #[test]
+    fn truncated_bytes_are_rejected() {
+        let encoded = encode_bytes(&300u64.to_le_bytes());
+        assert!(decode_bytes(&encoded[..encoded.len() - 1]).is_err());
+    }
+}
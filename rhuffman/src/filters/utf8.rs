@@ -0,0 +1,192 @@
+//! Text coded as its chars, checked to be UTF-8. The [policy](Utf8Policy) says what happens
+//! to bytes that are not: the input is rejected, they are replaced with U+FFFD, or they are
+//! escaped so that decoding gives them back exactly.
+//!
+//! Escaped bytes are coded as the private use chars U+10FF80 to U+10FFFF, the byte being the
+//! low 8 bits. Those chars are escaped themselves when the input holds them, byte by byte, so
+//! that every escape decodes as a byte.
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+/// The first char escaping a byte, 0x80 less than the one of byte 0x80, the lowest byte of
+/// an invalid sequence
+const ESCAPES: u32 = 0x10_ff00;
+const FIRST_ESCAPE: u32 = ESCAPES + 0x80;
+
+/// What to do with bytes that are not UTF-8, recorded in the header so that decompression
+/// undoes it
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Utf8Policy {
+    /// The input is rejected
+    #[default]
+    Fail,
+    /// Every invalid sequence is replaced with U+FFFD, which decompresses as such
+    Replace,
+    /// Every byte of an invalid sequence is escaped, and decompresses as itself
+    Escape,
+}
+
+impl FromStr for Utf8Policy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fail" => Ok(Utf8Policy::Fail),
+            "replace" => Ok(Utf8Policy::Replace),
+            "escape" => Ok(Utf8Policy::Escape),
+            _ => Err(format!("Unknown UTF-8 policy \"{}\"", s)),
+        }
+    }
+}
+
+impl fmt::Display for Utf8Policy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Utf8Policy::Fail => write!(f, "fail"),
+            Utf8Policy::Replace => write!(f, "replace"),
+            Utf8Policy::Escape => write!(f, "escape"),
+        }
+    }
+}
+
+/// The UTF-8 of the text, its invalid sequences handled as the policy says
+/// ## Errors
+/// Returns Err if the data is not UTF-8 and the policy is [Fail](Utf8Policy::Fail).
+/// ## Examples
+/// ```
+/// # use rhuffman::filters::utf8::{self, Utf8Policy};
+/// let text = b"caf\xe9";
+/// assert!(utf8::encode(text, Utf8Policy::Fail).is_err());
+/// assert_eq!(utf8::encode(text, Utf8Policy::Replace).unwrap(), "caf\u{fffd}".as_bytes());
+/// let escaped = utf8::encode(text, Utf8Policy::Escape).unwrap();
+/// assert_eq!(utf8::decode(&escaped, Utf8Policy::Escape).unwrap(), text);
+/// ```
+pub fn encode(data: &[u8], policy: Utf8Policy) -> Result<Vec<u8>, &'static str> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut rest = data;
+    while !rest.is_empty() {
+        let (valid, invalid) = match std::str::from_utf8(rest) {
+            Ok(valid) => (valid, &rest[rest.len()..]),
+            Err(error) => {
+                let (valid, invalid) = rest.split_at(error.valid_up_to());
+                let len = error.error_len().unwrap_or(invalid.len());
+                // Checked by from_utf8()
+                #[allow(clippy::expect_used)]
+                let valid = std::str::from_utf8(valid).expect("Invalid UTF-8");
+                (valid, &invalid[..len])
+            }
+        };
+        rest = &rest[valid.len() + invalid.len()..];
+        match policy {
+            Utf8Policy::Escape => {
+                for c in valid.chars() {
+                    match u32::from(c) >= FIRST_ESCAPE {
+                        true => escape(c.encode_utf8(&mut [0; 4]).as_bytes(), &mut out),
+                        false => out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+                    }
+                }
+            }
+            _ => out.extend_from_slice(valid.as_bytes()),
+        }
+        match policy {
+            _ if invalid.is_empty() => {}
+            Utf8Policy::Fail => return Err("The data is not valid UTF-8"),
+            Utf8Policy::Replace => out.extend_from_slice("\u{fffd}".as_bytes()),
+            Utf8Policy::Escape => escape(invalid, &mut out),
+        }
+    }
+    Ok(out)
+}
+
+/// Appends the escapes of the bytes, which are all 0x80 or more
+fn escape(bytes: &[u8], out: &mut Vec<u8>) {
+    for &byte in bytes {
+        // Private use chars, below char::MAX
+        #[allow(clippy::expect_used)]
+        let escape = char::from_u32(ESCAPES + u32::from(byte)).expect("Invalid escape");
+        out.extend_from_slice(escape.encode_utf8(&mut [0; 4]).as_bytes());
+    }
+}
+
+/// Reverses [encode()]
+/// ## Errors
+/// Returns Err if the data is not valid UTF-8, which [encode()] always writes.
+pub fn decode(data: &[u8], policy: Utf8Policy) -> Result<Vec<u8>, &'static str> {
+    let text = std::str::from_utf8(data).map_err(|_| "The decoded text is not valid UTF-8")?;
+    if policy != Utf8Policy::Escape {
+        return Ok(data.to_vec());
+    }
+    let mut out = Vec::with_capacity(data.len());
+    for c in text.chars() {
+        match u32::from(c) >= FIRST_ESCAPE {
+            true => out.push((u32::from(c) - ESCAPES) as u8),
+            false => out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_text_is_kept_under_every_policy() {
+        let text = "naïve café, 日本語 and 🦀".as_bytes();
+        for policy in &[Utf8Policy::Fail, Utf8Policy::Replace, Utf8Policy::Escape] {
+            let encoded = encode(text, *policy).unwrap();
+            assert_eq!(encoded, text);
+            assert_eq!(decode(&encoded, *policy).unwrap(), text);
+        }
+    }
+
+    #[test]
+    fn escapes_restore_any_bytes() {
+        let invalid: &[&[u8]] = &[
+            b"\xff\xfe",
+            b"lone \x80 continuation",
+            b"truncated \xe6\x97",
+            b"overlong \xc0\xaf and surrogate \xed\xa0\x80",
+        ];
+        // Chars in the escape range, written as text
+        let escape_chars = "\u{10ff80}\u{10ffff}".as_bytes();
+        for &data in invalid.iter().chain(&[escape_chars]) {
+            let escaped = encode(data, Utf8Policy::Escape).unwrap();
+            assert!(std::str::from_utf8(&escaped).is_ok());
+            assert_eq!(decode(&escaped, Utf8Policy::Escape).unwrap(), data);
+        }
+        for &data in invalid {
+            assert!(encode(data, Utf8Policy::Fail).is_err());
+        }
+        let all: Vec<u8> = (0..=255).collect();
+        let escaped = encode(&all, Utf8Policy::Escape).unwrap();
+        assert_eq!(decode(&escaped, Utf8Policy::Escape).unwrap(), all);
+    }
+
+    #[test]
+    fn invalid_sequences_are_replaced_once() {
+        let replaced = encode(b"a\xe6\x97b\xffc", Utf8Policy::Replace).unwrap();
+        assert_eq!(replaced, "a\u{fffd}b\u{fffd}c".as_bytes());
+        assert_eq!(decode(&replaced, Utf8Policy::Replace).unwrap(), replaced);
+    }
+
+    #[test]
+    fn decoding_checks_the_text() {
+        for policy in &[Utf8Policy::Fail, Utf8Policy::Replace, Utf8Policy::Escape] {
+            assert!(decode(b"bad \xff", *policy).is_err());
+        }
+        // Without escapes, escape chars are text
+        let text = "\u{10ff80}".as_bytes();
+        assert_eq!(decode(text, Utf8Policy::Fail).unwrap(), text);
+    }
+
+    #[test]
+    fn policy_names_round_trip() {
+        for policy in &[Utf8Policy::Fail, Utf8Policy::Replace, Utf8Policy::Escape] {
+            assert_eq!(policy.to_string().parse::<Utf8Policy>(), Ok(*policy));
+        }
+        assert!("lossy".parse::<Utf8Policy>().is_err());
+    }
+}
@@ -1,4 +1,68 @@
+pub mod analysis;
+pub mod canonical;
+pub mod count_min;
+pub mod fixed_codebook;
+mod flat_tree;
+pub mod frequency_source;
+mod huffman_code;
 pub mod huffman_decoder;
 pub mod huffman_encoder;
 pub mod huffman_generator;
-mod huffman_node;
+pub(crate) mod huffman_node;
+pub mod interleaved;
+mod lookup_table;
+#[cfg(feature = "serde")]
+pub mod serde_flat;
+#[cfg(all(test, feature = "serde"))]
+mod serde_roundtrip;
+#[cfg(feature = "simd")]
+mod simd;
+pub mod snapshot;
+
+pub use huffman_node::{HuffmanBranch, HuffmanLeaf};
+
+/// The one tree type shared by the generator, the encoder and the decoder. Match on its
+/// `Leaf` and `Branch` variants, or walk it with `visit()`.
+/// ## Serialization
+/// With the `serde` feature, a tree is an enum of the variant `Leaf` (index 0), a struct of
+/// the one field `symbol`, or `Branch` (index 1), a struct of the one field `links`, the pair
+/// of its left and right subtrees. These names, indices and orders do not change, legacy
+/// files depend on them, so a tree written through one serde format reads back the same
+/// through another:
+///
+/// - formats naming fields, e.g. JSON, and those that do not, e.g. postcard, carry the same
+///   tree,
+/// - `rmp_serde` reads MessagePack trees written with field names (`to_vec_named()`) or
+///   without (`to_vec()`, as legacy files are),
+/// - `serde_json` does not read trees deeper than 41 levels by default, as it limits
+///   nesting. Serialize them with [serde_flat] instead, in every format the other side reads.
+pub type HuffmanTree<T> = huffman_node::HuffmanNode<T>;
+
+/// The first `n` Fibonacci numbers, 1, 1, 2, 3..., saturating. As the counts of `n` symbols
+/// they make the deepest tree there is, a chain of codes up to `n - 1` bits long, which tests
+/// and the [conformance vectors](crate::container::testvectors) use for long codes.
+pub(crate) fn fibonacci_counts(n: usize) -> Vec<usize> {
+    let (mut previous, mut current) = (0usize, 1usize);
+    (0..n)
+        .map(|_| {
+            let next = previous.saturating_add(current);
+            previous = current;
+            current = next;
+            previous
+        })
+        .collect()
+}
+
+/// The tree of [fibonacci_counts()] for the symbols 0 to `n - 1`, the least frequent `0`
+#[cfg(test)]
+pub(crate) fn fibonacci_tree<T>(n: usize) -> HuffmanTree<T>
+where
+    T: std::hash::Hash + Eq + Ord + Clone + std::convert::TryFrom<usize>,
+    T::Error: std::fmt::Debug,
+{
+    let mut gen = huffman_generator::HuffmanGenerator::new();
+    for (symbol, count) in fibonacci_counts(n).into_iter().enumerate() {
+        gen.add_occurences_to_symbol(&T::try_from(symbol).unwrap(), count);
+    }
+    gen.into_huffman_tree().unwrap()
+}
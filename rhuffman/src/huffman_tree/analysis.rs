@@ -0,0 +1,437 @@
+//! How well symbols with given frequencies can be compressed, without encoding anything.
+//!
+//! Huffman coding gains nothing on a near-uniform distribution: when every symbol is about as
+//! frequent as the others, codes are as long as the symbols themselves. [analyze()] measures
+//! how close to uniform the frequencies are and the average code length they would get, so
+//! callers can skip or warn about data that will not shrink.
+//!
+//! Which symbols the data is best split in depends on the data: [probe()] compares a few
+//! [models](Model) on a sample of it.
+//!
+//! [to_csv()] and [to_vega_lite_json()] export the frequency and code length of each symbol,
+//! ready to plot.
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+
+use super::frequency_source::{huffman_tree, FrequencySource};
+
+/// Bytes of the input [probe()] analyzes at most, in slices spread over the input
+pub const PROBE_SAMPLE: usize = 1 << 20;
+
+/// Slices the sample of [probe()] is made of
+const PROBE_SLICES: usize = 16;
+
+/// Bits each symbol with a code is estimated to take in the header: the code length, on top
+/// of the symbol itself for alphabets wider than a byte
+const LENGTH_BITS: f64 = 4.0;
+
+/// Estimated ratios at or above this are considered incompressible, headers making up for the
+/// few bits saved
+pub const INCOMPRESSIBLE_RATIO: f64 = 0.95;
+
+/// What a frequency model says about compressing the data it was counted on
+#[derive(PartialEq, Debug, Clone, Copy)]
+#[must_use]
+pub struct Analysis {
+    /// Number of distinct symbols
+    pub symbols: usize,
+    /// Number of occurences of all symbols
+    pub total: u64,
+    /// Shannon entropy, in bits per symbol
+    pub entropy: f64,
+    /// Entropy relative to a uniform distribution over the same symbols, from 0 for a single
+    /// symbol to 1 for a uniform distribution
+    pub uniformity: f64,
+    /// Average length of the Huffman codes, in bits per symbol
+    pub code_bits: f64,
+}
+
+impl Analysis {
+    /// Estimated size of the encoded data relative to its size with `symbol_bits` bits per
+    /// symbol, not counting headers
+    pub fn ratio(&self, symbol_bits: u32) -> f64 {
+        self.code_bits / f64::from(symbol_bits)
+    }
+
+    /// Whether compressing symbols of `symbol_bits` bits is not worth it
+    pub fn looks_incompressible(&self, symbol_bits: u32) -> bool {
+        self.ratio(symbol_bits) >= INCOMPRESSIBLE_RATIO
+    }
+}
+
+/// How bytes are split in symbols, see [probe()]
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[non_exhaustive]
+pub enum Model {
+    /// Every byte is a symbol
+    Byte,
+    /// Every two bytes are a symbol
+    U16,
+    /// Every half byte is a symbol, with one tree for both halves
+    Nibble,
+    /// Every byte is a symbol, coded with a tree for the byte before it
+    Digram,
+}
+
+impl fmt::Display for Model {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Model::Byte => write!(f, "byte"),
+            Model::U16 => write!(f, "u16"),
+            Model::Nibble => write!(f, "nibble"),
+            Model::Digram => write!(f, "digram"),
+        }
+    }
+}
+
+/// How well a model does on the sample of [probe()]
+#[derive(PartialEq, Debug, Clone, Copy)]
+#[must_use]
+pub struct Probe {
+    pub model: Model,
+    /// Number of symbols with a code, over all the trees of the model
+    pub symbols: usize,
+    /// Estimated size of the coded sample and its trees relative to the sample
+    pub ratio: f64,
+}
+
+/// Estimates how well each [Model] compresses the data, best first. Only a sample of
+/// [PROBE_SAMPLE] bytes of larger inputs is analyzed, and the trees are counted with an
+/// estimate of their size in a header, so that models with many symbols do not win on
+/// small inputs only because their trees are left out. Returns nothing for empty data.
+/// ## Examples
+/// ```
+/// # use rhuffman::huffman_tree::analysis::{probe, Model};
+/// // Every byte is the one after the one before it, which only a digram model sees
+/// let data: Vec<u8> = (0..100_000u32).map(|i| (i * 7 % 256) as u8).collect();
+/// assert_eq!(probe(&data)[0].model, Model::Digram);
+/// ```
+pub fn probe(data: &[u8]) -> Vec<Probe> {
+    let sample = if data.len() <= PROBE_SAMPLE {
+        data.to_vec()
+    } else {
+        let (slice, stride) = (PROBE_SAMPLE / PROBE_SLICES, data.len() / PROBE_SLICES);
+        (0..PROBE_SLICES)
+            .flat_map(|i| &data[i * stride..i * stride + slice])
+            .copied()
+            .collect()
+    };
+    if sample.is_empty() {
+        return vec![];
+    }
+
+    let mut bytes = vec![0u64; 256];
+    let mut nibbles = vec![0u64; 16];
+    let mut pairs = vec![0u64; 1 << 16];
+    for (i, &byte) in sample.iter().enumerate() {
+        bytes[usize::from(byte)] += 1;
+        nibbles[usize::from(byte >> 4)] += 1;
+        nibbles[usize::from(byte & 0xf)] += 1;
+        // Context 0 for the first byte
+        let previous = if i == 0 { 0 } else { sample[i - 1] };
+        pairs[usize::from(previous) << 8 | usize::from(byte)] += 1;
+    }
+    let mut words = vec![0u64; 1 << 16];
+    for word in sample.chunks_exact(2) {
+        words[usize::from(word[0]) << 8 | usize::from(word[1])] += 1;
+    }
+
+    let sample_bits = sample.len() as f64 * 8.0;
+    let mut probes = vec![];
+    let mut add = |model: Model, trees: &[&[u64]], symbol_bits: f64, extra_bits: f64| {
+        let (mut bits, mut symbols) = (extra_bits, 0);
+        for counts in trees {
+            let (tree_bits, tree_symbols) = coded_bits(counts);
+            bits += tree_bits + tree_symbols as f64 * symbol_bits;
+            symbols += tree_symbols;
+        }
+        probes.push(Probe {
+            model,
+            symbols,
+            ratio: bits / sample_bits,
+        });
+    };
+    add(Model::Byte, &[&bytes], LENGTH_BITS, 0.0);
+    // An odd last byte is left as it is
+    let odd = (sample.len() % 2 * 8) as f64;
+    add(Model::U16, &[&words], 16.0 + LENGTH_BITS, odd);
+    add(Model::Nibble, &[&nibbles], LENGTH_BITS, 0.0);
+    let contexts: Vec<&[u64]> = pairs.chunks(256).collect();
+    add(Model::Digram, &contexts, LENGTH_BITS, 0.0);
+    probes.sort_by(|a, b| a.ratio.total_cmp(&b.ratio));
+    probes
+}
+
+/// Bits taken by coding the counted symbols, and the number of symbols with a code
+fn coded_bits(counts: &[u64]) -> (f64, usize) {
+    let counts: Vec<(usize, u64)> = counts
+        .iter()
+        .copied()
+        .enumerate()
+        .filter(|&(_, count)| count > 0)
+        .collect();
+    match analyze(&counts) {
+        Some(analysis) => (analysis.code_bits * analysis.total as f64, analysis.symbols),
+        None => (0.0, 0),
+    }
+}
+
+/// Analyzes the frequencies of the source.
+/// ## None
+/// Returns None if the source lists no symbol, or only symbols that never occur.
+/// ## Examples
+/// ```
+/// # use rhuffman::huffman_tree::analysis::analyze;
+/// let uniform: Vec<(u8, u64)> = (0..=255).map(|byte| (byte, 10)).collect();
+/// let analysis = analyze(&uniform).unwrap();
+/// assert_eq!(analysis.code_bits, 8.0);
+/// assert!(analysis.looks_incompressible(8));
+/// ```
+pub fn analyze<T, S>(source: &S) -> Option<Analysis>
+where
+    T: Eq + Ord + Clone,
+    S: FrequencySource<T> + ?Sized,
+{
+    let mut counts: BTreeMap<&T, u64> = BTreeMap::new();
+    for (symbol, count) in source.counts() {
+        let total = counts.entry(symbol).or_insert(0);
+        *total = total.saturating_add(count);
+    }
+    // Symbols that never occur would get codes, but take no room
+    counts.retain(|_, count| *count > 0);
+    let total = counts
+        .values()
+        .fold(0u64, |sum, &count| sum.saturating_add(count));
+    if total == 0 {
+        return None;
+    }
+
+    let entropy: f64 = counts
+        .values()
+        .map(|&count| {
+            let p = count as f64 / total as f64;
+            -p * p.log2()
+        })
+        .sum();
+    let symbols = counts.len();
+    let uniformity = if symbols > 1 {
+        entropy / (symbols as f64).log2()
+    } else {
+        0.0
+    };
+
+    let occuring: Vec<(T, u64)> = counts
+        .iter()
+        .map(|(&symbol, &count)| (symbol.clone(), count))
+        .collect();
+    let tree = huffman_tree(&occuring)?;
+    let mut weighted_len = 0.0;
+    tree.visit(&mut |path: &[bool], symbol: &T| {
+        weighted_len += path.len() as f64 * counts[symbol] as f64;
+    });
+    Some(Analysis {
+        symbols,
+        total,
+        entropy,
+        uniformity,
+        code_bits: weighted_len / total as f64,
+    })
+}
+
+/// Every occuring symbol with its count and the length of its code, most frequent first
+fn plot_rows<T, S>(source: &S) -> Vec<(T, u64, usize)>
+where
+    T: Eq + Ord + Clone,
+    S: FrequencySource<T> + ?Sized,
+{
+    let mut counts: BTreeMap<&T, u64> = BTreeMap::new();
+    for (symbol, count) in source.counts().filter(|&(_, count)| count > 0) {
+        let total = counts.entry(symbol).or_insert(0);
+        *total = total.saturating_add(count);
+    }
+    let occuring: Vec<(T, u64)> = counts
+        .iter()
+        .map(|(&symbol, &count)| (symbol.clone(), count))
+        .collect();
+    let mut rows = vec![];
+    if let Some(tree) = huffman_tree(&occuring) {
+        tree.visit(&mut |path: &[bool], symbol: &T| {
+            rows.push((symbol.clone(), counts[symbol], path.len()));
+        });
+    }
+    rows.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    rows
+}
+
+/// The frequencies of the source as CSV, one row per occuring symbol, most frequent first:
+/// `symbol,count,frequency,code_len`, the frequency being the share of all occurences.
+/// ## Examples
+/// ```
+/// # use rhuffman::huffman_tree::analysis::to_csv;
+/// let counts = vec![('a', 6u64), ('b', 3), ('c', 1), ('d', 0)];
+/// assert_eq!(
+///     to_csv(&counts),
+///     "symbol,count,frequency,code_len\na,6,0.6,1\nb,3,0.3,2\nc,1,0.1,2\n"
+/// );
+/// ```
+pub fn to_csv<T, S>(source: &S) -> String
+where
+    T: Eq + Ord + Clone + fmt::Display,
+    S: FrequencySource<T> + ?Sized,
+{
+    let rows = plot_rows(source);
+    let total = rows.iter().map(|row| row.1 as f64).sum::<f64>();
+    let mut csv = String::from("symbol,count,frequency,code_len\n");
+    for (symbol, count, code_len) in rows {
+        let symbol = symbol.to_string();
+        let symbol = if symbol.contains(&[',', '"', '\n', '\r'][..]) {
+            format!("\"{}\"", symbol.replace('"', "\"\""))
+        } else {
+            symbol
+        };
+        // Writing to a String does not fail
+        let _ = writeln!(
+            csv,
+            "{},{},{},{}",
+            symbol,
+            count,
+            count as f64 / total,
+            code_len
+        );
+    }
+    csv
+}
+
+/// The frequencies of the source as a [Vega-Lite](https://vega.github.io/vega-lite/) chart,
+/// with the rows of [to_csv()] inlined: the count of each symbol colored by the length of its
+/// code, beside the share of occurences coded with each length.
+pub fn to_vega_lite_json<T, S>(source: &S) -> String
+where
+    T: Eq + Ord + Clone + fmt::Display,
+    S: FrequencySource<T> + ?Sized,
+{
+    let rows = plot_rows(source);
+    let total = rows.iter().map(|row| row.1 as f64).sum::<f64>();
+    let values: Vec<String> = rows
+        .iter()
+        .map(|(symbol, count, code_len)| {
+            format!(
+                r#"{{"symbol":{},"count":{},"frequency":{},"code_len":{}}}"#,
+                json_string(&symbol.to_string()),
+                count,
+                *count as f64 / total,
+                code_len
+            )
+        })
+        .collect();
+    format!(
+        concat!(
+            r#"{{"$schema":"https://vega.github.io/schema/vega-lite/v5.json","#,
+            r#""data":{{"values":[{}]}},"hconcat":["#,
+            r#"{{"title":"Symbol counts","mark":"bar","encoding":{{"#,
+            r#""x":{{"field":"symbol","type":"nominal","sort":"-y"}},"#,
+            r#""y":{{"field":"count","type":"quantitative"}},"#,
+            r#""color":{{"field":"code_len","type":"ordinal","title":"code length"}}}}}},"#,
+            r#"{{"title":"Occurences by code length","mark":"bar","encoding":{{"#,
+            r#""x":{{"field":"code_len","type":"ordinal","title":"code length"}},"#,
+            r#""y":{{"aggregate":"sum","field":"frequency","type":"quantitative","#,
+            r#""title":"share of occurences"}}}}}}]}}"#,
+        ),
+        values.join(",")
+    )
+}
+
+fn json_string(s: &str) -> String {
+    let mut json = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skewed_frequencies_look_compressible() {
+        let skewed = vec![('a', 90u64), ('b', 5), ('c', 3), ('d', 2)];
+        let analysis = analyze(&skewed).unwrap();
+        assert_eq!(analysis.symbols, 4);
+        assert_eq!(analysis.total, 100);
+        assert!(analysis.uniformity < 0.5);
+        assert!(analysis.entropy <= analysis.code_bits);
+        assert!(analysis.code_bits < analysis.entropy + 1.0);
+        assert!(!analysis.looks_incompressible(8));
+        // Two bits a symbol is all a uniform code over four symbols needs
+        assert!(analysis.ratio(2) < 1.0);
+
+        let uniform = vec![('a', 25u64), ('b', 25), ('c', 25), ('d', 25)];
+        let analysis = analyze(&uniform).unwrap();
+        assert_eq!(analysis.uniformity, 1.0);
+        assert!(analysis.looks_incompressible(2));
+    }
+
+    #[test]
+    fn probes_pick_the_width_of_the_data() {
+        // Two byte symbols whose halves are each uniform
+        let words: Vec<u8> = (0..50_000u32)
+            .flat_map(|i| {
+                let word = [0x1234u16, 0xfe01, 0x8080, 0x0f70][(i * i % 7 % 4) as usize];
+                word.to_le_bytes()
+            })
+            .collect();
+        let probes = probe(&words);
+        assert_eq!(probes.len(), 4);
+        assert!(probes.windows(2).all(|pair| pair[0].ratio <= pair[1].ratio));
+        assert!(matches!(probes[0].model, Model::U16 | Model::Digram));
+
+        // Random nibbles of 0 to 3, which bytes only code as well with a larger tree
+        let mut state = 1u32;
+        let nibbles: Vec<u8> = (0..2_000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                (state >> 8) as u8 & 0x33
+            })
+            .collect();
+        assert_eq!(probe(&nibbles)[0].model, Model::Nibble);
+
+        assert!(probe(&[]).is_empty());
+        let large = vec![7u8; PROBE_SAMPLE * 3];
+        assert_eq!(probe(&large)[0].symbols, 1);
+    }
+
+    #[test]
+    fn plot_data_lists_every_occuring_symbol() {
+        let counts = vec![("x,y", 2u64), ("\"q\"", 2), ("z", 0)];
+        assert_eq!(
+            to_csv(&counts),
+            "symbol,count,frequency,code_len\n\"\"\"q\"\"\",2,0.5,1\n\"x,y\",2,0.5,1\n"
+        );
+        let json = to_vega_lite_json(&counts);
+        assert!(json.contains(r#"{"symbol":"\"q\"","count":2,"frequency":0.5,"code_len":1}"#));
+        assert!(!json.contains(r#""symbol":"z""#));
+        assert_eq!(json.matches('{').count(), json.matches('}').count());
+
+        let empty: Vec<(u8, u64)> = vec![];
+        assert_eq!(to_csv(&empty), "symbol,count,frequency,code_len\n");
+        assert!(to_vega_lite_json(&empty).contains(r#""values":[]"#));
+    }
+
+    #[test]
+    fn empty_sources_have_no_analysis() {
+        assert_eq!(analyze(&Vec::<(u8, u64)>::new()), None);
+        assert_eq!(analyze(&vec![(1u8, 0u64)]), None);
+        assert_eq!(analyze(&vec![(1u8, 0u64), (2, 7)]).unwrap().symbols, 1);
+    }
+}
@@ -0,0 +1,157 @@
+//! Canonical Huffman codes, which a decoder can rebuild from the code length of each symbol
+//! alone, as DEFLATE and most other formats using Huffman codes do. Store or send the
+//! [code_lengths()] of a tree and rebuild the same codes on the other side with
+//! [from_lengths()].
+use bit_vec::BitVec;
+
+use super::huffman_node::{HuffmanBranch, HuffmanLeaf, HuffmanNode};
+
+/// The code length of every symbol of the tree, in the order `visit()` reaches them
+pub fn code_lengths<T: Eq + Clone>(tree: &HuffmanNode<T>) -> Vec<(T, usize)> {
+    let mut lengths = vec![];
+    tree.visit(&mut |path: &[bool], symbol: &T| lengths.push((symbol.clone(), path.len())));
+    lengths
+}
+
+/// Builds the canonical tree for the code lengths: shorter codes come first, and codes of
+/// the same length are assigned in increasing symbol order. Two trees with the same code
+/// lengths therefore have the same canonical tree, which is why only the lengths need to be
+/// stored.
+///
+/// A single symbol makes a lone leaf whatever its length. Returns None if there are no
+/// symbols, a length is zero, or the lengths do not describe a complete prefix code.
+pub fn from_lengths<T: Eq + Ord + Clone>(lengths: &[(T, usize)]) -> Option<HuffmanNode<T>> {
+    match lengths {
+        [] => return None,
+        [(symbol, _)] => return Some(HuffmanNode::Leaf(HuffmanLeaf::new(symbol.clone()))),
+        _ => {}
+    }
+    let mut sorted: Vec<&(T, usize)> = lengths.iter().collect();
+    sorted.sort_by(|(a, a_len), (b, b_len)| a_len.cmp(b_len).then_with(|| a.cmp(b)));
+
+    let mut arena = vec![Slot::Empty];
+    let mut code = BitVec::new();
+    for (i, (symbol, len)) in sorted.into_iter().enumerate() {
+        if *len == 0 {
+            return None;
+        }
+        if i > 0 && !increment(&mut code) {
+            // More codes than the lengths leave room for
+            return None;
+        }
+        code.grow(len - code.len(), false);
+        insert(&mut arena, &code, symbol.clone())?;
+    }
+    // A complete code ends on the all-ones code of its longest length
+    if !code.all() {
+        return None;
+    }
+    into_node(&mut arena, 0)
+}
+
+/// Adds one to the code read as a binary number, false on overflow
+fn increment(code: &mut BitVec) -> bool {
+    for i in (0..code.len()).rev() {
+        if code[i] {
+            code.set(i, false);
+        } else {
+            code.set(i, true);
+            return true;
+        }
+    }
+    false
+}
+
+enum Slot<T> {
+    Empty,
+    Leaf(T),
+    Branch(usize, usize),
+}
+
+fn insert<T>(arena: &mut Vec<Slot<T>>, code: &BitVec, symbol: T) -> Option<()> {
+    let mut index = 0;
+    for bit in code.iter() {
+        index = match arena[index] {
+            Slot::Branch(left, right) => {
+                if bit {
+                    right
+                } else {
+                    left
+                }
+            }
+            Slot::Empty => {
+                let left = arena.len();
+                arena.push(Slot::Empty);
+                arena.push(Slot::Empty);
+                arena[index] = Slot::Branch(left, left + 1);
+                left + bit as usize
+            }
+            Slot::Leaf(_) => return None,
+        };
+    }
+    match arena[index] {
+        Slot::Empty => {
+            arena[index] = Slot::Leaf(symbol);
+            Some(())
+        }
+        _ => None,
+    }
+}
+
+fn into_node<T: Eq>(arena: &mut Vec<Slot<T>>, index: usize) -> Option<HuffmanNode<T>> {
+    match std::mem::replace(&mut arena[index], Slot::Empty) {
+        Slot::Empty => None,
+        Slot::Leaf(symbol) => Some(HuffmanNode::Leaf(HuffmanLeaf::new(symbol))),
+        Slot::Branch(left, right) => Some(HuffmanNode::Branch(HuffmanBranch::new(
+            into_node(arena, left)?,
+            into_node(arena, right)?,
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::huffman_generator::HuffmanGenerator;
+    use super::*;
+
+    #[test]
+    fn canonical_tree_keeps_code_lengths() {
+        let mut gen = HuffmanGenerator::new();
+        let text: Vec<char> = "this is an example of a huffman tree".chars().collect();
+        gen.add_occurences_from_iterator(&mut text.iter());
+        let tree = gen.into_huffman_tree().unwrap();
+
+        let mut lengths = code_lengths(&tree);
+        let canonical = from_lengths(&lengths).unwrap();
+        let mut canonical_lengths = code_lengths(&canonical);
+        lengths.sort();
+        canonical_lengths.sort();
+        assert_eq!(lengths, canonical_lengths);
+    }
+
+    #[test]
+    fn codes_are_assigned_in_canonical_order() {
+        let tree = from_lengths(&[('c', 2), ('a', 2), ('d', 1)]).unwrap();
+        let mut codes = vec![];
+        tree.visit(&mut |path: &[bool], symbol: &char| codes.push((*symbol, path.to_vec())));
+        assert_eq!(
+            codes,
+            vec![
+                ('d', vec![false]),
+                ('a', vec![true, false]),
+                ('c', vec![true, true])
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_lengths_are_rejected() {
+        assert!(from_lengths::<u8>(&[]).is_none());
+        // Incomplete
+        assert!(from_lengths(&[(1, 1), (2, 2)]).is_none());
+        // Oversubscribed
+        assert!(from_lengths(&[(1, 1), (2, 1), (3, 1)]).is_none());
+        assert!(from_lengths(&[(1, 0), (2, 1)]).is_none());
+        assert!(from_lengths(&[(7, 3)]).is_some());
+    }
+}
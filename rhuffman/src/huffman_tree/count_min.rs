@@ -0,0 +1,177 @@
+//! Approximate symbol counting in bounded memory, for alphabets too large to count exactly
+//! such as words or n-grams.
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+use super::frequency_source::FrequencySource;
+
+/// Counters per row when only the number of symbols to keep is given
+const COUNTERS_PER_KEPT_SYMBOL: usize = 16;
+const DEFAULT_DEPTH: usize = 4;
+
+/// A count-min sketch estimating how often every symbol occured, along with the `top_k`
+/// symbols estimated most frequent. Memory is bounded by the dimensions of the sketch and
+/// `top_k`, whatever the number of distinct symbols.
+///
+/// Estimates never undercount. They overcount by at most the total number of occurences
+/// times `e / width`, with a probability that falls exponentially with `depth`. Symbols
+/// outside the top `top_k` are forgotten, so they get no code in trees built from the sketch.
+/// ## Examples
+/// ```
+/// # use rhuffman::huffman_tree::count_min::CountMinSketch;
+/// # use rhuffman::huffman_tree::huffman_generator::HuffmanGenerator;
+/// let mut gen = HuffmanGenerator::builder()
+///     .approximate_counts(CountMinSketch::new(2))
+///     .build()
+///     .unwrap();
+/// let text = "the cat and the dog and the bird";
+/// gen.add_occurences_from_iterator(&mut text.split(' ').collect::<Vec<_>>().iter());
+///
+/// let mut words = vec![];
+/// gen.into_huffman_tree().unwrap().visit(&mut |_: &[bool], word: &&str| words.push(*word));
+/// words.sort_unstable();
+/// assert_eq!(words, vec!["and", "the"]);
+/// ```
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct CountMinSketch<T: Eq + Hash> {
+    width: usize,
+    depth: usize,
+    counters: Vec<u64>,
+    top_k: usize,
+    /// Up to twice `top_k` candidates, pruned back to `top_k` when full
+    candidates: HashMap<T, u64>,
+    /// Smallest estimate kept by the last pruning, rarer symbols can't enter the candidates
+    threshold: u64,
+}
+
+impl<T: Eq + Hash + Clone + Ord> CountMinSketch<T> {
+    /// A sketch keeping the `top_k` most frequent symbols, sized for them
+    pub fn new(top_k: usize) -> CountMinSketch<T> {
+        let width = top_k.saturating_mul(COUNTERS_PER_KEPT_SYMBOL).max(1024);
+        CountMinSketch::with_dimensions(width, DEFAULT_DEPTH, top_k)
+    }
+
+    /// A sketch of `depth` rows of `width` counters. Wider sketches overcount less, deeper
+    /// ones overcount less often. Both must be at least 1.
+    pub fn with_dimensions(width: usize, depth: usize, top_k: usize) -> CountMinSketch<T> {
+        let width = width.max(1);
+        let depth = depth.max(1);
+        CountMinSketch {
+            width,
+            depth,
+            counters: vec![0; width * depth],
+            top_k,
+            candidates: HashMap::new(),
+            threshold: 0,
+        }
+    }
+
+    pub fn add_occurences_to_symbol(&mut self, symbol: &T, occurences: u64) {
+        let estimate = self.increment(symbol, occurences);
+        if let Some(count) = self.candidates.get_mut(symbol) {
+            *count = estimate;
+        } else if self.top_k > 0 && estimate >= self.threshold {
+            self.candidates.insert(symbol.clone(), estimate);
+            if self.candidates.len() >= self.top_k.saturating_mul(2) {
+                self.prune();
+            }
+        }
+    }
+
+    /// The estimated number of occurences of the symbol, never less than the actual one
+    pub fn estimate(&self, symbol: &T) -> u64 {
+        self.rows(symbol)
+            .map(|index| self.counters[index])
+            .min()
+            .unwrap_or(0)
+    }
+
+    /// Adds to the counters of the symbol, returning its new estimate
+    fn increment(&mut self, symbol: &T, occurences: u64) -> u64 {
+        let mut estimate = u64::MAX;
+        for index in self.rows(symbol) {
+            let counter = &mut self.counters[index];
+            *counter = counter.saturating_add(occurences);
+            estimate = estimate.min(*counter);
+        }
+        estimate
+    }
+
+    /// Index of the counter of the symbol in every row, by double hashing
+    fn rows(&self, symbol: &T) -> impl Iterator<Item = usize> {
+        let mut hasher = DefaultHasher::new();
+        symbol.hash(&mut hasher);
+        let hash = hasher.finish();
+        let (first, second) = (hash & 0xffff_ffff, (hash >> 32) | 1);
+        let width = self.width as u64;
+        (0..self.depth as u64)
+            .map(move |row| (row * width + first.wrapping_add(row * second) % width) as usize)
+    }
+
+    /// The candidates ranked by decreasing estimate, ties broken by symbol
+    fn ranked(&self) -> Vec<(&T, u64)> {
+        let mut ranked: Vec<(&T, u64)> = self
+            .candidates
+            .iter()
+            .map(|(symbol, count)| (symbol, *count))
+            .collect();
+        ranked.sort_unstable_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+        ranked.truncate(self.top_k);
+        ranked
+    }
+
+    fn prune(&mut self) {
+        let kept: Vec<(T, u64)> = self
+            .ranked()
+            .into_iter()
+            .map(|(symbol, count)| (symbol.clone(), count))
+            .collect();
+        self.threshold = kept.last().map_or(0, |(_, count)| *count);
+        self.candidates = kept.into_iter().collect();
+    }
+}
+
+/// The `top_k` symbols estimated most frequent, with their estimates
+impl<T: Eq + Hash + Clone + Ord> FrequencySource<T> for CountMinSketch<T> {
+    fn counts<'a>(&'a self) -> impl Iterator<Item = (&'a T, u64)>
+    where
+        T: 'a,
+    {
+        self.ranked().into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimates_never_undercount() {
+        let mut sketch = CountMinSketch::with_dimensions(64, 4, 8);
+        for symbol in 0..1000u32 {
+            sketch.add_occurences_to_symbol(&symbol, u64::from(symbol % 7) + 1);
+        }
+        for symbol in 0..1000u32 {
+            assert!(sketch.estimate(&symbol) > u64::from(symbol % 7));
+        }
+    }
+
+    #[test]
+    fn heavy_hitters_are_kept_in_bounded_memory() {
+        let mut sketch = CountMinSketch::new(3);
+        for round in 0..200u32 {
+            for heavy in 0..3u32 {
+                sketch.add_occurences_to_symbol(&heavy, 10);
+            }
+            // A long tail of distinct symbols
+            sketch.add_occurences_to_symbol(&(1000 + round), 1);
+        }
+
+        assert!(sketch.candidates.len() < 6);
+        let mut kept: Vec<u32> = sketch.counts().map(|(symbol, _)| *symbol).collect();
+        kept.sort_unstable();
+        assert_eq!(kept, vec![0, 1, 2]);
+        assert_eq!(sketch.estimate(&0), 2000);
+    }
+}
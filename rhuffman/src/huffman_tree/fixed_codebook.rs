@@ -0,0 +1,319 @@
+//! Codebooks for small alphabets known at compile time, e.g. the 4 bases of DNA or the 16
+//! nibbles, stored in arrays and buildable in `const` items.
+//!
+//! Symbols are the indices `0..N`. Codes are the canonical codes of their lengths, the same
+//! [HuffmanDecoder](super::huffman_decoder::HuffmanDecoder) reads once given
+//! [to_tree()](FixedCodebook::to_tree), so a codebook can be trained on a desktop, its lengths
+//! copied into firmware and encoding done there without allocating.
+use std::convert::TryFrom;
+use std::fmt;
+
+use super::canonical;
+use super::HuffmanTree;
+
+/// The canonical code of every symbol of an alphabet of `N` symbols, at most 256.
+/// ## Examples
+/// ```
+/// # use rhuffman::huffman_tree::fixed_codebook::FixedCodebook;
+/// // A, C, G, T with A the most frequent
+/// const DNA: FixedCodebook<4> = match FixedCodebook::from_lengths([1, 2, 3, 3]) {
+///     Ok(codebook) => codebook,
+///     Err(_) => panic!("invalid lengths"),
+/// };
+/// assert_eq!(DNA.code(0), Some((0b0, 1)));
+/// assert_eq!(DNA.code(3), Some((0b111, 3)));
+///
+/// let mut out = [0u8; 2];
+/// assert_eq!(DNA.encode_to_slice(&[0, 1, 3, 0], &mut out), Ok(7));
+/// assert_eq!(out, [0b0101_1100, 0]);
+/// ```
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct FixedCodebook<const N: usize> {
+    /// Right-aligned, the first bit of the code being the most significant one
+    codes: [u32; N],
+    /// 0 for symbols without a code
+    lengths: [u8; N],
+}
+
+impl<const N: usize> FixedCodebook<N> {
+    /// Longest code a codebook can hold
+    pub const MAX_LEN: u8 = 32;
+
+    /// Assigns canonical codes to the code lengths of the symbols, as
+    /// [container](crate::container) trees do: shorter codes first, and codes of the same
+    /// length in increasing symbol order. A length of 0 leaves the symbol without a code.
+    /// ## Errors
+    /// Returns Err if `N` is over 256, a length is over [MAX_LEN](FixedCodebook::MAX_LEN), fewer
+    /// than two symbols have a code or the lengths do not describe a complete prefix code.
+    pub const fn from_lengths(lengths: [u8; N]) -> Result<FixedCodebook<N>, &'static str> {
+        if N > 256 {
+            return Err("Fixed codebooks hold at most 256 symbols");
+        }
+        let mut codes = [0u32; N];
+        let mut coded = 0;
+        // The next code to assign, with one more bit than the longest code so it can not
+        // overflow
+        let mut next = 0u64;
+        let mut len = 1;
+        while len <= FixedCodebook::<N>::MAX_LEN {
+            next <<= 1;
+            let mut symbol = 0;
+            while symbol < N {
+                if lengths[symbol] > FixedCodebook::<N>::MAX_LEN {
+                    return Err("Code length over 32 bits");
+                }
+                if lengths[symbol] == len {
+                    if next >> len != 0 {
+                        return Err("Lengths are oversubscribed");
+                    }
+                    codes[symbol] = next as u32;
+                    next += 1;
+                    coded += 1;
+                }
+                symbol += 1;
+            }
+            len += 1;
+        }
+        if coded < 2 {
+            return Err("Fewer than two symbols have a code");
+        }
+        // A complete code ends on the all-ones code of the longest length
+        if next != 1 << FixedCodebook::<N>::MAX_LEN {
+            return Err("Lengths leave codes unused");
+        }
+        Ok(FixedCodebook { codes, lengths })
+    }
+
+    /// The codebook with the code lengths of the tree, whose symbols must be below `N`
+    /// ## Errors
+    /// Returns Err if the tree has a symbol of `N` or more, or a code longer than
+    /// [MAX_LEN](FixedCodebook::MAX_LEN), or for the reasons
+    /// [from_lengths()](FixedCodebook::from_lengths) does.
+    pub fn from_tree(tree: &HuffmanTree<u8>) -> Result<FixedCodebook<N>, &'static str> {
+        let mut lengths = [0u8; N];
+        for (symbol, len) in canonical::code_lengths(tree) {
+            let slot = lengths
+                .get_mut(usize::from(symbol))
+                .ok_or("Symbol outside of the alphabet")?;
+            *slot = u8::try_from(len).map_err(|_| "Code length over 32 bits")?;
+        }
+        FixedCodebook::from_lengths(lengths)
+    }
+
+    /// The code of the symbol, right-aligned, and its length. None if the symbol has no code.
+    pub const fn code(&self, symbol: usize) -> Option<(u32, u8)> {
+        if symbol >= N || self.lengths[symbol] == 0 {
+            return None;
+        }
+        Some((self.codes[symbol], self.lengths[symbol]))
+    }
+
+    /// The code length of every symbol, 0 for symbols without a code
+    pub const fn lengths(&self) -> &[u8; N] {
+        &self.lengths
+    }
+
+    /// The canonical tree of the codes, to decode with a
+    /// [HuffmanDecoder](super::huffman_decoder::HuffmanDecoder)
+    pub fn to_tree(&self) -> HuffmanTree<u8> {
+        let lengths: Vec<(u8, usize)> = (0..N)
+            .filter(|&symbol| self.lengths[symbol] > 0)
+            .map(|symbol| (symbol as u8, usize::from(self.lengths[symbol])))
+            .collect();
+        // The lengths were checked to be a complete code of at least two symbols
+        #[allow(clippy::expect_used)]
+        canonical::from_lengths(&lengths).expect("Invalid code lengths")
+    }
+
+    /// Encodes the symbols into `out`, most significant bit first, and returns the number of
+    /// bits written. The bits of `out` past them are left as they were.
+    /// ## Errors
+    /// Returns Err if a symbol has no code or `out` is too short.
+    pub fn encode_to_slice(&self, symbols: &[u8], out: &mut [u8]) -> Result<usize, &'static str> {
+        let mut encoder = FixedCapEncoder::new(self, out);
+        encoder.push_all(symbols).map_err(|error| match error {
+            ErrorKind::OutputFull => "Output too short",
+            ErrorKind::UnknownSymbol => "Symbol without a code",
+        })?;
+        Ok(encoder.bit_len())
+    }
+}
+
+/// Why a [FixedCapEncoder] did not write a symbol
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// The code of the symbol does not fit in what is left of the output
+    OutputFull,
+    /// The symbol has no code
+    UnknownSymbol,
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorKind::OutputFull => write!(f, "Output is full"),
+            ErrorKind::UnknownSymbol => write!(f, "Symbol without a code"),
+        }
+    }
+}
+
+impl std::error::Error for ErrorKind {}
+
+/// Encodes symbols one at a time into a buffer of the caller, e.g. a radio frame, and never
+/// allocates. A symbol is written whole or not at all: once the output is full, what was
+/// written so far is still a valid encoding of the symbols before.
+/// ## Examples
+/// ```
+/// # use rhuffman::huffman_tree::fixed_codebook::{ErrorKind, FixedCapEncoder, FixedCodebook};
+/// let codebook = FixedCodebook::from_lengths([1, 2, 3, 3]).unwrap();
+/// let mut frame = [0xffu8; 1];
+/// let mut encoder = FixedCapEncoder::new(&codebook, &mut frame);
+/// assert_eq!(encoder.push_all(&[0, 1, 3]), Ok(()));
+/// assert_eq!(encoder.push(3), Err(ErrorKind::OutputFull));
+/// assert_eq!(encoder.bit_len(), 6);
+/// assert_eq!(encoder.finish(), 1);
+/// assert_eq!(frame, [0b0101_1100]);
+/// ```
+#[derive(Debug)]
+pub struct FixedCapEncoder<'a, const N: usize> {
+    codebook: &'a FixedCodebook<N>,
+    out: &'a mut [u8],
+    bit_len: usize,
+}
+
+impl<'a, const N: usize> FixedCapEncoder<'a, N> {
+    /// Encodes into `out`, from its first bit
+    pub fn new(codebook: &'a FixedCodebook<N>, out: &'a mut [u8]) -> FixedCapEncoder<'a, N> {
+        FixedCapEncoder {
+            codebook,
+            out,
+            bit_len: 0,
+        }
+    }
+
+    /// Appends the code of the symbol, most significant bit first
+    /// ## Errors
+    /// Returns Err, writing nothing, if the symbol has no code or its code does not fit.
+    pub fn push(&mut self, symbol: u8) -> Result<(), ErrorKind> {
+        let (code, len) = self
+            .codebook
+            .code(usize::from(symbol))
+            .ok_or(ErrorKind::UnknownSymbol)?;
+        let len = usize::from(len);
+        if len > self.remaining_bits() {
+            return Err(ErrorKind::OutputFull);
+        }
+        for shift in (0..len).rev() {
+            let mask = 0x80 >> (self.bit_len % 8);
+            if (code >> shift) & 1 == 1 {
+                self.out[self.bit_len / 8] |= mask;
+            } else {
+                self.out[self.bit_len / 8] &= !mask;
+            }
+            self.bit_len += 1;
+        }
+        Ok(())
+    }
+
+    /// Appends the codes of the symbols, stopping at the first one that can not be written
+    /// ## Errors
+    /// Returns Err as [push()](FixedCapEncoder::push) does, the symbols before the failing
+    /// one being written.
+    pub fn push_all(&mut self, symbols: &[u8]) -> Result<(), ErrorKind> {
+        symbols.iter().try_for_each(|&symbol| self.push(symbol))
+    }
+
+    /// Number of bits written so far
+    pub fn bit_len(&self) -> usize {
+        self.bit_len
+    }
+
+    /// Number of bits left in the output
+    pub fn remaining_bits(&self) -> usize {
+        self.out.len() * 8 - self.bit_len
+    }
+
+    /// Clears the unused bits of the last byte written to, and returns the number of bytes
+    /// written to
+    pub fn finish(self) -> usize {
+        let len = self.bit_len.div_ceil(8);
+        let used = self.bit_len % 8;
+        if used > 0 {
+            self.out[len - 1] &= 0xff << (8 - used);
+        }
+        len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::huffman_decoder::HuffmanDecoder;
+    use super::super::huffman_generator::HuffmanGenerator;
+    use super::*;
+    use bit_vec::BitVec;
+
+    #[test]
+    fn encoded_symbols_decode_with_the_tree() {
+        let text: Vec<u8> = b"fixed codebooks for nibbles"
+            .iter()
+            .flat_map(|byte| vec![byte >> 4, byte & 0xf])
+            .collect();
+        let mut gen = HuffmanGenerator::new();
+        gen.add_occurences_from_iterator(&mut text.iter());
+        let codebook = FixedCodebook::<16>::from_tree(&gen.into_huffman_tree().unwrap()).unwrap();
+
+        let mut out = [0u8; 64];
+        let bit_len = codebook.encode_to_slice(&text, &mut out).unwrap();
+        let mut bits = BitVec::from_bytes(&out);
+        bits.truncate(bit_len);
+        let decoder = HuffmanDecoder::new(codebook.to_tree());
+        assert_eq!(decoder.decode_unbounded(&bits).unwrap(), text);
+
+        assert!(codebook.encode_to_slice(&text, &mut [0u8; 4]).is_err());
+        assert!(FixedCodebook::<4>::from_tree(&codebook.to_tree()).is_err());
+    }
+
+    #[test]
+    fn full_outputs_keep_the_symbols_written() {
+        let codebook = FixedCodebook::from_lengths([1, 2, 3, 3]).unwrap();
+        let symbols: Vec<u8> = (0..100u8).map(|i| i % 7 % 4).collect();
+        let mut frame = [0xaau8; 5];
+        let mut encoder = FixedCapEncoder::new(&codebook, &mut frame);
+        let written = symbols
+            .iter()
+            .take_while(|&&symbol| encoder.push(symbol).is_ok())
+            .count();
+        assert!(written < symbols.len());
+        assert_eq!(encoder.push(symbols[written]), Err(ErrorKind::OutputFull));
+        assert_eq!(encoder.push(4), Err(ErrorKind::UnknownSymbol));
+        let bit_len = encoder.bit_len();
+        assert!(encoder.remaining_bits() < 3);
+        assert_eq!(encoder.finish(), bit_len.div_ceil(8));
+
+        let mut bits = BitVec::from_bytes(&frame);
+        assert!(bits
+            .iter()
+            .take(bit_len.div_ceil(8) * 8)
+            .skip(bit_len)
+            .all(|bit| !bit));
+        bits.truncate(bit_len);
+        let decoder = HuffmanDecoder::new(codebook.to_tree());
+        assert_eq!(
+            decoder.decode_unbounded(&bits).unwrap(),
+            &symbols[..written]
+        );
+    }
+
+    #[test]
+    fn invalid_lengths_are_rejected() {
+        assert!(FixedCodebook::from_lengths([1, 1, 0]).is_ok());
+        // Incomplete
+        assert!(FixedCodebook::from_lengths([1, 2, 0]).is_err());
+        // Oversubscribed
+        assert!(FixedCodebook::from_lengths([1, 1, 1]).is_err());
+        assert!(FixedCodebook::from_lengths([1, 0]).is_err());
+        assert!(FixedCodebook::from_lengths([1, 33, 33]).is_err());
+        assert!(FixedCodebook::from_lengths([0u8; 300]).is_err());
+    }
+}
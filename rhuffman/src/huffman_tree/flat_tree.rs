@@ -0,0 +1,154 @@
+use super::huffman_node::{HuffmanBranch, HuffmanLeaf, HuffmanNode};
+
+/// A node of a [FlatTree]. A branch only stores the index of its left child, the right child
+/// always directly follows it.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum FlatNode<T> {
+    Leaf(T),
+    Branch(usize),
+}
+
+/// Index-based [HuffmanNode] tree laid out breadth-first in a single `Vec`, so that walking
+/// down the tree touches neighbouring memory instead of chasing a `Box` per level. The root
+/// is always at index 0, and children always come after their parent.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct FlatTree<T> {
+    nodes: Vec<FlatNode<T>>,
+}
+
+impl<T: PartialEq + Eq + Clone> FlatTree<T> {
+    pub fn from_tree(tree: &HuffmanNode<T>) -> FlatTree<T> {
+        let mut nodes = vec![FlatNode::Branch(0)];
+        let mut pending = std::collections::VecDeque::new();
+        pending.push_back((tree, 0));
+
+        while let Some((node, index)) = pending.pop_front() {
+            match node {
+                HuffmanNode::Leaf(leaf) => nodes[index] = FlatNode::Leaf(leaf.symbol().clone()),
+                HuffmanNode::Branch(branch) => {
+                    let left = nodes.len();
+                    nodes.push(FlatNode::Branch(0));
+                    nodes.push(FlatNode::Branch(0));
+                    nodes[index] = FlatNode::Branch(left);
+                    pending.push_back((branch.left(), left));
+                    pending.push_back((branch.right(), left + 1));
+                }
+            }
+        }
+        FlatTree { nodes }
+    }
+
+    /// Rebuilds the tree of `Box`ed nodes, without recursion so any depth is rebuilt
+    pub fn to_tree(&self) -> HuffmanNode<T> {
+        let mut built: Vec<Option<HuffmanNode<T>>> = vec![None; self.nodes.len()];
+        // Children come after their parent, so they are built first
+        for index in (0..self.nodes.len()).rev() {
+            let node = match &self.nodes[index] {
+                FlatNode::Leaf(symbol) => Some(HuffmanNode::Leaf(HuffmanLeaf::new(symbol.clone()))),
+                FlatNode::Branch(left) => match (built[*left].take(), built[*left + 1].take()) {
+                    (Some(left), Some(right)) => {
+                        Some(HuffmanNode::Branch(HuffmanBranch::new(left, right)))
+                    }
+                    _ => None,
+                },
+            };
+            built[index] = node;
+        }
+        // from_tree() gives every branch two children, each with a single parent
+        #[allow(clippy::expect_used)]
+        built
+            .swap_remove(Self::ROOT)
+            .expect("The flat tree is incomplete")
+    }
+}
+
+impl<T> FlatTree<T> {
+    pub const ROOT: usize = 0;
+
+    pub fn node(&self, index: usize) -> &FlatNode<T> {
+        &self.nodes[index]
+    }
+
+    /// Index of the child reached from the branch whose left child is at `left` by following `bit`
+    pub fn child(left: usize, bit: bool) -> usize {
+        left + bit as usize
+    }
+
+    /// The symbol of a tree made of a single leaf, coded with no bits
+    pub fn lone_symbol(&self) -> Option<&T> {
+        match self.node(Self::ROOT) {
+            FlatNode::Leaf(symbol) => Some(symbol),
+            FlatNode::Branch(_) => None,
+        }
+    }
+
+    /// Same as [HuffmanNode::visit()]: calls the visitor with the path leading to every leaf,
+    /// left to right, and its symbol
+    pub fn visit(&self, visitor: &mut impl FnMut(&[bool], &T)) {
+        let mut path = Vec::new();
+        let mut pending = vec![(Self::ROOT, None)];
+        while let Some((index, step)) = pending.pop() {
+            if let Some((depth, bit)) = step {
+                path.truncate(depth);
+                path.push(bit);
+            }
+            match self.node(index) {
+                FlatNode::Leaf(symbol) => visitor(&path, symbol),
+                FlatNode::Branch(left) => {
+                    let depth = path.len();
+                    pending.push((Self::child(*left, true), Some((depth, true))));
+                    pending.push((Self::child(*left, false), Some((depth, false))));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fibonacci_tree;
+    use super::super::huffman_generator::HuffmanGenerator;
+    use super::*;
+
+    #[test]
+    fn flat_tree_preserves_paths() {
+        let mut gen = HuffmanGenerator::new();
+        gen.add_occurences_to_symbol(&'a', 9);
+        gen.add_occurences_to_symbol(&'b', 5);
+        gen.add_occurences_to_symbol(&'c', 2);
+        gen.add_occurences_to_symbol(&'d', 2);
+        let tree = gen.into_huffman_tree().unwrap();
+        let flat = FlatTree::from_tree(&tree);
+
+        tree.visit(&mut |path: &[bool], symbol: &char| {
+            let mut index = FlatTree::<char>::ROOT;
+            for &bit in path {
+                match flat.node(index) {
+                    FlatNode::Branch(left) => index = FlatTree::<char>::child(*left, bit),
+                    FlatNode::Leaf(_) => panic!("Reached a leaf before the end of the path"),
+                }
+            }
+            assert_eq!(flat.node(index), &FlatNode::Leaf(*symbol));
+        });
+    }
+
+    #[test]
+    fn flat_trees_rebuild_the_tree() {
+        let mut gen = HuffmanGenerator::new();
+        gen.add_occurences_from_iterator(
+            &mut b"the quick brown fox jumps over the lazy dog".iter(),
+        );
+        let tree = gen.into_huffman_tree().unwrap();
+        assert_eq!(FlatTree::from_tree(&tree).to_tree(), tree);
+
+        // A chain of 89 levels, rebuilt leaves first
+        let tree = fibonacci_tree::<u32>(90);
+        let flat = FlatTree::from_tree(&tree);
+        assert_eq!(flat.to_tree(), tree);
+        assert_eq!(flat.lone_symbol(), None);
+
+        let lone = HuffmanNode::Leaf(HuffmanLeaf::new('x'));
+        let flat = FlatTree::from_tree(&lone);
+        assert_eq!((flat.to_tree(), flat.lone_symbol()), (lone, Some(&'x')));
+    }
+}
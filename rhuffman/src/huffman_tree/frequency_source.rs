@@ -0,0 +1,207 @@
+//! Symbol frequencies that trees can be built from directly, wherever they come from.
+//!
+//! A [HuffmanGenerator](super::huffman_generator::HuffmanGenerator) counts occurences one by
+//! one. When the counts already exist elsewhere, in a map, the result of a database query or
+//! a sketch, implement [FrequencySource] for them and pass them to [huffman_tree()] or
+//! [HuffmanEncoder::from_frequencies()](super::huffman_encoder::HuffmanEncoder::from_frequencies)
+//! instead of copying them into a generator.
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::hash::BuildHasher;
+
+use super::huffman_generator::{build_sorted, build_weighted};
+use super::HuffmanTree;
+
+/// Anything that can list how often each symbol occurs.
+/// ## Examples
+/// ```
+/// # use rhuffman::huffman_tree::frequency_source::{huffman_tree, FrequencySource};
+/// struct Uniform(Vec<char>);
+///
+/// impl FrequencySource<char> for Uniform {
+///     fn counts<'a>(&'a self) -> impl Iterator<Item = (&'a char, u64)>
+///     where
+///         char: 'a,
+///     {
+///         self.0.iter().map(|symbol| (symbol, 1))
+///     }
+/// }
+///
+/// let tree = huffman_tree(&Uniform(vec!['a', 'b', 'c', 'd'])).unwrap();
+/// let mut depths = vec![];
+/// tree.visit(&mut |path: &[bool], _: &char| depths.push(path.len()));
+/// assert_eq!(depths, vec![2, 2, 2, 2]);
+/// ```
+pub trait FrequencySource<T> {
+    /// Every symbol with its count. A symbol listed more than once gets the sum of its counts.
+    fn counts<'a>(&'a self) -> impl Iterator<Item = (&'a T, u64)>
+    where
+        T: 'a;
+}
+
+/// Builds the tree for the frequencies of the source, the same a
+/// [HuffmanGenerator](super::huffman_generator::HuffmanGenerator) given those occurences
+/// would build.
+/// ## None
+/// Returns None if the source lists no symbol.
+pub fn huffman_tree<T, S>(source: &S) -> Option<HuffmanTree<T>>
+where
+    T: Eq + Ord + Clone,
+    S: FrequencySource<T> + ?Sized,
+{
+    let mut counts: Vec<(&T, u64)> = source.counts().collect();
+    counts.sort_unstable_by_key(|(symbol, _)| *symbol);
+    counts.dedup_by(|(symbol, count), (kept, total)| {
+        let duplicate = symbol == kept;
+        if duplicate {
+            *total = total.saturating_add(*count);
+        }
+        duplicate
+    });
+    build_weighted(
+        counts
+            .into_iter()
+            .map(|(symbol, count)| (symbol.clone(), count)),
+    )
+    .map(HuffmanTree::from)
+}
+
+/// Builds the tree for counts already sorted from the least to the most frequent symbol, in
+/// linear time instead of the `O(n log n)` of [huffman_tree()]. Worth it for huge alphabets,
+/// e.g. word models with millions of symbols, whose counts are often sorted already. Each
+/// symbol must appear once.
+///
+/// Codes are as short as with [huffman_tree()] overall, though symbols of equal counts may
+/// trade codes.
+/// ## Errors
+/// Returns Err if the counts are not sorted in increasing order.
+/// ## Examples
+/// ```
+/// # use rhuffman::huffman_tree::frequency_source::huffman_tree_from_sorted;
+/// let tree = huffman_tree_from_sorted(vec![("rare", 1), ("common", 2), ("everywhere", 4)])
+///     .unwrap()
+///     .unwrap();
+/// let mut depths = vec![];
+/// tree.visit(&mut |path: &[bool], symbol: &&str| depths.push((*symbol, path.len())));
+/// depths.sort_unstable();
+/// assert_eq!(depths, vec![("common", 2), ("everywhere", 1), ("rare", 2)]);
+///
+/// assert!(huffman_tree_from_sorted(vec![("common", 2), ("rare", 1)]).is_err());
+/// ```
+pub fn huffman_tree_from_sorted<T: Eq>(
+    counts: impl IntoIterator<Item = (T, u64)>,
+) -> Result<Option<HuffmanTree<T>>, &'static str> {
+    Ok(build_sorted(counts.into_iter())?.map(HuffmanTree::from))
+}
+
+fn saturate(count: usize) -> u64 {
+    u64::try_from(count).unwrap_or(u64::MAX)
+}
+
+impl<T, S: BuildHasher> FrequencySource<T> for HashMap<T, u64, S> {
+    fn counts<'a>(&'a self) -> impl Iterator<Item = (&'a T, u64)>
+    where
+        T: 'a,
+    {
+        self.iter().map(|(symbol, count)| (symbol, *count))
+    }
+}
+
+impl<T, S: BuildHasher> FrequencySource<T> for HashMap<T, usize, S> {
+    fn counts<'a>(&'a self) -> impl Iterator<Item = (&'a T, u64)>
+    where
+        T: 'a,
+    {
+        self.iter()
+            .map(|(symbol, count)| (symbol, saturate(*count)))
+    }
+}
+
+impl<T> FrequencySource<T> for BTreeMap<T, u64> {
+    fn counts<'a>(&'a self) -> impl Iterator<Item = (&'a T, u64)>
+    where
+        T: 'a,
+    {
+        self.iter().map(|(symbol, count)| (symbol, *count))
+    }
+}
+
+impl<T> FrequencySource<T> for BTreeMap<T, usize> {
+    fn counts<'a>(&'a self) -> impl Iterator<Item = (&'a T, u64)>
+    where
+        T: 'a,
+    {
+        self.iter()
+            .map(|(symbol, count)| (symbol, saturate(*count)))
+    }
+}
+
+impl<T> FrequencySource<T> for [(T, u64)] {
+    fn counts<'a>(&'a self) -> impl Iterator<Item = (&'a T, u64)>
+    where
+        T: 'a,
+    {
+        self.iter().map(|(symbol, count)| (symbol, *count))
+    }
+}
+
+impl<T> FrequencySource<T> for Vec<(T, u64)> {
+    fn counts<'a>(&'a self) -> impl Iterator<Item = (&'a T, u64)>
+    where
+        T: 'a,
+    {
+        self.as_slice().counts()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::huffman_generator::HuffmanGenerator;
+    use super::*;
+
+    #[test]
+    fn map_matches_generator() {
+        let text = "this is an example of a huffman tree";
+        let mut map: HashMap<char, usize> = HashMap::new();
+        let mut gen = HuffmanGenerator::new();
+        for symbol in text.chars() {
+            *map.entry(symbol).or_insert(0) += 1;
+            gen.add_occurences_to_symbol(&symbol, 1);
+        }
+
+        assert_eq!(huffman_tree(&map), gen.into_huffman_tree());
+    }
+
+    #[test]
+    fn repeated_symbols_are_summed() {
+        let split = vec![("a", 1), ("b", 3), ("a", 4), ("c", 2)];
+        let summed = [("c", 2), ("a", 5), ("b", 3)];
+        assert_eq!(huffman_tree(&split), huffman_tree(&summed[..]));
+        assert_eq!(huffman_tree::<&str, [(&str, u64)]>(&[]), None);
+    }
+
+    #[test]
+    fn sorted_counts_give_codes_as_short() {
+        fn cost(tree: &HuffmanTree<u32>, counts: &[(u32, u64)]) -> u64 {
+            let mut cost = 0;
+            tree.visit(&mut |path: &[bool], symbol: &u32| {
+                cost += path.len() as u64 * counts[*symbol as usize].1;
+            });
+            cost
+        }
+
+        for len in 1..200u32 {
+            let mut counts: Vec<(u32, u64)> = (0..len)
+                .map(|symbol| (symbol, u64::from(symbol * 7919 % 31 + symbol % 3)))
+                .collect();
+            counts.sort_unstable_by_key(|&(symbol, count)| (count, symbol));
+            let sorted = huffman_tree_from_sorted(counts.clone()).unwrap().unwrap();
+            counts.sort_unstable();
+            assert_eq!(
+                cost(&sorted, &counts),
+                cost(&huffman_tree(&counts).unwrap(), &counts)
+            );
+        }
+        assert_eq!(huffman_tree_from_sorted(Vec::<(u8, u64)>::new()), Ok(None));
+    }
+}
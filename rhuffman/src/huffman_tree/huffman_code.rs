@@ -0,0 +1,179 @@
+use std::{collections::HashMap, hash::Hash};
+
+use bit_vec::BitVec;
+
+/// A code short enough to be stored inline, with its bits right-aligned and the first bit
+/// of the code being the most significant one.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct SmallCode {
+    bits: u64,
+    len: u8,
+}
+
+impl SmallCode {
+    pub const MAX_LEN: usize = 64;
+
+    pub fn from_bitvec(code: &BitVec) -> Option<SmallCode> {
+        if code.len() > SmallCode::MAX_LEN {
+            return None;
+        }
+        let bits = code.iter().fold(0u64, |bits, bit| (bits << 1) | bit as u64);
+        Some(SmallCode {
+            bits,
+            len: code.len() as u8,
+        })
+    }
+
+    /// The bits of the code, right-aligned, and its length
+    pub fn parts(self) -> (u64, u8) {
+        (self.bits, self.len)
+    }
+
+    pub fn append_to(self, out: &mut BitVec) {
+        for shift in (0..self.len).rev() {
+            out.push((self.bits >> shift) & 1 == 1);
+        }
+    }
+}
+
+/// The symbol to code map of an encoder. When every code fits in a [SmallCode], which only
+/// trees with extremely skewed counts exceed, the codes are stored inline in the map so
+/// encoding never touches a per-symbol heap allocation. Otherwise each symbol keeps its own
+/// `BitVec`.
+pub enum CodeTable<T> {
+    Small(HashMap<T, SmallCode>),
+    General(HashMap<T, BitVec>),
+}
+
+impl<T: Eq + Hash> CodeTable<T> {
+    pub fn from_codes(codes: HashMap<T, BitVec>) -> CodeTable<T> {
+        if codes.values().all(|code| code.len() <= SmallCode::MAX_LEN) {
+            CodeTable::Small(
+                codes
+                    .into_iter()
+                    .filter_map(|(symbol, code)| Some((symbol, SmallCode::from_bitvec(&code)?)))
+                    .collect(),
+            )
+        } else {
+            CodeTable::General(codes)
+        }
+    }
+
+    /// The codes, if they are all packed
+    pub fn small_codes(&self) -> Option<&HashMap<T, SmallCode>> {
+        match self {
+            CodeTable::Small(codes) => Some(codes),
+            CodeTable::General(_) => None,
+        }
+    }
+
+    /// Appends the code of the symbol to `out`, returns false if the symbol has no code.
+    pub fn append_code(&self, symbol: &T, out: &mut BitVec) -> bool {
+        match self {
+            CodeTable::Small(codes) => match codes.get(symbol) {
+                Some(code) => {
+                    code.append_to(out);
+                    true
+                }
+                None => false,
+            },
+            CodeTable::General(codes) => match codes.get(symbol) {
+                Some(code) => {
+                    out.extend(code.iter());
+                    true
+                }
+                None => false,
+            },
+        }
+    }
+
+    /// The code of the symbol, None if it has no code
+    pub fn code(&self, symbol: &T) -> Option<BitVec> {
+        match self {
+            CodeTable::Small(codes) => codes.get(symbol).map(|&code| {
+                let mut bits = BitVec::with_capacity(usize::from(code.len));
+                code.append_to(&mut bits);
+                bits
+            }),
+            CodeTable::General(codes) => codes.get(symbol).cloned(),
+        }
+    }
+
+    /// Every symbol with its code, in no particular order
+    pub fn codes(&self) -> Vec<(&T, BitVec)> {
+        match self {
+            CodeTable::Small(codes) => codes
+                .iter()
+                .map(|(symbol, &code)| {
+                    let mut bits = BitVec::with_capacity(usize::from(code.len));
+                    code.append_to(&mut bits);
+                    (symbol, bits)
+                })
+                .collect(),
+            CodeTable::General(codes) => codes
+                .iter()
+                .map(|(symbol, code)| (symbol, code.clone()))
+                .collect(),
+        }
+    }
+
+    /// Length of the code of the symbol, None if it has no code
+    pub fn code_len(&self, symbol: &T) -> Option<usize> {
+        match self {
+            CodeTable::Small(codes) => codes.get(symbol).map(|code| usize::from(code.len)),
+            CodeTable::General(codes) => codes.get(symbol).map(BitVec::len),
+        }
+    }
+
+    /// Hands the code of the symbol to `push` as right-aligned runs of at most
+    /// [SmallCode::MAX_LEN] bits, returns false if the symbol has no code.
+    pub fn emit_code(&self, symbol: &T, mut push: impl FnMut(u64, u8)) -> bool {
+        match self {
+            CodeTable::Small(codes) => match codes.get(symbol) {
+                Some(code) => {
+                    push(code.bits, code.len);
+                    true
+                }
+                None => false,
+            },
+            CodeTable::General(codes) => match codes.get(symbol) {
+                Some(code) => {
+                    for bit in code.iter() {
+                        push(bit as u64, 1);
+                    }
+                    true
+                }
+                None => false,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn small_code_appends_bits_in_order() {
+        let code = BitVec::from_fn(11, |i| i % 3 == 0);
+        let mut out = BitVec::new();
+        SmallCode::from_bitvec(&code).unwrap().append_to(&mut out);
+        assert_eq!(out, code);
+    }
+
+    #[test]
+    fn long_codes_fall_back_to_general_table() {
+        let mut codes = HashMap::new();
+        codes.insert('a', BitVec::from_elem(SmallCode::MAX_LEN, true));
+        assert!(matches!(
+            CodeTable::from_codes(codes.clone()),
+            CodeTable::Small(_)
+        ));
+
+        codes.insert('b', BitVec::from_elem(SmallCode::MAX_LEN + 1, false));
+        assert!(matches!(
+            CodeTable::from_codes(codes),
+            CodeTable::General(_)
+        ));
+    }
+}
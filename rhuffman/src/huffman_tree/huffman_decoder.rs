@@ -1,51 +1,599 @@
+use super::canonical;
+use super::flat_tree::{FlatNode, FlatTree};
 use super::huffman_node::HuffmanNode;
+use super::lookup_table::{LookupTable, DEFAULT_ROOT_BITS, MAX_ROOT_BITS};
 
 use bit_vec::BitVec;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, Read};
+use std::iter;
+
+/// How many bytes [ReadSymbols] reads from its source at once
+const READ_CHUNK: usize = 8 * 1024;
+
+/// Why a [HuffmanDecoder] could not decode a buffer
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[non_exhaustive]
+pub enum DecodeError {
+    /// The buffer ends inside a code
+    UnexpectedEndOfInput,
+    /// The tree can not decode bits: it is a lone symbol, coded with none
+    InvalidTree,
+    /// A code is longer than the stall limit, see
+    /// [decode_with_stall_limit()](HuffmanDecoder::decode_with_stall_limit)
+    StallLimitExceeded,
+    /// A byte or more is left after the symbols asked for, see
+    /// [decode_exact()](HuffmanDecoder::decode_exact)
+    TrailingData,
+}
+
+impl From<DecodeError> for &'static str {
+    fn from(error: DecodeError) -> &'static str {
+        match error {
+            DecodeError::UnexpectedEndOfInput => "The data ends inside a code",
+            DecodeError::InvalidTree => "A lone symbol can not decode any bit",
+            DecodeError::StallLimitExceeded => "No symbol decoded within the stall limit",
+            DecodeError::TrailingData => "A byte or more is left after the last symbol",
+        }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", <&str>::from(*self))
+    }
+}
+
+impl std::error::Error for DecodeError {}
 
 pub struct HuffmanDecoder<T: PartialEq + Eq> {
-    root: HuffmanNode<T>,
+    flat: FlatTree<T>,
+    /// None when the tree is too deep for a table of reasonable size
+    table: Option<LookupTable<T>>,
 }
 
 impl<T: PartialEq + Eq + Clone> HuffmanDecoder<T> {
     pub fn new(tree: HuffmanNode<T>) -> HuffmanDecoder<T> {
-        HuffmanDecoder { root: tree }
+        let flat = FlatTree::from_tree(&tree);
+        let table = LookupTable::new(&flat, DEFAULT_ROOT_BITS);
+        HuffmanDecoder { flat, table }
+    }
+
+    /// Decodes through a table indexed by the next `bits` bits of the buffer, giving the
+    /// symbol and the length of its code in one lookup, instead of the default 9 bit table.
+    /// Codes longer than `bits` take a second lookup. Wider tables suit trees with many long
+    /// codes at the cost of `2^bits` entries, and are capped at 16 bits. 0 walks the tree bit
+    /// by bit instead, as do trees whose codes are too long for a table.
+    /// ## Examples
+    /// ```
+    /// # use rhuffman::huffman_tree::huffman_generator::HuffmanGenerator;
+    /// let text = b"the quick brown fox jumps over the lazy dog";
+    /// let mut gen = HuffmanGenerator::new();
+    /// gen.add_occurences_from_iterator(&mut text.iter());
+    /// let (encoder, decoder) = gen.into_encoder_decoder_pair().unwrap();
+    /// let decoder = decoder.with_lookup_table(12);
+    ///
+    /// let bits = encoder.encode(&mut text.iter()).unwrap();
+    /// assert_eq!(decoder.decode_unbounded(&bits).unwrap(), text);
+    /// ```
+    #[must_use]
+    pub fn with_lookup_table(mut self, bits: u8) -> HuffmanDecoder<T> {
+        self.table = match bits {
+            0 => None,
+            bits => LookupTable::new(&self.flat, bits.min(MAX_ROOT_BITS)),
+        };
+        self
     }
 
-    pub fn decode_unbounded(&self, buffer: &BitVec) -> Vec<T> {
+    /// Decodes every symbol of the buffer.
+    /// ## Errors
+    /// Returns Err if the buffer ends inside a code, or is not empty and the tree is a lone
+    /// symbol, whose code has no bits.
+    pub fn decode_unbounded(&self, buffer: &BitVec) -> Result<Vec<T>, DecodeError> {
+        let mut result = vec![];
+        self.decode_into(buffer, &mut result)?;
+        Ok(result)
+    }
+
+    /// Same as [decode_unbounded()](HuffmanDecoder::decode_unbounded), handing the symbols
+    /// to a caller-provided sink as they are decoded instead of collecting them: a `Vec`
+    /// whose allocation is reused across calls, a `String`, or any other [Extend].
+    /// ## Errors
+    /// Returns Err as [decode_unbounded()](HuffmanDecoder::decode_unbounded) does, after
+    /// handing over the symbols before the truncated code.
+    /// ## Examples
+    /// ```
+    /// # use rhuffman::huffman_tree::huffman_generator::HuffmanGenerator;
+    /// let text: Vec<char> = "abracadabra".chars().collect();
+    /// let mut gen = HuffmanGenerator::new();
+    /// gen.add_occurences_from_iterator(&mut text.iter());
+    /// let (encoder, decoder) = gen.into_encoder_decoder_pair().unwrap();
+    /// let bits = encoder.encode(&mut text.iter()).unwrap();
+    ///
+    /// let mut decoded = String::from("> ");
+    /// decoder.decode_into(&bits, &mut decoded).unwrap();
+    /// assert_eq!(decoded, "> abracadabra");
+    /// ```
+    pub fn decode_into<E: Extend<T>>(
+        &self,
+        buffer: &BitVec,
+        sink: &mut E,
+    ) -> Result<(), DecodeError> {
+        self.check_tree(buffer)?;
         let mut pos = 0;
+        let mut truncated = false;
+        sink.extend(iter::from_fn(|| {
+            if pos >= buffer.len() {
+                return None;
+            }
+            let symbol = self.decode_symbol(buffer, &mut pos);
+            truncated = symbol.is_none();
+            symbol
+        }));
+        match truncated {
+            true => Err(DecodeError::UnexpectedEndOfInput),
+            false => Ok(()),
+        }
+    }
+
+    /// Decodes exactly `n` symbols from the start of the buffer, for bits padded to a whole
+    /// byte, e.g. read with `BitVec::from_bytes()`, whose number of symbols is known. Padding
+    /// may decode as symbols, which is why the count is needed: the up to 7 bits left after
+    /// the last symbol are ignored. A lone symbol, coded with no bits, decodes `n` times.
+    /// ## Errors
+    /// Returns Err if the buffer ends before the `n`th symbol, or a byte or more is left
+    /// after it.
+    /// ## Examples
+    /// ```
+    /// # use rhuffman::huffman_tree::huffman_decoder::{DecodeError, HuffmanDecoder};
+    /// # use rhuffman::huffman_tree::huffman_generator::HuffmanGenerator;
+    /// # use bit_vec::BitVec;
+    /// let mut gen = HuffmanGenerator::new();
+    /// gen.add_occurences_from_iterator(&mut b"aaab".iter());
+    /// let (encoder, decoder) = gen.into_encoder_decoder_pair().unwrap();
+    /// // Each symbol takes one bit, the padding decodes as more of them
+    /// let bytes = encoder.encode(&mut b"ab".iter()).unwrap().to_bytes();
+    /// let bits = BitVec::from_bytes(&bytes);
+    /// assert_eq!(decoder.decode_unbounded(&bits).unwrap().len(), 8);
+    ///
+    /// assert_eq!(decoder.decode_exact(&bits, 2).unwrap(), b"ab");
+    /// assert_eq!(decoder.decode_exact(&bits, 9), Err(DecodeError::UnexpectedEndOfInput));
+    /// ```
+    pub fn decode_exact(&self, buffer: &BitVec, n: usize) -> Result<Vec<T>, DecodeError> {
+        if let Some(symbol) = self.flat.lone_symbol() {
+            return match buffer.len() < 8 {
+                true => Ok(vec![symbol.clone(); n]),
+                false => Err(DecodeError::TrailingData),
+            };
+        }
+        // Every code takes at least a bit, so a large `n` does not allocate more than the buffer
+        let mut result = Vec::with_capacity(n.min(buffer.len()));
+        let mut pos = 0;
+        for _ in 0..n {
+            result.push(
+                self.decode_symbol(buffer, &mut pos)
+                    .ok_or(DecodeError::UnexpectedEndOfInput)?,
+            );
+        }
+        if buffer.len() - pos >= 8 {
+            return Err(DecodeError::TrailingData);
+        }
+        Ok(result)
+    }
+
+    /// Decodes the symbol at `pos`, None if the buffer ends inside its code
+    pub(super) fn decode_symbol(&self, buffer: &BitVec, pos: &mut usize) -> Option<T> {
+        if let Some(symbol) = self
+            .table
+            .as_ref()
+            .and_then(|table| table.decode_symbol(buffer, pos))
+        {
+            return Some(symbol.clone());
+        }
+        // Codes cut short by the end of the buffer are left to the tree walk
+        HuffmanDecoder::decode_symbol_before(buffer, &self.flat, pos, buffer.len())
+    }
+
+    /// Same as [decode_unbounded()](HuffmanDecoder::decode_unbounded), giving up when no
+    /// symbol was produced after `stall_bits` bits of input. A corrupt or malicious tree can
+    /// map every input to one very deep path, this bounds the work done for each symbol of
+    /// untrusted input.
+    /// ## Errors
+    /// Returns Err when a code is longer than `stall_bits`, or as
+    /// [decode_unbounded()](HuffmanDecoder::decode_unbounded) does.
+    pub fn decode_with_stall_limit(
+        &self,
+        buffer: &BitVec,
+        stall_bits: usize,
+    ) -> Result<Vec<T>, DecodeError> {
         let mut result = vec![];
-        while pos < buffer.len() {
-            result.push(HuffmanDecoder::decode_single_symbol(
-                buffer, &self.root, &mut pos,
-            ))
-        }
-        result
-    }
-
-    fn decode_single_symbol(buffer: &BitVec, root: &HuffmanNode<T>, pos: &mut usize) -> T {
-        match root {
-            HuffmanNode::Branch(branch) => {
-                let bit_value = buffer[*pos];
-                let next_node = if bit_value {
-                    &branch.links.1
-                } else {
-                    &branch.links.0
-                };
-                *pos += 1;
-                HuffmanDecoder::decode_single_symbol(buffer, next_node, pos)
+        self.decode_into_with_stall_limit(buffer, stall_bits, &mut result)?;
+        Ok(result)
+    }
+
+    /// Same as [decode_with_stall_limit()](HuffmanDecoder::decode_with_stall_limit), handing
+    /// the symbols to the sink as [decode_into()](HuffmanDecoder::decode_into) does.
+    /// ## Errors
+    /// Returns Err as [decode_with_stall_limit()](HuffmanDecoder::decode_with_stall_limit)
+    /// does, after handing over the symbols before the failing code.
+    pub fn decode_into_with_stall_limit<E: Extend<T>>(
+        &self,
+        buffer: &BitVec,
+        stall_bits: usize,
+        sink: &mut E,
+    ) -> Result<(), DecodeError> {
+        self.check_tree(buffer)?;
+        let mut error = None;
+        let mut pos = 0;
+        sink.extend(iter::from_fn(|| {
+            if pos >= buffer.len() || error.is_some() {
+                return None;
+            }
+            let symbol = self.decode_symbol_within(buffer, &mut pos, stall_bits);
+            symbol.map_err(|e| error = Some(e)).ok()
+        }));
+        match error {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    /// Decodes the symbol at `pos`, Err if its code is longer than `stall_bits`
+    fn decode_symbol_within(
+        &self,
+        buffer: &BitVec,
+        pos: &mut usize,
+        stall_bits: usize,
+    ) -> Result<T, DecodeError> {
+        let start = *pos;
+        let symbol = self
+            .table
+            .as_ref()
+            .and_then(|table| table.decode_symbol(buffer, pos));
+        let symbol = match symbol {
+            Some(symbol) => symbol.clone(),
+            None => {
+                let end = buffer.len().min(start.saturating_add(stall_bits));
+                HuffmanDecoder::decode_symbol_before(buffer, &self.flat, pos, end).ok_or(
+                    if end == buffer.len() && end - start < stall_bits {
+                        DecodeError::UnexpectedEndOfInput
+                    } else {
+                        DecodeError::StallLimitExceeded
+                    },
+                )?
+            }
+        };
+        if *pos - start > stall_bits {
+            return Err(DecodeError::StallLimitExceeded);
+        }
+        Ok(symbol)
+    }
+
+    /// A lone symbol would be decoded forever without consuming any bit
+    pub(super) fn check_tree(&self, buffer: &BitVec) -> Result<(), DecodeError> {
+        match self.flat.lone_symbol() {
+            Some(_) if !buffer.is_empty() => Err(DecodeError::InvalidTree),
+            _ => Ok(()),
+        }
+    }
+
+    /// Walks the tree from `pos`, None if no leaf is reached before `end`
+    fn decode_symbol_before(
+        buffer: &BitVec,
+        tree: &FlatTree<T>,
+        pos: &mut usize,
+        end: usize,
+    ) -> Option<T> {
+        let mut index = FlatTree::<T>::ROOT;
+        loop {
+            match tree.node(index) {
+                FlatNode::Branch(_) if *pos >= end => return None,
+                FlatNode::Branch(left) => {
+                    index = FlatTree::<T>::child(*left, buffer[*pos]);
+                    *pos += 1;
+                }
+                FlatNode::Leaf(symbol) => return Some(symbol.clone()),
             }
-            HuffmanNode::Leaf(leaf) => leaf.symbol.clone(),
         }
     }
 
-    pub fn get_tree(&self) -> &HuffmanNode<T> {
-        &self.root
+    /// The tree of the decoder. Decoders only keep it [flattened](FlatTree), so it is
+    /// rebuilt on every call.
+    pub fn get_tree(&self) -> HuffmanNode<T> {
+        self.flat.to_tree()
+    }
+
+    /// The symbol of a tree made of a single leaf, which codes it with no bits
+    pub fn lone_symbol(&self) -> Option<&T> {
+        self.flat.lone_symbol()
+    }
+
+    /// Decodes `symbol_count` symbols from the bits read from `reader`, most significant bit
+    /// of each byte first as [encode()](super::huffman_encoder::HuffmanEncoder::encode)
+    /// produces them. Bytes are read as symbols are needed, so neither the encoded bits nor
+    /// the decoded symbols have to fit in memory. Bytes are read in chunks, so the reader
+    /// needs no buffering of its own.
+    /// ## Examples
+    /// ```
+    /// # use rhuffman::huffman_tree::huffman_generator::HuffmanGenerator;
+    /// let mut gen = HuffmanGenerator::new();
+    /// gen.add_occurences_from_iterator(&mut b"abracadabra".iter());
+    /// let (encoder, decoder) = gen.into_encoder_decoder_pair().unwrap();
+    /// let bytes = encoder.encode(&mut b"abracadabra".iter()).unwrap().to_bytes();
+    ///
+    /// let symbols: Result<Vec<u8>, _> = decoder.decode_from_reader(&bytes[..], 11).collect();
+    /// assert_eq!(symbols.unwrap(), b"abracadabra");
+    /// ```
+    pub fn decode_from_reader<R: Read>(
+        &self,
+        reader: R,
+        symbol_count: u64,
+    ) -> ReadSymbols<'_, T, R> {
+        ReadSymbols {
+            tree: &self.flat,
+            reader,
+            remaining: symbol_count,
+            buffer: vec![0; READ_CHUNK],
+            filled: 0,
+            pos: 0,
+            stall_bits: None,
+        }
+    }
+}
+
+/// Symbols decoded from an [io::Read], see
+/// [decode_from_reader()](HuffmanDecoder::decode_from_reader). Yields an error of kind
+/// [UnexpectedEof](io::ErrorKind::UnexpectedEof) if the reader ends before the last symbol,
+/// and nothing after an error.
+pub struct ReadSymbols<'a, T, R> {
+    tree: &'a FlatTree<T>,
+    reader: R,
+    remaining: u64,
+    buffer: Vec<u8>,
+    /// Number of bytes of the buffer read from the reader
+    filled: usize,
+    /// Position of the next bit in the buffer
+    pos: usize,
+    stall_bits: Option<usize>,
+}
+
+impl<'a, T, R: Read> ReadSymbols<'a, T, R> {
+    /// Yields an error of kind [InvalidData](io::ErrorKind::InvalidData) wrapping
+    /// [DecodeError::StallLimitExceeded] once a code takes more than `stall_bits` bits,
+    /// instead of reading on. A corrupt tree can map a whole stream to one deep code, and
+    /// the limit bounds how much is read before a symbol comes out.
+    /// ## Examples
+    /// ```
+    /// # use rhuffman::huffman_tree::huffman_generator::HuffmanGenerator;
+    /// let mut gen = HuffmanGenerator::new();
+    /// gen.add_occurences_from_iterator(&mut b"aaaabbc".iter());
+    /// let (encoder, decoder) = gen.into_encoder_decoder_pair().unwrap();
+    /// let bytes = encoder.encode(&mut b"c".iter()).unwrap().to_bytes();
+    /// // c takes 2 bits
+    /// assert!(decoder.decode_from_reader(&bytes[..], 1).with_stall_limit(2).all(|s| s.is_ok()));
+    /// assert!(decoder.decode_from_reader(&bytes[..], 1).with_stall_limit(1).all(|s| s.is_err()));
+    /// ```
+    #[must_use]
+    pub fn with_stall_limit(mut self, stall_bits: usize) -> ReadSymbols<'a, T, R> {
+        self.stall_bits = Some(stall_bits);
+        self
+    }
+
+    fn next_bit(&mut self) -> io::Result<bool> {
+        if self.pos == self.filled * 8 {
+            self.filled = loop {
+                match self.reader.read(&mut self.buffer) {
+                    Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                    result => break result?,
+                }
+            };
+            self.pos = 0;
+            if self.filled == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+        let bit = self.buffer[self.pos / 8] >> (7 - self.pos % 8) & 1 == 1;
+        self.pos += 1;
+        Ok(bit)
+    }
+}
+
+impl<'a, T: Clone, R: Read> Iterator for ReadSymbols<'a, T, R> {
+    type Item = io::Result<T>;
+
+    fn next(&mut self) -> Option<io::Result<T>> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let mut index = FlatTree::<T>::ROOT;
+        let mut code_bits = 0;
+        loop {
+            match self.tree.node(index) {
+                FlatNode::Branch(_) if Some(code_bits) == self.stall_bits => {
+                    self.remaining = 0;
+                    return Some(Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        DecodeError::StallLimitExceeded,
+                    )));
+                }
+                FlatNode::Branch(left) => match self.next_bit() {
+                    Ok(bit) => {
+                        index = FlatTree::<T>::child(*left, bit);
+                        code_bits += 1;
+                    }
+                    Err(error) => {
+                        self.remaining = 0;
+                        return Some(Err(error));
+                    }
+                },
+                FlatNode::Leaf(symbol) => return Some(Ok(symbol.clone())),
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, usize::try_from(self.remaining).ok())
+    }
+}
+
+/// Decoding a stream whose bits come in successive chunks, e.g. network packets, with a
+/// [HuffmanDecoder]. Codes may span chunks: the state keeps the part of the code read so far
+/// and completes it with the next chunk.
+/// ## Examples
+/// ```
+/// # use rhuffman::huffman_tree::huffman_decoder::DecoderState;
+/// # use rhuffman::huffman_tree::huffman_generator::HuffmanGenerator;
+/// # use bit_vec::BitVec;
+/// let mut gen = HuffmanGenerator::new();
+/// gen.add_occurences_from_iterator(&mut b"abracadabra".iter());
+/// let (encoder, decoder) = gen.into_encoder_decoder_pair().unwrap();
+/// let bits = encoder.encode(&mut b"abracadabra".iter()).unwrap();
+///
+/// let mut state = DecoderState::new(&decoder);
+/// let mut decoded = vec![];
+/// for chunk in bits.iter().collect::<Vec<bool>>().chunks(5) {
+///     let chunk: BitVec = chunk.iter().copied().collect();
+///     state.feed(&chunk, &mut decoded).unwrap();
+/// }
+/// state.finish().unwrap();
+/// assert_eq!(decoded, b"abracadabra");
+/// ```
+pub struct DecoderState<'a, T: PartialEq + Eq> {
+    decoder: &'a HuffmanDecoder<T>,
+    /// Node of the flat tree the bits fed so far lead to, the root between codes
+    node: usize,
+    /// Number of bits of the code `node` is in
+    code_bits: usize,
+    stall_bits: Option<usize>,
+}
+
+impl<'a, T: PartialEq + Eq + Clone> DecoderState<'a, T> {
+    /// Starts decoding at the first code of the stream
+    pub fn new(decoder: &'a HuffmanDecoder<T>) -> DecoderState<'a, T> {
+        DecoderState {
+            decoder,
+            node: FlatTree::<T>::ROOT,
+            code_bits: 0,
+            stall_bits: None,
+        }
+    }
+
+    /// Makes [feed()](DecoderState::feed) fail once a code takes more than `stall_bits`
+    /// bits, across chunks. A corrupt tree can map a whole stream to one deep code, which
+    /// would otherwise be fed forever without a symbol coming out.
+    /// ## Examples
+    /// ```
+    /// # use rhuffman::huffman_tree::huffman_decoder::{DecodeError, DecoderState};
+    /// # use rhuffman::huffman_tree::huffman_generator::HuffmanGenerator;
+    /// # use bit_vec::BitVec;
+    /// let mut gen = HuffmanGenerator::new();
+    /// gen.add_occurences_from_iterator(&mut b"aaaabbc".iter());
+    /// let (encoder, decoder) = gen.into_encoder_decoder_pair().unwrap();
+    /// // c takes 2 bits
+    /// let bits = encoder.encode(&mut b"c".iter()).unwrap();
+    ///
+    /// let mut state = DecoderState::new(&decoder).with_stall_limit(1);
+    /// let mut decoded = vec![];
+    /// assert_eq!(state.feed(&bits, &mut decoded), Err(DecodeError::StallLimitExceeded));
+    /// ```
+    #[must_use]
+    pub fn with_stall_limit(mut self, stall_bits: usize) -> DecoderState<'a, T> {
+        self.stall_bits = Some(stall_bits);
+        self
+    }
+
+    /// Decodes the chunk, handing the symbols completed by its bits to the sink. The bits of
+    /// a code the chunk ends inside are kept for the next one.
+    /// ## Errors
+    /// Returns Err if the chunk is not empty and the tree is a lone symbol, whose code has
+    /// no bits, or a code is longer than the [stall limit](DecoderState::with_stall_limit).
+    pub fn feed<E: Extend<T>>(&mut self, chunk: &BitVec, sink: &mut E) -> Result<(), DecodeError> {
+        let decoder = self.decoder;
+        decoder.check_tree(chunk)?;
+        let (node, code_bits) = (&mut self.node, &mut self.code_bits);
+        let stall_bits = self.stall_bits.unwrap_or(usize::MAX);
+        let mut stalled = false;
+        let mut pos = 0;
+        sink.extend(iter::from_fn(|| loop {
+            if *node == FlatTree::<T>::ROOT {
+                if pos >= chunk.len() {
+                    return None;
+                }
+                let start = pos;
+                if let Some(symbol) = decoder
+                    .table
+                    .as_ref()
+                    .and_then(|table| table.decode_symbol(chunk, &mut pos))
+                {
+                    if pos - start > stall_bits {
+                        stalled = true;
+                        return None;
+                    }
+                    return Some(symbol.clone());
+                }
+            }
+            match decoder.flat.node(*node) {
+                FlatNode::Branch(_) if *code_bits == stall_bits => {
+                    stalled = true;
+                    return None;
+                }
+                FlatNode::Branch(_) if pos >= chunk.len() => return None,
+                FlatNode::Branch(left) => {
+                    *node = FlatTree::<T>::child(*left, chunk[pos]);
+                    *code_bits += 1;
+                    pos += 1;
+                }
+                FlatNode::Leaf(symbol) => {
+                    *node = FlatTree::<T>::ROOT;
+                    *code_bits = 0;
+                    return Some(symbol.clone());
+                }
+            }
+        }));
+        match stalled {
+            true => Err(DecodeError::StallLimitExceeded),
+            false => Ok(()),
+        }
+    }
+
+    /// Whether the chunks fed so far end inside a code
+    pub fn is_partial(&self) -> bool {
+        self.node != FlatTree::<T>::ROOT
+    }
+
+    /// Ends the stream.
+    /// ## Errors
+    /// Returns Err if the chunks fed end inside a code.
+    pub fn finish(self) -> Result<(), DecodeError> {
+        match self.is_partial() {
+            true => Err(DecodeError::UnexpectedEndOfInput),
+            false => Ok(()),
+        }
+    }
+}
+
+impl<T: Eq + Ord + Clone> HuffmanDecoder<T> {
+    /// Decoder of the canonical code with these code lengths, as encoded by
+    /// [HuffmanEncoder::canonical_from_tree()](super::huffman_encoder::HuffmanEncoder::canonical_from_tree)
+    /// ## Errors
+    /// Returns Err if there are no symbols or the lengths are not those of a complete code.
+    pub fn from_code_lengths(lengths: &[(T, usize)]) -> Result<HuffmanDecoder<T>, &'static str> {
+        canonical::from_lengths(lengths)
+            .map(HuffmanDecoder::new)
+            .ok_or("The lengths do not describe a complete prefix code")
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::super::huffman_encoder::HuffmanEncoder;
     use super::super::huffman_generator::*;
+    use super::super::huffman_node::{HuffmanLeaf, HuffmanNode};
+    use super::super::{fibonacci_tree, HuffmanBranch};
+    use super::{DecodeError, DecoderState, HuffmanDecoder};
+    use bit_vec::BitVec;
     use std::fmt::Debug;
     use std::hash::Hash;
     fn encode_decode<T: Eq + Clone + Hash + Ord + Debug>(
@@ -56,7 +604,7 @@ mod tests {
         gen.add_occurences_from_iterator(gen_codes);
         let (encoder, decoder) = gen.into_encoder_decoder_pair().unwrap();
         let result = encoder.encode(encode).unwrap();
-        decoder.decode_unbounded(&result)
+        decoder.decode_unbounded(&result).unwrap()
     }
 
     #[test]
@@ -94,4 +642,271 @@ mod tests {
         let decoded = encode_decode(&mut literal.iter(), &mut literal.iter());
         assert_eq!(decoded, literal);
     }
+
+    #[test]
+    fn codes_longer_than_the_root_table_decode() {
+        let tree = fibonacci_tree::<u32>(18);
+        let (encoder, decoder) = (HuffmanEncoder::from_tree(&tree), HuffmanDecoder::new(tree));
+        assert!(decoder.table.is_some());
+
+        let literal: Vec<u32> = (0..18).chain((0..18).rev()).collect();
+        let encoded = encoder.encode(&mut literal.iter()).unwrap();
+        assert_eq!(decoder.decode_unbounded(&encoded).unwrap(), literal);
+
+        // The last code, for 0, is 17 bits long
+        let mut truncated = encoded.clone();
+        truncated.truncate(encoded.len() - 1);
+        assert_eq!(
+            decoder.decode_unbounded(&truncated),
+            Err(DecodeError::UnexpectedEndOfInput)
+        );
+        let mut out = vec![];
+        assert!(decoder.decode_into(&truncated, &mut out).is_err());
+        assert_eq!(out, &literal[..35]);
+    }
+
+    #[test]
+    fn exact_decoding_checks_the_count() {
+        let literal: Vec<u8> = b"mississippi river".to_vec();
+        let mut gen = HuffmanGenerator::new();
+        gen.add_occurences_from_iterator(&mut literal.iter());
+        let (encoder, decoder) = gen.into_encoder_decoder_pair().unwrap();
+        let mut bits = encoder.encode(&mut literal.iter()).unwrap();
+        assert_eq!(decoder.decode_exact(&bits, literal.len()).unwrap(), literal);
+        assert_eq!(
+            decoder.decode_exact(&bits, literal.len() + 1),
+            Err(DecodeError::UnexpectedEndOfInput)
+        );
+        assert_eq!(decoder.decode_exact(&bits, usize::MAX).ok(), None);
+
+        let padded = BitVec::from_bytes(&bits.to_bytes());
+        assert_eq!(
+            decoder.decode_exact(&padded, literal.len()).unwrap(),
+            literal
+        );
+        bits.grow(8, false);
+        assert_eq!(
+            decoder.decode_exact(&bits, literal.len()),
+            Err(DecodeError::TrailingData)
+        );
+
+        let lone = HuffmanDecoder::new(HuffmanNode::Leaf(HuffmanLeaf::new(b'x')));
+        assert_eq!(
+            lone.decode_exact(&BitVec::from_elem(7, false), 3).unwrap(),
+            b"xxx"
+        );
+        assert_eq!(lone.decode_exact(&bits, 3), Err(DecodeError::TrailingData));
+    }
+
+    #[test]
+    fn every_table_width_decodes_the_same() {
+        let literal: Vec<u8> = (0..20_000u32).map(|i| (i * i % 509) as u8).collect();
+        let mut gen = HuffmanGenerator::new();
+        gen.add_occurences_from_iterator(&mut literal.iter());
+        let tree = gen.into_huffman_tree().unwrap();
+        let encoder = super::super::huffman_encoder::HuffmanEncoder::from_tree(&tree);
+        let encoded = encoder.encode(&mut literal.iter()).unwrap();
+        let mut truncated = encoded.clone();
+        truncated.truncate(encoded.len() - 1);
+
+        for bits in [0, 1, 4, 8, 12, 16, 30] {
+            let decoder = HuffmanDecoder::new(tree.clone()).with_lookup_table(bits);
+            assert_eq!(decoder.table.is_some(), bits > 0);
+            assert_eq!(decoder.decode_unbounded(&encoded).unwrap(), literal);
+            assert_eq!(
+                decoder.decode_unbounded(&truncated),
+                Err(DecodeError::UnexpectedEndOfInput)
+            );
+        }
+    }
+
+    #[test]
+    fn reader_decoding_matches_buffer_decoding() {
+        let literal: Vec<u16> = (0..30_000u32)
+            .map(|i| (i * i % 1009) as u16 % 300)
+            .collect();
+        let mut gen = HuffmanGenerator::new();
+        gen.add_occurences_from_iterator(&mut literal.iter());
+        let (encoder, decoder) = gen.into_encoder_decoder_pair().unwrap();
+        let bytes = encoder.encode(&mut literal.iter()).unwrap().to_bytes();
+
+        // One byte per read, to cross refills at every bit position
+        struct Trickle<'a>(&'a [u8]);
+        impl std::io::Read for Trickle<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let n = self.0.len().min(buf.len()).min(1);
+                buf[..n].copy_from_slice(&self.0[..n]);
+                self.0 = &self.0[n..];
+                Ok(n)
+            }
+        }
+        let count = literal.len() as u64;
+        let decoded: Result<Vec<u16>, _> = decoder.decode_from_reader(&bytes[..], count).collect();
+        assert_eq!(decoded.unwrap(), literal);
+        let decoded: Result<Vec<u16>, _> =
+            decoder.decode_from_reader(Trickle(&bytes), count).collect();
+        assert_eq!(decoded.unwrap(), literal);
+
+        let mut truncated = decoder.decode_from_reader(&bytes[..bytes.len() / 2], count);
+        let error = truncated.find_map(Result::err).unwrap();
+        assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+        assert!(truncated.next().is_none());
+
+        // A lone symbol takes no bits
+        let mut gen = HuffmanGenerator::new();
+        gen.add_occurences_to_symbol(&'x', 3);
+        let decoder = gen.into_encoder_decoder_pair().unwrap().1;
+        let decoded: Vec<char> = decoder
+            .decode_from_reader(std::io::empty(), 4)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(decoded, vec!['x'; 4]);
+    }
+
+    #[test]
+    fn stall_limit_rejects_deep_codes() {
+        let tree = fibonacci_tree::<u32>(40);
+        let (encoder, decoder) = (HuffmanEncoder::from_tree(&tree), HuffmanDecoder::new(tree));
+        let shallow = encoder.encode(&mut [39u32, 38, 37].iter()).unwrap();
+        assert_eq!(
+            decoder.decode_with_stall_limit(&shallow, 8),
+            Ok(vec![39, 38, 37])
+        );
+
+        // The least frequent symbols sit 39 levels deep
+        let deep = encoder.encode(&mut [39u32, 0].iter()).unwrap();
+        assert_eq!(
+            decoder.decode_with_stall_limit(&deep, 8),
+            Err(DecodeError::StallLimitExceeded)
+        );
+        assert_eq!(decoder.decode_with_stall_limit(&deep, 39), Ok(vec![39, 0]));
+
+        let mut truncated = encoder.encode(&mut [0u32].iter()).unwrap();
+        truncated.truncate(20);
+        assert_eq!(
+            decoder.decode_with_stall_limit(&truncated, 64),
+            Err(DecodeError::UnexpectedEndOfInput)
+        );
+    }
+
+    #[test]
+    fn streaming_decoders_honor_the_stall_limit() {
+        let tree = fibonacci_tree::<u32>(40);
+        let (encoder, decoder) = (HuffmanEncoder::from_tree(&tree), HuffmanDecoder::new(tree));
+        let deep = encoder.encode(&mut [39u32, 0].iter()).unwrap();
+
+        // The deep code spans chunks, the limit counts its bits across them
+        let chunks: Vec<BitVec> = [0..5, 5..30, 30..deep.len()]
+            .iter()
+            .map(|range| range.clone().map(|i| deep[i]).collect())
+            .collect();
+        for (stall_bits, result) in &[(39, Ok(())), (38, Err(DecodeError::StallLimitExceeded))] {
+            let mut state = DecoderState::new(&decoder).with_stall_limit(*stall_bits);
+            let mut decoded = vec![];
+            let fed = chunks
+                .iter()
+                .try_for_each(|chunk| state.feed(chunk, &mut decoded));
+            assert_eq!(&fed, result);
+        }
+        let mut state = DecoderState::new(&decoder);
+        let mut decoded = vec![];
+        for chunk in &chunks {
+            state.feed(chunk, &mut decoded).unwrap();
+        }
+        assert_eq!(decoded, [39, 0]);
+
+        let bytes = deep.to_bytes();
+        let read = |stall_bits| {
+            decoder
+                .decode_from_reader(&bytes[..], 2)
+                .with_stall_limit(stall_bits)
+                .collect::<std::io::Result<Vec<u32>>>()
+        };
+        assert_eq!(read(39).unwrap(), [39, 0]);
+        let error = read(38).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn skewed_trees_decode_without_recursion() {
+        // Symbol i is coded with i ones then a zero, the last one with ones only
+        let n = 10_000u32;
+        let mut tree = HuffmanNode::Leaf(HuffmanLeaf::new(n - 1));
+        for symbol in (0..n - 1).rev() {
+            let leaf = HuffmanNode::Leaf(HuffmanLeaf::new(symbol));
+            tree = HuffmanNode::Branch(HuffmanBranch::new(leaf, tree));
+        }
+        let decoder = HuffmanDecoder::new(tree);
+
+        let symbols = vec![n - 1, 0, n / 2, n - 2, 1];
+        let mut bits = BitVec::new();
+        for &symbol in &symbols {
+            bits.grow(symbol as usize, true);
+            if symbol < n - 1 {
+                bits.push(false);
+            }
+        }
+        assert_eq!(decoder.decode_unbounded(&bits), Ok(symbols));
+        assert_eq!(
+            decoder.decode_with_stall_limit(&bits, 64),
+            Err(DecodeError::StallLimitExceeded)
+        );
+        bits.truncate(bits.len() - 1);
+        assert_eq!(
+            decoder.decode_unbounded(&bits),
+            Err(DecodeError::UnexpectedEndOfInput)
+        );
+    }
+
+    #[test]
+    fn lone_symbols_decode_no_bits() {
+        let mut gen = HuffmanGenerator::new();
+        gen.add_occurences_to_symbol(&'x', 3);
+        let decoder = gen.into_encoder_decoder_pair().unwrap().1;
+        assert_eq!(decoder.decode_unbounded(&BitVec::new()), Ok(vec![]));
+        let bits = BitVec::from_elem(3, false);
+        assert_eq!(
+            decoder.decode_unbounded(&bits),
+            Err(DecodeError::InvalidTree)
+        );
+        assert_eq!(
+            decoder.decode_with_stall_limit(&bits, 8),
+            Err(DecodeError::InvalidTree)
+        );
+    }
+
+    #[test]
+    fn chunked_streams_decode_like_whole_buffers() {
+        // Fibonacci-like counts give codes longer than the lookup table
+        let tree = fibonacci_tree::<u32>(20);
+        let (encoder, decoder) = (HuffmanEncoder::from_tree(&tree), HuffmanDecoder::new(tree));
+        let data: Vec<u32> = (0..500u32).map(|i| i * i % 20).collect();
+        let bits = encoder.encode(&mut data.iter()).unwrap();
+        let bools: Vec<bool> = bits.iter().collect();
+
+        let walking = HuffmanDecoder::new(decoder.get_tree()).with_lookup_table(0);
+        for decoder in &[&decoder, &walking] {
+            for &size in &[1, 3, 7, 19, 64, bools.len()] {
+                let mut state = DecoderState::new(decoder);
+                let mut decoded = vec![];
+                for chunk in bools.chunks(size) {
+                    state
+                        .feed(&chunk.iter().copied().collect(), &mut decoded)
+                        .unwrap();
+                }
+                state.feed(&BitVec::new(), &mut decoded).unwrap();
+                assert!(!state.is_partial());
+                state.finish().unwrap();
+                assert_eq!(decoded, data);
+            }
+        }
+
+        let mut state = DecoderState::new(&decoder);
+        let mut decoded = vec![];
+        let cut: BitVec = bools[..bools.len() - 1].iter().copied().collect();
+        state.feed(&cut, &mut decoded).unwrap();
+        assert!(state.is_partial());
+        assert_eq!(decoded, data[..data.len() - 1]);
+        assert_eq!(state.finish(), Err(DecodeError::UnexpectedEndOfInput));
+    }
 }
@@ -1,9 +1,54 @@
+use std::fmt;
+use std::io::{self, Write};
 use std::{collections::HashMap, hash::Hash};
 
 use bit_vec::BitVec;
 
+use super::canonical;
+use super::frequency_source::{self, FrequencySource};
+use super::huffman_code::{CodeTable, SmallCode};
 use super::huffman_generator::HuffmanGenerator;
 use super::huffman_node::HuffmanNode;
+#[cfg(feature = "simd")]
+use super::simd;
+
+/// Why [encode_to_writer()](HuffmanEncoder::encode_to_writer) stopped.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum EncodeError<T> {
+    /// The stream produced a symbol that is not part of the encoding
+    UnknownSymbol(T),
+    /// Writing to the sink failed
+    Io(io::Error),
+}
+
+impl<T> From<io::Error> for EncodeError<T> {
+    fn from(error: io::Error) -> Self {
+        EncodeError::Io(error)
+    }
+}
+
+impl<T: fmt::Debug> fmt::Display for EncodeError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodeError::UnknownSymbol(symbol) => write!(f, "Symbol {:?} has no code", symbol),
+            EncodeError::Io(error) => error.fmt(f),
+        }
+    }
+}
+
+impl<T: fmt::Debug> std::error::Error for EncodeError<T> {}
+
+/// How many packed bytes [encode_to_writer()](HuffmanEncoder::encode_to_writer) gathers
+/// before handing them to the sink, and [encode()](HuffmanEncoder::encode) before appending
+/// them to the bits.
+const WRITE_CHUNK: usize = 8 * 1024;
+
+/// Fewest symbols [encode_parallel()](HuffmanEncoder::encode_parallel) and
+/// [add_occurences_parallel()](super::huffman_generator::HuffmanGenerator::add_occurences_parallel)
+/// hand to a thread, below which splitting costs more than it saves
+#[cfg(feature = "rayon")]
+pub const PARALLEL_CHUNK: usize = 64 * 1024;
 
 /// The huffman encoder struct contains a Huffman encoding scheme that can then be used to encode various sequences
 /// of the symbols. Usually, that huffman encoding scheme is generated on a per-sample basis so as to optimize the
@@ -19,7 +64,7 @@ use super::huffman_node::HuffmanNode;
 /// assert_eq!( result.unwrap().to_bytes(), vec![0b11000010]);
 /// ```
 pub struct HuffmanEncoder<T: Eq + Hash + Clone + Ord> {
-    symbols: HashMap<T, BitVec>,
+    symbols: CodeTable<T>,
 }
 
 impl<T: Eq + Hash + Clone + Ord> HuffmanEncoder<T> {
@@ -39,53 +84,491 @@ impl<T: Eq + Hash + Clone + Ord> HuffmanEncoder<T> {
         }
     }
 
+    /// Generates a HuffmanEncoder for symbols occuring as often as the source says, see
+    /// [frequency_source](super::frequency_source)
+    pub fn from_frequencies<S: FrequencySource<T> + ?Sized>(
+        source: &S,
+    ) -> Result<HuffmanEncoder<T>, &'static str> {
+        match frequency_source::huffman_tree(source) {
+            Some(tree) => Ok(HuffmanEncoder::from_tree(&tree)),
+            None => Err("No symbols were provided"),
+        }
+    }
+
     /// Generates a [HuffmanEncoder](HuffmanEncoder) from the tree. You may obtain
     /// such a tree from a [HuffmanGenerator](super::huffman_generator::HuffmanGenerator)
     pub fn from_tree(tree: &HuffmanNode<T>) -> HuffmanEncoder<T> {
         let mut map = HashMap::new();
-
-        HuffmanEncoder::visit_tree(&tree, BitVec::new(), &mut map);
-        HuffmanEncoder { symbols: map }
+        // The walk is iterative, so deep trees do not overflow the stack
+        tree.visit(&mut |path: &[bool], symbol: &T| {
+            map.insert(symbol.clone(), path.iter().copied().collect::<BitVec>());
+        });
+        HuffmanEncoder {
+            symbols: CodeTable::from_codes(map),
+        }
     }
 
-    fn visit_tree(
-        tree: &HuffmanNode<T>,
-        mut current_prefix: BitVec,
-        symbols: &mut HashMap<T, BitVec>,
-    ) {
-        match tree {
-            HuffmanNode::Leaf(leaf) => {
-                symbols.insert(leaf.symbol.clone(), current_prefix);
-            }
-            HuffmanNode::Branch(branch) => {
-                let mut left_prefix = current_prefix.clone();
-                left_prefix.push(false);
-                current_prefix.push(true);
-                HuffmanEncoder::visit_tree(&branch.links.0, left_prefix, symbols);
-                HuffmanEncoder::visit_tree(&branch.links.1, current_prefix, symbols);
-            }
+    /// Same as [from_tree()](HuffmanEncoder::from_tree), with the codes reassigned in
+    /// [canonical](super::canonical) order: each symbol keeps the length of its code in the
+    /// tree, so the encoded data is as short, but decoders only need the code lengths.
+    /// ## Examples
+    /// ```
+    /// # use rhuffman::huffman_tree::canonical;
+    /// # use rhuffman::huffman_tree::huffman_decoder::HuffmanDecoder;
+    /// # use rhuffman::huffman_tree::huffman_encoder::HuffmanEncoder;
+    /// # use rhuffman::huffman_tree::huffman_generator::HuffmanGenerator;
+    /// let literal: Vec<char> = "abracadabra".chars().collect();
+    /// let mut gen = HuffmanGenerator::new();
+    /// gen.add_occurences_from_iterator(&mut literal.iter());
+    /// let tree = gen.into_huffman_tree().unwrap();
+    /// let encoder = HuffmanEncoder::canonical_from_tree(&tree);
+    /// let encoded = encoder.encode(&mut literal.iter()).unwrap();
+    ///
+    /// // Only the lengths need to reach the decoder
+    /// let lengths = canonical::code_lengths(&tree);
+    /// let decoder = HuffmanDecoder::from_code_lengths(&lengths).unwrap();
+    /// assert_eq!(decoder.decode_unbounded(&encoded).unwrap(), literal);
+    /// ```
+    pub fn canonical_from_tree(tree: &HuffmanNode<T>) -> HuffmanEncoder<T> {
+        match canonical::from_lengths(&canonical::code_lengths(tree)) {
+            Some(canonical) => HuffmanEncoder::from_tree(&canonical),
+            // Every tree has complete code lengths, this is only for the compiler
+            None => HuffmanEncoder::from_tree(tree),
         }
     }
 
+    /// Generates the encoder of the canonical code with these code lengths, see
+    /// [canonical](super::canonical)
+    /// ## Errors
+    /// Returns Err if there are no symbols or the lengths are not those of a complete code.
+    pub fn from_code_lengths(lengths: &[(T, usize)]) -> Result<HuffmanEncoder<T>, &'static str> {
+        canonical::from_lengths(lengths)
+            .map(|tree| HuffmanEncoder::from_tree(&tree))
+            .ok_or("The lengths do not describe a complete prefix code")
+    }
+
     /// Attempts to encode the given stream of symbols with the internal encoding.
     /// ## Errors
     /// If the stream produces a symbol that is not part of the encoding, encode returns Err containing a copy of the offending symbol.
     pub fn encode(&self, iter: &mut dyn Iterator<Item = &T>) -> Result<BitVec, T> {
         let mut bitvec = BitVec::new();
+        self.encode_append(iter, &mut bitvec)?;
+        Ok(bitvec)
+    }
+
+    /// The code of the symbol, first bit first, None if it is not part of the encoding. Codes
+    /// are stored packed, so each call builds the `BitVec`.
+    /// ## Examples
+    /// ```
+    /// # use rhuffman::huffman_tree::huffman_encoder::HuffmanEncoder;
+    /// let literal = ["B", "A", "A", "A", "A", "C"];
+    /// let encoder = HuffmanEncoder::from_symbols_iterator(&mut literal.iter()).unwrap();
+    /// assert!(encoder.code_for(&"A").unwrap().eq_vec(&[false]));
+    /// assert!(encoder.code_for(&"B").unwrap().eq_vec(&[true, true]));
+    /// assert_eq!(encoder.code_for(&"Z"), None);
+    /// ```
+    pub fn code_for(&self, symbol: &T) -> Option<BitVec> {
+        self.symbols.code(symbol)
+    }
+
+    /// Every symbol of the encoding with its code, sorted by symbol. The order only depends
+    /// on the codes, never on how they are stored, so dumps of the same codebook compare equal.
+    /// Codes are stored packed, so they are built as owned `BitVec`s, see
+    /// [code_for()](HuffmanEncoder::code_for).
+    /// ## Examples
+    /// ```
+    /// # use rhuffman::huffman_tree::huffman_encoder::HuffmanEncoder;
+    /// let literal = ["B", "A", "A", "A", "A", "C"];
+    /// let encoder = HuffmanEncoder::from_symbols_iterator(&mut literal.iter()).unwrap();
+    /// let table: Vec<_> = encoder.code_table().collect();
+    /// assert_eq!(table.len(), 3);
+    /// assert_eq!(table[0].0, &"A");
+    /// assert!(table[0].1.eq_vec(&[false]));
+    /// ```
+    pub fn code_table(&self) -> impl Iterator<Item = (&T, BitVec)> + '_ {
+        let mut codes = self.symbols.codes();
+        codes.sort_unstable_by(|a, b| a.0.cmp(b.0));
+        codes.into_iter()
+    }
+
+    /// Same as [code_table()](HuffmanEncoder::code_table), sorted by code length, then by
+    /// symbol: the canonical order, in which [from_code_lengths()](HuffmanEncoder::from_code_lengths)
+    /// assigns codes.
+    /// ## Examples
+    /// ```
+    /// # use rhuffman::huffman_tree::huffman_encoder::HuffmanEncoder;
+    /// let literal = ["C", "C", "C", "C", "D", "D", "B", "A"];
+    /// let encoder = HuffmanEncoder::from_symbols_iterator(&mut literal.iter()).unwrap();
+    /// let symbols: Vec<_> = encoder.iter_sorted_by_len().map(|(symbol, _)| *symbol).collect();
+    /// assert_eq!(symbols, ["C", "D", "A", "B"]);
+    /// ```
+    pub fn iter_sorted_by_len(&self) -> impl Iterator<Item = (&T, BitVec)> + '_ {
+        let mut codes = self.symbols.codes();
+        codes.sort_unstable_by(|a, b| (a.1.len(), a.0).cmp(&(b.1.len(), b.0)));
+        codes.into_iter()
+    }
+
+    /// Number of bits [encode()](HuffmanEncoder::encode) gives for the stream, computed
+    /// from the code lengths without encoding it, e.g. to size a buffer or to tell whether
+    /// compressing is worth it.
+    /// ## Errors
+    /// If the stream produces a symbol that is not part of the encoding, returns Err containing a copy of the offending symbol.
+    /// ## Examples
+    /// ```
+    /// # use rhuffman::huffman_tree::huffman_encoder::HuffmanEncoder;
+    /// let text = b"abracadabra";
+    /// let encoder = HuffmanEncoder::from_symbols_iterator(&mut text.iter()).unwrap();
+    /// let len = encoder.encoded_len(&mut text.iter()).unwrap();
+    /// assert_eq!(len, encoder.encode(&mut text.iter()).unwrap().len() as u64);
+    ///
+    /// let counts = vec![(b'a', 5), (b'b', 2), (b'r', 2), (b'c', 1), (b'd', 1)];
+    /// assert_eq!(encoder.encoded_len_of(&counts), Ok(len));
+    /// ```
+    pub fn encoded_len(&self, iter: &mut dyn Iterator<Item = &T>) -> Result<u64, T> {
+        let mut len = 0u64;
         for symbol in iter {
-            if let Some(code) = self.symbols.get(&symbol) {
-                bitvec.append(&mut code.clone());
-            } else {
-                return Err(symbol.clone());
+            match self.symbols.code_len(symbol) {
+                Some(code_len) => len = len.saturating_add(code_len as u64),
+                None => return Err(symbol.clone()),
             }
         }
-        Ok(bitvec)
+        Ok(len)
+    }
+
+    /// Same as [encoded_len()](HuffmanEncoder::encoded_len), for a stream with the symbol
+    /// counts of the source, see [frequency_source](super::frequency_source). Symbols
+    /// counted 0 times need no code.
+    /// ## Errors
+    /// Returns Err containing a copy of a symbol counted at least once that is not part of
+    /// the encoding.
+    pub fn encoded_len_of<S: FrequencySource<T> + ?Sized>(&self, source: &S) -> Result<u64, T> {
+        let mut len = 0u64;
+        for (symbol, count) in source.counts().filter(|&(_, count)| count > 0) {
+            match self.symbols.code_len(symbol) {
+                Some(code_len) => len = len.saturating_add((code_len as u64).saturating_mul(count)),
+                None => return Err(symbol.clone()),
+            }
+        }
+        Ok(len)
+    }
+
+    /// Same as [encode()](HuffmanEncoder::encode), packing the bits into bytes as they are
+    /// produced and writing them to `out`, so the encoded stream never has to fit in memory.
+    /// Bits are packed most significant first and the last byte is padded with zeros, which
+    /// gives the same bytes as `encode(iter)?.to_bytes()`. Returns the number of meaningful
+    /// bits written.
+    /// ## Errors
+    /// Returns [EncodeError::UnknownSymbol] with a copy of the offending symbol, or
+    /// [EncodeError::Io] if the sink fails. Bytes already written are left in the sink.
+    pub fn encode_to_writer<W: Write + ?Sized>(
+        &self,
+        iter: &mut dyn Iterator<Item = &T>,
+        out: &mut W,
+    ) -> Result<u64, EncodeError<T>> {
+        let mut packer = Packer::new();
+        for symbol in iter {
+            if !self
+                .symbols
+                .emit_code(symbol, |bits, len| packer.push(bits, len))
+            {
+                return Err(EncodeError::UnknownSymbol(symbol.clone()));
+            }
+            if packer.bytes.len() >= WRITE_CHUNK {
+                out.write_all(&packer.bytes)?;
+                packer.bytes.clear();
+            }
+        }
+        packer.pad();
+        out.write_all(&packer.bytes)?;
+        Ok(packer.written)
+    }
+
+    /// Same as [encode()](HuffmanEncoder::encode), appending to a caller-provided buffer so
+    /// its allocation can be reused across calls, see [append_packed()].
+    /// ## Errors
+    /// Returns Err containing a copy of the first symbol that is not part of the encoding.
+    /// The codes of the symbols before it are kept.
+    pub(crate) fn encode_append(
+        &self,
+        iter: &mut dyn Iterator<Item = &T>,
+        out: &mut BitVec,
+    ) -> Result<(), T> {
+        append_packed(iter, out, |symbol, packer| {
+            match self
+                .symbols
+                .emit_code(symbol, |bits, len| packer.push(bits, len))
+            {
+                true => Ok(()),
+                false => Err(symbol.clone()),
+            }
+        })
+    }
+
+    /// Appends the code of a single symbol, without the setup of
+    /// [encode_append()](HuffmanEncoder::encode_append).
+    pub(crate) fn encode_symbol(&self, symbol: &T, out: &mut BitVec) -> Result<(), T> {
+        match self.symbols.append_code(symbol, out) {
+            true => Ok(()),
+            false => Err(symbol.clone()),
+        }
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl<T: Eq + Hash + Clone + Ord + Sync + Send> HuffmanEncoder<T> {
+    /// Same as [encode()](HuffmanEncoder::encode) for a slice, encoding chunks of it on the
+    /// rayon thread pool and appending their bits in order. Slices of fewer than two
+    /// [PARALLEL_CHUNK]s, or a pool of a single thread, encode on the calling thread. Only
+    /// built with the `rayon` feature.
+    /// ## Errors
+    /// Returns Err containing a copy of the first symbol that is not part of the encoding.
+    /// ## Examples
+    /// ```
+    /// # use rhuffman::huffman_tree::huffman_encoder::HuffmanEncoder;
+    /// let data: Vec<u16> = (0..1_000_000u64).map(|i| (i * i % 1009) as u16).collect();
+    /// let encoder = HuffmanEncoder::from_symbols_iterator(&mut data.iter()).unwrap();
+    /// let bits = encoder.encode_parallel(&data).unwrap();
+    /// assert_eq!(bits, encoder.encode(&mut data.iter()).unwrap());
+    /// ```
+    pub fn encode_parallel(&self, symbols: &[T]) -> Result<BitVec, T> {
+        use rayon::prelude::*;
+
+        let threads = rayon::current_num_threads();
+        if threads == 1 || symbols.len() < PARALLEL_CHUNK * 2 {
+            return self.encode(&mut symbols.iter());
+        }
+        let chunk_len = symbols.len().div_ceil(threads * 4).max(PARALLEL_CHUNK);
+        let runs: Vec<Result<BitVec, T>> = symbols
+            .par_chunks(chunk_len)
+            .map(|chunk| self.encode(&mut chunk.iter()))
+            .collect();
+        let mut bits = BitVec::with_capacity(runs.iter().flatten().map(BitVec::len).sum());
+        for run in runs {
+            // Shifts the run to the bit the previous one ended on
+            bits.append(&mut run?);
+        }
+        Ok(bits)
+    }
+}
+
+impl HuffmanEncoder<u8> {
+    /// Same as [encode_append()](HuffmanEncoder::encode_append), through a [ByteEncoder]
+    /// when the codes fit in one.
+    pub(crate) fn encode_bytes_append(&self, data: &[u8], out: &mut BitVec) -> Result<(), u8> {
+        match ByteEncoder::new(self) {
+            Some(encoder) => encoder.encode_append(data, out),
+            None => self.encode_append(&mut data.iter(), out),
+        }
+    }
+}
+
+/// A [HuffmanEncoder] of bytes looking their codes up in a table indexed by the byte, rather
+/// than hashing every byte. Containers encode their blocks with one.
+/// ## Examples
+/// ```
+/// # use rhuffman::huffman_tree::huffman_encoder::{ByteEncoder, HuffmanEncoder};
+/// let text = b"abracadabra";
+/// let encoder = HuffmanEncoder::from_symbols_iterator(&mut text.iter()).unwrap();
+/// let bytes = ByteEncoder::new(&encoder).unwrap();
+/// assert_eq!(bytes.encode(text), encoder.encode(&mut text.iter()));
+/// assert_eq!(bytes.encode(b"abz"), Err(b'z'));
+/// ```
+#[derive(Debug, Clone)]
+pub struct ByteEncoder {
+    pub(crate) codes: [Option<SmallCode>; 256],
+    /// The codes as the vector path packs them, where the CPU has one and they are short
+    /// enough
+    #[cfg(feature = "simd")]
+    simd: Option<simd::Table>,
+}
+
+impl ByteEncoder {
+    /// The codes of the encoder in a table. None if one of them is longer than
+    /// [SmallCode::MAX_LEN] bits, which only trees over counts growing exponentially from one
+    /// byte to the next have.
+    pub fn new(encoder: &HuffmanEncoder<u8>) -> Option<ByteEncoder> {
+        let small = encoder.symbols.small_codes()?;
+        let mut codes = [None; 256];
+        for (&byte, &code) in small {
+            codes[usize::from(byte)] = Some(code);
+        }
+        Some(ByteEncoder {
+            #[cfg(feature = "simd")]
+            simd: simd::Table::new(&codes),
+            codes,
+        })
+    }
+
+    /// Same as [HuffmanEncoder::encode()]
+    /// ## Errors
+    /// Returns Err containing the first byte that is not part of the encoding.
+    pub fn encode(&self, data: &[u8]) -> Result<BitVec, u8> {
+        let mut bits = BitVec::new();
+        self.encode_append(data, &mut bits)?;
+        Ok(bits)
+    }
+
+    /// Same as [encode()](ByteEncoder::encode), appending to a caller-provided buffer
+    pub(crate) fn encode_append(&self, data: &[u8], out: &mut BitVec) -> Result<(), u8> {
+        #[cfg(feature = "simd")]
+        if let Some(table) = &self.simd {
+            return table.encode_append(data, out);
+        }
+        append_packed(&mut data.iter(), out, |&byte, packer| {
+            match self.codes[usize::from(byte)] {
+                Some(code) => {
+                    let (bits, len) = code.parts();
+                    packer.push(bits, len);
+                    Ok(())
+                }
+                None => Err(byte),
+            }
+        })
+    }
+}
+
+/// Packs the codes `emit` gives for the symbols into bytes, appending them to `out` a chunk at
+/// a time, word by word, rather than a bit at a time. The output is sized for the whole stream
+/// from the lengths of the codes of the first chunk and the size hint of the iterator, rather
+/// than grown chunk after chunk.
+/// ## Errors
+/// Returns the first error of `emit`. The codes of the symbols before it are kept.
+fn append_packed<I, E>(
+    iter: &mut I,
+    out: &mut BitVec,
+    mut emit: impl FnMut(I::Item, &mut Packer) -> Result<(), E>,
+) -> Result<(), E>
+where
+    I: Iterator + ?Sized,
+{
+    let start = out.len();
+    // Every code takes a bit at least, except the one of a lone symbol
+    out.reserve(iter.size_hint().0);
+    let mut packer = Packer::new();
+    let mut symbols = 0u64;
+    let mut result = Ok(());
+    while let Some(symbol) = iter.next() {
+        if let Err(error) = emit(symbol, &mut packer) {
+            result = Err(error);
+            break;
+        }
+        symbols += 1;
+        if packer.bytes.len() >= WRITE_CHUNK {
+            if out.len() == start {
+                // Sizes the output once, from the length of the codes of the first chunk
+                let total = u128::from(symbols) + iter.size_hint().0 as u128;
+                let estimate = total * u128::from(packer.written) / u128::from(symbols);
+                out.reserve(estimate.min(usize::MAX as u128) as usize);
+            }
+            out.append(&mut BitVec::from_bytes(&packer.bytes));
+            packer.bytes.clear();
+        }
+    }
+    packer.pad();
+    out.append(&mut BitVec::from_bytes(&packer.bytes));
+    // Drops the padding of the last byte
+    out.truncate(start + packer.written as usize);
+    result
+}
+
+/// Codes packed into bytes, most significant bit first
+struct Packer {
+    bytes: Vec<u8>,
+    /// Bits not yet packed in a byte, right-aligned, always fewer than 8 between codes
+    pending: u128,
+    pending_len: u32,
+    /// Bits pushed so far
+    written: u64,
+}
+
+impl Packer {
+    fn new() -> Packer {
+        Packer {
+            bytes: Vec::new(),
+            pending: 0,
+            pending_len: 0,
+            written: 0,
+        }
+    }
+
+    fn push(&mut self, bits: u64, len: u8) {
+        self.pending = (self.pending << len) | u128::from(bits);
+        self.pending_len += u32::from(len);
+        while self.pending_len >= 8 {
+            self.pending_len -= 8;
+            self.bytes.push((self.pending >> self.pending_len) as u8);
+        }
+        self.pending &= (1 << self.pending_len) - 1;
+        self.written += u64::from(len);
+    }
+
+    /// Packs the pending bits in a last byte, padded with zeros
+    fn pad(&mut self) {
+        if self.pending_len > 0 {
+            self.bytes
+                .push((self.pending << (8 - self.pending_len)) as u8);
+            self.pending = 0;
+            self.pending_len = 0;
+        }
+    }
+}
+
+/// Encodes symbols as they arrive, e.g. from an event stream, with a [HuffmanEncoder], for
+/// input that can not be handed over as a single iterator.
+/// ## Examples
+/// ```
+/// # use rhuffman::huffman_tree::huffman_encoder::{EncoderSink, HuffmanEncoder};
+/// let encoder = HuffmanEncoder::from_symbols_iterator(&mut b"abracadabra".iter()).unwrap();
+/// let mut sink = EncoderSink::new(&encoder);
+/// sink.push(&b'a').unwrap();
+/// sink.push_slice(b"bracadabra").unwrap();
+/// assert_eq!(sink.push(&b'z'), Err(b'z'));
+///
+/// let bits = sink.finish();
+/// assert_eq!(bits, encoder.encode(&mut b"abracadabra".iter()).unwrap());
+/// ```
+pub struct EncoderSink<'a, T: Eq + Hash + Clone + Ord> {
+    encoder: &'a HuffmanEncoder<T>,
+    bits: BitVec,
+}
+
+impl<'a, T: Eq + Hash + Clone + Ord> EncoderSink<'a, T> {
+    pub fn new(encoder: &'a HuffmanEncoder<T>) -> EncoderSink<'a, T> {
+        EncoderSink {
+            encoder,
+            bits: BitVec::new(),
+        }
+    }
+
+    /// Appends the code of the symbol
+    /// ## Errors
+    /// Returns Err containing a copy of the symbol if it is not part of the encoding, leaving
+    /// the bits as they were.
+    pub fn push(&mut self, symbol: &T) -> Result<(), T> {
+        self.encoder.encode_symbol(symbol, &mut self.bits)
+    }
+
+    /// Appends the codes of the symbols, in order
+    /// ## Errors
+    /// Returns Err containing a copy of the first symbol that is not part of the encoding.
+    /// The symbols before it are kept.
+    pub fn push_slice(&mut self, symbols: &[T]) -> Result<(), T> {
+        self.encoder
+            .encode_append(&mut symbols.iter(), &mut self.bits)
+    }
+
+    /// The bits of every symbol pushed, as [encode()](HuffmanEncoder::encode) gives them
+    pub fn finish(self) -> BitVec {
+        self.bits
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::huffman_tree::fibonacci_tree;
+    use crate::huffman_tree::huffman_decoder::HuffmanDecoder;
     use crate::huffman_tree::huffman_generator::HuffmanGenerator;
+    use crate::huffman_tree::huffman_node::{HuffmanBranch, HuffmanLeaf};
 
     use super::*;
 
@@ -152,6 +635,91 @@ mod tests {
         );
     }
 
+    #[test]
+    fn codes_longer_than_small_codes_round_trip() {
+        // Fibonacci weights produce the deepest possible tree, one level per symbol
+        let tree = fibonacci_tree::<u32>(80);
+        let (encoder, decoder) = (HuffmanEncoder::from_tree(&tree), HuffmanDecoder::new(tree));
+        assert!(matches!(encoder.symbols, CodeTable::General(_)));
+
+        let literal: Vec<u32> = (0..80).rev().collect();
+        let encoded = encoder.encode(&mut literal.iter()).unwrap();
+        assert_eq!(decoder.decode_unbounded(&encoded).unwrap(), literal);
+        let mut written = vec![];
+        encoder
+            .encode_to_writer(&mut literal.iter(), &mut written)
+            .unwrap();
+        assert_eq!(written, encoded.to_bytes());
+    }
+
+    #[test]
+    fn canonical_codes_follow_lengths_then_symbols() {
+        let lengths = [("D", 3), ("A", 1), ("C", 3), ("B", 2)];
+        let encoder = HuffmanEncoder::from_code_lengths(&lengths).unwrap();
+        let result = encoder.encode(&mut ["A", "B", "C", "D"].iter()).unwrap();
+        assert_eq!(result.to_bytes(), vec![0b01011011, 0b10000000]);
+        assert!(HuffmanEncoder::from_code_lengths(&[("A", 1), ("B", 2)]).is_err());
+
+        // The tree gives A the code 1, canonical order gives it 0
+        let mut gen = HuffmanGenerator::new();
+        gen.add_occurences_to_symbol(&"A", 2);
+        gen.add_occurences_to_symbol(&"B", 2);
+        let tree = gen.into_huffman_tree().unwrap();
+        let plain = HuffmanEncoder::from_tree(&tree).encode(&mut ["A"].iter());
+        assert!(plain.unwrap().eq_vec(&[true]));
+        let canonical = HuffmanEncoder::canonical_from_tree(&tree).encode(&mut ["A"].iter());
+        assert!(canonical.unwrap().eq_vec(&[false]));
+    }
+
+    #[test]
+    fn writer_output_matches_encode() {
+        let literal: Vec<u8> = (0..20_000u32).map(|i| (i * i % 251) as u8 % 37).collect();
+        let encoder = HuffmanEncoder::from_symbols_iterator(&mut literal.iter()).unwrap();
+        let mut out = Vec::new();
+        let bits = encoder
+            .encode_to_writer(&mut literal.iter(), &mut out)
+            .unwrap();
+        let expected = encoder.encode(&mut literal.iter()).unwrap();
+        assert_eq!(bits, expected.len() as u64);
+        assert_eq!(out, expected.to_bytes());
+
+        // Codes too long for the small table take the general path
+        let encoder = HuffmanEncoder::from_tree(&fibonacci_tree::<u32>(80));
+        let literal: Vec<u32> = (0..24).chain(0..5).collect();
+        let mut out = Vec::new();
+        encoder
+            .encode_to_writer(&mut literal.iter(), &mut out)
+            .unwrap();
+        assert_eq!(out, encoder.encode(&mut literal.iter()).unwrap().to_bytes());
+
+        let unknown = encoder.encode_to_writer(&mut [99u32].iter(), &mut Vec::new());
+        assert!(matches!(unknown, Err(EncodeError::UnknownSymbol(99))));
+    }
+
+    #[test]
+    fn large_and_deep_trees_build_encoders() {
+        let mut gen = HuffmanGenerator::new();
+        for symbol in 0..=u16::MAX {
+            gen.add_occurences_to_symbol(&symbol, 1);
+        }
+        let encoder = HuffmanEncoder::from_tree(&gen.into_huffman_tree().unwrap());
+        let encoded = encoder.encode(&mut [0u16, u16::MAX].iter()).unwrap();
+        assert_eq!(encoded.len(), 32);
+
+        // Symbol i is coded with i ones then a zero, the last one with ones only
+        let n = 10_000u32;
+        let mut tree = HuffmanNode::Leaf(HuffmanLeaf::new(n - 1));
+        for symbol in (0..n - 1).rev() {
+            let leaf = HuffmanNode::Leaf(HuffmanLeaf::new(symbol));
+            tree = HuffmanNode::Branch(HuffmanBranch::new(leaf, tree));
+        }
+        let encoder = HuffmanEncoder::from_tree(&tree);
+        let encoded = encoder.encode(&mut [n - 1, 0].iter()).unwrap();
+        assert_eq!(encoded.len(), n as usize);
+        assert!(encoded.iter().take(n as usize - 1).all(|bit| bit));
+        assert!(!encoded[n as usize - 1]);
+    }
+
     #[test]
     fn encoder_from_iterator() {
         let literal = [
@@ -165,4 +733,128 @@ mod tests {
             vec![0b01010011, 0b11100010, 0b00100010, 0b11111000]
         );
     }
+
+    #[test]
+    fn sinks_encode_like_iterators() {
+        let data: Vec<u16> = (0..2_000u32).map(|i| (i * i % 1009) as u16 % 50).collect();
+        let encoder = HuffmanEncoder::from_symbols_iterator(&mut data.iter()).unwrap();
+        let mut sink = EncoderSink::new(&encoder);
+        for (i, chunk) in data.chunks(37).enumerate() {
+            match i % 2 {
+                0 => sink.push_slice(chunk).unwrap(),
+                _ => chunk.iter().for_each(|symbol| sink.push(symbol).unwrap()),
+            }
+        }
+        assert_eq!(sink.finish(), encoder.encode(&mut data.iter()).unwrap());
+
+        let mut sink = EncoderSink::new(&encoder);
+        assert_eq!(sink.push_slice(&[data[0], 999, data[1]]), Err(999));
+        assert_eq!(sink.push(&999), Err(999));
+        assert_eq!(
+            sink.finish(),
+            encoder.encode(&mut data[..1].iter()).unwrap()
+        );
+    }
+
+    #[test]
+    fn chunks_append_after_unaligned_bits() {
+        // Enough codes to fill several chunks, appended after a partial byte
+        let data: Vec<u16> = (0..100_000u64).map(|i| (i * i % 1009) as u16).collect();
+        let encoder = HuffmanEncoder::from_symbols_iterator(&mut data.iter()).unwrap();
+        let mut expected = BitVec::from_elem(3, true);
+        for symbol in &data {
+            expected.extend(encoder.code_for(symbol).unwrap().iter());
+        }
+        assert!(expected.len() > 3 * 8 * WRITE_CHUNK);
+
+        let mut bits = BitVec::from_elem(3, true);
+        encoder.encode_append(&mut data.iter(), &mut bits).unwrap();
+        assert_eq!(bits, expected);
+    }
+
+    #[test]
+    fn byte_encoders_encode_like_encoders() {
+        let data: Vec<u8> = (0..50_000u64).map(|i| (i * i % 251) as u8).collect();
+        let encoder = HuffmanEncoder::from_symbols_iterator(&mut data.iter()).unwrap();
+        let bytes = ByteEncoder::new(&encoder).unwrap();
+        assert_eq!(bytes.encode(&data), encoder.encode(&mut data.iter()));
+        assert_eq!(bytes.encode(&[data[0], 255, data[1]]), Err(255));
+
+        // Codes longer than a SmallCode have no table, and are encoded all the same
+        let encoder = HuffmanEncoder::from_tree(&fibonacci_tree::<u8>(80));
+        assert!(ByteEncoder::new(&encoder).is_none());
+        let data: Vec<u8> = (0..80).collect();
+        let mut bits = BitVec::new();
+        encoder.encode_bytes_append(&data, &mut bits).unwrap();
+        assert_eq!(bits, encoder.encode(&mut data.iter()).unwrap());
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn parallel_encoding_stitches_chunks() {
+        // Odd code lengths leave every chunk ending inside a block
+        let len = PARALLEL_CHUNK * 5 + 123;
+        let data: Vec<u16> = (0..len as u64).map(|i| (i * i % 1009) as u16).collect();
+        let encoder = HuffmanEncoder::from_symbols_iterator(&mut data.iter()).unwrap();
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(4)
+            .build()
+            .unwrap();
+        let bits = pool.install(|| encoder.encode_parallel(&data)).unwrap();
+        assert_eq!(bits, encoder.encode(&mut data.iter()).unwrap());
+        assert_eq!(
+            encoder.encode_parallel(&data[..10]).unwrap().len() as u64,
+            encoder.encoded_len(&mut data[..10].iter()).unwrap()
+        );
+        assert_eq!(encoder.encode_parallel(&[]).unwrap(), BitVec::new());
+
+        let mut damaged = data;
+        damaged[PARALLEL_CHUNK * 4 + 1] = 5000;
+        damaged[PARALLEL_CHUNK * 2 + 7] = 4000;
+        let result = pool.install(|| encoder.encode_parallel(&damaged));
+        assert_eq!(result, Err(4000));
+    }
+
+    #[test]
+    fn encoded_len_matches_encoding() {
+        let data: Vec<u16> = (0..3_000u32).map(|i| (i * i % 1013) as u16 % 300).collect();
+        let encoder = HuffmanEncoder::from_symbols_iterator(&mut data.iter()).unwrap();
+        let bits = encoder.encode(&mut data.iter()).unwrap().len() as u64;
+        assert_eq!(encoder.encoded_len(&mut data.iter()), Ok(bits));
+
+        let mut counts: Vec<(u16, u64)> = data.iter().map(|&symbol| (symbol, 1)).collect();
+        assert_eq!(encoder.encoded_len_of(&counts), Ok(bits));
+        counts.push((999, 0));
+        assert_eq!(encoder.encoded_len_of(&counts), Ok(bits));
+        counts.push((999, 1));
+        assert_eq!(encoder.encoded_len_of(&counts), Err(999));
+        assert_eq!(encoder.encoded_len(&mut [1, 999].iter()), Err(999));
+    }
+
+    #[test]
+    fn codes_are_those_encoded() {
+        // A chain deeper than a SmallCode, so both kinds of code tables are covered
+        for &depth in &[5u32, 80] {
+            let encoder = HuffmanEncoder::from_tree(&fibonacci_tree::<u32>(depth as usize));
+            for symbol in 0..depth {
+                let code = encoder.code_for(&symbol).unwrap();
+                assert_eq!(code, encoder.encode(&mut [symbol].iter()).unwrap());
+            }
+            assert_eq!(encoder.code_for(&depth), None);
+
+            let table: Vec<(&u32, BitVec)> = encoder.code_table().collect();
+            assert_eq!(table.len(), depth as usize);
+            for (i, (symbol, code)) in table.into_iter().enumerate() {
+                assert_eq!(*symbol, i as u32);
+                assert_eq!(Some(code), encoder.code_for(symbol));
+            }
+
+            let lengths: Vec<usize> = encoder
+                .iter_sorted_by_len()
+                .map(|(_, code)| code.len())
+                .collect();
+            assert_eq!(lengths.len(), depth as usize);
+            assert!(lengths.windows(2).all(|pair| pair[0] <= pair[1]));
+        }
+    }
 }
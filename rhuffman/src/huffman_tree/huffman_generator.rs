@@ -1,15 +1,22 @@
 use std::{
-    collections::{BinaryHeap, HashMap},
+    collections::{BinaryHeap, HashMap, VecDeque},
     hash::Hash,
 };
 
+use super::canonical;
+use super::count_min::CountMinSketch;
+use super::frequency_source::FrequencySource;
+use super::huffman_decoder::HuffmanDecoder;
 use super::huffman_encoder::HuffmanEncoder;
-use super::huffman_node::Weighted;
-use super::{huffman_decoder::HuffmanDecoder, huffman_node::HuffmanNode};
+#[cfg(feature = "rayon")]
+use super::huffman_encoder::PARALLEL_CHUNK;
+use super::huffman_node::{HuffmanBranch, HuffmanLeaf, HuffmanNode, Weighted};
+use super::snapshot::{CountDelta, Snapshot};
 
-use std::cmp::Reverse;
+use std::cmp::Ordering;
+use std::convert::TryFrom;
 
-pub type HuffmanTree<T> = HuffmanNode<T>;
+pub use super::HuffmanTree;
 
 /// The primary purpose of this struct is to
 /// help generate the frequency analysis necessary
@@ -23,6 +30,22 @@ where
     T: Eq + Hash + Clone + Ord,
 {
     symbols: HashMap<T, usize>,
+    /// Counts occurences instead of `symbols` when set
+    sketch: Option<CountMinSketch<T>>,
+    reserved: HashMap<T, usize>,
+    max_symbols: Option<usize>,
+    min_weight: usize,
+    cost: Option<Cost<T>>,
+    max_code_length: Option<usize>,
+}
+
+impl<T> Default for HuffmanGenerator<T>
+where
+    T: Eq + Hash + Clone + Ord,
+{
+    fn default() -> Self {
+        HuffmanGenerator::new()
+    }
 }
 
 impl<T> HuffmanGenerator<T>
@@ -32,15 +55,27 @@ where
     pub fn new() -> HuffmanGenerator<T> {
         HuffmanGenerator {
             symbols: HashMap::new(),
+            sketch: None,
+            reserved: HashMap::new(),
+            max_symbols: None,
+            min_weight: 0,
+            cost: None,
+            max_code_length: None,
         }
     }
 
+    /// Starts configuring a generator with reserved symbols or a capped alphabet, see
+    /// [HuffmanGeneratorBuilder]
+    pub fn builder() -> HuffmanGeneratorBuilder<T> {
+        HuffmanGeneratorBuilder::new()
+    }
+
     /// Allows building the frequency analysis by adding the symbols
     /// and their associated weights. Call [`into_huffman_tree`](HuffmanGenerator::into_huffman_tree)
     /// to complete the frequency analysis and obtain a HuffmanTree, suitable
     /// to get a [HuffmanEncoder](super::huffman_encoder::HuffmanEncoder) or for decoding.
     ///
-    /// Successive calls to this method are additive e.g.
+    /// Successive calls to this method are additive, counts saturating at `usize::MAX`, e.g.
     /// ```
     /// # use rhuffman::huffman_tree::huffman_generator::HuffmanGenerator;
     /// let mut two_plus_two = HuffmanGenerator::new();
@@ -53,9 +88,14 @@ where
     /// assert_eq!(two_plus_two, four);
     /// ```
     pub fn add_occurences_to_symbol(&mut self, symbol: &T, occurences: usize) {
+        if let Some(sketch) = &mut self.sketch {
+            let occurences = u64::try_from(occurences).unwrap_or(u64::MAX);
+            sketch.add_occurences_to_symbol(symbol, occurences);
+            return;
+        }
         let entry = self.symbols.get_mut(symbol);
         match entry {
-            Some(count) => *count += occurences,
+            Some(count) => *count = count.saturating_add(occurences),
             None => {
                 self.symbols.insert(symbol.clone(), occurences);
             }
@@ -68,6 +108,66 @@ where
         }
     }
 
+    /// `len` symbols drawn at random with the frequencies added so far, e.g. to generate
+    /// synthetic data shaped like a real workload. `rng` returns uniformly random `u64`s,
+    /// such as `|| rng.next_u64()` with the `rand` crate. The stream is empty if no
+    /// occurences were added.
+    /// ## Examples
+    /// ```
+    /// # use rhuffman::huffman_tree::huffman_generator::HuffmanGenerator;
+    /// let mut gen = HuffmanGenerator::new();
+    /// gen.add_occurences_to_symbol(&'a', 3);
+    /// gen.add_occurences_to_symbol(&'b', 1);
+    ///
+    /// let mut state = 1u64;
+    /// let xorshift = move || {
+    ///     state ^= state << 13;
+    ///     state ^= state >> 7;
+    ///     state ^= state << 17;
+    ///     state
+    /// };
+    /// let sample: Vec<char> = gen.sample_stream(xorshift, 10_000).collect();
+    /// let a = sample.iter().filter(|&&symbol| symbol == 'a').count();
+    /// assert!((7000..8000).contains(&a));
+    /// ```
+    pub fn sample_stream(
+        &self,
+        mut rng: impl FnMut() -> u64,
+        len: usize,
+    ) -> impl Iterator<Item = T> {
+        let mut counts: Vec<(&T, u64)> = self.counts().filter(|(_, count)| *count > 0).collect();
+        // Same stream for the same random numbers, whatever the hash map order
+        counts.sort_unstable();
+        let mut total = 0u64;
+        let mut cumulative = Vec::with_capacity(counts.len());
+        for (symbol, count) in counts {
+            total = total.saturating_add(count);
+            cumulative.push((total, symbol.clone()));
+        }
+        let len = if total == 0 { 0 } else { len };
+        (0..len).filter_map(move |_| {
+            // Scales the random number to the total weight without the bias of a modulo
+            let draw = ((u128::from(rng()) * u128::from(total)) >> 64) as u64;
+            let index = cumulative.partition_point(|(end, _)| *end <= draw);
+            cumulative.get(index).map(|(_, symbol)| symbol.clone())
+        })
+    }
+
+    /// The counts added so far, to diff later ones against, see [snapshot](super::snapshot).
+    /// Estimates when counting approximately.
+    pub fn snapshot(&self) -> Snapshot<T> {
+        Snapshot::new(self.counts().map(|(symbol, count)| (symbol.clone(), count)))
+    }
+
+    /// Adds the occurences of the delta, as
+    /// [add_occurences_to_symbol](HuffmanGenerator::add_occurences_to_symbol) would
+    pub fn apply_delta(&mut self, delta: &CountDelta<T>) {
+        for (symbol, count) in &delta.counts {
+            let count = usize::try_from(*count).unwrap_or(usize::MAX);
+            self.add_occurences_to_symbol(symbol, count);
+        }
+    }
+
     pub fn into_encoder_decoder_pair(self) -> Option<(HuffmanEncoder<T>, HuffmanDecoder<T>)> {
         if let Some(tree) = self.into_huffman_tree() {
             let encoder = HuffmanEncoder::from_tree(&tree);
@@ -80,30 +180,546 @@ where
     /// Construct a huffman tree from the symbols and occurences added
     /// through [`add_occurences_to_symbol`](HuffmanGenerator::add_occurences_to_symbol)
     /// ## None
-    /// Returns None if none, or a single, symbol were added to the symbols table.
-    pub fn into_huffman_tree(self) -> Option<HuffmanNode<T>> {
-        if self.symbols.len() == 0 {
-            return None;
+    /// Returns None if none, or a single, symbol were added to the symbols table, or if there
+    /// are more symbols than codes of the [maximum code length](HuffmanGeneratorBuilder::max_code_length).
+    pub fn into_huffman_tree(self) -> Option<HuffmanTree<T>> {
+        self.into_weighted_tree().map(HuffmanTree::from)
+    }
+
+    /// Same as [into_huffman_tree](HuffmanGenerator::into_huffman_tree), but keeps the total
+    /// weight of the tree
+    pub(crate) fn into_weighted_tree(self) -> Option<Weighted<T>> {
+        let mut counts = match &self.sketch {
+            Some(sketch) => sketch
+                .counts()
+                .map(|(symbol, count)| {
+                    let count = usize::try_from(count).unwrap_or(usize::MAX);
+                    (symbol.clone(), count)
+                })
+                .collect(),
+            None => self.symbols,
+        };
+        let reserved = self.reserved;
+        for (symbol, min_weight) in &reserved {
+            let count = counts.entry(symbol.clone()).or_insert(0);
+            *count = (*count).max(*min_weight);
+        }
+        if let Some(max_symbols) = self.max_symbols {
+            if counts.len() > max_symbols {
+                // Keep the most frequent symbols, reserved ones first
+                let mut ranked: Vec<(T, usize)> = counts.into_iter().collect();
+                ranked.sort_by(|(a, a_count), (b, b_count)| {
+                    let a_reserved = reserved.contains_key(a);
+                    let b_reserved = reserved.contains_key(b);
+                    b_reserved
+                        .cmp(&a_reserved)
+                        .then(b_count.cmp(a_count))
+                        .then(a.cmp(b))
+                });
+                ranked.truncate(max_symbols);
+                counts = ranked.into_iter().collect();
+            }
+        }
+
+        let min_weight = self.min_weight;
+        let cost = self.cost;
+        let weights = counts.into_iter().map(|(symbol, count)| {
+            let weight = u64::try_from(count.max(min_weight)).unwrap_or(u64::MAX);
+            match cost {
+                Some(Cost(cost)) => {
+                    let weight = cost(&symbol, weight);
+                    (symbol, weight)
+                }
+                None => (symbol, weight),
+            }
+        });
+        match self.max_code_length {
+            Some(max_len) => build_limited(weights.collect(), max_len),
+            None => build_weighted(weights),
+        }
+    }
+}
+
+/// The cost function of a generator, equal to another when it is the same function
+#[derive(Debug)]
+struct Cost<T>(fn(&T, u64) -> u64);
+
+impl<T> Clone for Cost<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Cost<T> {}
+
+impl<T> PartialEq for Cost<T> {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::fn_addr_eq(self.0, other.0)
+    }
+}
+
+impl<T> Eq for Cost<T> {}
+
+/// Builds the tree from the weight of every symbol, each symbol appearing once. None if
+/// there are no symbols.
+///
+/// Merges the two lightest nodes until one is left, equal weights taken in the order of
+/// [HuffmanNode]. That order only depends on the depth of the leftmost leaf of a node and on
+/// its symbol, so the heap holds those as integers rather than the nodes, which stay in
+/// place. Symbols are only compared once, to sort them.
+pub(crate) fn build_weighted<T: Eq + Ord>(
+    weights: impl Iterator<Item = (T, u64)>,
+) -> Option<Weighted<T>> {
+    let mut leaves: Vec<(T, u64)> = weights.collect();
+    leaves.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+    let mut pending = BinaryHeap::with_capacity(leaves.len());
+    let mut nodes = Vec::with_capacity(leaves.len());
+    for (slot, (symbol, weight)) in leaves.into_iter().enumerate() {
+        pending.push(Pending {
+            weight,
+            spine: 0,
+            slot,
+        });
+        nodes.push(Some(HuffmanNode::Leaf(HuffmanLeaf::new(symbol))));
+    }
+
+    while let Some(lower) = pending.pop() {
+        let greater = match pending.pop() {
+            Some(greater) => greater,
+            None => {
+                return nodes[lower.slot]
+                    .take()
+                    .map(|root| Weighted::new(root, lower.weight))
+            }
+        };
+        let left = nodes[greater.slot].take()?;
+        let right = nodes[lower.slot].take()?;
+        // The branch takes the place of its left child, whose leftmost leaf it shares
+        nodes[greater.slot] = Some(HuffmanNode::Branch(HuffmanBranch::new(left, right)));
+        pending.push(Pending {
+            // Saturating keeps the tree valid, only the ordering of gigantic weights degrades
+            weight: greater.weight.saturating_add(lower.weight),
+            spine: greater.spine + 1,
+            slot: greater.slot,
+        });
+    }
+    None
+}
+
+/// A node waiting to be merged by [build_weighted()]: its weight, the depth of its leftmost
+/// leaf and its place in `nodes`, which is the rank of the symbol of that leaf
+#[derive(PartialEq, Eq)]
+struct Pending {
+    weight: u64,
+    spine: usize,
+    slot: usize,
+}
+
+impl PartialOrd for Pending {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Reversed, the heap pops the lightest node first, then the one with the deepest leftmost
+// leaf, then the one with the lowest symbol, as HuffmanNode orders them
+impl Ord for Pending {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .weight
+            .cmp(&self.weight)
+            .then(self.spine.cmp(&other.spine))
+            .then(other.slot.cmp(&self.slot))
+    }
+}
+
+/// Builds the canonical tree of the optimal code whose codes are at most `max_len` bits, each
+/// symbol appearing once. None if there are no symbols, or more than `2^max_len`.
+fn build_limited<T: Eq + Ord + Clone>(
+    mut weights: Vec<(T, u64)>,
+    max_len: usize,
+) -> Option<Weighted<T>> {
+    if weights.len() < 2 {
+        return build_weighted(weights.into_iter());
+    }
+    weights.sort_unstable_by(|(a, a_weight), (b, b_weight)| a_weight.cmp(b_weight).then(a.cmp(b)));
+    let sorted: Vec<u64> = weights.iter().map(|(_, weight)| *weight).collect();
+    let lengths = package_merge(&sorted, max_len)?;
+    let total = sorted
+        .iter()
+        .fold(0u64, |total, &weight| total.saturating_add(weight));
+    let lengths: Vec<(T, usize)> = weights
+        .into_iter()
+        .map(|(symbol, _)| symbol)
+        .zip(lengths)
+        .collect();
+    canonical::from_lengths(&lengths).map(|tree| Weighted::new(tree, total))
+}
+
+/// An item of a package-merge list: a symbol, or a package of two items of the list before
+enum Item {
+    Leaf(usize),
+    Package(usize, usize),
+}
+
+/// The code length of each of the weights, sorted in increasing order, minimizing the total
+/// weighted length with no code longer than `max_len`. None if there are more than
+/// `2^max_len` weights.
+///
+/// Package-merge: each list holds the leaves and the pairs of consecutive items of the list
+/// before, in increasing weight. The first `2n - 2` items of the last list hold every leaf
+/// once per bit of its code.
+fn package_merge(weights: &[u64], max_len: usize) -> Option<Vec<usize>> {
+    let n = weights.len();
+    if u32::try_from(max_len)
+        .ok()
+        .and_then(|bits| 1usize.checked_shl(bits))
+        .is_some_and(|codes| codes < n)
+    {
+        return None;
+    }
+    // No optimal code is deeper than n - 1 bits
+    let max_len = max_len.min(n - 1);
+    let leaves: Vec<(u64, Item)> = (0..n).map(|i| (weights[i], Item::Leaf(i))).collect();
+    let mut lists: Vec<Vec<(u64, Item)>> = vec![];
+    for _ in 1..max_len {
+        let previous = lists.last().unwrap_or(&leaves);
+        let mut packages = (0..previous.len() / 2).map(|i| {
+            let weight = previous[2 * i].0.saturating_add(previous[2 * i + 1].0);
+            (weight, Item::Package(2 * i, 2 * i + 1))
+        });
+        let mut list = Vec::with_capacity(n + previous.len() / 2);
+        let mut package = packages.next();
+        for (i, &weight) in weights.iter().enumerate() {
+            while let Some((package_weight, _)) = package {
+                if package_weight >= weight {
+                    break;
+                }
+                list.extend(package.take());
+                package = packages.next();
+            }
+            list.push((weight, Item::Leaf(i)));
         }
+        list.extend(package);
+        list.extend(packages);
+        lists.push(list);
+    }
 
-        let mut symbols = BinaryHeap::new();
-        for (symbol, count) in self.symbols.into_iter() {
-            symbols.push(Reverse(Weighted::new_leaf(symbol, count as u64)));
+    let mut lengths = vec![0; n];
+    let last = lists.len();
+    let mut pending: Vec<(usize, usize)> = (0..2 * n - 2).map(|i| (last, i)).collect();
+    while let Some((level, index)) = pending.pop() {
+        let list = match level {
+            0 => &leaves,
+            level => &lists[level - 1],
+        };
+        match list[index].1 {
+            Item::Leaf(symbol) => lengths[symbol] += 1,
+            Item::Package(a, b) => pending.extend([(level - 1, a), (level - 1, b)]),
         }
+    }
+    Some(lengths)
+}
+
+/// Builds the tree from the weight of every symbol, each symbol appearing once and the
+/// lightest first, in linear time: merged nodes are created in increasing weight too, so the
+/// two lightest nodes are always at the front of either queue. None if there are no symbols.
+/// ## Errors
+/// Returns Err if the weights are not in increasing order.
+pub(crate) fn build_sorted<T: Eq>(
+    weights: impl Iterator<Item = (T, u64)>,
+) -> Result<Option<Weighted<T>>, &'static str> {
+    let mut leaves = VecDeque::new();
+    for (symbol, weight) in weights {
+        if leaves
+            .back()
+            .is_some_and(|last: &Weighted<T>| last.get_weight() > weight)
+        {
+            return Err("Counts are not sorted in increasing order");
+        }
+        leaves.push_back(Weighted::new_leaf(symbol, weight));
+    }
+    // Leaves first on ties, which keeps the longest code as short as possible
+    fn lightest<T: Eq>(
+        leaves: &mut VecDeque<Weighted<T>>,
+        branches: &mut VecDeque<Weighted<T>>,
+    ) -> Option<Weighted<T>> {
+        match (leaves.front(), branches.front()) {
+            (Some(leaf), Some(branch)) if branch.get_weight() < leaf.get_weight() => {
+                branches.pop_front()
+            }
+            (Some(_), _) => leaves.pop_front(),
+            (None, _) => branches.pop_front(),
+        }
+    }
+
+    let mut branches = VecDeque::new();
+    while let Some(lower) = lightest(&mut leaves, &mut branches) {
+        match lightest(&mut leaves, &mut branches) {
+            Some(greater) => branches.push_back(Weighted::new_branch(greater, lower)),
+            None => return Ok(Some(lower)),
+        }
+    }
+    Ok(None)
+}
 
-        while symbols.len() > 1 {
-            let lower = symbols.pop().unwrap().0;
-            let greater = symbols.pop().unwrap().0;
+#[cfg(feature = "rayon")]
+impl<T> HuffmanGenerator<T>
+where
+    T: Eq + Hash + Clone + Ord + Sync,
+{
+    /// Same as [add_occurences_from_iterator()](HuffmanGenerator::add_occurences_from_iterator)
+    /// for a slice, counting chunks of it on the rayon thread pool and merging their counts.
+    /// Slices of fewer than two [PARALLEL_CHUNK]s, or a pool of a single thread, are counted
+    /// on the calling thread. Only built with the `rayon` feature.
+    /// ## Examples
+    /// ```
+    /// # use rhuffman::huffman_tree::huffman_generator::HuffmanGenerator;
+    /// let data: Vec<u16> = (0..1_000_000u64).map(|i| (i * i % 1009) as u16).collect();
+    /// let mut gen = HuffmanGenerator::new();
+    /// gen.add_occurences_parallel(&data);
+    /// let mut expected = HuffmanGenerator::new();
+    /// expected.add_occurences_from_iterator(&mut data.iter());
+    /// assert_eq!(gen, expected);
+    /// ```
+    pub fn add_occurences_parallel(&mut self, symbols: &[T]) {
+        use rayon::prelude::*;
 
-            symbols.push(Reverse(Weighted::new_branch(greater, lower)));
+        let threads = rayon::current_num_threads();
+        if threads == 1 || symbols.len() < PARALLEL_CHUNK * 2 {
+            return self.add_occurences_from_iterator(&mut symbols.iter());
+        }
+        let chunk_len = symbols.len().div_ceil(threads * 4).max(PARALLEL_CHUNK);
+        let counts = symbols
+            .par_chunks(chunk_len)
+            .map(|chunk| {
+                let mut counts = HashMap::new();
+                for symbol in chunk {
+                    *counts.entry(symbol).or_insert(0) += 1;
+                }
+                counts
+            })
+            .reduce(HashMap::new, |mut counts, mut other| {
+                if counts.len() < other.len() {
+                    std::mem::swap(&mut counts, &mut other);
+                }
+                for (symbol, count) in other {
+                    *counts.entry(symbol).or_insert(0) += count;
+                }
+                counts
+            });
+        for (symbol, count) in counts {
+            self.add_occurences_to_symbol(symbol, count);
+        }
+    }
+}
+
+impl HuffmanGenerator<u8> {
+    /// Same as [add_occurences_from_iterator()](HuffmanGenerator::add_occurences_from_iterator),
+    /// counting the bytes in a table indexed by the byte first, rather than hashing every byte.
+    /// ## Examples
+    /// ```
+    /// # use rhuffman::huffman_tree::huffman_generator::HuffmanGenerator;
+    /// let mut gen = HuffmanGenerator::new();
+    /// gen.add_occurences_from_bytes(b"abracadabra");
+    /// let mut expected = HuffmanGenerator::new();
+    /// expected.add_occurences_from_iterator(&mut b"abracadabra".iter());
+    /// assert_eq!(gen, expected);
+    /// ```
+    pub fn add_occurences_from_bytes(&mut self, bytes: &[u8]) {
+        let mut counts = [0usize; 256];
+        for &byte in bytes {
+            counts[usize::from(byte)] += 1;
+        }
+        for (byte, &count) in (0..=255u8).zip(counts.iter()) {
+            if count > 0 {
+                self.add_occurences_to_symbol(&byte, count);
+            }
         }
+    }
+}
 
-        Some(symbols.pop().unwrap().0.into())
+/// The occurences added so far, without the reserved symbols, limits or minimum weight of
+/// the generator. Estimates when counting approximately.
+impl<T> FrequencySource<T> for HuffmanGenerator<T>
+where
+    T: Eq + Hash + Clone + Ord,
+{
+    fn counts<'a>(&'a self) -> impl Iterator<Item = (&'a T, u64)>
+    where
+        T: 'a,
+    {
+        self.symbols
+            .iter()
+            .map(|(symbol, count)| (symbol, u64::try_from(*count).unwrap_or(u64::MAX)))
+            .chain(self.sketch.iter().flat_map(|sketch| sketch.counts()))
+    }
+}
+
+/// Configures a [HuffmanGenerator] before any occurence is added.
+///
+/// Reserved symbols always receive a code, even when they never appear in the training data,
+/// which suits end-of-stream markers, escapes or protocol control codes.
+/// ## Examples
+/// ```
+/// # use rhuffman::huffman_tree::huffman_generator::HuffmanGenerator;
+/// # use rhuffman::huffman_tree::huffman_encoder::HuffmanEncoder;
+/// let mut gen = HuffmanGenerator::builder()
+///     .reserve_symbol('$', 1)
+///     .build()
+///     .unwrap();
+/// gen.add_occurences_from_iterator(&mut "abracadabra".chars().collect::<Vec<_>>().iter());
+///
+/// let encoder = HuffmanEncoder::from_tree(&gen.into_huffman_tree().unwrap());
+/// assert!(encoder.encode(&mut ['a', '$'].iter()).is_ok());
+/// ```
+pub struct HuffmanGeneratorBuilder<T>
+where
+    T: Eq + Hash + Clone + Ord,
+{
+    reserved: HashMap<T, usize>,
+    max_symbols: Option<usize>,
+    min_weight: usize,
+    sketch: Option<CountMinSketch<T>>,
+    cost: Option<Cost<T>>,
+    max_code_length: Option<usize>,
+}
+
+impl<T> Default for HuffmanGeneratorBuilder<T>
+where
+    T: Eq + Hash + Clone + Ord,
+{
+    fn default() -> Self {
+        HuffmanGeneratorBuilder::new()
+    }
+}
+
+impl<T> HuffmanGeneratorBuilder<T>
+where
+    T: Eq + Hash + Clone + Ord,
+{
+    pub fn new() -> HuffmanGeneratorBuilder<T> {
+        HuffmanGeneratorBuilder {
+            reserved: HashMap::new(),
+            max_symbols: None,
+            min_weight: 0,
+            sketch: None,
+            cost: None,
+            max_code_length: None,
+        }
+    }
+
+    /// Guarantees the symbol a code, weighted as if it occured at least `min_weight` times.
+    /// Reserving the same symbol again keeps the highest weight.
+    #[must_use]
+    pub fn reserve_symbol(mut self, symbol: T, min_weight: usize) -> Self {
+        let weight = self.reserved.entry(symbol).or_insert(0);
+        *weight = (*weight).max(min_weight);
+        self
+    }
+
+    /// Weighs every symbol as if it occured at least `min_weight` times. Raising the weight of
+    /// very rare symbols shortens their codes, which bounds how long the longest code can get
+    /// at a small cost in compression.
+    #[must_use]
+    pub fn min_weight(mut self, min_weight: usize) -> Self {
+        self.min_weight = min_weight;
+        self
+    }
+
+    /// Only the `max_symbols` most frequent symbols receive a code, the others are pruned.
+    /// Reserved symbols are never pruned and count towards that limit.
+    #[must_use]
+    pub fn max_symbols(mut self, max_symbols: usize) -> Self {
+        self.max_symbols = Some(max_symbols);
+        self
+    }
+
+    /// Counts occurences in the sketch instead of exactly, bounding the memory used to count
+    /// huge alphabets at a small cost in compression. Only the symbols the sketch keeps, and
+    /// reserved ones, receive a code.
+    #[must_use]
+    pub fn approximate_counts(mut self, sketch: CountMinSketch<T>) -> Self {
+        self.sketch = Some(sketch);
+        self
+    }
+
+    /// Builds the tree from the weight `cost` gives each symbol and its number of occurences,
+    /// after reserved symbols and the minimum weight are applied, instead of from the number
+    /// of occurences alone. Scaling the count of a symbol by how much its bits cost, e.g. on
+    /// links where some symbols are sent over a costlier channel, shortens its code.
+    /// ## Examples
+    /// ```
+    /// # use rhuffman::huffman_tree::huffman_generator::HuffmanGenerator;
+    /// # use rhuffman::huffman_tree::huffman_encoder::HuffmanEncoder;
+    /// // Bits of 'c' cost ten times as much
+    /// let mut gen = HuffmanGenerator::builder()
+    ///     .cost(|symbol, count| if *symbol == 'c' { count * 10 } else { count })
+    ///     .build()
+    ///     .unwrap();
+    /// gen.add_occurences_from_iterator(&mut "aaaabbbc".chars().collect::<Vec<_>>().iter());
+    ///
+    /// let encoder = HuffmanEncoder::from_tree(&gen.into_huffman_tree().unwrap());
+    /// assert_eq!(encoder.encode(&mut ['c'].iter()).unwrap().len(), 1);
+    /// ```
+    #[must_use]
+    pub fn cost(mut self, cost: fn(&T, u64) -> u64) -> Self {
+        self.cost = Some(Cost(cost));
+        self
+    }
+
+    /// Bounds the length of every code to `max_len` bits, as table-driven decoders and formats
+    /// such as DEFLATE, capped at 15 bits, require. The tree is the canonical tree of the
+    /// optimal code under that bound, found with package-merge, so the codes only get longer
+    /// on average when the bound actually shortens some of them. Trees of more than
+    /// `2^max_len` symbols can not be built.
+    /// ## Examples
+    /// ```
+    /// # use rhuffman::huffman_tree::huffman_generator::HuffmanGenerator;
+    /// let mut gen = HuffmanGenerator::builder().max_code_length(4).build().unwrap();
+    /// for (symbol, count) in (0..10u32).zip([1, 1, 2, 3, 5, 8, 13, 21, 34, 55]) {
+    ///     gen.add_occurences_to_symbol(&symbol, count);
+    /// }
+    /// let mut longest = 0;
+    /// gen.into_huffman_tree()
+    ///     .unwrap()
+    ///     .visit(&mut |code: &[bool], _: &u32| longest = longest.max(code.len()));
+    /// assert_eq!(longest, 4);
+    /// ```
+    #[must_use]
+    pub fn max_code_length(mut self, max_len: usize) -> Self {
+        self.max_code_length = Some(max_len);
+        self
+    }
+
+    /// ## Errors
+    /// Returns Err if there are more reserved symbols than [max_symbols](HuffmanGeneratorBuilder::max_symbols)
+    /// allows, as some of them would have to be pruned, or if the
+    /// [maximum code length](HuffmanGeneratorBuilder::max_code_length) is 0.
+    pub fn build(self) -> Result<HuffmanGenerator<T>, &'static str> {
+        if let Some(max_symbols) = self.max_symbols {
+            if self.reserved.len() > max_symbols {
+                return Err("More symbols are reserved than max_symbols allows");
+            }
+        }
+        if self.max_code_length == Some(0) {
+            return Err("Codes can not be limited to 0 bits");
+        }
+        Ok(HuffmanGenerator {
+            symbols: HashMap::new(),
+            sketch: self.sketch,
+            reserved: self.reserved,
+            max_symbols: self.max_symbols,
+            min_weight: self.min_weight,
+            cost: self.cost,
+            max_code_length: self.max_code_length,
+        })
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::super::fibonacci_counts;
     use super::*;
 
     #[test]
@@ -142,7 +758,6 @@ mod tests {
         assert_eq!(tree, Weighted::new_leaf("A", 2).into());
     }
 
-    #[ignore = "Broken since no more weights"]
     #[test]
     fn two_symbols_generate_branch_tree() {
         let mut generator = HuffmanGenerator::new();
@@ -150,7 +765,269 @@ mod tests {
         generator.add_occurences_to_symbol(&"B", 4);
         generator.add_occurences_to_symbol(&"C", 1);
 
-        // let tree_weight = generator.into_huffman_tree().unwrap().get_weight();
-        // assert_eq!(7, tree_weight)
+        let tree_weight = generator.into_weighted_tree().unwrap().get_weight();
+        assert_eq!(7, tree_weight)
+    }
+
+    #[test]
+    fn reserved_symbols_receive_codes_when_unseen() {
+        let mut generator = HuffmanGenerator::builder()
+            .reserve_symbol("EOS", 1)
+            .reserve_symbol("ESC", 3)
+            .build()
+            .unwrap();
+        generator.add_occurences_to_symbol(&"A", 10);
+        generator.add_occurences_to_symbol(&"ESC", 1);
+
+        let tree = generator.into_weighted_tree().unwrap();
+        assert_eq!(tree.get_weight(), 14);
+    }
+
+    #[test]
+    fn max_symbols_prunes_rare_symbols_but_keeps_reserved() {
+        let mut generator = HuffmanGenerator::builder()
+            .reserve_symbol("EOS", 1)
+            .max_symbols(2)
+            .build()
+            .unwrap();
+        generator.add_occurences_to_symbol(&"A", 10);
+        generator.add_occurences_to_symbol(&"B", 5);
+        generator.add_occurences_to_symbol(&"C", 1);
+
+        let mut symbols = vec![];
+        let tree = generator.into_huffman_tree().unwrap();
+        tree.visit(&mut |_: &[bool], symbol: &&str| symbols.push(*symbol));
+        symbols.sort_unstable();
+        assert_eq!(symbols, vec!["A", "EOS"]);
+    }
+
+    #[test]
+    fn reserving_more_than_max_symbols_fails() {
+        let builder = HuffmanGenerator::builder()
+            .reserve_symbol("EOS", 1)
+            .reserve_symbol("ESC", 1)
+            .max_symbols(1);
+        assert!(builder.build().is_err());
+    }
+
+    #[test]
+    fn min_weight_shortens_rare_symbol_codes() {
+        fn longest_code(mut generator: HuffmanGenerator<u32>) -> usize {
+            // Fibonacci weights produce the deepest possible tree
+            for (symbol, count) in (0..24).zip(fibonacci_counts(24)) {
+                generator.add_occurences_to_symbol(&symbol, count);
+            }
+            let mut longest = 0;
+            let tree = generator.into_huffman_tree().unwrap();
+            tree.visit(&mut |path: &[bool], _: &u32| longest = longest.max(path.len()));
+            longest
+        }
+
+        assert_eq!(longest_code(HuffmanGenerator::new()), 23);
+        let floored = HuffmanGenerator::builder()
+            .min_weight(1000)
+            .build()
+            .unwrap();
+        assert!(longest_code(floored) <= 12);
+    }
+
+    #[test]
+    fn limited_codes_are_optimal_under_the_bound() {
+        fn lengths(max_len: Option<usize>, counts: &[usize]) -> Option<Vec<usize>> {
+            let mut builder = HuffmanGenerator::builder();
+            if let Some(max_len) = max_len {
+                builder = builder.max_code_length(max_len);
+            }
+            let mut generator = builder.build().unwrap();
+            for (symbol, &count) in counts.iter().enumerate() {
+                generator.add_occurences_to_symbol(&symbol, count);
+            }
+            let mut lengths = vec![0; counts.len()];
+            let tree = generator.into_huffman_tree()?;
+            tree.visit(&mut |code: &[bool], symbol: &usize| lengths[*symbol] = code.len());
+            Some(lengths)
+        }
+        let cost = |lengths: &[usize], counts: &[usize]| -> usize {
+            lengths
+                .iter()
+                .zip(counts)
+                .map(|(len, count)| len * count)
+                .sum()
+        };
+
+        let fibonacci = fibonacci_counts(24);
+        let unlimited = lengths(None, &fibonacci).unwrap();
+        assert_eq!(unlimited.iter().max(), Some(&23));
+        // A bound the tree already satisfies may only break ties differently
+        let loose = lengths(Some(30), &fibonacci).unwrap();
+        assert_eq!(cost(&loose, &fibonacci), cost(&unlimited, &fibonacci));
+
+        for max_len in 5..23 {
+            let limited = lengths(Some(max_len), &fibonacci).unwrap();
+            assert_eq!(limited.iter().max(), Some(&max_len));
+            let kraft: f64 = limited.iter().map(|&len| 0.5f64.powi(len as i32)).sum();
+            assert_eq!(kraft, 1.0);
+        }
+        // 8 codes of at most 3 bits can only all be 3 bits long
+        let flat = lengths(Some(3), &[1, 1, 1, 1, 1, 1, 100, 100]).unwrap();
+        assert_eq!(flat, vec![3; 8]);
+        // Against every complete code of 7 symbols up to 4 bits
+        let mut best = vec![];
+        for mut code in 0..4usize.pow(7) {
+            let lengths: Vec<usize> = (0..7)
+                .map(|_| {
+                    let len = code % 4 + 1;
+                    code /= 4;
+                    len
+                })
+                .collect();
+            if lengths.iter().map(|&len| 16 >> len).sum::<usize>() == 16 {
+                best.push(lengths);
+            }
+        }
+        for counts in [[1, 2, 4, 8, 16, 32, 64], [5, 5, 5, 1, 90, 3, 40]] {
+            let optimal = best.iter().map(|lengths| cost(lengths, &counts)).min();
+            let limited = lengths(Some(4), &counts).unwrap();
+            assert_eq!(Some(cost(&limited, &counts)), optimal);
+        }
+
+        assert_eq!(lengths(Some(2), &[1; 5]), None);
+        assert_eq!(lengths(Some(2), &[1; 4]), Some(vec![2; 4]));
+        assert_eq!(lengths(Some(1), &[7]), Some(vec![0]));
+        assert!(HuffmanGenerator::<u8>::builder()
+            .max_code_length(0)
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn cost_weighs_reserved_and_counted_symbols() {
+        let mut generator = HuffmanGenerator::builder()
+            .reserve_symbol("EOS", 2)
+            .cost(|symbol, count| match *symbol {
+                "EOS" => count * 3,
+                _ => count,
+            })
+            .build()
+            .unwrap();
+        generator.add_occurences_to_symbol(&"A", 4);
+        generator.add_occurences_to_symbol(&"B", 1);
+
+        let tree = generator.into_weighted_tree().unwrap();
+        assert_eq!(tree.get_weight(), 11);
+    }
+
+    #[test]
+    fn ties_are_broken_in_the_order_of_nodes() {
+        // Merging whole weighted nodes, as trees were first built
+        fn by_nodes<T: Eq + Ord>(weights: Vec<(T, u64)>) -> Option<Weighted<T>> {
+            let mut symbols: BinaryHeap<_> = weights
+                .into_iter()
+                .map(|(symbol, weight)| std::cmp::Reverse(Weighted::new_leaf(symbol, weight)))
+                .collect();
+            while let Some(std::cmp::Reverse(lower)) = symbols.pop() {
+                match symbols.pop() {
+                    Some(std::cmp::Reverse(greater)) => {
+                        let branch = Weighted::new_branch(greater, lower);
+                        symbols.push(std::cmp::Reverse(branch));
+                    }
+                    None => return Some(lower),
+                }
+            }
+            None
+        }
+        let same = |weights: Vec<(u32, u64)>| {
+            let expected = by_nodes(weights.clone()).map(HuffmanTree::from);
+            assert_eq!(
+                build_weighted(weights.into_iter()).map(HuffmanTree::from),
+                expected
+            );
+        };
+        same(vec![]);
+        same(vec![(3, 1)]);
+        same((0..1000).map(|i| (i, 1)).collect());
+        same((0..1000).rev().map(|i| (i, u64::from(i % 7 + 1))).collect());
+        same(
+            (0..1000)
+                .map(|i| ((i * 389) % 1000, u64::from(i % 3) << (i % 40)))
+                .collect(),
+        );
+        let words: Vec<(String, u64)> = (0..500u64)
+            .map(|i| (format!("w{}", i * 7919 % 500), i % 5 + 1))
+            .collect();
+        assert_eq!(
+            build_weighted(words.clone().into_iter()).map(HuffmanTree::from),
+            by_nodes(words).map(HuffmanTree::from)
+        );
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn parallel_counts_merge_every_chunk() {
+        let len = PARALLEL_CHUNK * 5 + 123;
+        let data: Vec<u16> = (0..len as u64).map(|i| (i * i % 1009) as u16).collect();
+        let mut expected = HuffmanGenerator::new();
+        expected.add_occurences_to_symbol(&7, 3);
+        expected.add_occurences_from_iterator(&mut data.iter());
+
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(4)
+            .build()
+            .unwrap();
+        let mut gen = HuffmanGenerator::new();
+        gen.add_occurences_to_symbol(&7, 3);
+        pool.install(|| gen.add_occurences_parallel(&data));
+        assert_eq!(gen, expected);
+
+        let mut gen = HuffmanGenerator::new();
+        gen.add_occurences_parallel(&data[..10]);
+        gen.add_occurences_parallel(&[]);
+        let mut expected = HuffmanGenerator::new();
+        expected.add_occurences_from_iterator(&mut data[..10].iter());
+        assert_eq!(gen, expected);
+    }
+
+    #[test]
+    fn huge_weights_do_not_overflow() {
+        let mut generator = HuffmanGenerator::new();
+        generator.add_occurences_to_symbol(&"A", usize::MAX);
+        generator.add_occurences_to_symbol(&"B", usize::MAX);
+        generator.add_occurences_to_symbol(&"C", 1);
+
+        let tree = generator.into_weighted_tree().unwrap();
+        assert_eq!(tree.get_weight(), u64::MAX);
+    }
+
+    #[test]
+    fn samples_follow_the_counts() {
+        let mut generator = HuffmanGenerator::new();
+        for (symbol, count) in &[(0u8, 50), (1, 30), (2, 20), (3, 0)] {
+            generator.add_occurences_to_symbol(symbol, *count);
+        }
+        let mut state = 0x9e37_79b9_7f4a_7c15u64;
+        let splitmix = move || {
+            state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^ (z >> 31)
+        };
+        let mut seen = [0usize; 4];
+        for symbol in generator.sample_stream(splitmix, 100_000) {
+            seen[usize::from(symbol)] += 1;
+        }
+        assert_eq!(seen.iter().sum::<usize>(), 100_000);
+        assert_eq!(seen[3], 0);
+        for (&seen, expected) in seen.iter().zip(&[50_000, 30_000, 20_000]) {
+            assert!(seen.abs_diff(*expected) < 1000, "{} {}", seen, expected);
+        }
+
+        // The lowest and highest random numbers land on the first and last symbols
+        let edges: Vec<u8> = generator.sample_stream(|| 0, 1).collect();
+        assert_eq!(edges, vec![0]);
+        let edges: Vec<u8> = generator.sample_stream(|| u64::MAX, 1).collect();
+        assert_eq!(edges, vec![2]);
+        let empty = HuffmanGenerator::<u8>::new();
+        assert_eq!(empty.sample_stream(|| 0, 10).count(), 0);
     }
 }
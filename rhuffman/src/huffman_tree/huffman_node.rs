@@ -1,31 +1,41 @@
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
 
 use HuffmanNode::*;
 /// This node is used while building the Huffman tree, it
-/// keeps track of its weight but should not be used for children nodes as weight is useless for children nodes
-
+/// keeps track of its weight but should not be used for children nodes as weight is useless for children nodes.
+///
+/// It never leaves the crate: converting it into a [HuffmanNode] drops the weight, and
+/// [Weighted::new] attaches one back.
 #[derive(PartialEq, Eq, Debug)]
-pub struct Weighted<T: Eq>(HuffmanNode<T>, u64);
+pub(crate) struct Weighted<T: Eq>(HuffmanNode<T>, u64);
 
 impl<T> Weighted<T>
 where
     T: PartialEq + Eq,
 {
+    pub fn new(node: HuffmanNode<T>, weight: u64) -> Weighted<T> {
+        Weighted(node, weight)
+    }
+
     pub fn get_weight(&self) -> u64 {
         self.1
     }
 
+    pub fn node(&self) -> &HuffmanNode<T> {
+        &self.0
+    }
+
     pub fn new_leaf(symbol: T, weight: u64) -> Weighted<T> {
-        Weighted(HuffmanNode::Leaf(HuffmanLeaf { symbol }), weight)
+        Weighted::new(HuffmanNode::Leaf(HuffmanLeaf::new(symbol)), weight)
     }
 
     pub fn new_branch(greater: Weighted<T>, lower: Weighted<T>) -> Weighted<T> {
-        let sum_of_weights = greater.get_weight() + lower.get_weight();
-        Weighted(
-            HuffmanNode::Branch(HuffmanBranch {
-                links: (Box::new(greater.into()), Box::new(lower.into())),
-            }),
+        // Saturating keeps the tree valid, only the ordering of gigantic weights degrades
+        let sum_of_weights = greater.get_weight().saturating_add(lower.get_weight());
+        Weighted::new(
+            HuffmanNode::Branch(HuffmanBranch::new(greater.into(), lower.into())),
             sum_of_weights,
         )
     }
@@ -48,14 +58,7 @@ where
     fn cmp(&self, other: &Self) -> Ordering {
         let ordering = self.get_weight().cmp(&other.get_weight());
         if let Ordering::Equal = ordering {
-            match (self, other) {
-                (Weighted(Leaf(_), ..), Weighted(Branch(_), ..)) => Ordering::Greater,
-                (Weighted(Branch(_), ..), Weighted(Leaf(_), ..)) => Ordering::Less,
-                (Weighted(Leaf(me), ..), Weighted(Leaf(other), ..)) => me.symbol.cmp(&other.symbol),
-                (Weighted(Branch(me), ..), Weighted(Branch(other), ..)) => {
-                    me.links.0.cmp(&other.links.0)
-                }
-            }
+            self.node().cmp(other.node())
         } else {
             ordering
         }
@@ -72,7 +75,107 @@ where
     Branch(HuffmanBranch<T>),
 }
 
-impl<T: PartialEq + Eq> HuffmanNode<T> {}
+impl<T: PartialEq + Eq> HuffmanNode<T> {
+    /// Walks every leaf of the tree, calling the visitor with the path leading to the leaf and its symbol.
+    /// A `false` in the path means the left link was taken and `true` the right one, which is also the
+    /// code the [HuffmanEncoder](super::huffman_encoder::HuffmanEncoder) assigns to that symbol.
+    ///
+    /// Leaves are visited left to right.
+    /// ## Examples
+    /// ```
+    /// # use rhuffman::huffman_tree::huffman_generator::HuffmanGenerator;
+    /// let mut gen = HuffmanGenerator::new();
+    /// gen.add_occurences_to_symbol(&'a', 3);
+    /// gen.add_occurences_to_symbol(&'b', 1);
+    /// let tree = gen.into_huffman_tree().unwrap();
+    ///
+    /// let mut codes = vec![];
+    /// tree.visit(&mut |path: &[bool], symbol: &char| codes.push((path.to_vec(), *symbol)));
+    /// assert_eq!(codes, vec![(vec![false], 'a'), (vec![true], 'b')]);
+    /// ```
+    pub fn visit(&self, visitor: &mut impl FnMut(&[bool], &T)) {
+        let mut path = Vec::new();
+        let mut pending = vec![(self, None)];
+        while let Some((node, step)) = pending.pop() {
+            if let Some((depth, bit)) = step {
+                path.truncate(depth);
+                path.push(bit);
+            }
+            match node {
+                Leaf(leaf) => visitor(&path, leaf.symbol()),
+                Branch(branch) => {
+                    let depth = path.len();
+                    pending.push((branch.right(), Some((depth, true))));
+                    pending.push((branch.left(), Some((depth, false))));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl<T: PartialEq + Eq + Clone> HuffmanNode<T> {
+    /// Generates a structurally valid tree from fuzzer input: every branch has two children,
+    /// no symbol appears twice and no leaf is deeper than `max_depth`. The leaves are picked
+    /// from `alphabet`, which must not be empty and should not contain duplicates.
+    pub fn arbitrary_with(
+        u: &mut arbitrary::Unstructured,
+        max_depth: usize,
+        alphabet: &[T],
+    ) -> arbitrary::Result<HuffmanNode<T>> {
+        if alphabet.is_empty() {
+            return Err(arbitrary::Error::NotEnoughData);
+        }
+        let capacity = 1usize.checked_shl(max_depth as u32).unwrap_or(usize::MAX);
+        let leaf_count = u.int_in_range(1..=alphabet.len().min(capacity))?;
+
+        let mut remaining: Vec<&T> = alphabet.iter().collect();
+        let mut symbols = Vec::with_capacity(leaf_count);
+        for _ in 0..leaf_count {
+            let index = u.choose_index(remaining.len())?;
+            symbols.push(remaining.swap_remove(index).clone());
+        }
+        HuffmanNode::arbitrary_subtree(u, max_depth, symbols)
+    }
+
+    fn arbitrary_subtree(
+        u: &mut arbitrary::Unstructured,
+        depth: usize,
+        mut symbols: Vec<T>,
+    ) -> arbitrary::Result<HuffmanNode<T>> {
+        if let [symbol] = symbols.as_slice() {
+            return Ok(Leaf(HuffmanLeaf::new(symbol.clone())));
+        }
+        // Each side can hold at most 2^(depth - 1) leaves
+        let half = 1usize.checked_shl(depth as u32 - 1).unwrap_or(usize::MAX);
+        let lowest = symbols.len().saturating_sub(half).max(1);
+        let highest = (symbols.len() - 1).min(half);
+        let split = u.int_in_range(lowest..=highest)?;
+
+        let right = symbols.split_off(split);
+        Ok(Branch(HuffmanBranch::new(
+            HuffmanNode::arbitrary_subtree(u, depth - 1, symbols)?,
+            HuffmanNode::arbitrary_subtree(u, depth - 1, right)?,
+        )))
+    }
+
+    /// Generates a bitstream that decodes without error against this tree, made of up to
+    /// `max_symbols` complete codes. Feed raw fuzzer bytes to the decoder to test invalid streams.
+    pub fn arbitrary_bitstream(
+        &self,
+        u: &mut arbitrary::Unstructured,
+        max_symbols: usize,
+    ) -> arbitrary::Result<bit_vec::BitVec> {
+        let mut codes = vec![];
+        self.visit(&mut |path: &[bool], _: &T| codes.push(path.to_vec()));
+
+        let mut bits = bit_vec::BitVec::new();
+        for _ in 0..u.int_in_range(0..=max_symbols)? {
+            bits.extend(u.choose(&codes)?.iter().copied());
+        }
+        Ok(bits)
+    }
+}
 
 impl<T> From<Weighted<T>> for HuffmanNode<T>
 where
@@ -80,12 +183,8 @@ where
 {
     fn from(weighted: Weighted<T>) -> Self {
         match weighted {
-            Weighted(Leaf(leaf), ..) => HuffmanNode::Leaf(HuffmanLeaf {
-                symbol: leaf.symbol,
-            }),
-            Weighted(Branch(HuffmanBranch { links }), ..) => {
-                HuffmanNode::Branch(HuffmanBranch { links })
-            }
+            Weighted(Leaf(leaf), ..) => HuffmanNode::Leaf(HuffmanLeaf::new(leaf.into_symbol())),
+            Weighted(Branch(branch), ..) => HuffmanNode::Branch(branch),
         }
     }
 }
@@ -108,8 +207,8 @@ where
         match (self, other) {
             (HuffmanNode::Leaf(_), HuffmanNode::Branch(_)) => Ordering::Greater,
             (HuffmanNode::Branch(_), HuffmanNode::Leaf(_)) => Ordering::Less,
-            (HuffmanNode::Leaf(me), HuffmanNode::Leaf(other)) => me.symbol.cmp(&other.symbol),
-            (HuffmanNode::Branch(me), HuffmanNode::Branch(other)) => me.links.0.cmp(&other.links.0),
+            (HuffmanNode::Leaf(me), HuffmanNode::Leaf(other)) => me.symbol().cmp(other.symbol()),
+            (HuffmanNode::Branch(me), HuffmanNode::Branch(other)) => me.left().cmp(other.left()),
         }
     }
 }
@@ -120,14 +219,140 @@ pub struct HuffmanLeaf<T>
 where
     T: PartialEq + Eq,
 {
+    #[deprecated(note = "use HuffmanLeaf::symbol() instead, the field will become private")]
     pub symbol: T,
 }
 
+#[allow(deprecated)]
+impl<T: PartialEq + Eq> HuffmanLeaf<T> {
+    pub(crate) fn new(symbol: T) -> HuffmanLeaf<T> {
+        HuffmanLeaf { symbol }
+    }
+
+    /// The symbol encoded by the path leading to this leaf
+    pub fn symbol(&self) -> &T {
+        &self.symbol
+    }
+
+    pub(crate) fn into_symbol(self) -> T {
+        self.symbol
+    }
+}
+
 #[derive(PartialEq, Eq, Debug, Clone)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct HuffmanBranch<T>
 where
     T: PartialEq + Eq,
 {
+    #[deprecated(note = "use HuffmanBranch::children() instead, the field will become private")]
     pub links: (Box<HuffmanNode<T>>, Box<HuffmanNode<T>>),
 }
+
+#[allow(deprecated)]
+impl<T: PartialEq + Eq> HuffmanBranch<T> {
+    pub(crate) fn new(left: HuffmanNode<T>, right: HuffmanNode<T>) -> HuffmanBranch<T> {
+        HuffmanBranch {
+            links: (Box::new(left), Box::new(right)),
+        }
+    }
+
+    /// Both children of this branch, the left one first
+    pub fn children(&self) -> (&HuffmanNode<T>, &HuffmanNode<T>) {
+        (&self.links.0, &self.links.1)
+    }
+
+    /// The child reached by a `false` bit
+    pub fn left(&self) -> &HuffmanNode<T> {
+        &self.links.0
+    }
+
+    /// The child reached by a `true` bit
+    pub fn right(&self) -> &HuffmanNode<T> {
+        &self.links.1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::huffman_encoder::HuffmanEncoder;
+    use super::super::huffman_generator::HuffmanGenerator;
+    use super::*;
+
+    #[test]
+    fn branch_accessors_expose_children_in_order() {
+        let branch = HuffmanBranch::new(
+            HuffmanNode::Leaf(HuffmanLeaf::new("L")),
+            HuffmanNode::Leaf(HuffmanLeaf::new("R")),
+        );
+        assert_eq!(branch.children(), (branch.left(), branch.right()));
+        match (branch.left(), branch.right()) {
+            (Leaf(left), Leaf(right)) => assert_eq!((left.symbol(), right.symbol()), (&"L", &"R")),
+            _ => panic!("Children should be leaves"),
+        }
+    }
+
+    #[cfg(feature = "arbitrary")]
+    #[test]
+    fn arbitrary_trees_respect_depth_and_alphabet() {
+        use super::super::huffman_decoder::HuffmanDecoder;
+
+        let alphabet: Vec<u8> = (0..=255).collect();
+        let data: Vec<u8> = (0..4096u32).map(|i| (i * 7919 % 251) as u8).collect();
+        let mut u = arbitrary::Unstructured::new(&data);
+        for max_depth in 1..10 {
+            let tree = HuffmanNode::arbitrary_with(&mut u, max_depth, &alphabet).unwrap();
+            let mut seen = std::collections::HashSet::new();
+            tree.visit(&mut |path: &[bool], symbol: &u8| {
+                assert!(path.len() <= max_depth);
+                assert!(seen.insert(*symbol));
+            });
+
+            let bits = tree.arbitrary_bitstream(&mut u, 16).unwrap();
+            let _ = HuffmanDecoder::new(tree).decode_unbounded(&bits);
+        }
+    }
+
+    #[test]
+    fn visit_paths_match_encoder_codes() {
+        let mut gen = HuffmanGenerator::new();
+        gen.add_occurences_to_symbol(&"A", 9);
+        gen.add_occurences_to_symbol(&"B", 5);
+        gen.add_occurences_to_symbol(&"C", 2);
+        gen.add_occurences_to_symbol(&"D", 2);
+        let tree = gen.into_huffman_tree().unwrap();
+        let encoder = HuffmanEncoder::from_tree(&tree);
+
+        let mut visited = 0;
+        tree.visit(&mut |path: &[bool], symbol: &&str| {
+            let code = encoder.encode(&mut [*symbol].iter()).unwrap();
+            assert!(code.iter().eq(path.iter().copied()));
+            visited += 1;
+        });
+        assert_eq!(visited, 4);
+    }
+
+    #[test]
+    fn weighted_conversion_keeps_the_tree() {
+        let mut gen = HuffmanGenerator::new();
+        gen.add_occurences_to_symbol(&"A", 3);
+        gen.add_occurences_to_symbol(&"B", 1);
+        let tree = gen.into_huffman_tree().unwrap();
+
+        let weighted = Weighted::new(tree.clone(), 4);
+        assert_eq!(weighted.node(), &tree);
+        assert_eq!(weighted.get_weight(), 4);
+        assert_eq!(HuffmanNode::from(weighted), tree);
+    }
+
+    #[test]
+    fn visit_single_leaf_has_empty_path() {
+        let mut gen = HuffmanGenerator::new();
+        gen.add_occurences_to_symbol(&"A", 1);
+        let tree = gen.into_huffman_tree().unwrap();
+
+        let mut paths = vec![];
+        tree.visit(&mut |path: &[bool], _: &&str| paths.push(path.len()));
+        assert_eq!(paths, vec![0]);
+    }
+}
@@ -0,0 +1,157 @@
+//! Two related streams, e.g. token ids and their flags, coded with a tree each into a single
+//! bitstream. Symbols come in pairs, one of each stream, and each pair is coded as the code
+//! of its first symbol followed by the code of its second, so the bits only depend on the
+//! pairs and the trees.
+//! ## Examples
+//! ```
+//! # use rhuffman::huffman_tree::interleaved;
+//! # use rhuffman::huffman_tree::huffman_generator::HuffmanGenerator;
+//! let tokens = [12u32, 7, 12, 12, 3];
+//! let flags = [false, false, true, false, false];
+//! let mut tokens_gen = HuffmanGenerator::new();
+//! tokens_gen.add_occurences_from_iterator(&mut tokens.iter());
+//! let mut flags_gen = HuffmanGenerator::new();
+//! flags_gen.add_occurences_from_iterator(&mut flags.iter());
+//! let (tokens_encoder, tokens_decoder) = tokens_gen.into_encoder_decoder_pair().unwrap();
+//! let (flags_encoder, flags_decoder) = flags_gen.into_encoder_decoder_pair().unwrap();
+//!
+//! let bits = interleaved::encode(
+//!     &tokens_encoder,
+//!     &flags_encoder,
+//!     &mut tokens.iter().zip(flags.iter()),
+//! )
+//! .unwrap();
+//! let pairs = interleaved::decode(&tokens_decoder, &flags_decoder, &bits).unwrap();
+//! assert_eq!(pairs[1], (7, false));
+//! assert_eq!(pairs.len(), 5);
+//! ```
+use bit_vec::BitVec;
+use std::hash::Hash;
+
+use super::huffman_decoder::{DecodeError, HuffmanDecoder};
+use super::huffman_encoder::HuffmanEncoder;
+
+/// A symbol with no code in the tree of its stream
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum UnknownSymbol<A, B> {
+    First(A),
+    Second(B),
+}
+
+/// Codes the pairs, the symbol of the first stream of each pair with `first` and the other
+/// with `second`.
+/// ## Errors
+/// Returns Err with a copy of the first symbol that has no code in the tree of its stream.
+pub fn encode<A, B>(
+    first: &HuffmanEncoder<A>,
+    second: &HuffmanEncoder<B>,
+    pairs: &mut dyn Iterator<Item = (&A, &B)>,
+) -> Result<BitVec, UnknownSymbol<A, B>>
+where
+    A: Eq + Hash + Clone + Ord,
+    B: Eq + Hash + Clone + Ord,
+{
+    let mut bits = BitVec::new();
+    for (a, b) in pairs {
+        first
+            .encode_symbol(a, &mut bits)
+            .map_err(UnknownSymbol::First)?;
+        second
+            .encode_symbol(b, &mut bits)
+            .map_err(UnknownSymbol::Second)?;
+    }
+    Ok(bits)
+}
+
+/// Decodes every pair of the buffer.
+/// ## Errors
+/// Returns Err if the buffer ends inside a pair, or is not empty and both trees are a lone
+/// symbol, whose pairs are coded with no bits.
+pub fn decode<A, B>(
+    first: &HuffmanDecoder<A>,
+    second: &HuffmanDecoder<B>,
+    buffer: &BitVec,
+) -> Result<Vec<(A, B)>, DecodeError>
+where
+    A: Eq + Clone,
+    B: Eq + Clone,
+{
+    // A pair takes a bit as long as one of the trees is not a lone symbol
+    if first.check_tree(buffer).is_err() && second.check_tree(buffer).is_err() {
+        return Err(DecodeError::InvalidTree);
+    }
+    let mut pairs = vec![];
+    let mut pos = 0;
+    while pos < buffer.len() {
+        let a = first.decode_symbol(buffer, &mut pos);
+        let b = second.decode_symbol(buffer, &mut pos);
+        match (a, b) {
+            (Some(a), Some(b)) => pairs.push((a, b)),
+            _ => return Err(DecodeError::UnexpectedEndOfInput),
+        }
+    }
+    Ok(pairs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::huffman_tree::huffman_generator::HuffmanGenerator;
+    use std::iter;
+
+    fn codec<T: Eq + Hash + Clone + Ord>(symbols: &[T]) -> (HuffmanEncoder<T>, HuffmanDecoder<T>) {
+        let mut gen = HuffmanGenerator::new();
+        gen.add_occurences_from_iterator(&mut symbols.iter());
+        gen.into_encoder_decoder_pair().unwrap()
+    }
+
+    #[test]
+    fn pairs_round_trip() {
+        let ids: Vec<u16> = (0..1_000u32).map(|i| (i * i % 997) as u16 % 120).collect();
+        let flags: Vec<u8> = (0..1_000u32).map(|i| (i % 7 == 0) as u8).collect();
+        let (ids_encoder, ids_decoder) = codec(&ids);
+        let (flags_encoder, flags_decoder) = codec(&flags);
+
+        let pairs = &mut ids.iter().zip(flags.iter());
+        let bits = encode(&ids_encoder, &flags_encoder, pairs).unwrap();
+        let ids_len = ids_encoder.encoded_len(&mut ids.iter()).unwrap();
+        let flags_len = flags_encoder.encoded_len(&mut flags.iter()).unwrap();
+        assert_eq!(bits.len() as u64, ids_len + flags_len);
+        let decoded = decode(&ids_decoder, &flags_decoder, &bits).unwrap();
+        let expected: Vec<(u16, u8)> = ids.iter().copied().zip(flags.iter().copied()).collect();
+        assert_eq!(decoded, expected);
+
+        let mut truncated = bits.clone();
+        truncated.truncate(bits.len() - 1);
+        assert_eq!(
+            decode(&ids_decoder, &flags_decoder, &truncated),
+            Err(DecodeError::UnexpectedEndOfInput)
+        );
+        assert_eq!(
+            encode(
+                &ids_encoder,
+                &flags_encoder,
+                &mut [(&1, &9)].iter().copied()
+            ),
+            Err(UnknownSymbol::Second(9))
+        );
+    }
+
+    #[test]
+    fn lone_symbols_take_no_bits() {
+        let ids = [4u8, 9, 4, 4];
+        let (ids_encoder, ids_decoder) = codec(&ids);
+        let (lone_encoder, lone_decoder) = codec(&['x']);
+
+        let pairs = &mut ids.iter().zip(iter::repeat(&'x'));
+        let bits = encode(&ids_encoder, &lone_encoder, pairs).unwrap();
+        assert_eq!(bits.len(), 4);
+        let decoded = decode(&ids_decoder, &lone_decoder, &bits).unwrap();
+        assert_eq!(decoded, [(4, 'x'), (9, 'x'), (4, 'x'), (4, 'x')]);
+
+        assert_eq!(
+            decode(&lone_decoder, &lone_decoder, &bits),
+            Err(DecodeError::InvalidTree)
+        );
+    }
+}
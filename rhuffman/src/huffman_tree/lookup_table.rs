@@ -0,0 +1,211 @@
+use std::collections::BTreeMap;
+
+use bit_vec::BitVec;
+
+use super::flat_tree::FlatTree;
+
+/// Width of the root table when none is requested
+pub const DEFAULT_ROOT_BITS: u8 = 9;
+/// Widest root table, of 64k entries
+pub const MAX_ROOT_BITS: u8 = 16;
+/// Subtables wider than this would cost more memory than they save time, trees needing them
+/// are decoded by walking the tree instead
+pub const MAX_SUBTABLE_BITS: u8 = 12;
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+enum Entry {
+    /// `len` is the full length of the code, root bits included
+    Symbol {
+        symbol: u32,
+        len: u8,
+    },
+    Subtable {
+        offset: u32,
+        bits: u8,
+    },
+}
+
+/// Two-level decoding table in the style of zlib's inflate. The next `root_bits` bits of the
+/// stream index the root table, which either directly gives the symbol and the length of its
+/// code, or points to a subtable indexed by the bits that follow. Only codes longer than the
+/// root share subtables, so memory stays proportional to the number of long codes instead of
+/// growing as `2^max_len`.
+///
+/// Tables are indexed with the first bit of the stream in the least significant position,
+/// matching the order of bits in the storage of a `BitVec`.
+#[derive(Debug, Clone)]
+pub struct LookupTable<T> {
+    root_bits: u8,
+    entries: Vec<Entry>,
+    symbols: Vec<T>,
+}
+
+impl<T: PartialEq + Eq + Clone> LookupTable<T> {
+    /// Returns None for trees made of a single leaf, or trees whose long codes would need
+    /// subtables wider than [MAX_SUBTABLE_BITS].
+    pub fn new(tree: &FlatTree<T>, root_bits: u8) -> Option<LookupTable<T>> {
+        let mut codes = vec![];
+        let mut too_deep = false;
+        tree.visit(&mut |path: &[bool], symbol: &T| {
+            // Skewed trees can be thousands of levels deep, their codes are not worth folding
+            if path.len() > 64 {
+                too_deep = true;
+                return;
+            }
+            let bits = path
+                .iter()
+                .rev()
+                .fold(0u64, |bits, &bit| (bits << 1) | bit as u64);
+            codes.push((bits, path.len(), symbol.clone()));
+        });
+
+        let max_len = codes.iter().map(|(_, len, _)| *len).max()?;
+        if max_len == 0 || too_deep {
+            return None;
+        }
+        let root_bits = root_bits.min(max_len as u8).max(1);
+        let root_mask = (1u64 << root_bits) - 1;
+
+        // Width of the subtable needed by each root prefix of the long codes
+        let mut subtable_bits = BTreeMap::new();
+        for (bits, len, _) in &codes {
+            if *len > root_bits as usize {
+                let width = subtable_bits.entry(bits & root_mask).or_insert(0);
+                *width = (*width).max(*len - root_bits as usize);
+            }
+        }
+        if subtable_bits
+            .values()
+            .any(|&width| width > MAX_SUBTABLE_BITS as usize)
+        {
+            return None;
+        }
+
+        let placeholder = Entry::Symbol { symbol: 0, len: 0 };
+        let mut entries = vec![placeholder; 1 << root_bits];
+        let mut subtable_offsets = BTreeMap::new();
+        for (&prefix, &width) in &subtable_bits {
+            let offset = entries.len();
+            entries.resize(offset + (1 << width), placeholder);
+            entries[prefix as usize] = Entry::Subtable {
+                offset: offset as u32,
+                bits: width as u8,
+            };
+            subtable_offsets.insert(prefix, (offset, width));
+        }
+
+        let mut symbols = Vec::with_capacity(codes.len());
+        for (bits, len, symbol) in codes {
+            let entry = Entry::Symbol {
+                symbol: symbols.len() as u32,
+                len: len as u8,
+            };
+            symbols.push(symbol);
+
+            let (offset, width, code, code_len) = if len <= root_bits as usize {
+                (0, root_bits as usize, bits, len)
+            } else {
+                let (offset, width) = subtable_offsets[&(bits & root_mask)];
+                (offset, width, bits >> root_bits, len - root_bits as usize)
+            };
+            // Every index whose low bits are the code decodes to it
+            for high in 0..1usize << (width - code_len) {
+                entries[offset + ((high << code_len) | code as usize)] = entry;
+            }
+        }
+
+        Some(LookupTable {
+            root_bits,
+            entries,
+            symbols,
+        })
+    }
+}
+
+impl<T> LookupTable<T> {
+    /// Decodes the symbol starting at `pos`. Returns None without moving `pos` when fewer
+    /// bits remain in the buffer than the code is long.
+    pub fn decode_symbol(&self, buffer: &BitVec, pos: &mut usize) -> Option<&T> {
+        let remaining = buffer.len() - *pos;
+        let mut entry = self.entries[peek(buffer, *pos, self.root_bits) as usize];
+        if let Entry::Subtable { offset, bits } = entry {
+            let index = peek(buffer, *pos + self.root_bits as usize, bits);
+            entry = self.entries[offset as usize + index as usize];
+        }
+        match entry {
+            Entry::Symbol { symbol, len } if len as usize <= remaining => {
+                *pos += len as usize;
+                Some(&self.symbols[symbol as usize])
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The next `count` bits at `pos`, first bit least significant, with zeros past the end
+fn peek(buffer: &BitVec, pos: usize, count: u8) -> u64 {
+    let storage = buffer.storage();
+    let block = pos / 32;
+    let low = u64::from(storage.get(block).copied().unwrap_or(0));
+    let high = u64::from(storage.get(block + 1).copied().unwrap_or(0));
+    (((high << 32) | low) >> (pos % 32)) & ((1 << count) - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fibonacci_tree;
+    use super::super::huffman_node::HuffmanNode;
+    use super::*;
+
+    fn assert_decodes_every_code(tree: &HuffmanNode<u32>, table: &LookupTable<u32>) {
+        tree.visit(&mut |path: &[bool], symbol: &u32| {
+            let mut buffer: BitVec = path.iter().copied().collect();
+            buffer.extend([true, false, true].iter().copied());
+            let mut pos = 0;
+            assert_eq!(table.decode_symbol(&buffer, &mut pos), Some(symbol));
+            assert_eq!(pos, path.len());
+        });
+    }
+
+    #[test]
+    fn long_codes_go_through_subtables() {
+        let tree = fibonacci_tree::<u32>(16);
+        let table = LookupTable::new(&FlatTree::from_tree(&tree), 8).unwrap();
+        assert!(table.entries.len() > 1 << 8);
+        assert!(table.entries.len() < 1 << 15);
+        assert_decodes_every_code(&tree, &table);
+    }
+
+    #[test]
+    fn short_codes_only_need_the_root() {
+        let tree = fibonacci_tree::<u32>(6);
+        let table = LookupTable::new(&FlatTree::from_tree(&tree), DEFAULT_ROOT_BITS).unwrap();
+        assert_eq!(table.entries.len(), 1 << 5);
+        assert_decodes_every_code(&tree, &table);
+    }
+
+    #[test]
+    fn too_deep_trees_are_refused() {
+        assert!(LookupTable::new(
+            &FlatTree::from_tree(&fibonacci_tree::<u32>(30)),
+            DEFAULT_ROOT_BITS
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn truncated_code_is_not_consumed() {
+        let tree = fibonacci_tree::<u32>(16);
+        let table = LookupTable::new(&FlatTree::from_tree(&tree), 8).unwrap();
+        let mut longest = vec![];
+        tree.visit(&mut |path: &[bool], _: &u32| {
+            if path.len() > longest.len() {
+                longest = path.to_vec();
+            }
+        });
+        let buffer: BitVec = longest[..10].iter().copied().collect();
+        let mut pos = 0;
+        assert_eq!(table.decode_symbol(&buffer, &mut pos), None);
+        assert_eq!(pos, 0);
+    }
+}
@@ -0,0 +1,101 @@
+//! Trees serialized as the flat list of their nodes rather than nested, for formats that limit
+//! nesting. `serde_json` reads at most 128 levels of nesting by default, so trees deeper than
+//! 41 levels, e.g. those of skewed frequencies, can not be read back from the nested form, see
+//! [HuffmanTree](super::HuffmanTree). Only built with the `serde` feature.
+//!
+//! The nodes are listed in preorder, a branch as no value (`None`, `null` in JSON) followed by
+//! its left then right subtrees, and a leaf as its symbol. Use it on a field with
+//! `#[serde(with = "rhuffman::huffman_tree::serde_flat")]`.
+//! ## Examples
+//! ```
+//! # use rhuffman::huffman_tree::HuffmanTree;
+//! # use rhuffman::huffman_tree::huffman_generator::HuffmanGenerator;
+//! # use serde::{Deserialize, Serialize};
+//! #[derive(Serialize, Deserialize)]
+//! struct Model {
+//!     #[serde(with = "rhuffman::huffman_tree::serde_flat")]
+//!     tree: HuffmanTree<u8>,
+//! }
+//!
+//! let mut gen = HuffmanGenerator::new();
+//! gen.add_occurences_from_iterator(&mut b"abracadabra".iter());
+//! let model = Model { tree: gen.into_huffman_tree().unwrap() };
+//! let json = serde_json::to_string(&model).unwrap();
+//! assert_eq!(json, r#"{"tree":[null,null,null,98,null,100,99,114,97]}"#);
+//! let read: Model = serde_json::from_str(&json).unwrap();
+//! assert_eq!(read.tree, model.tree);
+//! ```
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::huffman_node::{HuffmanBranch, HuffmanLeaf, HuffmanNode};
+
+/// Writes the nodes of the tree in preorder
+pub fn serialize<T, S>(tree: &HuffmanNode<T>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: PartialEq + Eq + Serialize,
+    S: Serializer,
+{
+    let mut nodes = vec![];
+    let mut pending = vec![tree];
+    while let Some(node) = pending.pop() {
+        match node {
+            HuffmanNode::Leaf(leaf) => nodes.push(Some(leaf.symbol())),
+            HuffmanNode::Branch(branch) => {
+                nodes.push(None);
+                pending.push(branch.right());
+                pending.push(branch.left());
+            }
+        }
+    }
+    nodes.serialize(serializer)
+}
+
+/// Reads the tree back from its nodes in preorder. It is rebuilt without recursion, so any
+/// depth is read.
+/// ## Errors
+/// Fails if the nodes are not those of a single tree: a branch missing a subtree, or nodes
+/// left over once the tree is complete.
+pub fn deserialize<'de, T, D>(deserializer: D) -> Result<HuffmanNode<T>, D::Error>
+where
+    T: PartialEq + Eq + Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    let nodes: Vec<Option<T>> = Vec::deserialize(deserializer)?;
+    // Branches being read, with their left subtree once it is complete
+    let mut branches: Vec<Option<HuffmanNode<T>>> = vec![];
+    let mut nodes = nodes.into_iter();
+    let mut root = None;
+    for node in nodes.by_ref() {
+        let mut complete = match node {
+            None => {
+                branches.push(None);
+                continue;
+            }
+            Some(symbol) => HuffmanNode::Leaf(HuffmanLeaf::new(symbol)),
+        };
+        loop {
+            match branches.pop() {
+                Some(None) => {
+                    branches.push(Some(complete));
+                    break;
+                }
+                Some(Some(left)) => {
+                    complete = HuffmanNode::Branch(HuffmanBranch::new(left, complete));
+                }
+                None => {
+                    root = Some(complete);
+                    break;
+                }
+            }
+        }
+        if root.is_some() {
+            break;
+        }
+    }
+    match root {
+        Some(root) if nodes.next().is_none() => Ok(root),
+        Some(_) => Err(D::Error::custom("Nodes follow the end of the tree")),
+        None => Err(D::Error::custom("The tree ends inside a branch")),
+    }
+}
@@ -0,0 +1,149 @@
+//! Test support checking that trees read back the same through the serde formats bindings use:
+//! JSON, MessagePack with and without field names, and postcard. Pins the representation
+//! [HuffmanTree](super::HuffmanTree) documents, which legacy files depend on.
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use super::fibonacci_tree;
+use super::huffman_generator::HuffmanGenerator;
+use super::huffman_node::{HuffmanBranch, HuffmanLeaf, HuffmanNode};
+use super::HuffmanTree;
+
+/// A tree serialized with [serde_flat](super::serde_flat)
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(bound(serialize = "T: Serialize", deserialize = "T: Deserialize<'de>"))]
+struct Flat<T: Eq> {
+    #[serde(with = "super::serde_flat")]
+    tree: HuffmanTree<T>,
+}
+
+/// The value read back after serializing it with each format, or the error of reading it
+fn through_every_format<V>(value: &V) -> Vec<(&'static str, Result<V, String>)>
+where
+    V: Serialize + DeserializeOwned,
+{
+    let json = serde_json::to_string(value).unwrap();
+    let msgpack = rmp_serde::to_vec(value).unwrap();
+    let named = rmp_serde::to_vec_named(value).unwrap();
+    let postcard = postcard::to_allocvec(value).unwrap();
+    vec![
+        (
+            "json",
+            serde_json::from_str(&json).map_err(|e| e.to_string()),
+        ),
+        (
+            "msgpack",
+            rmp_serde::from_slice(&msgpack).map_err(|e| e.to_string()),
+        ),
+        (
+            "msgpack named",
+            rmp_serde::from_slice(&named).map_err(|e| e.to_string()),
+        ),
+        (
+            "postcard",
+            postcard::from_bytes(&postcard).map_err(|e| e.to_string()),
+        ),
+    ]
+}
+
+fn assert_round_trips<V>(value: &V)
+where
+    V: Serialize + DeserializeOwned + PartialEq + std::fmt::Debug,
+{
+    for (format, read) in through_every_format(value) {
+        assert_eq!(read.as_ref(), Ok(value), "through {}", format);
+    }
+}
+
+fn tree_of<T: Eq + std::hash::Hash + Clone + Ord>(symbols: &[T]) -> HuffmanTree<T> {
+    let mut gen = HuffmanGenerator::new();
+    gen.add_occurences_from_iterator(&mut symbols.iter());
+    gen.into_huffman_tree().unwrap()
+}
+
+/// A chain of codes `depth` levels deep, from counts growing like Fibonacci numbers
+fn skewed(depth: usize) -> HuffmanTree<u8> {
+    fibonacci_tree(depth + 1)
+}
+
+#[test]
+fn trees_round_trip_through_every_format() {
+    let bytes: Vec<u8> = (0..5_000u64).map(|i| (i * i % 251) as u8).collect();
+    assert_round_trips(&tree_of(&bytes));
+    let chars: Vec<char> = "ünïcödé sýmbols, and plain ones".chars().collect();
+    assert_round_trips(&tree_of(&chars));
+    let words: Vec<String> = "the cat and the hat and the bat"
+        .split(' ')
+        .map(String::from)
+        .collect();
+    assert_round_trips(&tree_of(&words));
+    let wide: Vec<u16> = (0..5_000u32).map(|i| (i * 7 % 1013) as u16).collect();
+    assert_round_trips(&tree_of(&wide));
+    assert_round_trips(&tree_of(&[42u8]));
+
+    assert_round_trips(&Flat {
+        tree: tree_of(&bytes),
+    });
+    assert_round_trips(&Flat {
+        tree: tree_of(&words),
+    });
+}
+
+#[test]
+fn the_representation_is_pinned() {
+    let tree = HuffmanNode::Branch(HuffmanBranch::new(
+        HuffmanNode::Leaf(HuffmanLeaf::new(7u8)),
+        HuffmanNode::Branch(HuffmanBranch::new(
+            HuffmanNode::Leaf(HuffmanLeaf::new(8)),
+            HuffmanNode::Leaf(HuffmanLeaf::new(9)),
+        )),
+    ));
+    assert_eq!(
+        serde_json::to_string(&tree).unwrap(),
+        r#"{"Branch":{"links":[{"Leaf":{"symbol":7}},{"Branch":{"links":[{"Leaf":{"symbol":8}},{"Leaf":{"symbol":9}}]}}]}}"#
+    );
+    // As legacy files hold them: variants as a map of their index, structs as arrays
+    assert_eq!(
+        rmp_serde::to_vec(&tree).unwrap(),
+        [
+            0x81, 1, 0x91, 0x92, 0x81, 0, 0x91, 7, 0x81, 1, 0x91, 0x92, 0x81, 0, 0x91, 8, 0x81, 0,
+            0x91, 9
+        ]
+    );
+    assert_eq!(
+        postcard::to_allocvec(&tree).unwrap(),
+        [1, 0, 7, 1, 0, 8, 0, 9]
+    );
+    let flat = Flat { tree };
+    assert_eq!(
+        serde_json::to_string(&flat).unwrap(),
+        r#"{"tree":[null,7,null,8,9]}"#
+    );
+}
+
+#[test]
+fn deep_trees_take_the_flat_form_in_json() {
+    assert_round_trips(&skewed(41));
+    let deep = skewed(42);
+    for (format, read) in through_every_format(&deep) {
+        match format {
+            "json" => assert!(read.unwrap_err().contains("recursion limit")),
+            _ => assert_eq!(read, Ok(deep.clone()), "through {}", format),
+        }
+    }
+    assert_round_trips(&Flat { tree: skewed(80) });
+}
+
+#[test]
+fn flat_trees_must_be_complete() {
+    for json in [
+        r#"{"tree":[]}"#,
+        r#"{"tree":[null,1]}"#,
+        r#"{"tree":[1,2]}"#,
+        r#"{"tree":[null,1,2,3]}"#,
+    ] {
+        assert!(serde_json::from_str::<Flat<u8>>(json).is_err(), "{}", json);
+    }
+    let lone: Flat<u8> = serde_json::from_str(r#"{"tree":[5]}"#).unwrap();
+    assert_eq!(lone.tree, HuffmanNode::Leaf(HuffmanLeaf::new(5)));
+}
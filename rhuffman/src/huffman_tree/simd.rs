@@ -0,0 +1,346 @@
+//! Packing the codes of a [ByteEncoder](super::huffman_encoder::ByteEncoder) with vector
+//! instructions, AVX2 on x86_64 and NEON on aarch64. Only built with the `simd` feature.
+//!
+//! Codes of at most [MAX_LEN] bits are stored first bit lowest, the order [BitVec] keeps its
+//! blocks in. Eight bytes at a time, their codes are looked up and merged pairwise in the
+//! lanes of a vector, each code shifted by the length of the codes before it, down to two
+//! words of at most 64 bits. The words are then appended to whole blocks of the output,
+//! rather than bit by bit.
+use bit_vec::BitVec;
+
+use super::huffman_code::SmallCode;
+
+/// Longest code the vector path packs, four of them fill a word
+pub const MAX_LEN: u8 = 16;
+
+/// Set in the entries of bytes with no code
+const UNCODED: u32 = 1 << 31;
+const LEN_SHIFT: u32 = 16;
+
+/// Words packed before they are appended to the output
+const WRITE_CHUNK: usize = 1024;
+
+/// The codes of the 256 bytes, as packed by the vector path of this CPU
+#[derive(Debug, Clone)]
+pub struct Table {
+    /// The code of each byte, first bit lowest, its length above [LEN_SHIFT], or [UNCODED]
+    entries: [u32; 256],
+}
+
+impl Table {
+    /// None if a code is longer than [MAX_LEN] bits, or the CPU has no vector path
+    pub fn new(codes: &[Option<SmallCode>; 256]) -> Option<Table> {
+        if !supported() {
+            return None;
+        }
+        let mut entries = [UNCODED; 256];
+        for (entry, code) in entries.iter_mut().zip(codes.iter()) {
+            if let Some(code) = code {
+                let (bits, len) = code.parts();
+                if len > MAX_LEN {
+                    return None;
+                }
+                let reversed = match len {
+                    0 => 0,
+                    len => bits.reverse_bits() >> (64 - len),
+                };
+                *entry = reversed as u32 | u32::from(len) << LEN_SHIFT;
+            }
+        }
+        Some(Table { entries })
+    }
+
+    /// Appends the codes of the bytes to `out`.
+    /// ## Errors
+    /// Returns Err containing the first byte with no code. The codes of the bytes before it
+    /// are kept.
+    pub fn encode_append(&self, data: &[u8], out: &mut BitVec) -> Result<(), u8> {
+        let mut packer = WordPacker::new();
+        let mut groups = data.chunks_exact(8);
+        let mut result = Ok(());
+        for (i, group) in (&mut groups).enumerate() {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(group);
+            match self.merge(&bytes) {
+                Some(((low, low_len), (high, high_len))) => {
+                    packer.push(low, low_len);
+                    packer.push(high, high_len);
+                }
+                None => {
+                    // Packs the codes up to the byte with none
+                    result = self.push_each(group, &mut packer);
+                    break;
+                }
+            }
+            if packer.words.len() >= WRITE_CHUNK {
+                packer.flush(out, (i + 1) * 8, data.len());
+            }
+        }
+        if result.is_ok() {
+            result = self.push_each(groups.remainder(), &mut packer);
+        }
+        packer.finish(out);
+        result
+    }
+
+    fn push_each(&self, bytes: &[u8], packer: &mut WordPacker) -> Result<(), u8> {
+        for &byte in bytes {
+            let entry = self.entries[usize::from(byte)];
+            if entry & UNCODED != 0 {
+                return Err(byte);
+            }
+            packer.push(u64::from(entry & 0xFFFF), entry >> LEN_SHIFT);
+        }
+        Ok(())
+    }
+
+    /// The codes of the first four bytes and of the last four, with their lengths. None if a
+    /// byte has no code.
+    fn merge(&self, bytes: &[u8; 8]) -> Option<((u64, u32), (u64, u32))> {
+        #[cfg(target_arch = "x86_64")]
+        // SAFETY: tables are only built where AVX2 is detected
+        unsafe {
+            merge_avx2(&self.entries, bytes)
+        }
+        #[cfg(target_arch = "aarch64")]
+        // SAFETY: tables are only built where NEON is detected
+        unsafe {
+            merge_neon(&self.entries, bytes)
+        }
+        #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+        {
+            let _ = bytes;
+            None
+        }
+    }
+}
+
+fn supported() -> bool {
+    #[cfg(target_arch = "x86_64")]
+    {
+        std::is_x86_feature_detected!("avx2")
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        std::arch::is_aarch64_feature_detected!("neon")
+    }
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    {
+        false
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn merge_avx2(entries: &[u32; 256], bytes: &[u8; 8]) -> Option<((u64, u32), (u64, u32))> {
+    use std::arch::x86_64::*;
+
+    let indices = _mm256_cvtepu8_epi32(_mm_loadl_epi64(bytes.as_ptr() as *const __m128i));
+    let entries = _mm256_i32gather_epi32::<4>(entries.as_ptr() as *const i32, indices);
+    // UNCODED is the sign bit of the lanes
+    if _mm256_movemask_ps(_mm256_castsi256_ps(entries)) != 0 {
+        return None;
+    }
+    let codes = _mm256_and_si256(entries, _mm256_set1_epi32(0xFFFF));
+    let lens = _mm256_srli_epi32::<16>(entries);
+
+    // Every 64-bit lane holds two codes, merged into one of at most 32 bits
+    let low = _mm256_set1_epi64x(0xFFFF_FFFF);
+    let first_lens = _mm256_and_si256(lens, low);
+    let codes = _mm256_or_si256(
+        _mm256_and_si256(codes, low),
+        _mm256_sllv_epi64(_mm256_srli_epi64::<32>(codes), first_lens),
+    );
+    let lens = _mm256_add_epi64(first_lens, _mm256_srli_epi64::<32>(lens));
+
+    // Then lanes 0 and 1, and lanes 2 and 3, into words of at most 64 bits
+    let codes = _mm256_or_si256(
+        codes,
+        _mm256_sllv_epi64(_mm256_srli_si256::<8>(codes), lens),
+    );
+    let lens = _mm256_add_epi64(lens, _mm256_srli_si256::<8>(lens));
+    Some((
+        (
+            _mm256_extract_epi64::<0>(codes) as u64,
+            _mm256_extract_epi64::<0>(lens) as u32,
+        ),
+        (
+            _mm256_extract_epi64::<2>(codes) as u64,
+            _mm256_extract_epi64::<2>(lens) as u32,
+        ),
+    ))
+}
+
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "neon")]
+unsafe fn merge_neon(entries: &[u32; 256], bytes: &[u8; 8]) -> Option<((u64, u32), (u64, u32))> {
+    use std::arch::aarch64::*;
+
+    // NEON has no gather
+    let mut looked_up = [0u32; 8];
+    for (entry, &byte) in looked_up.iter_mut().zip(bytes.iter()) {
+        *entry = entries[usize::from(byte)];
+    }
+    if looked_up.iter().fold(0, |all, entry| all | entry) & UNCODED != 0 {
+        return None;
+    }
+
+    let (first_codes, first_lens) = merge_pairs_neon(vld1q_u32(looked_up.as_ptr()));
+    let (last_codes, last_lens) = merge_pairs_neon(vld1q_u32(looked_up[4..].as_ptr()));
+
+    // Then the two pairs of each half into words of at most 64 bits
+    let lens = vuzp1q_u64(first_lens, last_lens);
+    let codes = vorrq_u64(
+        vuzp1q_u64(first_codes, last_codes),
+        vshlq_u64(
+            vuzp2q_u64(first_codes, last_codes),
+            vreinterpretq_s64_u64(lens),
+        ),
+    );
+    let lens = vaddq_u64(lens, vuzp2q_u64(first_lens, last_lens));
+    Some((
+        (vgetq_lane_u64::<0>(codes), vgetq_lane_u64::<0>(lens) as u32),
+        (vgetq_lane_u64::<1>(codes), vgetq_lane_u64::<1>(lens) as u32),
+    ))
+}
+
+/// Every 64-bit lane holds two codes, merged into one of at most 32 bits
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "neon")]
+unsafe fn merge_pairs_neon(
+    entries: std::arch::aarch64::uint32x4_t,
+) -> (
+    std::arch::aarch64::uint64x2_t,
+    std::arch::aarch64::uint64x2_t,
+) {
+    use std::arch::aarch64::*;
+
+    let codes = vreinterpretq_u64_u32(vandq_u32(entries, vdupq_n_u32(0xFFFF)));
+    let lens = vreinterpretq_u64_u32(vshrq_n_u32::<16>(entries));
+    let low = vdupq_n_u64(0xFFFF_FFFF);
+    let first_lens = vandq_u64(lens, low);
+    let codes = vorrq_u64(
+        vandq_u64(codes, low),
+        vshlq_u64(vshrq_n_u64::<32>(codes), vreinterpretq_s64_u64(first_lens)),
+    );
+    (codes, vaddq_u64(first_lens, vshrq_n_u64::<32>(lens)))
+}
+
+/// Codes packed into words, first bit lowest
+struct WordPacker {
+    words: Vec<u64>,
+    /// Bits not yet packed in a word, always fewer than 64 between codes
+    pending: u128,
+    pending_len: u32,
+    /// Bits appended to the output so far
+    flushed: usize,
+}
+
+impl WordPacker {
+    fn new() -> WordPacker {
+        WordPacker {
+            words: Vec::with_capacity(WRITE_CHUNK),
+            pending: 0,
+            pending_len: 0,
+            flushed: 0,
+        }
+    }
+
+    fn push(&mut self, bits: u64, len: u32) {
+        self.pending |= u128::from(bits) << self.pending_len;
+        self.pending_len += len;
+        if self.pending_len >= 64 {
+            self.words.push(self.pending as u64);
+            self.pending >>= 64;
+            self.pending_len -= 64;
+        }
+    }
+
+    /// Appends the packed words to `out`, sizing it for the whole stream on the first call
+    /// from how many bits the bytes packed so far took
+    fn flush(&mut self, out: &mut BitVec, packed_bytes: usize, total_bytes: usize) {
+        let len = self.words.len() * 64;
+        if self.flushed == 0 {
+            let estimate = len as u128 * total_bytes as u128 / packed_bytes as u128;
+            out.reserve(estimate.min(usize::MAX as u128) as usize);
+        }
+        append_words(out, &self.words, len);
+        self.words.clear();
+        self.flushed += len;
+    }
+
+    fn finish(mut self, out: &mut BitVec) {
+        let len = self.words.len() * 64 + self.pending_len as usize;
+        self.words.push(self.pending as u64);
+        append_words(out, &self.words, len);
+    }
+}
+
+/// Appends the first `len` bits of the words, first bit lowest, to `out` a block at a time
+fn append_words(out: &mut BitVec, words: &[u64], len: usize) {
+    let start = out.len();
+    out.grow(len, false);
+    let shift = start % 32;
+    let blocks = words
+        .iter()
+        .flat_map(|&word| [word as u32, (word >> 32) as u32])
+        .take(len.div_ceil(32));
+    // SAFETY: only bits below the new length are set, the bits past it stay zero
+    let storage = unsafe { out.storage_mut() };
+    for (i, block) in blocks.enumerate() {
+        let index = start / 32 + i;
+        if let Some(first) = storage.get_mut(index) {
+            *first |= block << shift;
+        }
+        if shift > 0 {
+            if let Some(second) = storage.get_mut(index + 1) {
+                *second |= block >> (32 - shift);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fibonacci_tree;
+    use super::super::huffman_encoder::{ByteEncoder, HuffmanEncoder};
+    use super::*;
+
+    fn table(encoder: &HuffmanEncoder<u8>) -> Option<Table> {
+        Table::new(&ByteEncoder::new(encoder)?.codes)
+    }
+
+    #[test]
+    fn packs_like_the_encoder() {
+        let data: Vec<u8> = (0..100_000u64).map(|i| (i * i % 251 % 97) as u8).collect();
+        let encoder = HuffmanEncoder::from_symbols_iterator(&mut data.iter()).unwrap();
+        let table = match table(&encoder) {
+            Some(table) => table,
+            // No vector path on this CPU
+            None => return,
+        };
+
+        for len in [0, 1, 7, 8, 9, 1000, data.len()] {
+            for offset in [0, 5, 32] {
+                let mut expected = BitVec::from_elem(offset, true);
+                expected.append(&mut encoder.encode(&mut data[..len].iter()).unwrap());
+                let mut packed = BitVec::from_elem(offset, true);
+                table.encode_append(&data[..len], &mut packed).unwrap();
+                assert_eq!(packed, expected);
+            }
+        }
+
+        let mut damaged = data[..20].to_vec();
+        damaged[13] = 200;
+        let mut packed = BitVec::new();
+        assert_eq!(table.encode_append(&damaged, &mut packed), Err(200));
+        assert_eq!(packed, encoder.encode(&mut damaged[..13].iter()).unwrap());
+    }
+
+    #[test]
+    fn long_codes_are_left_to_the_scalar_path() {
+        // Fibonacci counts make a chain of codes up to 19 bits long
+        let encoder = HuffmanEncoder::from_tree(&fibonacci_tree::<u8>(20));
+        assert!(table(&encoder).is_none());
+    }
+}
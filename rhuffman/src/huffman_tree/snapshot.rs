@@ -0,0 +1,107 @@
+//! Shipping the counts of a [HuffmanGenerator] as they grow, for training spread over many
+//! jobs. Each job takes a [snapshot](HuffmanGenerator::snapshot) of its generator whenever it
+//! reports, and sends the [CountDelta] since its last report instead of a whole model. The
+//! aggregator [applies](HuffmanGenerator::apply_delta) the deltas to its own generator.
+//! ## Examples
+//! ```
+//! # use rhuffman::huffman_tree::huffman_generator::HuffmanGenerator;
+//! let mut shard = HuffmanGenerator::new();
+//! let mut aggregator = HuffmanGenerator::new();
+//!
+//! let reported = shard.snapshot();
+//! shard.add_occurences_from_iterator(&mut "abracadabra".chars().collect::<Vec<_>>().iter());
+//! let now = shard.snapshot();
+//! aggregator.apply_delta(&now.delta(&reported));
+//!
+//! assert_eq!(aggregator.snapshot(), now);
+//! ```
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::hash::Hash;
+
+#[cfg(doc)]
+use super::huffman_generator::HuffmanGenerator;
+
+/// The counts of a generator at some point
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Snapshot<T: Eq + Hash> {
+    counts: HashMap<T, u64>,
+}
+
+/// Occurences to add to a generator, each symbol listed once and in increasing order
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CountDelta<T> {
+    pub counts: Vec<(T, u64)>,
+}
+
+impl<T: Eq + Hash + Clone + Ord> Snapshot<T> {
+    pub(crate) fn new(counts: impl Iterator<Item = (T, u64)>) -> Snapshot<T> {
+        let mut snapshot = Snapshot {
+            counts: HashMap::new(),
+        };
+        for (symbol, count) in counts {
+            let total = snapshot.counts.entry(symbol).or_insert(0);
+            *total = total.saturating_add(count);
+        }
+        snapshot
+    }
+
+    /// The occurences added between `since` and this snapshot of the same generator. Symbols
+    /// whose count did not grow are left out.
+    pub fn delta(&self, since: &Snapshot<T>) -> CountDelta<T> {
+        let mut counts: Vec<(T, u64)> = self
+            .counts
+            .iter()
+            .filter_map(|(symbol, &count)| {
+                let added = count.saturating_sub(since.counts.get(symbol).copied().unwrap_or(0));
+                (added > 0).then(|| (symbol.clone(), added))
+            })
+            .collect();
+        counts.sort_unstable();
+        CountDelta { counts }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::huffman_tree::huffman_generator::HuffmanGenerator;
+
+    #[test]
+    fn deltas_of_shards_add_up() {
+        let data: Vec<u8> = (0..4_000u32).map(|i| (i * i % 97) as u8).collect();
+        let mut whole = HuffmanGenerator::new();
+        whole.add_occurences_from_iterator(&mut data.iter());
+
+        let mut aggregator = HuffmanGenerator::new();
+        let mut shards = [HuffmanGenerator::new(), HuffmanGenerator::new()];
+        let mut reported: Vec<_> = shards.iter().map(|shard| shard.snapshot()).collect();
+        for (i, chunk) in data.chunks(300).enumerate() {
+            let shard = &mut shards[i % 2];
+            shard.add_occurences_from_iterator(&mut chunk.iter());
+            let now = shard.snapshot();
+            let delta = now.delta(&reported[i % 2]);
+            assert!(delta.counts.windows(2).all(|pair| pair[0].0 < pair[1].0));
+            aggregator.apply_delta(&delta);
+            reported[i % 2] = now;
+        }
+        assert_eq!(aggregator, whole);
+        assert!(reported[0].delta(&reported[0]).counts.is_empty());
+    }
+
+    #[test]
+    fn deltas_saturate_the_counts() {
+        let huge = CountDelta {
+            counts: vec![(b'a', u64::MAX), (b'b', 1)],
+        };
+        let mut aggregator = HuffmanGenerator::new();
+        aggregator.apply_delta(&huge);
+        aggregator.apply_delta(&huge);
+        let counts = aggregator.snapshot();
+        let expected = Snapshot::new(vec![(b'a', usize::MAX as u64), (b'b', 2)].into_iter());
+        assert_eq!(counts, expected);
+        assert!(aggregator.into_huffman_tree().is_some());
+    }
+}
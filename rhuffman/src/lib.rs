@@ -1 +1,25 @@
+//! Generate and use Huffman encodings for your symbols, and compress bytes into
+//! self-describing [containers](container).
+//!
+//! ## Panics
+//! No input makes the library panic: functions reading data, e.g. [container::decompress()],
+//! [container::inspect()] or the filters' `reverse`, return Err on anything they can not
+//! decode, however corrupted or malicious. `unwrap()`, `expect()` and `panic!()` are denied
+//! outside of tests to keep it that way, and the tests run every reader on a corpus of
+//! truncated and mutated containers. [regression::replay()] runs them on inputs of your own.
+#![cfg_attr(
+    not(test),
+    deny(clippy::unwrap_used, clippy::expect_used, clippy::panic)
+)]
+#[cfg(feature = "debug-alloc")]
+pub mod alloc_counter;
+pub mod auto;
+pub mod bench;
+pub mod container;
+pub mod entropy;
+pub mod filters;
 pub mod huffman_tree;
+pub mod presets;
+pub mod regression;
+pub mod report;
+mod varint;
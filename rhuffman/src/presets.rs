@@ -0,0 +1,236 @@
+//! Named codebooks shared across an application. A [Preset] is trained once, registered
+//! under an id, and then looked up by that id wherever data has to be encoded or decoded,
+//! so that compressed payloads only need to carry the id instead of the whole tree.
+use std::{
+    collections::HashMap,
+    hash::{Hash, Hasher},
+    sync::{Arc, OnceLock, RwLock},
+};
+
+use crate::huffman_tree::{
+    huffman_decoder::HuffmanDecoder, huffman_encoder::HuffmanEncoder,
+    huffman_generator::HuffmanTree,
+};
+
+pub type PresetId = u32;
+
+/// A codebook registered in a [Registry], with its encoder and decoder built up front.
+pub struct Preset<T: Eq + Hash + Clone + Ord> {
+    id: PresetId,
+    fingerprint: u64,
+    tree: HuffmanTree<T>,
+    encoder: HuffmanEncoder<T>,
+    decoder: HuffmanDecoder<T>,
+}
+
+impl<T: Eq + Hash + Clone + Ord> Preset<T> {
+    pub fn new(id: PresetId, tree: HuffmanTree<T>) -> Preset<T> {
+        Preset {
+            id,
+            fingerprint: fingerprint(&tree),
+            encoder: HuffmanEncoder::from_tree(&tree),
+            decoder: HuffmanDecoder::new(tree.clone()),
+            tree,
+        }
+    }
+
+    pub fn id(&self) -> PresetId {
+        self.id
+    }
+
+    /// A hash of the codes of the tree, identical across processes and platforms for the same
+    /// tree. Useful to record which version of a preset some data was encoded with.
+    pub fn fingerprint(&self) -> u64 {
+        self.fingerprint
+    }
+
+    pub fn tree(&self) -> &HuffmanTree<T> {
+        &self.tree
+    }
+
+    pub fn encoder(&self) -> &HuffmanEncoder<T> {
+        &self.encoder
+    }
+
+    pub fn decoder(&self) -> &HuffmanDecoder<T> {
+        &self.decoder
+    }
+}
+
+/// A thread-safe map from [PresetId] to [Preset]. Lookups hand out an `Arc` so a preset
+/// stays usable by whoever holds it for as long as needed.
+///
+/// Byte codebooks are usually registered in the process-wide [global()] registry.
+/// ## Examples
+/// ```
+/// # use rhuffman::presets::Registry;
+/// # use rhuffman::huffman_tree::huffman_generator::HuffmanGenerator;
+/// let mut gen = HuffmanGenerator::new();
+/// gen.add_occurences_from_iterator(&mut b"abracadabra".iter());
+///
+/// let registry = Registry::new();
+/// registry.register(7, gen.into_huffman_tree().unwrap()).unwrap();
+///
+/// let preset = registry.get(7).unwrap();
+/// let encoded = preset.encoder().encode(&mut b"cab".iter()).unwrap();
+/// assert_eq!(preset.decoder().decode_unbounded(&encoded).unwrap(), b"cab");
+/// ```
+pub struct Registry<T: Eq + Hash + Clone + Ord> {
+    presets: RwLock<HashMap<PresetId, Arc<Preset<T>>>>,
+}
+
+impl<T: Eq + Hash + Clone + Ord> Default for Registry<T> {
+    fn default() -> Self {
+        Registry::new()
+    }
+}
+
+impl<T: Eq + Hash + Clone + Ord> Registry<T> {
+    pub fn new() -> Registry<T> {
+        Registry {
+            presets: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Registers the tree under the given id.
+    /// ## Errors
+    /// Returns Err if another preset is already registered under that id.
+    pub fn register(
+        &self,
+        id: PresetId,
+        tree: HuffmanTree<T>,
+    ) -> Result<Arc<Preset<T>>, &'static str> {
+        let preset = Arc::new(Preset::new(id, tree));
+        let mut presets = self.presets.write().unwrap_or_else(|e| e.into_inner());
+        if presets.contains_key(&id) {
+            return Err("A preset is already registered under this id");
+        }
+        presets.insert(id, preset.clone());
+        Ok(preset)
+    }
+
+    /// Registers the tree under the given id, atomically swapping out any preset already
+    /// registered under it. Holders of the previous preset keep using it until they drop it,
+    /// so in-flight work is never interrupted by a reload.
+    ///
+    /// Returns the preset now registered, which later replacements leave untouched.
+    pub fn replace(&self, id: PresetId, tree: HuffmanTree<T>) -> Arc<Preset<T>> {
+        let preset = Arc::new(Preset::new(id, tree));
+        let mut presets = self.presets.write().unwrap_or_else(|e| e.into_inner());
+        presets.insert(id, preset.clone());
+        preset
+    }
+
+    pub fn get(&self, id: PresetId) -> Option<Arc<Preset<T>>> {
+        let presets = self.presets.read().unwrap_or_else(|e| e.into_inner());
+        presets.get(&id).cloned()
+    }
+
+    /// The ids of every registered preset, in increasing order
+    pub fn ids(&self) -> Vec<PresetId> {
+        let presets = self.presets.read().unwrap_or_else(|e| e.into_inner());
+        let mut ids: Vec<PresetId> = presets.keys().copied().collect();
+        ids.sort_unstable();
+        ids
+    }
+}
+
+/// FNV-1a, chosen over `DefaultHasher` because its output must not change between releases
+pub(crate) struct Fnv1a(u64);
+
+impl Fnv1a {
+    pub(crate) fn new() -> Fnv1a {
+        Fnv1a(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for Fnv1a {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+fn fingerprint<T: Eq + Hash>(tree: &HuffmanTree<T>) -> u64 {
+    let mut hasher = Fnv1a::new();
+    tree.visit(&mut |path: &[bool], symbol: &T| {
+        hasher.write_u64(path.len() as u64);
+        for bit in path {
+            hasher.write_u8(*bit as u8);
+        }
+        symbol.hash(&mut hasher);
+    });
+    hasher.finish()
+}
+
+/// The process-wide registry of byte codebooks.
+pub fn global() -> &'static Registry<u8> {
+    static GLOBAL: OnceLock<Registry<u8>> = OnceLock::new();
+    GLOBAL.get_or_init(Registry::new)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::huffman_tree::huffman_generator::HuffmanGenerator;
+
+    fn tree_from(text: &[u8]) -> HuffmanTree<u8> {
+        let mut gen = HuffmanGenerator::new();
+        gen.add_occurences_from_iterator(&mut text.iter());
+        gen.into_huffman_tree().unwrap()
+    }
+
+    #[test]
+    fn registering_twice_under_one_id_fails() {
+        let registry = Registry::new();
+        registry.register(1, tree_from(b"ab")).unwrap();
+        assert!(registry.register(1, tree_from(b"cd")).is_err());
+        assert_eq!(registry.get(1).unwrap().tree(), &tree_from(b"ab"));
+    }
+
+    #[test]
+    fn replacing_keeps_previous_preset_alive_for_holders() {
+        let registry = Registry::new();
+        registry.register(1, tree_from(b"ab")).unwrap();
+        let in_flight = registry.get(1).unwrap();
+
+        let current = registry.replace(1, tree_from(b"abbbc"));
+        assert!(Arc::ptr_eq(&current, &registry.get(1).unwrap()));
+        assert_ne!(current.fingerprint(), in_flight.fingerprint());
+        registry.replace(1, tree_from(b"abc"));
+        assert_eq!(current.tree(), &tree_from(b"abbbc"));
+        assert_eq!(in_flight.tree(), &tree_from(b"ab"));
+    }
+
+    #[test]
+    fn fingerprint_only_depends_on_the_tree() {
+        assert_eq!(
+            Preset::new(1, tree_from(b"hello")).fingerprint(),
+            Preset::new(2, tree_from(b"hello")).fingerprint()
+        );
+    }
+
+    #[test]
+    fn registry_is_shared_across_threads() {
+        let registry = Arc::new(Registry::new());
+        let handles: Vec<_> = (0..8)
+            .map(|id| {
+                let registry = registry.clone();
+                std::thread::spawn(move || {
+                    registry.register(id, tree_from(b"hello world")).unwrap();
+                    assert_eq!(registry.get(id).unwrap().id(), id);
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(registry.ids(), (0..8).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,204 @@
+//! Replaying inputs that once caused trouble, e.g. crashes found by a fuzzer or files from
+//! bug reports, to check that they are still rejected with an error instead of a panic.
+//!
+//! A corpus is a directory of files, each one an input, in any tree of subdirectories, e.g.
+//! one per kind of damage. [replay()] runs every reader of the library on each of them, and
+//! [replay_with()] runs code of your own, so integrations can be held to the same guardrail.
+//! ## Examples
+//! ```no_run
+//! # use rhuffman::regression;
+//! # use std::path::Path;
+//! let corpus = Path::new("tests/corpus");
+//! assert!(regression::replay(corpus).unwrap().is_empty());
+//! let panics = regression::replay_with(corpus, "my_import", |input| {
+//!     let _ = rhuffman::container::decompress(input);
+//! });
+//! assert!(panics.unwrap().is_empty());
+//! ```
+use std::fs;
+use std::io::{self, Read};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+
+use crate::container::{self, channels, delta, tar, zip, DecodeOptions, HuffmanReader};
+use crate::filters::utf8::Utf8Policy;
+use crate::filters::Filter;
+
+/// A reader that panicked on an input of the corpus
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Panic {
+    pub input: PathBuf,
+    /// Name of the reader, e.g. `decompress`
+    pub reader: &'static str,
+    /// The message of the panic, empty if it was not a string
+    pub message: String,
+}
+
+const STRICT: DecodeOptions = DecodeOptions {
+    strict: true,
+    threads: 0,
+    plugins: None,
+    stall_bits: None,
+    max_output: None,
+};
+
+/// Decodes the blocks of a container on several threads, which takes another path
+const THREADED: DecodeOptions = DecodeOptions {
+    threads: 2,
+    ..STRICT
+};
+
+/// A reader of the library, by name
+type Reader = (&'static str, fn(&[u8]));
+
+/// Every function of the library reading untrusted data. Errors are expected and
+/// ignored, only panics count.
+pub(crate) const READERS: &[Reader] = &[
+    ("decompress", |input| {
+        let _ = container::decompress(input);
+    }),
+    ("decompress_strict", |input| {
+        let _ = container::decompress_with_options(input, &STRICT);
+    }),
+    ("decompress_threaded", |input| {
+        let _ = container::decompress_with_options(input, &THREADED);
+    }),
+    ("inspect", |input| {
+        let _ = container::inspect(input);
+    }),
+    ("diagnose", |input| {
+        let _ = container::diagnose(input, &STRICT);
+    }),
+    ("recover", |input| {
+        let _ = container::recover(input);
+    }),
+    ("HuffmanReader", |input| {
+        let _ = HuffmanReader::with_options(input, &STRICT).read_to_end(&mut vec![]);
+    }),
+    ("delta::apply", |input| {
+        if let Ok(base) = container::compress(b"delta base", &container::Options::default()) {
+            let _ = delta::apply(&base, input);
+        }
+    }),
+    ("tar::members", |input| {
+        let _ = tar::members(input);
+    }),
+    ("Channels::from_bytes", |input| {
+        let _ = channels::Channels::from_bytes(input);
+    }),
+    ("ZipReader", |input| {
+        if let Ok(reader) = zip::ZipReader::new(input) {
+            for entry in reader.entries() {
+                let _ = reader.read(entry);
+            }
+        }
+    }),
+    ("Filter::reverse", |input| {
+        for filter in &[
+            Filter::Timeseries,
+            Filter::Nibbles,
+            Filter::NibblePlanes,
+            Filter::Rle,
+            Filter::Chars(Utf8Policy::Escape),
+        ] {
+            let _ = filter.reverse(input);
+        }
+    }),
+];
+
+/// Runs every reader of the library on every file under `dir`, returning the panics, in the
+/// order of the paths of the inputs. The panic hook still runs, so the messages are also
+/// printed on stderr by default.
+/// ## Errors
+/// Returns Err if the directory or one of its files can not be read.
+pub fn replay(dir: &Path) -> io::Result<Vec<Panic>> {
+    let mut panics = vec![];
+    for (path, input) in inputs(dir)? {
+        for &(reader, read) in READERS {
+            panics.extend(run(&path, reader, || read(&input)));
+        }
+    }
+    Ok(panics)
+}
+
+/// Same as [replay()], running `check` on the inputs instead of the readers of the library.
+/// Panics are reported under the name of the reader.
+/// ## Errors
+/// Returns Err if the directory or one of its files can not be read.
+pub fn replay_with<F: Fn(&[u8])>(
+    dir: &Path,
+    reader: &'static str,
+    check: F,
+) -> io::Result<Vec<Panic>> {
+    let mut panics = vec![];
+    for (path, input) in inputs(dir)? {
+        panics.extend(run(&path, reader, || check(&input)));
+    }
+    Ok(panics)
+}
+
+fn run(input: &Path, reader: &'static str, read: impl FnOnce()) -> Option<Panic> {
+    let payload = panic::catch_unwind(AssertUnwindSafe(read)).err()?;
+    let message = match (
+        payload.downcast_ref::<&str>(),
+        payload.downcast_ref::<String>(),
+    ) {
+        (Some(message), _) => message.to_string(),
+        (_, Some(message)) => message.clone(),
+        _ => String::new(),
+    };
+    Some(Panic {
+        input: input.to_path_buf(),
+        reader,
+        message,
+    })
+}
+
+/// Every file under the directory with its contents, sorted by path
+fn inputs(dir: &Path) -> io::Result<Vec<(PathBuf, Vec<u8>)>> {
+    let mut pending = vec![dir.to_path_buf()];
+    let mut inputs = vec![];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else {
+                let input = fs::read(&path)?;
+                inputs.push((path, input));
+            }
+        }
+    }
+    inputs.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+    Ok(inputs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::Options;
+
+    #[test]
+    fn corpora_are_replayed() {
+        let dir = std::env::temp_dir().join(format!("rhuffman-regression-{}", std::process::id()));
+        fs::create_dir_all(dir.join("truncated")).unwrap();
+        let valid = container::compress(b"replayed input", &Options::default()).unwrap();
+        fs::write(dir.join("valid"), &valid).unwrap();
+        fs::write(dir.join("truncated/half"), &valid[..valid.len() / 2]).unwrap();
+
+        assert_eq!(replay(&dir).unwrap(), vec![]);
+        let panics = replay_with(&dir, "short", |input| {
+            assert!(input.len() == valid.len(), "too short")
+        });
+        let panics = panics.unwrap();
+        assert_eq!(panics.len(), 1);
+        assert_eq!(panics[0].input, dir.join("truncated/half"));
+        assert_eq!(
+            (panics[0].reader, panics[0].message.as_str()),
+            ("short", "too short")
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(replay(&dir).is_err());
+    }
+}
@@ -0,0 +1,136 @@
+//! Types of the JSON reports `rhuff-compress --json` prints, for tools reading them. With the
+//! `serde` feature they implement `Serialize` and `Deserialize`, and
+//! `serde_json::from_str::<report::Stats>(line)` reads back what the binary printed.
+//!
+//! Each report carries the [SCHEMA_VERSION] it was written with. Fields are only added
+//! within a version, so a reader of version 1 reads every report of version 1. Fields that
+//! are not always there are left out rather than null.
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Version of the report schema, bumped when a field is renamed, removed or changes meaning
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Printed on stderr by `--stats`
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Stats {
+    pub schema: u32,
+    /// `compress` or `decompress`
+    pub operation: String,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    /// `bytes_out / bytes_in`, 0 for an empty input
+    pub ratio: f64,
+    pub wall_seconds: f64,
+    /// Resource usage of the process, where the platform reports it
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub user_seconds: Option<f64>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub system_seconds: Option<f64>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub peak_rss_bytes: Option<u64>,
+}
+
+/// Printed on stderr when a run fails
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Failure {
+    pub schema: u32,
+    pub error: String,
+    /// What the failure happened in, e.g. `"file"` or `"operation"`
+    pub context: BTreeMap<String, String>,
+}
+
+/// Printed on stderr for something that did not stop the run
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Warning {
+    pub schema: u32,
+    pub warning: String,
+    /// Block the warning is about
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub block: Option<u64>,
+    /// Ratio the input is expected to compress to
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub estimated_ratio: Option<f64>,
+}
+
+/// Printed on stdout by `tune`
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Tune {
+    pub schema: u32,
+    /// Number of folds the corpus was split in
+    pub folds: u64,
+    pub models: Vec<TunedModel>,
+    /// Name of the model written as a preset
+    pub best: String,
+}
+
+/// How a model did in `tune`
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TunedModel {
+    /// e.g. `byte`, `byte-top-64` or `nibble-all`
+    pub model: String,
+    /// Compressed size over original size on the held-out folds it could code
+    pub ratio: f64,
+    /// Folds with a symbol the model has no code for
+    pub failed_folds: u64,
+}
+
+/// Printed on stderr by `--probe`
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Probe {
+    pub schema: u32,
+    /// Best first
+    pub models: Vec<ProbedModel>,
+    /// Model the input was compressed with, the best one a compressed file can code
+    pub chosen: String,
+}
+
+/// Printed on stderr by `migrate`
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Migrate {
+    pub schema: u32,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    /// Filter of the legacy file, kept in the container
+    pub filter: String,
+    /// Checksum recorded in the container
+    pub checksum: String,
+    /// Whether the payload was copied bit for bit rather than encoded again
+    pub payload_kept: bool,
+    pub payload_bits: u64,
+}
+
+/// How a model did in `--probe`
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ProbedModel {
+    /// `byte`, `u16`, `nibble` or `digram`
+    pub model: String,
+    /// Symbols with a code, over all the trees of the model
+    pub symbols: u64,
+    /// Estimated compressed size over original size of the sample
+    pub ratio: f64,
+}
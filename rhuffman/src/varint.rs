@@ -0,0 +1,44 @@
+//! LEB128 variable length integers, used wherever a length or count is stored in a byte stream.
+
+pub fn write(mut value: u64, out: &mut Vec<u8>) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+pub fn read(data: &[u8], pos: &mut usize) -> Result<u64, &'static str> {
+    let mut value = 0u64;
+    let mut shift = 0;
+    loop {
+        let byte = *data.get(*pos).ok_or("Truncated varint")?;
+        *pos += 1;
+        if shift >= 64 {
+            return Err("Varint is too long");
+        }
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn varints_round_trip() {
+        let mut out = vec![];
+        for value in &[0, 1, 127, 128, 300, u64::MAX] {
+            write(*value, &mut out);
+        }
+        let mut pos = 0;
+        for value in &[0, 1, 127, 128, 300, u64::MAX] {
+            assert_eq!(read(&out, &mut pos), Ok(*value));
+        }
+        assert_eq!(pos, out.len());
+    }
+}